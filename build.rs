@@ -0,0 +1,24 @@
+/*!
+Build script, generating a C header for the C API when the `capi` feature is enabled
+*/
+
+fn main() {
+    #[cfg(feature = "capi")]
+    {
+        let crate_dir = std::env::var("CARGO_MANIFEST_DIR").expect("Set by cargo");
+        let out_dir = std::env::var("OUT_DIR").expect("Set by cargo");
+        println!("cargo:rerun-if-changed=src/capi.rs");
+        println!("cargo:rerun-if-env-changed=STOCKBURN_HEADER_DIR");
+        let bindings = cbindgen::Builder::new()
+            .with_crate(&crate_dir)
+            .with_language(cbindgen::Language::C)
+            .with_include_guard("STOCKBURN_H")
+            .generate()
+            .expect("Failed to generate C header");
+        bindings.write_to_file(std::path::Path::new(&out_dir).join("stockburn.h"));
+        // Build scripts should only write to `OUT_DIR`, so the header is only copied elsewhere on request
+        if let Some(header_dir) = std::env::var_os("STOCKBURN_HEADER_DIR") {
+            bindings.write_to_file(std::path::Path::new(&header_dir).join("stockburn.h"));
+        }
+    }
+}
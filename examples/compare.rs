@@ -0,0 +1,141 @@
+/*!
+Compare several checkpoints side by side on the same validation data
+*/
+use anyhow::format_err;
+use chrono::Duration;
+use clap::{App, Arg};
+use stockburn::data::{
+    clocks, dataset::Dataset, default_clock_periods, load_dir, load_files, parse_durations,
+    scale::scale_ticks_with_raw, Tick,
+};
+use stockburn::eval::compare;
+use stockburn::train::read_preprocessing;
+use stockburn::util::parse_device;
+use tracing::{info, warn};
+use tracing_subscriber::EnvFilter;
+
+const SEQ_LEN: usize = 180;
+const BATCH_SIZE: usize = 256;
+
+pub fn main() -> anyhow::Result<()> {
+    let matches = App::new("Stockburn Compare")
+        .version("1.0")
+        .author("Jad Elkhaleq Ghalayini <jad.ghalayini@mail.utoronto.ca>")
+        .about("Evaluates several checkpoints on the same validation data, printing a table of their metrics")
+        .arg(
+            Arg::with_name("CHECKPOINTS")
+                .help("The checkpoints to compare, the first of which is the baseline")
+                .required(true)
+                .multiple(true),
+        )
+        .arg(
+            Arg::with_name("data")
+                .long("data")
+                .help("The validation data files, one per stock")
+                .takes_value(true)
+                .multiple(true)
+                .required_unless("data-dir"),
+        )
+        .arg(
+            Arg::with_name("data-dir")
+                .long("data-dir")
+                .help("Load every file matching the pattern in this directory as validation data")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("pattern")
+                .long("pattern")
+                .help("The glob pattern of files to load from the data directory")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("clocks")
+                .long("clocks")
+                .help("The periods of the clock inputs the checkpoints were trained with")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("device")
+                .short("d")
+                .long("device")
+                .help("The device to evaluate on")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("significance")
+                .long("significance")
+                .help("Test whether each checkpoint's squared errors differ from the baseline's"),
+        )
+        .get_matches();
+
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("warn"));
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr)
+        .init();
+
+    let device = parse_device(matches.value_of("device").unwrap_or("cuda"))?;
+    let clock_periods = match matches.value_of("clocks") {
+        Some(periods) => parse_durations(periods)
+            .ok_or_else(|| format_err!("Invalid clock periods {:?}", periods))?,
+        None => default_clock_periods(Duration::minutes(1)),
+    };
+    let (_date_inputs, clock_fn) = clocks::<f32>(&clock_periods);
+
+    let checkpoints: Vec<&str> = matches
+        .values_of("CHECKPOINTS")
+        .expect("Required")
+        .collect();
+
+    // Load and preprocess validation data as the baseline's training data was, keeping the raw ticks targets are
+    // computed from and skipping symbols without any ticks past the warm-up period. `compare` checks that every other
+    // checkpoint was preprocessed in the same way.
+    let preprocessing = read_preprocessing(checkpoints[0])?.unwrap_or_default();
+    let data = match matches.value_of("data-dir") {
+        Some(dir) => load_dir(dir, matches.value_of("pattern").unwrap_or("*.csv*"))?,
+        None => load_files(matches.values_of("data").expect("Required"))?,
+    };
+    let mut symbols = Vec::new();
+    let mut scaled = Vec::new();
+    let mut raw = Vec::new();
+    for (symbol, ticks) in data {
+        let pairs = scale_ticks_with_raw(&ticks, &preprocessing.scaler);
+        if pairs.is_empty() {
+            warn!(
+                "Could not read any ticks past the warm-up period for symbol {}",
+                symbol
+            );
+            continue;
+        }
+        let (mut ticks, raw_ticks): (Vec<Tick>, Vec<Tick>) = pairs.into_iter().unzip();
+        if let Some(quantiles) = &preprocessing.quantiles {
+            for tick in ticks.iter_mut() {
+                *tick = quantiles.transform_tick(tick);
+            }
+        }
+        symbols.push(symbol);
+        scaled.push(ticks);
+        raw.push(raw_ticks);
+    }
+    let dataset = Dataset::from_ticks(symbols, scaled).with_raw(raw);
+    info!(
+        stocks = dataset.stocks(),
+        rows = dataset.len(),
+        "Loaded validation data"
+    );
+
+    let comparison = compare(
+        &checkpoints,
+        &dataset,
+        clock_fn,
+        BATCH_SIZE,
+        SEQ_LEN,
+        device,
+        matches.is_present("significance"),
+    )?;
+    println!("{}", comparison);
+    if let Some(best) = comparison.best() {
+        println!("best: {}", best.name);
+    }
+    Ok(())
+}
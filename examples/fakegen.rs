@@ -1,6 +1,7 @@
 /*!
 Generate and sample some fake tick data
 */
+use chrono::Duration;
 use clap::{App, Arg};
 use rustyline::error::ReadlineError;
 use rustyline::Editor;
@@ -20,8 +21,24 @@ fn main() {
                 .takes_value(true),
         )
         .arg(Arg::with_name("no-header").help("Do not output a CSV header"))
+        .arg(
+            Arg::with_name("interval")
+                .short("i")
+                .long("interval")
+                .help("The length of each bar in seconds, from 1 to 3600. Defaults to 60")
+                .takes_value(true),
+        )
         .get_matches();
-    let mut tick_gen = cubic_fake_ticks();
+    let interval = if let Some(interval) = matches.value_of("interval") {
+        i64::from_str_radix(&interval, 10).expect("Invalid bar interval!")
+    } else {
+        60
+    };
+    assert!(
+        (1..=3600).contains(&interval),
+        "Bar interval must be between 1 and 3600 seconds!"
+    );
+    let mut tick_gen = fake_ticks(Duration::seconds(interval));
     let mut rl = Editor::<()>::new();
     let n = if let Some(n) = matches.value_of("no-ticks") {
         usize::from_str_radix(&n, 10).expect("Invalid number of ticks!")
@@ -32,7 +32,7 @@ fn main() {
     } else {
         IoSources::Stdin(stdin())
     };
-    let mut ticks = csv::Reader::from_reader(reader)
+    let mut ticks = csv::Reader::from_reader(files::decompress(reader))
         .into_deserialize()
         .map(|tick| tick.expect("Error reading tick"))
         .peekable();
@@ -41,16 +41,7 @@ fn main() {
     } else {
         return;
     };
-    let mut scaler = TickExpScaler {
-        t: first_tick.t,
-        o: ExpScaler::start(first_tick.o, 0.999, 0.999),
-        h: ExpScaler::start(first_tick.h, 0.999, 0.999),
-        l: ExpScaler::start(first_tick.l, 0.999, 0.999),
-        c: ExpScaler::start(first_tick.c, 0.999, 0.999),
-        v: ExpScaler::start(first_tick.v, 0.999, 0.999),
-        vw: ExpScaler::start(first_tick.vw, 0.999, 0.999),
-        n: ExpScaler::start(first_tick.n, 0.999, 0.999),
-    };
+    let mut scaler = TickExpScaler::with_default_start(first_tick);
     println!("t,o,h,l,c,v,vw,n");
     while let Some(tick) = ticks.next() {
         let scaled = scaler.tick(tick);
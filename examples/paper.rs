@@ -0,0 +1,323 @@
+/*!
+Paper trade a checkpoint in real time against live Alpaca minute bars, logging hypothetical fills and PnL
+*/
+use anyhow::format_err;
+use chrono::{DateTime, Duration, NaiveDateTime, Utc};
+use clap::{App, Arg};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::BufWriter;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use stockburn::backtest::{
+    paper::{PaperStep, PaperTrader},
+    BacktestConfig,
+};
+use stockburn::data::alpaca::{AlpacaClient, Timeframe};
+use stockburn::data::{
+    clocks, default_clock_periods, parse_durations, Symbol, SymbolRegistry, TargetKind, Tick,
+};
+#[cfg(feature = "metrics")]
+use stockburn::metrics::Metrics;
+use stockburn::predict::Predictor;
+use stockburn::serve::{DriftMonitor, StatusTracker, TrainingDistribution};
+use stockburn::trading::{Strategy, ThresholdStrategy, VolatilityScaledStrategy};
+use stockburn::train::Shutdown;
+use stockburn::util::parse_device;
+use tracing::{info, warn};
+use tracing_subscriber::EnvFilter;
+
+const READ_TIMEOUT_SECS: u64 = 5;
+const VOLATILITY_DECAY: f64 = 0.99;
+const ERROR_WINDOW: usize = 390;
+const DRIFT_WINDOW: usize = 390;
+const DRIFT_ALPHA: f64 = 0.01;
+
+/// The diagnostics of the paper trader, served while it runs
+struct Monitoring {
+    /// The diagnostics of the predictor, served at `/status`
+    tracker: Arc<Mutex<StatusTracker>>,
+    /// The drift of the live inputs from the training distribution, if it is known
+    drift: Option<DriftMonitor>,
+    /// The metrics served at `/metrics`
+    #[cfg(feature = "metrics")]
+    metrics: Metrics,
+}
+
+impl Monitoring {
+    /// Record a traded bar, which took a given time to predict and trade on
+    fn observe<DF, S>(
+        &mut self,
+        trader: &PaperTrader<DF, S>,
+        step: &PaperStep,
+        latency: std::time::Duration,
+    ) where
+        DF: FnMut(DateTime<Utc>, &mut Vec<f32>),
+        S: Strategy,
+    {
+        self.tracker
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .observe(&trader.predictor, step.t, &step.ticks);
+        if let Some(drift) = &mut self.drift {
+            drift.observe(&trader.predictor);
+        }
+        #[cfg(feature = "metrics")]
+        {
+            self.metrics.observe_serving_latency(latency);
+            if let Some(drift) = &self.drift {
+                self.metrics.record_drift(drift);
+            }
+        }
+        #[cfg(not(feature = "metrics"))]
+        let _ = latency;
+    }
+}
+
+pub fn main() -> anyhow::Result<()> {
+    let matches = App::new("Stockburn Paper")
+        .version("1.0")
+        .author("Jad Elkhaleq Ghalayini <jad.ghalayini@mail.utoronto.ca>")
+        .about("Paper trades a checkpoint against live Alpaca minute bars, logging hypothetical fills and PnL")
+        .arg(
+            Arg::with_name("CHECKPOINT")
+                .help("The checkpoint to trade")
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("symbols")
+                .long("symbols")
+                .help("The symbols of the checkpoint's stocks, in order, if not saved with the checkpoint")
+                .takes_value(true)
+                .multiple(true),
+        )
+        .arg(
+            Arg::with_name("clocks")
+                .long("clocks")
+                .help("The periods of the clock inputs the checkpoint was trained with")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("device")
+                .short("d")
+                .long("device")
+                .help("The device to run the model on")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("warmup-minutes")
+                .long("warmup-minutes")
+                .help("The number of minutes of historical bars to warm up the model's state on")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("capital")
+                .long("capital")
+                .help("The starting capital of the simulated portfolio")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("strategy")
+                .long("strategy")
+                .help("The strategy converting predictions into signals")
+                .possible_values(&["threshold", "volatility"])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("threshold")
+                .long("threshold")
+                .help("The minimum absolute signal to trade on")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("monitor-addr")
+                .long("monitor-addr")
+                .help("Serve the predictor's diagnostics at /status, and Prometheus metrics at /metrics if built with the metrics feature, on this address")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("reference")
+                .long("reference")
+                .help("A JSON training distribution of scaled ticks, written by stockburn_a, to monitor live inputs for drift against")
+                .requires("monitor-addr")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("trades")
+                .long("trades")
+                .help("Write the hypothetical trades to this CSV file on exit")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("equity")
+                .long("equity")
+                .help("Write the equity curve to this CSV file on exit")
+                .takes_value(true),
+        )
+        .get_matches();
+
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr)
+        .init();
+
+    let device = parse_device(matches.value_of("device").unwrap_or("cpu"))?;
+    let clock_periods = match matches.value_of("clocks") {
+        Some(periods) => parse_durations(periods)
+            .ok_or_else(|| format_err!("Invalid clock periods {:?}", periods))?,
+        None => default_clock_periods(Duration::minutes(1)),
+    };
+    let (_date_inputs, clock_fn) = clocks::<f32>(&clock_periods);
+    let warmup = Duration::minutes(
+        matches
+            .value_of("warmup-minutes")
+            .unwrap_or("390")
+            .parse()?,
+    );
+    let mut config = BacktestConfig::default();
+    if let Some(capital) = matches.value_of("capital") {
+        config.capital = capital.parse()?;
+    }
+    let threshold = matches.value_of("threshold").unwrap_or("0").parse()?;
+    let strategy: Box<dyn Strategy> = match matches.value_of("strategy") {
+        Some("volatility") => Box::new(VolatilityScaledStrategy::new(VOLATILITY_DECAY, threshold)),
+        _ => Box::new(ThresholdStrategy {
+            threshold,
+            ..ThresholdStrategy::default()
+        }),
+    };
+
+    let checkpoint = matches.value_of("CHECKPOINT").expect("Required");
+    let (_vs, predictor) = Predictor::from_checkpoint(checkpoint, device, clock_fn)?;
+    let target_kind = predictor.lstm.desc.target_kind;
+    if matches.value_of("strategy") == Some("volatility") && target_kind != TargetKind::Return {
+        return Err(format_err!(
+            "The volatility strategy needs a checkpoint predicting returns, not {:?}",
+            target_kind
+        ));
+    }
+    let predictor = match matches.values_of("symbols") {
+        Some(symbols) => {
+            predictor.with_symbols(SymbolRegistry::new(symbols.map(Symbol::from).collect())?)?
+        }
+        None => predictor,
+    };
+    let registry = predictor.symbols.clone().ok_or_else(|| {
+        format_err!(
+            "Checkpoint {} has no symbols, pass them with --symbols",
+            checkpoint
+        )
+    })?;
+    let mut trader = PaperTrader::new(predictor, strategy, config)?;
+
+    // Serve the predictor's diagnostics, monitoring drift if the training distribution is known
+    let mut monitoring = match matches.value_of("monitor-addr") {
+        Some(addr) => {
+            let mut tracker = StatusTracker::new(checkpoint, ERROR_WINDOW);
+            let drift = match matches.value_of("reference") {
+                Some(path) => {
+                    let reference: TrainingDistribution =
+                        serde_json::from_reader(File::open(path)?)?;
+                    tracker = tracker.with_reference(reference.clone());
+                    Some(DriftMonitor::new(reference, DRIFT_WINDOW, DRIFT_ALPHA))
+                }
+                None => None,
+            };
+            let tracker = Arc::new(Mutex::new(tracker));
+            #[cfg(feature = "metrics")]
+            let metrics = Metrics::default();
+            #[cfg(feature = "metrics")]
+            stockburn::serve::spawn_monitoring_server(addr, tracker.clone(), metrics.clone())?;
+            #[cfg(not(feature = "metrics"))]
+            stockburn::serve::spawn_status_server(addr, tracker.clone())?;
+            info!("Serving diagnostics on {}", addr);
+            Some(Monitoring {
+                tracker,
+                drift,
+                #[cfg(feature = "metrics")]
+                metrics,
+            })
+        }
+        None => None,
+    };
+
+    // Warm up the model's state on recent history, one bar of every symbol at a time
+    let client = AlpacaClient::from_env()?;
+    let now = Utc::now();
+    let history = client.bars(registry.symbols(), Timeframe::Minute, now - warmup, now)?;
+    let mut bars: BTreeMap<NaiveDateTime, BTreeMap<Symbol, Tick>> = BTreeMap::new();
+    for (symbol, ticks) in history {
+        for tick in ticks {
+            bars.entry(tick.t).or_default().insert(symbol.clone(), tick);
+        }
+    }
+    info!(bars = bars.len(), "Warming up");
+    for (t, ticks) in bars.iter() {
+        trader.warm_up(*t, ticks);
+    }
+
+    let shutdown = Shutdown::install()?;
+    let mut stream = client.stream_minute_bars(registry.symbols())?;
+    stream
+        .tcp_stream()
+        .set_read_timeout(Some(std::time::Duration::from_secs(READ_TIMEOUT_SECS)))?;
+    info!(symbols = registry.len(), "Paper trading");
+    while !shutdown.requested() {
+        // Every symbol's bar arrives shortly after the minute closes, so a quiet stream means the bar is complete
+        let received = stream.next();
+        let started = Instant::now();
+        let step = match received {
+            Some(Ok((symbol, tick))) => trader.on_tick(symbol, tick),
+            Some(Err(err)) => match err.downcast_ref::<tungstenite::Error>() {
+                Some(tungstenite::Error::Io(io))
+                    if io.kind() == std::io::ErrorKind::WouldBlock
+                        || io.kind() == std::io::ErrorKind::TimedOut =>
+                {
+                    trader.flush()
+                }
+                _ => return Err(err),
+            },
+            None => {
+                warn!("Alpaca stream closed");
+                break;
+            }
+        };
+        if let Some(step) = step {
+            if let Some(monitoring) = &mut monitoring {
+                monitoring.observe(&trader, &step, started.elapsed());
+            }
+            for fill in step.fills.iter() {
+                info!(
+                    t = %fill.t,
+                    symbol = %fill.symbol,
+                    shares = fill.shares,
+                    price = fill.price,
+                    cost = fill.cost,
+                    "Fill"
+                );
+            }
+            info!(t = %step.t, equity = step.equity, pnl = step.pnl, "Bar");
+        }
+    }
+    if let Some(step) = trader.flush() {
+        info!(t = %step.t, equity = step.equity, pnl = step.pnl, "Bar");
+    }
+    info!(
+        equity = trader.backtest.equity(),
+        fills = trader.fills.len(),
+        "Stopped paper trading"
+    );
+
+    if let Some(path) = matches.value_of("trades") {
+        trader
+            .backtest
+            .write_trades(BufWriter::new(File::create(path)?))?;
+    }
+    if let Some(path) = matches.value_of("equity") {
+        trader
+            .backtest
+            .write_equity_curve(BufWriter::new(File::create(path)?))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,226 @@
+/*!
+Replay historical ticks through the live paper trading path, bar by bar, to reproduce a live run deterministically
+*/
+use anyhow::format_err;
+use chrono::Duration;
+use clap::{App, Arg};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::BufWriter;
+use stockburn::backtest::{
+    paper::PaperTrader,
+    replay::{write_signals, Replay},
+    BacktestConfig,
+};
+use stockburn::data::{
+    clocks, default_clock_periods, load_dir, load_files, parse_durations, Symbol, SymbolRegistry,
+    TargetKind, Tick,
+};
+use stockburn::predict::Predictor;
+use stockburn::trading::{Strategy, ThresholdStrategy, VolatilityScaledStrategy};
+use stockburn::util::parse_device;
+use tracing::info;
+use tracing_subscriber::EnvFilter;
+
+const VOLATILITY_DECAY: f64 = 0.99;
+
+pub fn main() -> anyhow::Result<()> {
+    let matches = App::new("Stockburn Replay")
+        .version("1.0")
+        .author("Jad Elkhaleq Ghalayini <jad.ghalayini@mail.utoronto.ca>")
+        .about("Replays historical ticks through the live paper trading path, logging hypothetical fills and PnL")
+        .arg(
+            Arg::with_name("CHECKPOINT")
+                .help("The checkpoint to trade")
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("STOCKS")
+                .help("The CSV files of historical ticks to replay, one per symbol")
+                .required_unless("data-dir")
+                .multiple(true),
+        )
+        .arg(
+            Arg::with_name("data-dir")
+                .long("data-dir")
+                .help("Replay every file matching --pattern in this directory")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("pattern")
+                .long("pattern")
+                .help("The glob pattern of files to load from --data-dir")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("symbols")
+                .long("symbols")
+                .help("The symbols of the checkpoint's stocks, in order, if not saved with the checkpoint")
+                .takes_value(true)
+                .multiple(true),
+        )
+        .arg(
+            Arg::with_name("clocks")
+                .long("clocks")
+                .help("The periods of the clock inputs the checkpoint was trained with")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("device")
+                .short("d")
+                .long("device")
+                .help("The device to run the model on")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("warmup-minutes")
+                .long("warmup-minutes")
+                .help("The number of minutes from the first tick to warm up the model's state on without trading")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("capital")
+                .long("capital")
+                .help("The starting capital of the simulated portfolio")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("strategy")
+                .long("strategy")
+                .help("The strategy converting predictions into signals")
+                .possible_values(&["threshold", "volatility"])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("threshold")
+                .long("threshold")
+                .help("The minimum absolute signal to trade on")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("trades")
+                .long("trades")
+                .help("Write the hypothetical trades to this CSV file on exit")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("equity")
+                .long("equity")
+                .help("Write the equity curve to this CSV file on exit")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("signals")
+                .long("signals")
+                .help("Write the signals of every bar to this CSV file")
+                .takes_value(true),
+        )
+        .get_matches();
+
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr)
+        .init();
+
+    let device = parse_device(matches.value_of("device").unwrap_or("cpu"))?;
+    let clock_periods = match matches.value_of("clocks") {
+        Some(periods) => parse_durations(periods)
+            .ok_or_else(|| format_err!("Invalid clock periods {:?}", periods))?,
+        None => default_clock_periods(Duration::minutes(1)),
+    };
+    let (_date_inputs, clock_fn) = clocks::<f32>(&clock_periods);
+    let warmup = Duration::minutes(
+        matches
+            .value_of("warmup-minutes")
+            .unwrap_or("390")
+            .parse()?,
+    );
+    let mut config = BacktestConfig::default();
+    if let Some(capital) = matches.value_of("capital") {
+        config.capital = capital.parse()?;
+    }
+    let threshold = matches.value_of("threshold").unwrap_or("0").parse()?;
+    let strategy: Box<dyn Strategy> = match matches.value_of("strategy") {
+        Some("volatility") => Box::new(VolatilityScaledStrategy::new(VOLATILITY_DECAY, threshold)),
+        _ => Box::new(ThresholdStrategy {
+            threshold,
+            ..ThresholdStrategy::default()
+        }),
+    };
+
+    let checkpoint = matches.value_of("CHECKPOINT").expect("Required");
+    let (_vs, predictor) = Predictor::from_checkpoint(checkpoint, device, clock_fn)?;
+    let target_kind = predictor.lstm.desc.target_kind;
+    if matches.value_of("strategy") == Some("volatility") && target_kind != TargetKind::Return {
+        return Err(format_err!(
+            "The volatility strategy needs a checkpoint predicting returns, not {:?}",
+            target_kind
+        ));
+    }
+    let predictor = match matches.values_of("symbols") {
+        Some(symbols) => {
+            predictor.with_symbols(SymbolRegistry::new(symbols.map(Symbol::from).collect())?)?
+        }
+        None => predictor,
+    };
+    let registry = predictor.symbols.clone().ok_or_else(|| {
+        format_err!(
+            "Checkpoint {} has no symbols, pass them with --symbols",
+            checkpoint
+        )
+    })?;
+    let mut trader = PaperTrader::new(predictor, strategy, config)?;
+
+    let data = match matches.value_of("data-dir") {
+        Some(dir) => load_dir(dir, matches.value_of("pattern").unwrap_or("*.csv*"))?,
+        None => load_files(matches.values_of("STOCKS").expect("Required"))?,
+    };
+    let data: BTreeMap<Symbol, Vec<Tick>> = data
+        .into_iter()
+        .filter(|(symbol, _)| registry.index(symbol).is_some())
+        .collect();
+
+    let start = data
+        .values()
+        .flat_map(|ticks| ticks.iter().map(|tick| tick.t))
+        .min()
+        .ok_or_else(|| format_err!("No ticks of the checkpoint's symbols to replay"))?;
+    let mut replay = Replay::new(&data);
+    let bars = replay.warm_up(&mut trader, start + warmup);
+    info!(bars, ticks = replay.remaining(), "Replaying");
+    let steps = replay.run(&mut trader);
+    for step in steps.iter() {
+        for fill in step.fills.iter() {
+            info!(
+                t = %fill.t,
+                symbol = %fill.symbol,
+                shares = fill.shares,
+                price = fill.price,
+                cost = fill.cost,
+                "Fill"
+            );
+        }
+        info!(t = %step.t, equity = step.equity, pnl = step.pnl, "Bar");
+    }
+    info!(
+        equity = trader.backtest.equity(),
+        fills = trader.fills.len(),
+        "Finished replay"
+    );
+
+    if let Some(path) = matches.value_of("trades") {
+        trader
+            .backtest
+            .write_trades(BufWriter::new(File::create(path)?))?;
+    }
+    if let Some(path) = matches.value_of("equity") {
+        trader
+            .backtest
+            .write_equity_curve(BufWriter::new(File::create(path)?))?;
+    }
+    if let Some(path) = matches.value_of("signals") {
+        write_signals(BufWriter::new(File::create(path)?), &steps)?;
+    }
+    Ok(())
+}
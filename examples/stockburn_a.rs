@@ -3,32 +3,73 @@ A very simple stock LSTM model
 */
 
 use anyhow::format_err;
-use chrono::Duration;
+use chrono::{DateTime, Duration, Utc};
 use clap::{App, Arg};
 use indicatif::{ProgressBar, ProgressStyle};
+use std::collections::BTreeMap;
 use std::fs::File;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use stockburn::data::{
     clocks,
-    polygon::{read_ticks, POLYGON_DATETIME},
-    scale::TickExpScaler,
-    Tick,
+    dataset::Dataset,
+    default_clock_periods, load_dir, load_files,
+    metadata::{self, read_metadata, SymbolMetadata},
+    parse_durations,
+    scale::{
+        quantile::{DEFAULT_QUANTILES, DEFAULT_SUBSAMPLE},
+        scale_ticks_with_raw, QuantileTransformer, TickScalerConfig,
+    },
+    Symbol, SymbolRegistry, Target, TargetKind, Tick,
 };
-use stockburn::lstm::StockLSTMDesc;
+use stockburn::eval::ConfusionMatrix;
+use stockburn::lstm::{
+    batch::{BatchRing, BatchStats, ScaledTicks},
+    init::Initialization,
+    loss::{LossFn, SampleWeighted, WeightedMse},
+    sector::SectorDesc,
+    StockLSTM, StockLSTMDesc,
+};
+#[cfg(feature = "metrics")]
+use stockburn::metrics::{metrics_route, Metrics, TrainingMetrics};
+use stockburn::serve::TrainingDistribution;
+use stockburn::train::{
+    fine_tune, gpu_memory, read_symbols, save_checkpoint, validate, verify_data, write_symbols,
+    Augmentation, BatchEnd, Budget, Callback, Callbacks, Curriculum, EarlyStopping, EpochMetrics,
+    LayerSelection, MetricsCsv, Mixup, Phase, Preprocessing, ReduceOnPlateau, SampleWeighting,
+    Shutdown, ValidationSchedule,
+};
+use stockburn::util::parse_device;
 use tch::nn::{OptimizerConfig, RNN};
-use tch::{nn, Device, Kind, Reduction};
+use tch::{nn, Device, Reduction, Tensor};
+use tracing::{debug, info, info_span, warn};
+use tracing_subscriber::EnvFilter;
 
 const LEARNING_RATE: f64 = 0.01;
 const AVERAGE_DECAY_RATE: f64 = 0.999;
 const RANGE_DECAY_RATE: f64 = 0.999;
 const HIDDEN_SIZE: usize = 256;
+const SECTOR_HIDDEN_SIZE: usize = 32;
 const LSTM_LAYERS: usize = 2;
 const SEQ_LEN: usize = 180;
 const BATCH_SIZE: usize = 256;
+const START_SEQ_LEN: usize = 32;
+const START_BATCH_SIZE: usize = 64;
 const EPOCHS: u64 = 100;
 const TRAIN_TEST_RATIO: f64 = 0.95;
+const CLOSE_LOSS_WEIGHT: f32 = 1.0;
+const VOLUME_LOSS_WEIGHT: f32 = 0.1;
+const VOLATILITY_LOSS_WEIGHT: f32 = 0.1;
+const VALIDATION_RATIO: f64 = 0.02;
+const PLATEAU_FACTOR: f64 = 0.5;
+const PLATEAU_PATIENCE: usize = 5;
+const MIN_LEARNING_RATE: f64 = 1e-5;
+const MAX_ZERO_FILL_RATE: f64 = 0.5;
+const REFERENCE_SAMPLES: usize = 10_000;
 
-pub fn train_test_split(mut ticks: Vec<Vec<Tick>>, ratio: f64) -> (Vec<Vec<Tick>>, Vec<Vec<Tick>>) {
+pub fn train_test_split<T: Clone>(
+    mut ticks: Vec<Vec<T>>,
+    ratio: f64,
+) -> (Vec<Vec<T>>, Vec<Vec<T>>) {
     let samples: usize = ticks.iter().map(|ticks| ticks.len()).max().unwrap_or(0);
     let train_samples: usize = (samples as f64 * ratio) as usize;
     let mut test_samples = Vec::with_capacity(ticks.len());
@@ -45,82 +86,407 @@ pub fn train_test_split(mut ticks: Vec<Vec<Tick>>, ratio: f64) -> (Vec<Vec<Tick>
     (ticks, test_samples)
 }
 
-pub fn run_network(verbosity: usize, input_files: &[String], device: Device) -> anyhow::Result<()> {
-    // Length check for input files
-    let stocks = input_files.len();
-    if stocks == 0 {
-        return Err(format_err!(
-            "StockLSTM needs at least one input file, recieved zero!"
-        ));
-    }
-
-    // Load input files
-    let input_files_style =
-        ProgressStyle::default_bar().template("Loading files: {wide_bar} {pos}/{len}: {msg:15}");
-    let input_files_progress = ProgressBar::new(stocks as u64);
-    let mut ticks: Vec<Vec<Tick>> = Vec::new();
-
-    input_files_progress.set_style(input_files_style);
-
-    for filename in input_files {
-        input_files_progress.set_message(filename);
-        let file = File::open(Path::new(filename))?;
-        let mut file_ticks = read_ticks(file, Some(POLYGON_DATETIME));
-        if file_ticks.is_empty() {
-            if verbosity >= 1 {
-                eprintln!("WARNING: could not read any ticks from file {}", filename);
-            }
-        } else {
-            let first = file_ticks[0];
-            let mut scaler = TickExpScaler::with_start(first, AVERAGE_DECAY_RATE, RANGE_DECAY_RATE);
-            for tick in file_ticks.iter_mut() {
-                *tick = scaler.tick(*tick);
-            }
-            ticks.push(file_ticks);
+/// Scale each symbol's ticks, pairing them with the raw ticks targets are computed from, dropping their warm-up
+/// periods and skipping symbols without any ticks
+fn scale_data(
+    data: BTreeMap<Symbol, Vec<Tick>>,
+    config: &TickScalerConfig,
+) -> BTreeMap<Symbol, Vec<(Tick, Tick)>> {
+    let mut scaled = BTreeMap::new();
+    for (symbol, ticks) in data {
+        let ticks = scale_ticks_with_raw(&ticks, config);
+        if ticks.is_empty() {
+            warn!(
+                "Could not read any ticks past the warm-up period for symbol {}",
+                symbol
+            );
+            continue;
         }
-        input_files_progress.inc(1);
+        debug!("Loaded {} ticks for symbol {}", ticks.len(), symbol);
+        scaled.insert(symbol, ticks);
     }
-    input_files_progress.finish_and_clear();
+    scaled
+}
 
-    // Clock function setup
-    let clock_periods = &[
-        Duration::minutes(5),
-        Duration::minutes(10),
-        Duration::minutes(30),
-        Duration::hours(1),
-        Duration::days(1),
-        Duration::weeks(1),
-        Duration::weeks(4),
-        Duration::days(365),
-    ];
-    let (date_inputs, clock_fn) = clocks::<f32>(clock_periods);
+/// Build a dataset from each symbol's pairs of scaled and raw ticks
+fn scaled_dataset(symbols: Vec<Symbol>, pairs: Vec<Vec<(Tick, Tick)>>) -> Dataset {
+    let (ticks, raw): (Vec<Vec<Tick>>, Vec<Vec<Tick>>) = pairs
+        .into_iter()
+        .map(|pairs| pairs.into_iter().unzip())
+        .unzip();
+    Dataset::from_ticks(symbols, ticks).with_raw(raw)
+}
 
-    // Network setup
-    if verbosity >= 2 {
-        eprintln!("Setting up network");
-    }
-    let vs = nn::VarStore::new(device);
-    let lstm_desc = StockLSTMDesc {
-        additional_inputs: 0,
+/// Describe the network trained from scratch, optionally grouping its stocks by sector
+fn network_desc(
+    stocks: usize,
+    date_inputs: usize,
+    sectors: Option<SectorDesc>,
+    dropout: f64,
+) -> StockLSTMDesc {
+    StockLSTMDesc {
         stocks,
         date_inputs,
+        dropout,
         hidden: HIDDEN_SIZE,
         layers: LSTM_LAYERS,
+        heads: vec![Target::Close, Target::Volume, Target::Volatility],
+        init: Initialization::recommended(),
+        sectors,
+        ..Default::default()
+    }
+}
+
+/// Get the predicted change of each stock's close from a batch of inputs and outputs, of shape `[batch, sequence,
+/// stocks]`: the predicted return for changes, or the predicted minus the current close for levels, which are in the
+/// units of the inputs. Returns `None` if the network does not predict closes, or only ranks them across stocks.
+fn predicted_close_changes(lstm: &StockLSTM, input: &Tensor, output: &Tensor) -> Option<Tensor> {
+    let predicted = lstm.head_output(output, Target::Close)?;
+    match lstm.desc.target_kind {
+        TargetKind::Return => Some(predicted),
+        TargetKind::Level => {
+            let layout = lstm.input_layout();
+            let columns = (0..lstm.stocks)
+                .map(|stock| Some(layout.column(&format!("stock{}.c", stock))? as i64))
+                .collect::<Option<Vec<_>>>()?;
+            let columns = Tensor::of_slice(&columns).to_device(input.device());
+            Some(predicted - input.index_select(2, &columns))
+        }
+        TargetKind::Rank | TargetKind::ZScore => None,
+    }
+}
+
+/// Run the data pipeline for one epoch without building a network, printing batch counts, zero fill rates and
+/// feature statistics, and warning of likely misconfigurations
+fn verify(
+    data: BTreeMap<Symbol, Vec<Tick>>,
+    clock_periods: &[Duration],
+    scaler: &TickScalerConfig,
+) -> anyhow::Result<()> {
+    let (symbols, pairs) = scale_data(data, scaler).into_iter().unzip();
+    let dataset = scaled_dataset(symbols, pairs);
+    if dataset.stocks() == 0 {
+        return Err(format_err!("No symbols with any ticks to verify"));
+    }
+    let (date_inputs, clock_fn) = clocks::<f32>(clock_periods);
+    let desc = network_desc(dataset.stocks(), date_inputs, None, 0.0);
+    let report = verify_data(&desc, &dataset, clock_fn, BATCH_SIZE, SEQ_LEN);
+    println!("{}", report);
+    for problem in report.problems(MAX_ZERO_FILL_RATE) {
+        warn!("{}", problem);
+    }
+    Ok(())
+}
+
+/// Save a checkpoint when stopping early, due to a shutdown request or running out of time, given a function saving a
+/// checkpoint under a name
+fn early_checkpoint<S>(save: S, epoch: u64, shutdown: &Shutdown) -> anyhow::Result<()>
+where
+    S: FnOnce(&str) -> anyhow::Result<PathBuf>,
+{
+    let (reason, name) = if shutdown.requested() {
+        ("Shutdown requested", format!("emergency-epoch{}", epoch))
+    } else {
+        ("Out of time", format!("budget-epoch{}", epoch))
     };
-    let lstm = lstm_desc.build(&vs);
+    let path = save(&name)?;
+    warn!("{}: saved checkpoint to {:?}", reason, path);
+    Ok(())
+}
+
+/// A held-out validation slice, together with the schedules driven by validation passes over it
+struct Validation {
+    /// The validation slice
+    dataset: Dataset,
+    /// How often to run validation passes
+    schedule: ValidationSchedule,
+    /// Reduces the learning rate when the validation loss plateaus
+    plateau: ReduceOnPlateau,
+    /// Stops training when the validation loss stops improving, if enabled
+    stopping: Option<EarlyStopping>,
+}
+
+impl Validation {
+    /// Run a validation pass, reducing the learning rate on a plateau. Returns the pass's metrics, and whether
+    /// training should stop early.
+    fn run<DF>(
+        &mut self,
+        lstm: &StockLSTM,
+        clock_fn: DF,
+        device: Device,
+        epoch: u64,
+        lr: &mut f64,
+    ) -> (EpochMetrics, bool)
+    where
+        DF: FnMut(DateTime<Utc>, &mut Vec<f32>),
+    {
+        let batches = lstm.batches(&self.dataset, &[], clock_fn, BATCH_SIZE, SEQ_LEN);
+        let metrics = validate(lstm, batches, device, epoch);
+        let loss = metrics.average_loss();
+        debug!(validation_loss = loss, "Finished validation pass");
+        self.plateau.lr = *lr;
+        if let Some(reduced) = self.plateau.observe(loss) {
+            info!(
+                lr = reduced,
+                "Validation loss plateaued, reducing learning rate"
+            );
+            *lr = reduced;
+        }
+        let stop = match &mut self.stopping {
+            Some(stopping) => {
+                stopping.observe(loss);
+                stopping.should_stop()
+            }
+            None => false,
+        };
+        (metrics, stop)
+    }
+}
 
-    if verbosity >= 2 {
-        eprintln!("Initializing optimizer");
+/// Options controlling a training run
+pub struct TrainOptions<'a> {
+    /// Whether to print a confusion matrix of predicted close directions every epoch
+    pub directional: bool,
+    /// Whether to allocate batches in pinned memory
+    pub pin_memory: bool,
+    /// The directory to save checkpoints to when stopping early
+    pub checkpoint_dir: &'a Path,
+    /// The resources the run may use
+    pub budget: Budget,
+    /// The periods of the clock inputs
+    pub clock_periods: Vec<Duration>,
+    /// The parameters of the input scalers
+    pub scaler: TickScalerConfig,
+    /// Whether to map scaled ticks through a quantile transform fitted on the training data
+    pub quantile_transform: bool,
+    /// The schedule of training batch shapes
+    pub curriculum: Curriculum,
+    /// How often to validate on a held-out slice of the training data, if at all
+    pub validation: Option<ValidationSchedule>,
+    /// The number of validation passes without improvement after which to stop, if any
+    pub patience: Option<usize>,
+    /// Hooks invoked at epoch and batch boundaries
+    pub callbacks: Callbacks,
+    /// The segment length to recompute activations over during the backward pass, if checkpointing gradients
+    pub checkpoint_segment: Option<usize>,
+    /// The random perturbations applied to training inputs
+    pub augmentation: Augmentation,
+    /// The mixup blending training windows with each other
+    pub mixup: Mixup,
+    /// The weighting of training windows in the loss
+    pub sample_weighting: SampleWeighting,
+    /// The pretrained checkpoint to fine-tune, and the recurrent layers to freeze, if fine-tuning
+    pub fine_tuning: Option<(&'a Path, LayerSelection)>,
+    /// The metadata of each symbol to group stocks by sector with when training from scratch, if any
+    pub sector_metadata: Option<BTreeMap<Symbol, SymbolMetadata>>,
+    /// The dropout probability of a network trained from scratch, which is stored in its checkpoint
+    pub dropout: f64,
+    /// The metrics to publish training step timings to, if any
+    #[cfg(feature = "metrics")]
+    pub metrics: Option<Metrics>,
+}
+
+pub fn run_network(
+    data: BTreeMap<Symbol, Vec<Tick>>,
+    device: Device,
+    options: TrainOptions,
+) -> anyhow::Result<()> {
+    let TrainOptions {
+        directional,
+        pin_memory,
+        checkpoint_dir,
+        budget,
+        clock_periods,
+        scaler,
+        quantile_transform,
+        curriculum,
+        validation,
+        patience,
+        mut callbacks,
+        checkpoint_segment,
+        augmentation,
+        mixup,
+        sample_weighting,
+        fine_tuning,
+        sector_metadata,
+        dropout,
+        #[cfg(feature = "metrics")]
+        metrics,
+    } = options;
+
+    // Scale input data, skipping symbols without any ticks
+    let (symbols, ticks): (Vec<Symbol>, Vec<Vec<(Tick, Tick)>>) =
+        scale_data(data, &scaler).into_iter().unzip();
+    let registry = SymbolRegistry::new(symbols)?;
+
+    // Length check for input data
+    let stocks = ticks.len();
+    if stocks == 0 {
+        return Err(format_err!(
+            "StockLSTM needs at least one input stock, recieved zero!"
+        ));
+    }
+
+    // Clock function setup
+    let (date_inputs, clock_fn) = clocks::<f32>(&clock_periods);
+
+    // Network setup, starting from a pretrained checkpoint if fine-tuning
+    debug!("Setting up network");
+    let (vs, mut lstm) = match fine_tuning {
+        Some((checkpoint, freeze)) => {
+            let (vs, lstm) = fine_tune(checkpoint, device, freeze)?;
+            if lstm.stocks != stocks || lstm.date_inputs != date_inputs {
+                return Err(format_err!(
+                    "Checkpoint {:?} has {} stocks and {} date inputs, but the data has {} and {}",
+                    checkpoint,
+                    lstm.stocks,
+                    lstm.date_inputs,
+                    stocks,
+                    date_inputs
+                ));
+            }
+            if let Some(pretrained) = read_symbols(checkpoint)? {
+                if pretrained != registry {
+                    return Err(format_err!(
+                        "Checkpoint {:?} was trained on symbols {:?}, but the data has {:?}",
+                        checkpoint,
+                        pretrained.symbols(),
+                        registry.symbols()
+                    ));
+                }
+            }
+            (vs, lstm)
+        }
+        None => {
+            let vs = nn::VarStore::new(device);
+            // Malformed metadata records are skipped when read, so a file without a single usable record would
+            // otherwise put every stock in the unknown sector
+            let sectors = match sector_metadata {
+                Some(sector_metadata) => {
+                    let sectors = metadata::sectors(registry.symbols(), &sector_metadata);
+                    if sectors.iter().all(Option::is_none) {
+                        return Err(format_err!(
+                            "None of the {} symbols has a sector in the metadata file, which may be malformed",
+                            stocks
+                        ));
+                    }
+                    Some(SectorDesc::new(&sectors, SECTOR_HIDDEN_SIZE))
+                }
+                None => None,
+            };
+            if let Some(sectors) = &sectors {
+                info!(
+                    "Grouping {} stocks into sectors {:?}",
+                    stocks, sectors.names
+                );
+            }
+            let lstm = network_desc(stocks, date_inputs, sectors, dropout).build(&vs);
+            (vs, lstm)
+        }
+    };
+    let lstm_desc = lstm.desc.clone();
+    let directional = if directional && lstm_desc.target_kind.is_cross_sectional() {
+        warn!(
+            "Network predicts {} targets, which have no direction: not printing confusion matrices",
+            lstm_desc.target_kind.name()
+        );
+        false
+    } else {
+        directional
+    };
+    for head in lstm.heads.iter_mut() {
+        head.weight = match head.target {
+            Target::Close => CLOSE_LOSS_WEIGHT,
+            Target::Volume => VOLUME_LOSS_WEIGHT,
+            Target::Volatility => VOLATILITY_LOSS_WEIGHT,
+            _ => 1.0,
+        }
     }
+
+    debug!("Initializing optimizer");
     let mut opt = nn::Adam::default()
         .build(&vs, LEARNING_RATE)
         .map_err(|err| format_err!("Error building optimizer: {:#?}", err))?;
 
-    if verbosity >= 1 {
-        eprintln!("Beginning training");
-    }
+    info!("Beginning training");
+
+    let (training_data, mut testing_data) = train_test_split(ticks, TRAIN_TEST_RATIO);
 
-    let (training_data, testing_data) = train_test_split(ticks, TRAIN_TEST_RATIO);
+    // Hold out the end of the training data for validation passes during training
+    let (mut training_data, mut validation_data) = match validation {
+        Some(_) => {
+            let (training_data, validation_data) =
+                train_test_split(training_data, 1.0 - VALIDATION_RATIO);
+            (training_data, Some(validation_data))
+        }
+        None => (training_data, None),
+    };
+
+    // Map scaled ticks through quantiles of the training ticks, pooled over stocks
+    let quantiles = if quantile_transform {
+        let quantiles = QuantileTransformer::fit_ticks(
+            training_data.iter().flatten().map(|(scaled, _raw)| scaled),
+            DEFAULT_QUANTILES,
+            DEFAULT_SUBSAMPLE,
+            &mut rand::thread_rng(),
+        )?;
+        let transform = |data: &mut Vec<Vec<(Tick, Tick)>>| {
+            for (scaled, _raw) in data.iter_mut().flatten() {
+                *scaled = quantiles.transform_tick(scaled);
+            }
+        };
+        transform(&mut training_data);
+        transform(&mut testing_data);
+        if let Some(validation_data) = &mut validation_data {
+            transform(validation_data);
+        }
+        Some(quantiles)
+    } else {
+        None
+    };
+    let mut validation = validation
+        .zip(validation_data)
+        .map(|(schedule, validation_data)| Validation {
+            dataset: scaled_dataset(registry.symbols().to_vec(), validation_data),
+            schedule,
+            plateau: ReduceOnPlateau::new(
+                LEARNING_RATE,
+                PLATEAU_FACTOR,
+                PLATEAU_PATIENCE,
+                MIN_LEARNING_RATE,
+            ),
+            stopping: patience.map(|patience| EarlyStopping::new(patience, 0.0)),
+        });
+
+    // Save checkpoints together with how their inputs were preprocessed and the symbols they were trained on
+    let preprocessing = Preprocessing { scaler, quantiles };
+    let save = |metrics: &[EpochMetrics], name: &str| -> anyhow::Result<PathBuf> {
+        let path = save_checkpoint(
+            &vs,
+            &lstm_desc,
+            &preprocessing,
+            metrics,
+            checkpoint_dir,
+            name,
+        )?;
+        write_symbols(&registry, &path)?;
+        Ok(path)
+    };
+
+    // Save the distribution of the scaled training ticks, for live inputs to be monitored for drift against
+    let scaled_training: Vec<Vec<Tick>> = training_data
+        .iter()
+        .map(|pairs| pairs.iter().map(|(scaled, _raw)| *scaled).collect())
+        .collect();
+    let reference = TrainingDistribution::pooled(
+        scaled_training.iter().map(|ticks| &ticks[..]),
+        REFERENCE_SAMPLES,
+    );
+    std::fs::create_dir_all(checkpoint_dir)?;
+    serde_json::to_writer(
+        File::create(checkpoint_dir.join("training_distribution.json"))?,
+        &reference,
+    )?;
 
     // Get tick counts
     let total_training_ticks: usize = training_data.iter().map(|ticks| ticks.len()).sum();
@@ -134,18 +500,49 @@ pub fn run_network(verbosity: usize, input_files: &[String], device: Device) ->
 
     let mut training_ticks: Vec<_> = training_data
         .iter()
-        .map(|ticks| ticks.iter().copied().peekable())
+        .map(|ticks| ScaledTicks::new(ticks.iter().copied()))
         .collect();
     let mut testing_ticks: Vec<_> = testing_data
         .iter()
-        .map(|ticks| ticks.iter().copied().peekable())
+        .map(|ticks| ScaledTicks::new(ticks.iter().copied()))
         .collect();
 
+    // Double-buffer batches, so that each batch is written while the previous one is transferred to the device
+    let pin_memory = pin_memory && device != Device::Cpu;
+    let ring = |batch_size: usize, seq_len: usize| {
+        BatchRing::new(vec![
+            lstm.batch_buffer(batch_size, seq_len, pin_memory),
+            lstm.batch_buffer(batch_size, seq_len, pin_memory),
+        ])
+    };
+    let mut testing_buffers = ring(BATCH_SIZE, SEQ_LEN);
+
+    // Finish the current batch and save a checkpoint on SIGINT or SIGTERM
+    let shutdown = Shutdown::install()?;
+    let mut metrics = Vec::new();
+    let timer = budget.start();
+    let mut rng = rand::thread_rng();
+    let mut lr = LEARNING_RATE;
+
     // Loop over the data
     for epoch in 0..EPOCHS {
         // === INITIALIZATION ===
 
         epochs_progress.println(format!("Epoch {}", epoch));
+        let epoch_span = info_span!("epoch", epoch);
+        let _epoch_guard = epoch_span.enter();
+        callbacks.on_epoch_start(epoch);
+
+        // Grow training batches as the curriculum progresses, reusing the testing buffers once fully grown
+        let (batch_size, seq_len) = curriculum.at(epoch as usize);
+        let mut curriculum_buffers;
+        let training_buffers = if (batch_size, seq_len) == (BATCH_SIZE, SEQ_LEN) {
+            &mut testing_buffers
+        } else {
+            debug!(batch_size, seq_len, "Training on curriculum batches");
+            curriculum_buffers = ring(batch_size, seq_len);
+            &mut curriculum_buffers
+        };
 
         // === TRAINING ===
 
@@ -154,56 +551,196 @@ pub fn run_network(verbosity: usize, input_files: &[String], device: Device) ->
         data_progress.set_style(data_progress_style.clone());
         data_progress.set_message("no loss");
 
-        let mut batch = 0;
-        let mut sum_loss = 0.0;
-        let mut max_loss = -f64::INFINITY;
-        let mut min_loss = f64::INFINITY;
-
-        // Pack training data as batches
-        while let Some((input_batch, output_batch)) = lstm.make_batches(
-            std::iter::repeat(&[][..]),
-            clock_fn,
-            &mut training_ticks,
-            BATCH_SIZE,
-            SEQ_LEN,
-        ) {
-            // Send everything to the GPU
-            let input_batch = input_batch.to_device(device);
-            let output_batch = output_batch.to_device(device);
+        let mut training = EpochMetrics::new(epoch, Phase::Training);
+        let mut training_stats = BatchStats::new(stocks);
+        let mut stop_early = false;
 
-            // Feedforward loss
-            let (loss, _state) = lstm.loss(
+        loop {
+            #[cfg(feature = "metrics")]
+            let step_start = std::time::Instant::now();
+
+            // Pack training data as batches, and send everything to the GPU
+            let buffer = training_buffers.next_mut();
+            if lstm
+                .make_batches_into(
+                    std::iter::repeat(&[][..]),
+                    clock_fn,
+                    &mut training_ticks,
+                    buffer,
+                    Some(&mut training_stats),
+                )
+                .is_none()
+            {
+                break;
+            }
+            let (input_batch, output_batch) = buffer.to_device(device);
+            let sample_weights = if sample_weighting.is_enabled() {
+                let rows_after = training_ticks.iter().map(|ticks| ticks.len()).max();
+                let weights = sample_weighting.weights(&buffer.closes(), rows_after.unwrap_or(0));
+                Some(weights.to_device(device))
+            } else {
+                None
+            };
+            let (input_batch, output_batch) =
+                augmentation.apply(&lstm, &input_batch, &output_batch);
+            let (input_batch, output_batch, sample_weights) = mixup.apply_weighted(
                 &input_batch,
                 &output_batch,
-                &lstm.zero_state(BATCH_SIZE as i64),
+                sample_weights.as_ref(),
+                &mut rng,
             );
-            //lstm_state = state;
 
-            // Optimize
-            opt.backward_step_clip(&loss, 0.5);
+            // Weight outputs by head, and windows by their sample weights if any
+            let head_weighted = WeightedMse {
+                weights: lstm.loss_weight_tensor().to_device(device),
+            };
+            let sample_weighted;
+            let loss_fn: &dyn LossFn = match sample_weights {
+                Some(weights) => {
+                    sample_weighted = SampleWeighted {
+                        loss_fn: &head_weighted,
+                        weights,
+                    };
+                    &sample_weighted
+                }
+                None => &head_weighted,
+            };
+
+            // Feedforward loss and optimize, recomputing activations segment by segment if checkpointing
+            #[cfg(feature = "metrics")]
+            let compute_start = std::time::Instant::now();
+            let state = lstm.zero_state(batch_size as i64);
+            let loss = match checkpoint_segment {
+                Some(segment_length) => {
+                    opt.zero_grad();
+                    let (loss, _state) = lstm.checkpointed_backward(
+                        loss_fn,
+                        &input_batch,
+                        &output_batch,
+                        None,
+                        &state,
+                        segment_length,
+                    );
+                    opt.clip_grad_value(0.5);
+                    opt.step();
+                    loss
+                }
+                None => {
+                    let (loss, _state) =
+                        lstm.loss_with(loss_fn, &input_batch, &output_batch, None, &state);
+                    opt.backward_step_clip(&loss, 0.5);
+                    f64::from(loss)
+                }
+            };
+            #[cfg(feature = "metrics")]
+            {
+                if let Some(metrics) = &metrics {
+                    metrics.observe_training_step(compute_start.elapsed(), step_start.elapsed());
+                }
+            }
 
             // Advance progress bar, set message
-            batch += 1;
-            let loss = f64::from(loss);
-            sum_loss += loss;
-            max_loss = max_loss.max(loss);
-            min_loss = min_loss.min(loss);
+            training.push(loss);
+            let end = BatchEnd {
+                epoch,
+                batch: training.batches,
+                loss,
+            };
+            callbacks.on_batch_end(&end, &mut lr);
             let ticks_left: usize = training_ticks.iter().map(|ticks| ticks.len()).sum();
             data_progress.set_position((total_training_ticks - ticks_left) as u64);
             data_progress.set_message(&format!("loss = {:.5}", loss));
+
+            // Validate on the held-out slice, if due
+            if let Some(validation) = &mut validation {
+                if validation.schedule.due(training.batches) {
+                    let (validated, stop) = validation.run(&lstm, clock_fn, device, epoch, &mut lr);
+                    metrics.push(validated);
+                    if stop {
+                        stop_early = true;
+                        break;
+                    }
+                }
+            }
+            opt.set_lr(lr);
+
+            // Finish the current batch before shutting down or running out of budget
+            if shutdown.requested()
+                || timer.out_of_time()
+                || timer.epoch_exhausted(training.batches)
+            {
+                break;
+            }
         }
 
         // Destroy the batch progress bar
         data_progress.finish_and_clear();
+        metrics.push(training);
+        if shutdown.requested() || timer.out_of_time() {
+            callbacks.on_train_end(&metrics);
+            return early_checkpoint(|name| save(&metrics, name), epoch, &shutdown);
+        }
+
+        // Validate at the end of every epoch, stopping once the validation loss stops improving
+        if let Some(validation) = &mut validation {
+            if !stop_early {
+                let (validated, stop) = validation.run(&lstm, clock_fn, device, epoch, &mut lr);
+                opt.set_lr(lr);
+                epochs_progress.println(format!(
+                    "average validation loss = {}",
+                    validated.average_loss()
+                ));
+                metrics.push(validated);
+                stop_early = stop;
+            }
+        }
+        if stop_early {
+            let name = format!("early-stop-epoch{}", epoch);
+            let path = save(&metrics, &name)?;
+            info!(
+                "Validation loss stopped improving: saved checkpoint to {:?}",
+                path
+            );
+            callbacks.on_train_end(&metrics);
+            return Ok(());
+        }
 
         // Print training losses
+        debug!(
+            batches = training.batches,
+            average_loss = training.average_loss(),
+            max_loss = training.max_loss,
+            min_loss = training.min_loss,
+            "Finished training"
+        );
         epochs_progress.println(format!(
             "average training loss = {}, max training loss = {}, min training loss = {}",
-            sum_loss / batch as f64,
-            max_loss,
-            min_loss
+            training.average_loss(),
+            training.max_loss,
+            training.min_loss
         ));
 
+        // Warn of stocks missing from too many training rows, which usually means misaligned clocks
+        debug!(
+            empty_row_rate = training_stats.empty_row_rate(),
+            mean_skew = training_stats.mean_skew(),
+            max_skew = training_stats.max_skew,
+            "Training batch alignment"
+        );
+        for (symbol, rate) in registry
+            .symbols()
+            .iter()
+            .zip(training_stats.zero_fill_rates())
+        {
+            if rate > MAX_ZERO_FILL_RATE {
+                warn!(
+                    "{} was zero filled in {:.1}% of training rows",
+                    symbol,
+                    100.0 * rate
+                );
+            }
+        }
+
         // === TESTING ===
 
         let mut lstm_state = lstm.zero_state(BATCH_SIZE as i64);
@@ -211,84 +748,106 @@ pub fn run_network(verbosity: usize, input_files: &[String], device: Device) ->
         data_progress.set_style(data_progress_style.clone());
         data_progress.set_message("no loss");
 
-        let mut batch = 0;
-        let mut sum_loss = 0.0;
-        let mut max_loss = -f64::INFINITY;
-        let mut min_loss = f64::INFINITY;
-        let mut total_true_positives = 0;
-        let mut total_true_negatives = 0;
-        let mut total_false_positives = 0;
-        let mut total_false_negatives = 0;
-
-        while let Some((input_batch, output_batch)) = lstm.make_batches(
-            std::iter::repeat(&[][..]),
-            clock_fn,
-            &mut testing_ticks,
-            BATCH_SIZE,
-            SEQ_LEN,
-        ) {
-            // Send everything to the GPU
-            let input_batch = input_batch.to_device(device);
-            let output_batch = output_batch.to_device(device);
+        let mut testing = EpochMetrics::new(epoch, Phase::Testing);
+        let mut confusion = ConfusionMatrix::new(0.0);
+        let mut sum_head_losses = vec![0.0; lstm.heads.len()];
+
+        loop {
+            // Pack testing data as batches, and send everything to the GPU
+            let buffer = testing_buffers.next_mut();
+            if lstm
+                .make_batches_into(
+                    std::iter::repeat(&[][..]),
+                    clock_fn,
+                    &mut testing_ticks,
+                    buffer,
+                    None,
+                )
+                .is_none()
+            {
+                break;
+            }
+            let (input_batch, output_batch) = buffer.to_device(device);
             
             // Feedforward loss
             let (output, state) = lstm.seq_init(&input_batch, &lstm_state);
             lstm_state = state;
 
             let mse_loss = output.mse_loss(&output_batch, Reduction::Sum);
-            let positives = output_batch.gt(0.0);
-            let negatives = positives.logical_not();
-            let predicted_positives = output.gt(0.0);
-            let predicted_negatives = predicted_positives.logical_not();
-            let false_positives = predicted_positives.logical_and(&negatives);
-            let true_negatives = predicted_negatives.logical_and(&negatives);
-            std::mem::drop(negatives);
-            let true_positives = predicted_positives.logical_and(&positives);
-            std::mem::drop(predicted_positives);
-            let false_negatives = predicted_negatives.logical_and(&positives);
-            std::mem::drop(positives);
-            total_true_positives += i64::from(true_positives.sum(Kind::Int64));
-            total_true_negatives += i64::from(true_negatives.sum(Kind::Int64));
-            total_false_positives += i64::from(false_positives.sum(Kind::Int64));
-            total_false_negatives += i64::from(false_negatives.sum(Kind::Int64));
+            let head_losses = lstm.head_losses(&output, &output_batch, None);
+            for (sum, loss) in sum_head_losses.iter_mut().zip(head_losses) {
+                *sum += f64::from(loss);
+            }
+            if directional {
+                // Directions are classified by the sign of real returns, skipping missing ticks
+                if let Some(predicted) = predicted_close_changes(&lstm, &input_batch, &output) {
+                    confusion.push_returns(&predicted, &buffer.closes(), &buffer.next_closes());
+                }
+            }
 
             // Advance progress bar, set message
-            batch += 1;
             let loss = f64::from(mse_loss);
-            sum_loss += loss;
-            max_loss = max_loss.max(loss);
-            min_loss = min_loss.min(loss);
+            testing.push(loss);
             let ticks_left: usize = testing_ticks.iter().map(|ticks| ticks.len()).sum();
             data_progress.set_position((total_testing_ticks - ticks_left) as u64);
             data_progress.set_message(&format!("loss = {:.5}", loss));
+
+            // Finish the current batch before shutting down or running out of time
+            if shutdown.requested() || timer.out_of_time() {
+                break;
+            }
         }
 
         // Destroy the batch progress bar
         data_progress.finish_and_clear();
+        metrics.push(testing);
+        if shutdown.requested() || timer.out_of_time() {
+            callbacks.on_train_end(&metrics);
+            return early_checkpoint(|name| save(&metrics, name), epoch, &shutdown);
+        }
 
         // Print testing losses
+        debug!(
+            batches = testing.batches,
+            average_loss = testing.average_loss(),
+            max_loss = testing.max_loss,
+            min_loss = testing.min_loss,
+            "Finished testing"
+        );
         epochs_progress.println(format!(
             "average testing loss = {}, max testing loss = {}, min testing loss = {}",
-            sum_loss / batch as f64,
-            max_loss,
-            min_loss
+            testing.average_loss(),
+            testing.max_loss,
+            testing.min_loss
         ));
+        for (head, sum) in lstm.heads.iter().zip(sum_head_losses.iter()) {
+            epochs_progress.println(format!(
+                "average testing {} loss = {}",
+                head.name(),
+                sum / testing.batches as f64
+            ));
+        }
 
-        let total_right = total_true_positives + total_true_negatives;
-        let total_wrong = total_false_negatives + total_false_positives;
+        if directional {
+            epochs_progress.println(format!("{}", confusion));
+        }
 
-        epochs_progress.println(format!(
-            "tp = {}, fp = {} (ratio = {}), tn = {}, fn = {} (ratio = {}) ==> right = {}, wrong = {} (ratio = {})",
-            total_true_positives,
-            total_false_positives,
-            total_true_positives as f64 / total_false_positives as f64,
-            total_true_negatives,
-            total_false_negatives,
-            total_true_negatives as f64 / total_false_negatives as f64,
-            total_right,
-            total_wrong,
-            total_right as f64 / total_wrong as f64
-        ));
+        // Report GPU memory usage, to diagnose out of memory conditions before they happen
+        if let Some(memory) = gpu_memory(device) {
+            info!(
+                used_mib = memory.used_mib,
+                total_mib = memory.total_mib,
+                "GPU memory usage"
+            );
+            epochs_progress.println(format!(
+                "GPU memory usage = {}/{} MiB ({:.1}%)",
+                memory.used_mib,
+                memory.total_mib,
+                100.0 * memory.utilization()
+            ));
+        }
+
+        callbacks.on_epoch_end(epoch, &metrics);
 
         // === CLEANUP ===
 
@@ -301,13 +860,14 @@ pub fn run_network(verbosity: usize, input_files: &[String], device: Device) ->
             break;
         }
         for (i, ticks) in training_data.iter().enumerate() {
-            training_ticks[i] = ticks.iter().copied().peekable();
+            training_ticks[i] = ScaledTicks::new(ticks.iter().copied());
         }
         for (i, ticks) in testing_data.iter().enumerate() {
-            testing_ticks[i] = ticks.iter().copied().peekable();
+            testing_ticks[i] = ScaledTicks::new(ticks.iter().copied());
         }
     }
 
+    callbacks.on_train_end(&metrics);
     Ok(())
 }
 
@@ -319,17 +879,40 @@ pub fn main() -> anyhow::Result<()> {
         .about("An LSTM which attempts to predict the price changes of stocks")
         .arg(
             Arg::with_name("STOCKS")
-                .help("Input stock data in Polygon format")
-                .required(true)
+                .help("Input stock data in Polygon format, with symbols inferred from file names")
+                .required_unless("data-dir")
+                .conflicts_with("data-dir")
                 .multiple(true),
         )
+        .arg(
+            Arg::with_name("data-dir")
+                .long("data-dir")
+                .help("Load every file matching --pattern in a directory")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("pattern")
+                .long("pattern")
+                .help("File name pattern for --data-dir. Defaults to *.csv*")
+                .takes_value(true),
+        )
         .arg(
             Arg::with_name("device")
                 .short("d")
                 .long("device")
-                .help("Device to use: cuda, cpu. Defaults to cuda")
+                .help("Device to use: cpu, cuda or cuda:N for the Nth GPU. Defaults to cuda")
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("pin-memory")
+                .long("pin-memory")
+                .help("Allocate batches in pinned memory, overlapping GPU transfers with compute"),
+        )
+        .arg(
+            Arg::with_name("directional")
+                .long("directional")
+                .help("Print a confusion matrix of predicted close directions every epoch"),
+        )
         .arg(
             Arg::with_name("verbose")
                 .short("v")
@@ -337,22 +920,305 @@ pub fn main() -> anyhow::Result<()> {
                 .help("Sets the level of verbosity")
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("checkpoint-dir")
+                .long("checkpoint-dir")
+                .help("Directory to save checkpoints to on shutdown. Defaults to the current directory")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("max-wall-clock")
+                .long("max-wall-clock")
+                .help("Stop training and save a checkpoint after this many seconds")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("max-batches-per-epoch")
+                .long("max-batches-per-epoch")
+                .help("Train on at most this many batches each epoch")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("clocks")
+                .long("clocks")
+                .help("Comma-separated clock periods, e.g. 5m,30m,1h,1d,1w. Defaults to clocks for 1m bars")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("curriculum-epochs")
+                .long("curriculum-epochs")
+                .help("Grow training sequences and batches from a small start to full size over this many epochs")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("validate-every")
+                .long("validate-every")
+                .help("Validate on a held-out slice of the training data every this many batches, and every epoch")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("patience")
+                .long("patience")
+                .help("Stop after this many validation passes without improvement. Requires --validate-every")
+                .requires("validate-every")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("metrics-csv")
+                .long("metrics-csv")
+                .help("Rewrite a CSV file of training, testing and validation metrics every epoch")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("metrics-addr")
+                .long("metrics-addr")
+                .help("Serve Prometheus training metrics at /metrics on this address. Requires the metrics feature")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("checkpoint-segment")
+                .long("checkpoint-segment")
+                .help("Recompute activations in segments of this many steps when backpropagating, saving memory")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("noise")
+                .long("noise")
+                .help("Add Gaussian noise of this standard deviation to training inputs")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("scale-jitter")
+                .long("scale-jitter")
+                .help("Scale each training input feature by a random factor within this fraction of one")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("dropout")
+                .long("dropout")
+                .help("Drop out the LSTM output with this probability when training from scratch, enabling uncertainty estimates")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("time-warp")
+                .long("time-warp")
+                .help("Resample training sequences at a random speed within this fraction of one")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("mixup-alpha")
+                .long("mixup-alpha")
+                .help("Blend pairs of training windows with coefficients drawn from Beta(alpha, alpha)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("volatility-weighting")
+                .long("volatility-weighting")
+                .help("Weight training windows by their realized volatility to this power")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("recency-half-life")
+                .long("recency-half-life")
+                .help("Halve the weight of training windows for every this many rows they are from the end")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("fine-tune")
+                .long("fine-tune")
+                .help("Fine-tune a pretrained checkpoint, freezing its recurrent layers and training its heads")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("freeze-layers")
+                .long("freeze-layers")
+                .help("Only freeze this many recurrent layers, counting from the input, when fine-tuning")
+                .requires("fine-tune")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("sectors")
+                .long("sectors")
+                .help("Group stocks by sector, read from a CSV file with columns symbol and sector, with a shared hidden block per sector")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("scaler-warmup")
+                .long("scaler-warmup")
+                .help("Only update each symbol's scaler on its first this many ticks, dropping them from training")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("quantile-transform")
+                .long("quantile-transform")
+                .help("Map each field of scaled ticks to an approximately normal distribution by its quantiles on the training data"),
+        )
+        .arg(
+            Arg::with_name("verify-data")
+                .long("verify-data")
+                .help("Run the data pipeline for one epoch without training, reporting batch counts, zero fill rates and feature statistics"),
+        )
+        .arg(
+            Arg::with_name("log-json")
+                .long("log-json")
+                .help("Log as JSON lines, for machine consumption"),
+        )
         .get_matches();
 
-    let input_files = matches.values_of_lossy("STOCKS").expect("Required");
     let verbosity = matches
         .value_of("verbose")
         .map(|v| usize::from_str_radix(v, 10))
         .unwrap_or(Ok(0))?;
 
-    let device: Device = match matches.value_of("device").unwrap_or("cuda") {
-        "cuda" => Device::cuda_if_available(),
-        "cpu" => Device::Cpu,
-        device => Err(format_err!("Invalid value for device: {:?}", device))?,
+    // Log to stderr, filtering by RUST_LOG if set and by verbosity otherwise
+    let level = match verbosity {
+        0 => "warn",
+        1 => "info",
+        2 => "debug",
+        _ => "trace",
+    };
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(level));
+    let subscriber = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr);
+    if matches.is_present("log-json") {
+        subscriber.json().init();
+    } else {
+        subscriber.init();
+    }
+
+    let device = parse_device(matches.value_of("device").unwrap_or("cuda"))?;
+    info!("Device: {:?}", device);
+
+    let budget = Budget {
+        max_wall_clock: matches
+            .value_of("max-wall-clock")
+            .map(|secs| secs.parse().map(std::time::Duration::from_secs))
+            .transpose()?,
+        max_batches_per_epoch: matches
+            .value_of("max-batches-per-epoch")
+            .map(|batches| batches.parse())
+            .transpose()?,
+    };
+
+    let clock_periods = match matches.value_of("clocks") {
+        Some(periods) => parse_durations(periods)
+            .ok_or_else(|| format_err!("Invalid clock periods {:?}", periods))?,
+        None => default_clock_periods(Duration::minutes(1)),
+    };
+    info!("Clock periods: {:?}", clock_periods);
+
+    let curriculum = Curriculum {
+        start_batch_size: START_BATCH_SIZE,
+        start_sequence_length: START_SEQ_LEN,
+        epochs: matches
+            .value_of("curriculum-epochs")
+            .map(|epochs| epochs.parse())
+            .transpose()?
+            .unwrap_or(0),
+        ..Curriculum::constant(BATCH_SIZE, SEQ_LEN)
+    };
+
+    let data = match matches.value_of("data-dir") {
+        Some(dir) => load_dir(dir, matches.value_of("pattern").unwrap_or("*.csv*"))?,
+        None => load_files(matches.values_of("STOCKS").expect("Required"))?,
     };
-    if verbosity >= 1 {
-        eprintln!("Device: {:?}", device);
+    info!("Loaded {} symbols", data.len());
+    let scaler = TickScalerConfig::uniform(AVERAGE_DECAY_RATE, RANGE_DECAY_RATE).with_warmup(
+        matches
+            .value_of("scaler-warmup")
+            .map(|warmup| warmup.parse())
+            .transpose()?
+            .unwrap_or(0),
+    );
+    if matches.is_present("verify-data") {
+        return verify(data, &clock_periods, &scaler);
     }
 
-    run_network(verbosity, &input_files, device)
+    let mut callbacks = Callbacks::default();
+    if let Some(path) = matches.value_of("metrics-csv") {
+        callbacks.push(MetricsCsv { path: path.into() });
+    }
+    #[cfg(feature = "metrics")]
+    let metrics = match matches.value_of("metrics-addr") {
+        Some(addr) => {
+            let metrics = Metrics::default();
+            let route = metrics.clone();
+            stockburn::serve::spawn_server(addr, move |path| metrics_route(&route, path))?;
+            info!("Serving metrics on {}", addr);
+            callbacks.push(TrainingMetrics::new(metrics.clone(), BATCH_SIZE * SEQ_LEN));
+            Some(metrics)
+        }
+        None => None,
+    };
+    #[cfg(not(feature = "metrics"))]
+    {
+        if matches.is_present("metrics-addr") {
+            warn!("Ignoring --metrics-addr, as stockburn was built without the metrics feature");
+        }
+    }
+
+    let parse_float = |name: &str| -> anyhow::Result<f64> {
+        Ok(matches
+            .value_of(name)
+            .map(|value| value.parse())
+            .transpose()?
+            .unwrap_or(0.0))
+    };
+    let augmentation = Augmentation {
+        noise_std: parse_float("noise")?,
+        scale_jitter: parse_float("scale-jitter")?,
+        time_warp: parse_float("time-warp")?,
+    };
+
+    let freeze = match matches.value_of("freeze-layers") {
+        Some(layers) => LayerSelection::First(layers.parse()?),
+        None => LayerSelection::All,
+    };
+    let fine_tuning = matches
+        .value_of("fine-tune")
+        .map(|checkpoint| (Path::new(checkpoint), freeze));
+    let sector_metadata = match matches.value_of("sectors") {
+        Some(path) => Some(read_metadata(File::open(path)?)),
+        None => None,
+    };
+
+    let options = TrainOptions {
+        directional: matches.is_present("directional"),
+        pin_memory: matches.is_present("pin-memory"),
+        checkpoint_dir: Path::new(matches.value_of("checkpoint-dir").unwrap_or(".")),
+        budget,
+        clock_periods,
+        scaler,
+        quantile_transform: matches.is_present("quantile-transform"),
+        curriculum,
+        validation: matches
+            .value_of("validate-every")
+            .map(|batches| batches.parse())
+            .transpose()?
+            .map(|every_batches| ValidationSchedule { every_batches }),
+        patience: matches
+            .value_of("patience")
+            .map(|patience| patience.parse())
+            .transpose()?,
+        callbacks,
+        checkpoint_segment: matches
+            .value_of("checkpoint-segment")
+            .map(|segment| segment.parse())
+            .transpose()?,
+        augmentation,
+        mixup: Mixup {
+            alpha: parse_float("mixup-alpha")?,
+        },
+        sample_weighting: SampleWeighting {
+            volatility: parse_float("volatility-weighting")?,
+            recency_half_life: parse_float("recency-half-life")?,
+        },
+        fine_tuning,
+        sector_metadata,
+        dropout: parse_float("dropout")?,
+        #[cfg(feature = "metrics")]
+        metrics,
+    };
+    run_network(data, device, options)
 }
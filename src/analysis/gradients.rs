@@ -0,0 +1,138 @@
+/*!
+Gradient attribution: which timesteps and fields of an input window drive a model's predictions
+*/
+use crate::data::Target;
+use crate::lstm::{layout::Layout, loss::LossFn, loss::WeightedMse, StockLSTM};
+use tch::nn::RNN;
+use tch::{Device, Kind, Tensor};
+
+/// The quantity whose gradient with respect to the inputs is computed
+#[derive(Debug, Copy, Clone)]
+pub enum Objective<'a> {
+    /// The model's weighted loss against a tensor of outputs, of the same shape as the model's outputs
+    Loss(&'a Tensor),
+    /// The model's prediction of a target for a stock at the last timestep of the window, summed over the batch
+    Prediction {
+        /// The target predicted
+        target: Target,
+        /// The stock whose prediction is attributed
+        stock: usize,
+    },
+}
+
+/// Compute the gradient of an objective with respect to a window of inputs of shape `[sequence, features]` or
+/// `[batch, sequence, features]`, run from a zero state without dropout. The result has the same shape as the
+/// inputs, lives on the CPU, and its last dimension is laid out as in `StockLSTM::input_layout`.
+///
+/// Returns `None` if a prediction of a target the model does not predict, or of an out of range stock, is requested.
+/// Gradients are not accumulated into the model's variables.
+pub fn input_gradients(lstm: &StockLSTM, inputs: &Tensor, objective: Objective) -> Option<Tensor> {
+    let unbatched = inputs.dim() == 2;
+    let inputs = if unbatched {
+        inputs.unsqueeze(0)
+    } else {
+        inputs.shallow_clone()
+    };
+    let inputs = inputs.to_kind(Kind::Float).detach().set_requires_grad(true);
+    let batch_size = inputs.size()[0];
+    let (yhat, _) = lstm.forward_t(&inputs, &lstm.zero_state(batch_size), false);
+    let scalar = match objective {
+        Objective::Loss(outputs) => {
+            let outputs = if unbatched {
+                outputs.unsqueeze(0)
+            } else {
+                outputs.shallow_clone()
+            };
+            let loss_fn = WeightedMse {
+                weights: lstm.loss_weight_tensor(),
+            };
+            loss_fn.loss(&yhat, &outputs.to_device(yhat.device()), None)
+        }
+        Objective::Prediction { target, stock } => {
+            if stock >= lstm.stocks {
+                return None;
+            }
+            lstm.head_output(&yhat, target)?
+                .select(1, -1)
+                .select(1, stock as i64)
+                .sum(Kind::Float)
+        }
+    };
+    let gradients = Tensor::run_backward(&[scalar], &[&inputs], false, false);
+    let gradients = gradients[0].to_device(Device::Cpu);
+    Some(if unbatched {
+        gradients.squeeze1(0)
+    } else {
+        gradients
+    })
+}
+
+/// Sum the absolute values of a tensor of input gradients over every dimension but the last, returning the total
+/// saliency of each input column named as in a layout, from most to least salient
+pub fn column_saliency(gradients: &Tensor, layout: &Layout) -> Vec<(String, f64)> {
+    let features = *gradients
+        .size()
+        .last()
+        .expect("Gradients have a feature dimension");
+    let totals = Vec::<f64>::from(
+        &gradients
+            .abs()
+            .to_kind(Kind::Double)
+            .view([-1, features])
+            .sum1(&[0], false, Kind::Double),
+    );
+    let mut saliency: Vec<(String, f64)> = layout
+        .column_names()
+        .map(String::from)
+        .zip(totals)
+        .collect();
+    saliency.sort_by(|left, right| {
+        right
+            .1
+            .partial_cmp(&left.1)
+            .unwrap_or(std::cmp::Ordering::Equal)
+    });
+    saliency
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lstm::StockLSTMDesc;
+    use tch::nn::VarStore;
+
+    #[test]
+    fn gradients_align_with_inputs() {
+        let vs = VarStore::new(Device::Cpu);
+        let lstm = StockLSTMDesc {
+            additional_inputs: 1,
+            date_inputs: 2,
+            stocks: 2,
+            hidden: 4,
+            layers: 1,
+            heads: vec![Target::Close, Target::Volume],
+            ..Default::default()
+        }
+        .build(&vs);
+        let inputs = Tensor::randn(&[6, lstm.no_inputs() as i64], tch::kind::FLOAT_CPU);
+        let objective = Objective::Prediction {
+            target: Target::Close,
+            stock: 1,
+        };
+        let gradients = input_gradients(&lstm, &inputs, objective).unwrap();
+        assert_eq!(gradients.size(), inputs.size());
+        let outputs = Tensor::randn(&[6, lstm.no_outputs() as i64], tch::kind::FLOAT_CPU);
+        let loss_gradients = input_gradients(&lstm, &inputs, Objective::Loss(&outputs)).unwrap();
+        assert_eq!(loss_gradients.size(), inputs.size());
+        for (_, var) in vs.variables() {
+            assert!(!var.grad().defined());
+        }
+        let missing = Objective::Prediction {
+            target: Target::Volatility,
+            stock: 0,
+        };
+        assert!(input_gradients(&lstm, &inputs, missing).is_none());
+        let saliency = column_saliency(&gradients, &lstm.input_layout());
+        assert_eq!(saliency.len(), lstm.no_inputs());
+    }
+}
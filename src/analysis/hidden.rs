@@ -0,0 +1,64 @@
+/*!
+Exporting the hidden states learned by a model, e.g. for clustering or regime analysis
+*/
+use crate::data::Symbol;
+use std::io::Write;
+use tch::{Device, Kind, Tensor};
+
+/// Write a tensor of hidden states of shape `[batch, sequence, hidden]`, as returned by `StockLSTM::hidden_sequence`,
+/// to CSV, with one row per timestep of each sequence. Each sequence is labeled by the symbol at the same index, so
+/// that, e.g., the hidden states of a single stock model run over a batch of symbols can be dumped per symbol.
+///
+/// Columns are `symbol`, `step` and `h0`, `h1`, ... for each hidden feature. Panics if the number of symbols does not
+/// match the batch size.
+pub fn write_hidden<W: Write>(
+    wtr: W,
+    symbols: &[Symbol],
+    hidden: &Tensor,
+) -> Result<(), csv::Error> {
+    let (batch, steps, features) = hidden.size3().expect("Hidden states have three dimensions");
+    assert_eq!(
+        symbols.len() as i64,
+        batch,
+        "Need one symbol per sequence of hidden states!"
+    );
+    let hidden = hidden.to_device(Device::Cpu).to_kind(Kind::Float);
+    let mut wtr = csv::Writer::from_writer(wtr);
+    let mut record = vec!["symbol".to_string(), "step".to_string()];
+    record.extend((0..features).map(|feature| format!("h{}", feature)));
+    wtr.write_record(&record)?;
+    for (ix, symbol) in symbols.iter().enumerate() {
+        let sequence = hidden.get(ix as i64);
+        for step in 0..steps {
+            record.clear();
+            record.push(symbol.to_string());
+            record.push(step.to_string());
+            record.extend(
+                Vec::<f32>::from(&sequence.get(step))
+                    .into_iter()
+                    .map(|value| value.to_string()),
+            );
+            wtr.write_record(&record)?;
+        }
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hidden_states_are_written_per_symbol() {
+        let hidden = Tensor::arange(12, tch::kind::FLOAT_CPU).view([2, 3, 2]);
+        let mut csv = Vec::new();
+        write_hidden(&mut csv, &["AMD".into(), "MSFT".into()], &hidden).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let lines: Vec<_> = csv.lines().collect();
+        assert_eq!(lines.len(), 7);
+        assert_eq!(lines[0], "symbol,step,h0,h1");
+        assert_eq!(lines[1], "AMD,0,0,1");
+        assert_eq!(lines[6], "MSFT,2,10,11");
+    }
+}
@@ -0,0 +1,10 @@
+/*!
+Tools for analysing trained models, e.g. which input features drive their predictions
+*/
+pub mod gradients;
+pub mod hidden;
+pub mod importance;
+
+pub use gradients::{column_saliency, input_gradients, Objective};
+pub use hidden::write_hidden;
+pub use importance::{permutation_importance, Granularity, Importance};
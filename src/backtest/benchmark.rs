@@ -0,0 +1,84 @@
+/*!
+Benchmark strategies, run alongside a model's strategy on identical data and cost assumptions
+*/
+use super::risk::RiskReport;
+use super::{Allocation, Backtest, BacktestConfig};
+use chrono::NaiveDateTime;
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+/// A backtest of a model's signals, together with buy-and-hold and random-signal benchmarks
+#[derive(Debug, Clone)]
+pub struct BenchmarkedBacktest<R = StdRng> {
+    /// The backtest of the model's signals
+    pub model: Backtest,
+    /// A buy-and-hold benchmark, which allocates capital equally between the stocks priced at the first step and
+    /// then never trades
+    pub buy_and_hold: Backtest,
+    /// A random benchmark, trading on the model's signals shuffled between stocks with random signs
+    pub random: Backtest,
+    /// The RNG used to generate random signals
+    pub rng: R,
+}
+
+/// Risk reports for a model's strategy and its benchmarks
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BenchmarkReport {
+    /// The report for the model's strategy
+    pub model: RiskReport,
+    /// The report for the buy-and-hold benchmark
+    pub buy_and_hold: RiskReport,
+    /// The report for the random benchmark
+    pub random: RiskReport,
+}
+
+impl BenchmarkedBacktest<StdRng> {
+    /// Start a new benchmarked backtest over a given number of stocks, seeding the random benchmark
+    pub fn new(config: BacktestConfig, stocks: usize, seed: u64) -> BenchmarkedBacktest<StdRng> {
+        BenchmarkedBacktest::with_rng(config, stocks, StdRng::seed_from_u64(seed))
+    }
+}
+
+impl<R: Rng> BenchmarkedBacktest<R> {
+    /// Start a new benchmarked backtest over a given number of stocks, using a given RNG for the random benchmark
+    pub fn with_rng(config: BacktestConfig, stocks: usize, rng: R) -> BenchmarkedBacktest<R> {
+        let hold_config = BacktestConfig {
+            allocation: Allocation::EqualWeight,
+            long: true,
+            threshold: 0.0,
+            ..config
+        };
+        BenchmarkedBacktest {
+            model: Backtest::new(config, stocks),
+            buy_and_hold: Backtest::new(hold_config, stocks),
+            random: Backtest::new(config, stocks),
+            rng,
+        }
+    }
+    /// Advance the model and all benchmarks by one step, returning the model's equity
+    pub fn step(&mut self, t: NaiveDateTime, prices: &[Option<f64>], signals: &[f64]) -> f64 {
+        if self.buy_and_hold.equity_curve.is_empty() {
+            let long = vec![1.0; prices.len()];
+            self.buy_and_hold.step(t, prices, &long);
+        } else {
+            self.buy_and_hold.mark(t, prices);
+        }
+        let mut random = signals.to_vec();
+        random.shuffle(&mut self.rng);
+        for signal in random.iter_mut() {
+            if self.rng.gen::<bool>() {
+                *signal = -*signal
+            }
+        }
+        self.random.step(t, prices, &random);
+        self.model.step(t, prices, signals)
+    }
+    /// Compute risk reports for the model and all benchmarks
+    pub fn report(&self, periods_per_year: f64, confidence: f64) -> BenchmarkReport {
+        BenchmarkReport {
+            model: self.model.risk_report(periods_per_year, confidence),
+            buy_and_hold: self.buy_and_hold.risk_report(periods_per_year, confidence),
+            random: self.random.risk_report(periods_per_year, confidence),
+        }
+    }
+}
@@ -0,0 +1,411 @@
+/*!
+Portfolio-level backtesting of trading signals across many stocks
+*/
+use crate::data::SymbolRegistry;
+use crate::trading::{sizing::RealizedVolatility, Position};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::io::Write;
+
+pub mod benchmark;
+pub mod paper;
+pub mod replay;
+pub mod risk;
+use risk::RiskReport;
+
+/// How to allocate capital between the stocks with an active signal
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Allocation {
+    /// Allocate capital equally between all stocks with an active signal
+    EqualWeight,
+    /// Allocate capital inversely proportionally to each stock's realized volatility
+    VolatilityScaled,
+    /// Allocate capital equally between the `k` stocks with the largest absolute signal, i.e. predicted return
+    TopK(usize),
+}
+
+/// The configuration of a portfolio backtest
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BacktestConfig {
+    /// The starting capital
+    pub capital: f64,
+    /// How to allocate capital between stocks
+    pub allocation: Allocation,
+    /// Whether long positions are allowed
+    pub long: bool,
+    /// Whether short positions are allowed
+    pub short: bool,
+    /// The minimum absolute signal for a stock to be traded
+    pub threshold: f64,
+    /// The maximum absolute position in any single stock, as a fraction of equity
+    pub max_position: f64,
+    /// The maximum gross exposure, as a fraction of equity
+    pub max_gross: f64,
+    /// The proportional cost of trading, as a fraction of the value traded
+    pub cost: f64,
+    /// The per-step decay of the `RealizedVolatility` estimate of each stock, used to scale allocations
+    pub volatility_decay: f64,
+}
+
+impl Default for BacktestConfig {
+    fn default() -> BacktestConfig {
+        BacktestConfig {
+            capital: 1_000_000.0,
+            allocation: Allocation::EqualWeight,
+            long: true,
+            short: true,
+            threshold: 0.0,
+            max_position: 1.0,
+            max_gross: 1.0,
+            cost: 0.0,
+            volatility_decay: 0.99,
+        }
+    }
+}
+
+/// A point on an equity curve
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EquityPoint {
+    /// The time of this point
+    pub t: NaiveDateTime,
+    /// The total value of the portfolio
+    pub equity: f64,
+    /// The cash held
+    pub cash: f64,
+    /// The gross exposure, i.e. the total absolute value of all positions
+    pub gross: f64,
+}
+
+/// A completed trade, i.e. a position (or part of one) which was opened and then closed
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TradeRecord {
+    /// The index of the stock traded
+    pub stock: usize,
+    /// The time the position was opened
+    pub entry_t: NaiveDateTime,
+    /// The time the position was closed
+    pub exit_t: NaiveDateTime,
+    /// The number of shares traded, negative for short positions
+    pub size: f64,
+    /// The average price the position was opened at
+    pub entry_price: f64,
+    /// The price the position was closed at
+    pub exit_price: f64,
+    /// The profit or loss of the trade, excluding transaction costs (which are reflected in the equity curve)
+    pub pnl: f64,
+}
+
+/// An open position in a single stock
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct OpenTrade {
+    /// The time the position was opened
+    entry_t: NaiveDateTime,
+    /// The average price the position was opened at
+    entry_price: f64,
+}
+
+/// The state of a portfolio backtest
+#[derive(Debug, Clone, PartialEq)]
+pub struct Backtest {
+    /// The configuration of this backtest
+    pub config: BacktestConfig,
+    /// The cash currently held
+    pub cash: f64,
+    /// The number of shares currently held of each stock, negative for short positions
+    pub positions: Vec<f64>,
+    /// The last known price of each stock
+    pub prices: Vec<Option<f64>>,
+    /// The realized volatility estimate of each stock
+    pub volatilities: Vec<RealizedVolatility>,
+    /// The equity curve so far
+    pub equity_curve: Vec<EquityPoint>,
+    /// The trades completed so far
+    pub trades: Vec<TradeRecord>,
+    /// The open trade in each stock, if any
+    open_trades: Vec<Option<OpenTrade>>,
+}
+
+impl Backtest {
+    /// Start a new backtest over a given number of stocks
+    pub fn new(config: BacktestConfig, stocks: usize) -> Backtest {
+        Backtest {
+            config,
+            cash: config.capital,
+            positions: vec![0.0; stocks],
+            prices: vec![None; stocks],
+            volatilities: vec![RealizedVolatility::new(config.volatility_decay); stocks],
+            equity_curve: Vec::new(),
+            trades: Vec::new(),
+            open_trades: vec![None; stocks],
+        }
+    }
+    /// Get the current value of the portfolio, marking each position at its last known price
+    pub fn equity(&self) -> f64 {
+        self.cash
+            + self
+                .positions
+                .iter()
+                .zip(self.prices.iter())
+                .map(|(position, price)| position * price.unwrap_or(0.0))
+                .sum::<f64>()
+    }
+    /// Get the current gross exposure of the portfolio
+    pub fn gross(&self) -> f64 {
+        self.positions
+            .iter()
+            .zip(self.prices.iter())
+            .map(|(position, price)| (position * price.unwrap_or(0.0)).abs())
+            .sum()
+    }
+    /// Get the open positions of the portfolio, given the symbols of its stocks, marking each at its last known price
+    pub fn open_positions(&self, symbols: &SymbolRegistry) -> Vec<Position> {
+        self.positions
+            .iter()
+            .zip(self.prices.iter())
+            .zip(self.open_trades.iter())
+            .enumerate()
+            .filter(|(_, ((shares, _), _))| **shares != 0.0)
+            .filter_map(|(stock, ((shares, price), open))| {
+                Some(Position {
+                    symbol: symbols.symbol(stock)?.clone(),
+                    shares: *shares,
+                    entry_price: open.map_or(0.0, |open| open.entry_price),
+                    market_value: shares * price.unwrap_or(0.0),
+                })
+            })
+            .collect()
+    }
+    /// Update the last known prices and volatility estimates of each stock
+    fn update_prices(&mut self, prices: &[Option<f64>]) {
+        for ((last, volatility), price) in self
+            .prices
+            .iter_mut()
+            .zip(self.volatilities.iter_mut())
+            .zip(prices.iter())
+        {
+            let price = match price {
+                Some(price) if price.is_finite() && *price > 0.0 => *price,
+                _ => continue,
+            };
+            volatility.push(price);
+            *last = Some(price);
+        }
+    }
+    /// Compute the target portfolio weight of each stock given its signal, i.e. predicted return
+    pub fn target_weights(&self, signals: &[f64]) -> Vec<f64> {
+        let config = &self.config;
+        let mut candidates: Vec<usize> = (0..self.positions.len())
+            .filter(|&ix| {
+                let signal = signals.get(ix).copied().unwrap_or(0.0);
+                self.prices[ix].is_some()
+                    && signal.is_finite()
+                    && signal.abs() > config.threshold
+                    && ((signal > 0.0 && config.long) || (signal < 0.0 && config.short))
+            })
+            .collect();
+        if let Allocation::TopK(k) = config.allocation {
+            candidates.sort_by(|&l, &r| {
+                signals[r]
+                    .abs()
+                    .partial_cmp(&signals[l].abs())
+                    .unwrap_or(Ordering::Equal)
+            });
+            candidates.truncate(k);
+        }
+        let mut weights = vec![0.0; self.positions.len()];
+        for &ix in candidates.iter() {
+            let raw = match config.allocation {
+                Allocation::EqualWeight | Allocation::TopK(_) => 1.0,
+                Allocation::VolatilityScaled => match self.volatilities[ix].volatility() {
+                    Some(volatility) if volatility > 0.0 => 1.0 / volatility,
+                    _ => 0.0,
+                },
+            };
+            weights[ix] = raw * signals[ix].signum();
+        }
+        let total: f64 = weights.iter().map(|weight| weight.abs()).sum();
+        if total == 0.0 {
+            return weights;
+        }
+        for weight in weights.iter_mut() {
+            *weight = (*weight * config.max_gross / total)
+                .max(-config.max_position)
+                .min(config.max_position);
+        }
+        weights
+    }
+    /// Update the trade log for a stock whose position is about to change to `target` shares at a given price
+    fn record_trade(&mut self, t: NaiveDateTime, ix: usize, price: f64, target: f64) {
+        let current = self.positions[ix];
+        let opened = if current == 0.0 || target == 0.0 || current.signum() != target.signum() {
+            // Close the entire current position, if any, and open a new one
+            self.close_trade(t, ix, price, current);
+            if target != 0.0 {
+                Some(OpenTrade {
+                    entry_t: t,
+                    entry_price: price,
+                })
+            } else {
+                None
+            }
+        } else if target.abs() < current.abs() {
+            // Close part of the current position
+            self.close_trade(t, ix, price, current - target);
+            self.open_trades[ix]
+        } else {
+            // Grow the current position, averaging the entry price
+            self.open_trades[ix].map(|open| OpenTrade {
+                entry_t: open.entry_t,
+                entry_price: (open.entry_price * current + price * (target - current)) / target,
+            })
+        };
+        self.open_trades[ix] = opened;
+    }
+    /// Record closing `size` shares of the open trade in a stock at a given price
+    fn close_trade(&mut self, t: NaiveDateTime, ix: usize, price: f64, size: f64) {
+        if let Some(open) = self.open_trades[ix] {
+            self.trades.push(TradeRecord {
+                stock: ix,
+                entry_t: open.entry_t,
+                exit_t: t,
+                size,
+                entry_price: open.entry_price,
+                exit_price: price,
+                pnl: size * (price - open.entry_price),
+            })
+        }
+    }
+    /// Compute a risk report for this backtest, given the number of steps per year and the confidence level for
+    /// value at risk
+    pub fn risk_report(&self, periods_per_year: f64, confidence: f64) -> RiskReport {
+        RiskReport::new(
+            &self.equity_curve,
+            &self.trades,
+            periods_per_year,
+            confidence,
+        )
+    }
+    /// Write this backtest's trade log to a Writer as CSV
+    /// On success, return how many trades were written
+    pub fn write_trades<W: Write>(&self, wtr: W) -> Result<usize, csv::Error> {
+        write_records(wtr, self.trades.iter())
+    }
+    /// Write this backtest's equity curve to a Writer as CSV
+    /// On success, return how many points were written
+    pub fn write_equity_curve<W: Write>(&self, wtr: W) -> Result<usize, csv::Error> {
+        write_records(wtr, self.equity_curve.iter())
+    }
+    /// Advance the backtest by one step: mark the portfolio to the given prices (`None` for stocks without a price
+    /// at this time), then rebalance towards the allocation implied by the given signals.
+    ///
+    /// Returns the equity after rebalancing.
+    pub fn step(&mut self, t: NaiveDateTime, prices: &[Option<f64>], signals: &[f64]) -> f64 {
+        self.update_prices(prices);
+        self.rebalance(t, signals);
+        self.record_equity(t)
+    }
+    /// Advance the backtest by one step without trading, only marking the portfolio to the given prices
+    ///
+    /// Returns the equity at the given prices.
+    pub fn mark(&mut self, t: NaiveDateTime, prices: &[Option<f64>]) -> f64 {
+        self.update_prices(prices);
+        self.record_equity(t)
+    }
+    /// Rebalance the portfolio towards the allocation implied by the given signals at the last known prices
+    fn rebalance(&mut self, t: NaiveDateTime, signals: &[f64]) {
+        let equity = self.equity();
+        let weights = self.target_weights(signals);
+        for (ix, weight) in weights.iter().enumerate() {
+            let price = if let Some(price) = self.prices[ix] {
+                price
+            } else {
+                continue;
+            };
+            let target = if equity > 0.0 {
+                weight * equity / price
+            } else {
+                0.0
+            };
+            let traded = target - self.positions[ix];
+            if traded == 0.0 {
+                continue;
+            }
+            let value = traded * price;
+            self.cash -= value + value.abs() * self.config.cost;
+            self.record_trade(t, ix, price, target);
+            self.positions[ix] = target;
+        }
+    }
+    /// Record the current equity on the equity curve, returning it
+    fn record_equity(&mut self, t: NaiveDateTime) -> f64 {
+        let equity = self.equity();
+        self.equity_curve.push(EquityPoint {
+            t,
+            equity,
+            cash: self.cash,
+            gross: self.gross(),
+        });
+        equity
+    }
+}
+
+/// Write serializable records to a Writer as CSV
+/// On success, return how many records were written
+fn write_records<W, I, T>(wtr: W, records: I) -> Result<usize, csv::Error>
+where
+    W: Write,
+    I: Iterator<Item = T>,
+    T: Serialize,
+{
+    let mut wtr = csv::Writer::from_writer(wtr);
+    let mut written = 0;
+    for record in records {
+        wtr.serialize(record)?;
+        written += 1;
+    }
+    wtr.flush()?;
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, NaiveDate};
+
+    #[test]
+    fn equal_weight_long_short() {
+        let t = NaiveDate::from_ymd(2020, 10, 12).and_hms(14, 30, 0);
+        let mut backtest = Backtest::new(BacktestConfig::default(), 2);
+        backtest.step(t, &[Some(10.0), Some(20.0)], &[1.0, -1.0]);
+        assert_eq!(backtest.positions, vec![50_000.0, -25_000.0]);
+        let equity = backtest.step(
+            t + Duration::minutes(1),
+            &[Some(11.0), Some(18.0)],
+            &[0.0, 0.0],
+        );
+        assert!((equity - 1_100_000.0).abs() < 1e-6);
+        assert_eq!(backtest.positions, vec![0.0, 0.0]);
+        assert_eq!(backtest.equity_curve.len(), 2);
+        assert_eq!(backtest.trades.len(), 2);
+        assert_eq!(backtest.trades[0].pnl, 50_000.0);
+        assert_eq!(backtest.trades[1].pnl, 50_000.0);
+        let mut csv = Vec::new();
+        assert_eq!(backtest.write_trades(&mut csv).unwrap(), 2);
+        assert!(csv.starts_with(b"stock,entry_t,exit_t,size,entry_price,exit_price,pnl\n"));
+    }
+
+    #[test]
+    fn top_k_respects_limits() {
+        let config = BacktestConfig {
+            allocation: Allocation::TopK(1),
+            short: false,
+            max_position: 0.5,
+            ..Default::default()
+        };
+        let mut backtest = Backtest::new(config, 3);
+        backtest.update_prices(&[Some(1.0), Some(1.0), Some(1.0)]);
+        let weights = backtest.target_weights(&[0.1, 0.3, -0.5]);
+        assert_eq!(weights, vec![0.0, 0.5, 0.0]);
+    }
+}
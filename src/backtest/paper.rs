@@ -0,0 +1,232 @@
+/*!
+Paper trading: running a `Predictor` on live ticks in real time and trading its predictions against a simulated broker,
+the backtest's fill model, to log hypothetical fills and PnL before trading for real
+*/
+use super::{Backtest, BacktestConfig};
+use crate::data::{Symbol, Tick};
+use crate::predict::Predictor;
+use crate::trading::{Fill, Signal, Strategy, StrategyContext};
+use anyhow::format_err;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use std::collections::BTreeMap;
+
+/// The result of trading on the ticks of a single bar
+#[derive(Debug, Clone, PartialEq)]
+pub struct PaperStep {
+    /// The time of the bar
+    pub t: NaiveDateTime,
+    /// The raw ticks of the bar, as fed to the predictor
+    pub ticks: BTreeMap<Symbol, Tick>,
+    /// The signals emitted by the strategy
+    pub signals: Vec<Signal>,
+    /// The fills made rebalancing towards the signals
+    pub fills: Vec<Fill>,
+    /// The equity after rebalancing
+    pub equity: f64,
+    /// The change in equity since the previous bar
+    pub pnl: f64,
+}
+
+/// Trades the signals a strategy derives from a predictor's predictions against the simulated broker of a `Backtest`
+/// as live ticks arrive.
+///
+/// Ticks arrive one symbol at a time, so each bar's ticks are buffered until a tick of a later bar arrives, at which
+/// point the bar is fed to the predictor and the portfolio is rebalanced at the bar's closes.
+#[derive(Debug)]
+pub struct PaperTrader<DF, S> {
+    /// The predictor, which must have symbols
+    pub predictor: Predictor<DF>,
+    /// The strategy converting predictions into signals
+    pub strategy: S,
+    /// The simulated broker and its portfolio
+    pub backtest: Backtest,
+    /// Every fill so far
+    pub fills: Vec<Fill>,
+    /// The time of the bar being buffered, if any
+    pending_t: Option<NaiveDateTime>,
+    /// The ticks of the bar being buffered
+    pending: BTreeMap<Symbol, Tick>,
+}
+
+impl<DF, S> PaperTrader<DF, S>
+where
+    DF: FnMut(DateTime<Utc>, &mut Vec<f32>),
+    S: Strategy,
+{
+    /// Start paper trading a predictor with symbols on the signals of a strategy, with a simulated portfolio of a
+    /// given configuration
+    pub fn new(
+        predictor: Predictor<DF>,
+        strategy: S,
+        config: BacktestConfig,
+    ) -> anyhow::Result<PaperTrader<DF, S>> {
+        if predictor.symbols.is_none() {
+            return Err(format_err!("Paper trading needs a predictor with symbols"));
+        }
+        let backtest = Backtest::new(config, predictor.lstm.stocks);
+        Ok(PaperTrader {
+            predictor,
+            strategy,
+            backtest,
+            fills: Vec::new(),
+            pending_t: None,
+            pending: BTreeMap::new(),
+        })
+    }
+    /// Feed a bar of historical ticks to the predictor without trading, e.g. to warm up its state before going live
+    pub fn warm_up(&mut self, t: NaiveDateTime, ticks: &BTreeMap<Symbol, Tick>) {
+        self.predictor.push_symbols(t, ticks, &[]);
+        let prices = self.prices(ticks);
+        self.backtest.mark(t, &prices);
+    }
+    /// Receive a live tick. If it belongs to a later bar than the one being buffered, the buffered bar is traded first
+    /// and its result returned. Ticks of earlier bars than the one being buffered are dropped.
+    pub fn on_tick(&mut self, symbol: Symbol, tick: Tick) -> Option<PaperStep> {
+        let step = match self.pending_t {
+            Some(pending_t) if tick.t < pending_t => return None,
+            Some(pending_t) if tick.t > pending_t => self.flush(),
+            _ => None,
+        };
+        self.pending_t = Some(tick.t);
+        self.pending.insert(symbol, tick);
+        step
+    }
+    /// Trade the buffered bar, if any, e.g. once no more ticks are expected for it
+    pub fn flush(&mut self) -> Option<PaperStep> {
+        let t = self.pending_t.take()?;
+        let ticks = std::mem::replace(&mut self.pending, BTreeMap::new());
+        Some(self.trade(t, &ticks))
+    }
+    /// Get the last known closes of a bar's ticks, in stock order
+    fn prices(&self, ticks: &BTreeMap<Symbol, Tick>) -> Vec<Option<f64>> {
+        self.predictor
+            .symbols
+            .as_ref()
+            .expect("Checked on creation")
+            .arrange(ticks)
+            .into_iter()
+            .map(|tick| tick.map(|tick| tick.c))
+            .collect()
+    }
+    /// Feed a bar to the predictor and rebalance towards the signals of its predictions
+    fn trade(&mut self, t: NaiveDateTime, ticks: &BTreeMap<Symbol, Tick>) -> PaperStep {
+        let symbols = self.predictor.symbols.clone().expect("Checked on creation");
+        self.predictor.push_symbols(t, ticks, &[]);
+        // The ticks exactly as fed to the network, to compare level predictions against
+        let scaled = self
+            .predictor
+            .last_scaled_symbols()
+            .expect("Checked on creation");
+        let predictions = self.predictor.last_predictions().unwrap_or_default();
+        let positions = self.backtest.open_positions(&symbols);
+        let ctx = StrategyContext {
+            t,
+            ticks,
+            scaled: &scaled,
+            positions: &positions,
+        };
+        let signals = self.strategy.on_prediction(&ctx, &predictions);
+
+        let previous_equity = self.backtest.equity();
+        let previous_positions = self.backtest.positions.clone();
+        let prices = self.prices(ticks);
+        let equity = self
+            .backtest
+            .step(t, &prices, &Signal::arrange(&signals, &symbols));
+        let mut fills = Vec::new();
+        for (stock, (before, after)) in previous_positions
+            .iter()
+            .zip(self.backtest.positions.iter())
+            .enumerate()
+        {
+            if before == after {
+                continue;
+            }
+            let price = self.backtest.prices[stock].unwrap_or(0.0);
+            fills.push(Fill {
+                t,
+                symbol: symbols.symbol(stock).expect("One symbol per stock").clone(),
+                shares: after - before,
+                price,
+                cost: ((after - before) * price).abs() * self.backtest.config.cost,
+            });
+        }
+        self.fills.extend(fills.iter().cloned());
+        PaperStep {
+            t,
+            ticks: ticks.clone(),
+            signals,
+            fills,
+            equity,
+            pnl: equity - previous_equity,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::{SymbolRegistry, Target, TargetKind};
+    use crate::lstm::StockLSTMDesc;
+    use crate::trading::ThresholdStrategy;
+    use chrono::{Duration, NaiveDate};
+    use tch::nn::VarStore;
+    use tch::Device;
+
+    #[test]
+    fn paper_trading_buffers_bars() {
+        let desc = StockLSTMDesc {
+            stocks: 2,
+            hidden: 4,
+            layers: 1,
+            heads: vec![Target::Close],
+            target_kind: TargetKind::Return,
+            ..Default::default()
+        };
+        let vs = VarStore::new(Device::Cpu);
+        let lstm = desc.build(&vs);
+        let registry =
+            SymbolRegistry::new(vec![Symbol::from("AMD"), Symbol::from("NVDA")]).unwrap();
+        let predictor = Predictor::new(lstm, Device::Cpu, |_, _: &mut Vec<f32>| {}, 0.999, 0.999)
+            .with_symbols(registry)
+            .unwrap();
+        let mut trader = PaperTrader::new(
+            predictor,
+            ThresholdStrategy::default(),
+            BacktestConfig::default(),
+        )
+        .unwrap();
+
+        let start = NaiveDate::from_ymd(2020, 10, 12).and_hms(14, 30, 0);
+        let tick = |minute: i64, c: f64| Tick {
+            t: start + Duration::minutes(minute),
+            o: c,
+            h: c,
+            l: c,
+            c,
+            v: 100.0,
+            vw: c,
+            n: 1.0,
+        };
+        assert!(trader.on_tick(Symbol::from("AMD"), tick(0, 80.0)).is_none());
+        assert!(trader
+            .on_tick(Symbol::from("NVDA"), tick(0, 540.0))
+            .is_none());
+        let step = trader.on_tick(Symbol::from("AMD"), tick(1, 81.0)).unwrap();
+        assert_eq!(step.t, start);
+        assert_eq!(step.signals.len(), 2);
+        assert_eq!(step.pnl, 0.0);
+        // Any fills are at the bar's closes
+        for fill in step.fills.iter() {
+            let close = if fill.symbol.0 == "AMD" { 80.0 } else { 540.0 };
+            assert_eq!(fill.price, close);
+        }
+        assert!(trader
+            .on_tick(Symbol::from("NVDA"), tick(0, 541.0))
+            .is_none());
+        let step = trader.flush().unwrap();
+        assert_eq!(step.t, start + Duration::minutes(1));
+        assert!(trader.flush().is_none());
+        assert_eq!(trader.backtest.equity_curve.len(), 2);
+    }
+}
@@ -0,0 +1,174 @@
+/*!
+Replaying historical ticks through the live code path: the streaming `Predictor` and `Strategy` driven by a
+`PaperTrader`, rather than the batch path, so that discrepancies between live trading and backtests can be reproduced
+deterministically
+*/
+use super::paper::{PaperStep, PaperTrader};
+use super::write_records;
+use crate::data::{Symbol, Tick};
+use crate::trading::Strategy;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use std::collections::BTreeMap;
+use std::io::Write;
+
+/// A stream of historical ticks of many symbols, delivered one at a time in the order a live feed would deliver them:
+/// by time, and by symbol within each bar. A simulated clock tracks the time of the last tick delivered.
+#[derive(Debug, Clone)]
+pub struct Replay {
+    /// Every tick, in delivery order
+    ticks: Vec<(Symbol, Tick)>,
+    /// The index of the next tick to deliver
+    next: usize,
+}
+
+impl Replay {
+    /// Replay the ticks of each symbol
+    pub fn new(data: &BTreeMap<Symbol, Vec<Tick>>) -> Replay {
+        let mut ticks: Vec<(Symbol, Tick)> = data
+            .iter()
+            .flat_map(|(symbol, ticks)| ticks.iter().map(move |tick| (symbol.clone(), *tick)))
+            .collect();
+        // A stable sort keeps each symbol's ticks with equal timestamps in their original order
+        ticks.sort_by(|(left, left_tick), (right, right_tick)| {
+            left_tick.t.cmp(&right_tick.t).then_with(|| left.cmp(right))
+        });
+        Replay { ticks, next: 0 }
+    }
+    /// The simulated clock: the time of the last tick delivered, if any
+    pub fn now(&self) -> Option<NaiveDateTime> {
+        Some(self.ticks[..self.next].last()?.1.t)
+    }
+    /// The number of ticks left to deliver
+    pub fn remaining(&self) -> usize {
+        self.ticks.len() - self.next
+    }
+    /// Deliver every tick before a given time as bars of historical ticks to warm up a trader's predictor without
+    /// trading, as the paper trader does before going live. Returns the number of bars delivered.
+    pub fn warm_up<DF, S>(&mut self, trader: &mut PaperTrader<DF, S>, until: NaiveDateTime) -> usize
+    where
+        DF: FnMut(DateTime<Utc>, &mut Vec<f32>),
+        S: Strategy,
+    {
+        let mut bars = 0;
+        while let Some((_, first)) = self.ticks.get(self.next) {
+            let t = first.t;
+            if t >= until {
+                break;
+            }
+            let mut bar = BTreeMap::new();
+            while let Some((symbol, tick)) =
+                self.ticks.get(self.next).filter(|(_, tick)| tick.t == t)
+            {
+                bar.insert(symbol.clone(), *tick);
+                self.next += 1;
+            }
+            trader.warm_up(t, &bar);
+            bars += 1;
+        }
+        bars
+    }
+    /// Deliver every remaining tick to a trader as if it arrived live, flushing the last bar at the end of the stream,
+    /// and return the result of trading each bar
+    pub fn run<DF, S>(&mut self, trader: &mut PaperTrader<DF, S>) -> Vec<PaperStep>
+    where
+        DF: FnMut(DateTime<Utc>, &mut Vec<f32>),
+        S: Strategy,
+    {
+        let mut steps = Vec::new();
+        for (symbol, tick) in self {
+            steps.extend(trader.on_tick(symbol, tick));
+        }
+        steps.extend(trader.flush());
+        steps
+    }
+}
+
+impl Iterator for Replay {
+    type Item = (Symbol, Tick);
+
+    fn next(&mut self) -> Option<(Symbol, Tick)> {
+        let next = self.ticks.get(self.next)?.clone();
+        self.next += 1;
+        Some(next)
+    }
+}
+
+/// Write the signals emitted at each replayed bar to a Writer as CSV, for comparison against a backtest's or a live
+/// run's. On success, return how many signals were written.
+pub fn write_signals<W: Write>(wtr: W, steps: &[PaperStep]) -> Result<usize, csv::Error> {
+    write_records(wtr, steps.iter().flat_map(|step| step.signals.iter()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backtest::BacktestConfig;
+    use crate::data::{SymbolRegistry, Target, TargetKind};
+    use crate::lstm::StockLSTMDesc;
+    use crate::predict::Predictor;
+    use crate::trading::ThresholdStrategy;
+    use chrono::{Duration, NaiveDate};
+    use tch::nn::VarStore;
+    use tch::Device;
+
+    #[test]
+    fn replay_is_deterministic() {
+        let desc = StockLSTMDesc {
+            stocks: 2,
+            hidden: 4,
+            layers: 1,
+            heads: vec![Target::Close],
+            target_kind: TargetKind::Return,
+            ..Default::default()
+        };
+        let vs = VarStore::new(Device::Cpu);
+        let lstm = desc.build(&vs);
+        let registry =
+            SymbolRegistry::new(vec![Symbol::from("AMD"), Symbol::from("NVDA")]).unwrap();
+        let predictor = Predictor::new(lstm, Device::Cpu, |_, _: &mut Vec<f32>| {}, 0.999, 0.999)
+            .with_symbols(registry)
+            .unwrap();
+        let mut trader = PaperTrader::new(
+            predictor,
+            ThresholdStrategy::default(),
+            BacktestConfig::default(),
+        )
+        .unwrap();
+
+        let start = NaiveDate::from_ymd(2020, 10, 12).and_hms(14, 30, 0);
+        let ticks = |base: f64| -> Vec<Tick> {
+            (0..10)
+                .map(|minute| {
+                    let c = base + minute as f64;
+                    Tick {
+                        t: start + Duration::minutes(minute),
+                        o: c,
+                        h: c,
+                        l: c,
+                        c,
+                        v: 100.0,
+                        vw: c,
+                        n: 1.0,
+                    }
+                })
+                .collect()
+        };
+        let mut data = BTreeMap::new();
+        data.insert(Symbol::from("NVDA"), ticks(540.0));
+        data.insert(Symbol::from("AMD"), ticks(80.0)[2..].to_vec());
+
+        let mut replay = Replay::new(&data);
+        assert_eq!(replay.now(), None);
+        assert_eq!(replay.warm_up(&mut trader, start + Duration::minutes(3)), 3);
+        assert_eq!(replay.now(), Some(start + Duration::minutes(2)));
+        assert_eq!(replay.remaining(), 14);
+        let steps = replay.run(&mut trader);
+        assert_eq!(steps.len(), 7);
+        assert_eq!(steps[0].t, start + Duration::minutes(3));
+        assert!(steps.iter().all(|step| step.signals.len() == 2));
+        assert_eq!(replay.now(), Some(start + Duration::minutes(9)));
+
+        let mut written = Vec::new();
+        assert_eq!(write_signals(&mut written, &steps).unwrap(), 14);
+    }
+}
@@ -0,0 +1,161 @@
+/*!
+Performance and risk statistics for backtests
+*/
+use super::{EquityPoint, TradeRecord};
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+
+/// The number of trading minutes in a year on the NASDAQ
+pub const NASDAQ_MINUTES_PER_YEAR: f64 = 252.0 * 390.0;
+
+/// A report of the performance and risk of a backtest
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RiskReport {
+    /// The total return over the backtest
+    pub total_return: f64,
+    /// The mean return per period
+    pub mean_return: f64,
+    /// The standard deviation of returns per period
+    pub volatility: f64,
+    /// The annualized Sharpe ratio, assuming a zero risk-free rate
+    pub sharpe: f64,
+    /// The annualized Sortino ratio, assuming a zero target return
+    pub sortino: f64,
+    /// The maximum drawdown, as a fraction of the peak equity
+    pub max_drawdown: f64,
+    /// The historical value at risk per period at the report's confidence level, as a positive fraction of equity
+    pub var: f64,
+    /// The expected shortfall (conditional value at risk) per period at the report's confidence level
+    pub cvar: f64,
+    /// The confidence level used for value at risk
+    pub confidence: f64,
+    /// The total value traded divided by the average equity
+    pub turnover: f64,
+    /// The number of completed trades
+    pub trades: usize,
+    /// The fraction of completed trades with a positive profit
+    pub win_rate: f64,
+}
+
+/// Compute the per-period returns of an equity curve
+pub fn returns(equity_curve: &[EquityPoint]) -> Vec<f64> {
+    equity_curve
+        .windows(2)
+        .filter(|window| window[0].equity != 0.0)
+        .map(|window| window[1].equity / window[0].equity - 1.0)
+        .collect()
+}
+
+/// Compute the maximum drawdown of an equity curve, as a fraction of the peak equity
+pub fn max_drawdown(equity_curve: &[EquityPoint]) -> f64 {
+    let mut peak = f64::NEG_INFINITY;
+    let mut drawdown: f64 = 0.0;
+    for point in equity_curve {
+        peak = peak.max(point.equity);
+        if peak > 0.0 {
+            drawdown = drawdown.max(1.0 - point.equity / peak);
+        }
+    }
+    drawdown
+}
+
+/// Compute the historical value at risk and expected shortfall of a set of returns at a confidence level, as
+/// positive fractions
+pub fn value_at_risk(returns: &[f64], confidence: f64) -> (f64, f64) {
+    if returns.is_empty() {
+        return (0.0, 0.0);
+    }
+    let mut sorted = returns.to_vec();
+    sorted.sort_by(|l, r| l.partial_cmp(r).unwrap_or(Ordering::Equal));
+    let tail = (((1.0 - confidence) * sorted.len() as f64).ceil() as usize).max(1);
+    let var = -sorted[tail - 1];
+    let cvar = -sorted[..tail].iter().sum::<f64>() / tail as f64;
+    (var, cvar)
+}
+
+impl RiskReport {
+    /// Compute a risk report from an equity curve and trade log, given the number of equity curve periods per year
+    /// and the confidence level for value at risk
+    pub fn new(
+        equity_curve: &[EquityPoint],
+        trades: &[TradeRecord],
+        periods_per_year: f64,
+        confidence: f64,
+    ) -> RiskReport {
+        let returns = returns(equity_curve);
+        let n = returns.len().max(1) as f64;
+        let mean_return = returns.iter().sum::<f64>() / n;
+        let volatility = (returns
+            .iter()
+            .map(|r| (r - mean_return).powi(2))
+            .sum::<f64>()
+            / n)
+            .sqrt();
+        let downside = (returns.iter().map(|r| r.min(0.0).powi(2)).sum::<f64>() / n).sqrt();
+        let annualize = periods_per_year.sqrt();
+        let ratio = |deviation: f64| {
+            if deviation > 0.0 {
+                mean_return / deviation * annualize
+            } else {
+                0.0
+            }
+        };
+        let total_return = match (equity_curve.first(), equity_curve.last()) {
+            (Some(first), Some(last)) if first.equity != 0.0 => last.equity / first.equity - 1.0,
+            _ => 0.0,
+        };
+        let (var, cvar) = value_at_risk(&returns, confidence);
+        let average_equity = equity_curve.iter().map(|point| point.equity).sum::<f64>()
+            / equity_curve.len().max(1) as f64;
+        let traded: f64 = trades
+            .iter()
+            .map(|trade| trade.size.abs() * (trade.entry_price + trade.exit_price))
+            .sum();
+        let turnover = if average_equity > 0.0 {
+            traded / average_equity
+        } else {
+            0.0
+        };
+        let wins = trades.iter().filter(|trade| trade.pnl > 0.0).count();
+        RiskReport {
+            total_return,
+            mean_return,
+            volatility,
+            sharpe: ratio(volatility),
+            sortino: ratio(downside),
+            max_drawdown: max_drawdown(equity_curve),
+            var,
+            cvar,
+            confidence,
+            turnover,
+            trades: trades.len(),
+            win_rate: wins as f64 / trades.len().max(1) as f64,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, NaiveDate};
+
+    #[test]
+    fn drawdown_and_var() {
+        let t = NaiveDate::from_ymd(2020, 10, 12).and_hms(14, 30, 0);
+        let curve: Vec<EquityPoint> = [100.0, 120.0, 90.0, 110.0, 99.0]
+            .iter()
+            .enumerate()
+            .map(|(i, &equity)| EquityPoint {
+                t: t + Duration::minutes(i as i64),
+                equity,
+                cash: equity,
+                gross: 0.0,
+            })
+            .collect();
+        assert!((max_drawdown(&curve) - 0.25).abs() < 1e-12);
+        let report = RiskReport::new(&curve, &[], NASDAQ_MINUTES_PER_YEAR, 0.75);
+        assert!((report.total_return + 0.01).abs() < 1e-12);
+        assert!((report.var - 0.25).abs() < 1e-12);
+        assert_eq!(report.trades, 0);
+    }
+}
@@ -0,0 +1,426 @@
+/*!
+A C API for embedding a `Predictor` in other programs, enabled by the `capi` feature.
+
+All functions are `extern "C"` and operate on an opaque `StockburnPredictor` handle. Functions returning a `c_int`
+return zero on success and a negative value on failure, in which case `stockburn_last_error` describes the failure.
+When building with the `capi` feature, a C header for this API is generated as `stockburn.h` in the build script's
+output directory, and also copied into the directory named by the `STOCKBURN_HEADER_DIR` environment variable, if set.
+The library itself is only built as a Rust library by default; a shared or static C library can be built with e.g.
+`cargo rustc --release --lib --features capi --crate-type cdylib` (or `--crate-type staticlib`).
+*/
+use crate::data::{default_clock_periods, push_clock_period, Target, Tick};
+use crate::lstm::StockLSTMDesc;
+use crate::predict::Predictor;
+use crate::train::{load_weights, read_preprocessing};
+use crate::util::to_ns;
+use anyhow::format_err;
+use chrono::{DateTime, Duration, NaiveDateTime, Utc};
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int};
+use std::ptr;
+use tch::nn::VarStore;
+use tch::Device;
+
+/// The return code for a successful call
+pub const STOCKBURN_OK: c_int = 0;
+
+/// The return code for a call with an invalid argument, e.g. a null pointer or an out of range stock
+pub const STOCKBURN_INVALID_ARGUMENT: c_int = -1;
+
+/// The return code for a call which failed while loading weights or running the model
+pub const STOCKBURN_FAILURE: c_int = -2;
+
+/// The `Target` code for closing price heads
+pub const STOCKBURN_TARGET_CLOSE: u32 = 0;
+
+/// The `Target` code for volume heads
+pub const STOCKBURN_TARGET_VOLUME: u32 = 1;
+
+/// The `Target` code for volatility heads
+pub const STOCKBURN_TARGET_VOLATILITY: u32 = 2;
+
+/// The `Target` code for opening price heads
+pub const STOCKBURN_TARGET_OPEN: u32 = 3;
+
+/// The `Target` code for high price heads
+pub const STOCKBURN_TARGET_HIGH: u32 = 4;
+
+/// The `Target` code for low price heads
+pub const STOCKBURN_TARGET_LOW: u32 = 5;
+
+/// The `Target` code for volume weighted average price heads
+pub const STOCKBURN_TARGET_VWAP: u32 = 6;
+
+/// The `Target` code for trade count heads
+pub const STOCKBURN_TARGET_TRADES: u32 = 7;
+
+/// The `Target` code for true range heads
+pub const STOCKBURN_TARGET_TRUE_RANGE: u32 = 8;
+
+/// The time function used by predictors created through the C API: default clocks for the predictor's interval
+type ClockFunc = Box<dyn FnMut(DateTime<Utc>, &mut Vec<f32>)>;
+
+/// An opaque predictor handle, holding a model, its weights, its streaming state and the ticks pushed for the next
+/// timestep
+pub struct StockburnPredictor {
+    /// The variables of the model
+    vs: VarStore,
+    /// The underlying predictor
+    predictor: Predictor<ClockFunc>,
+    /// The ticks pushed for each stock since the last prediction
+    pending: Vec<Option<Tick>>,
+}
+
+thread_local! {
+    /// The last error which occured on this thread
+    static LAST_ERROR: RefCell<Option<CString>> = RefCell::new(None);
+}
+
+/// Record an error for `stockburn_last_error`, returning a given error code
+fn fail(code: c_int, message: impl Into<String>) -> c_int {
+    let message = CString::new(message.into().replace('\0', " ")).expect("Nul bytes removed");
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+    code
+}
+
+/// Convert a timestamp in nanoseconds since the Unix epoch to a `NaiveDateTime`
+fn from_ns(t_ns: i64) -> NaiveDateTime {
+    let (secs, nanos) = (
+        t_ns.div_euclid(1_000_000_000),
+        t_ns.rem_euclid(1_000_000_000),
+    );
+    NaiveDateTime::from_timestamp(secs, nanos as u32)
+}
+
+/// Get a description of the last error which occured on the calling thread, or null if none has. The returned string
+/// is owned by the library, and is valid until the next failing call on the same thread.
+#[no_mangle]
+pub extern "C" fn stockburn_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| {
+        last.borrow()
+            .as_ref()
+            .map_or(ptr::null(), |message| message.as_ptr())
+    })
+}
+
+/// Create a predictor for a freshly initialized model, returning null on failure. Heads are given as an array of
+/// `n_heads` target codes (`STOCKBURN_TARGET_*`), and date inputs are the default clocks for ticks `interval_secs`
+/// seconds apart. The predictor runs on the first CUDA device if `use_cuda` is nonzero and one is available. Weights
+/// should be loaded with `stockburn_predictor_load` before predicting.
+///
+/// # Safety
+/// `heads` must point to `n_heads` readable values, or may be null if `n_heads` is zero.
+#[no_mangle]
+#[allow(clippy::too_many_arguments)]
+pub unsafe extern "C" fn stockburn_predictor_new(
+    stocks: usize,
+    additional_inputs: usize,
+    hidden: usize,
+    layers: usize,
+    heads: *const u32,
+    n_heads: usize,
+    interval_secs: u32,
+    average_decay: f64,
+    range_decay: f64,
+    use_cuda: c_int,
+) -> *mut StockburnPredictor {
+    if stocks == 0 || hidden == 0 || layers == 0 || interval_secs == 0 {
+        fail(
+            STOCKBURN_INVALID_ARGUMENT,
+            "Stocks, hidden size, layers and interval must be nonzero",
+        );
+        return ptr::null_mut();
+    }
+    if heads.is_null() && n_heads != 0 {
+        fail(STOCKBURN_INVALID_ARGUMENT, "Null heads");
+        return ptr::null_mut();
+    }
+    let codes = if n_heads == 0 {
+        &[][..]
+    } else {
+        std::slice::from_raw_parts(heads, n_heads)
+    };
+    let mut targets = Vec::with_capacity(codes.len());
+    for code in codes {
+        targets.push(match *code {
+            STOCKBURN_TARGET_CLOSE => Target::Close,
+            STOCKBURN_TARGET_VOLUME => Target::Volume,
+            STOCKBURN_TARGET_VOLATILITY => Target::Volatility,
+            STOCKBURN_TARGET_OPEN => Target::Open,
+            STOCKBURN_TARGET_HIGH => Target::High,
+            STOCKBURN_TARGET_LOW => Target::Low,
+            STOCKBURN_TARGET_VWAP => Target::Vwap,
+            STOCKBURN_TARGET_TRADES => Target::Trades,
+            STOCKBURN_TARGET_TRUE_RANGE => Target::TrueRange,
+            code => {
+                fail(
+                    STOCKBURN_INVALID_ARGUMENT,
+                    format!("Unknown target code {}", code),
+                );
+                return ptr::null_mut();
+            }
+        })
+    }
+    let device = if use_cuda != 0 {
+        Device::cuda_if_available()
+    } else {
+        Device::Cpu
+    };
+    // The clocks of `data::clocks`, with each period converted to nanoseconds per radian once rather than per call
+    let periods: Vec<f32> = default_clock_periods(Duration::seconds(interval_secs as i64))
+        .into_iter()
+        .map(|period| to_ns::<f32>(period) / (2.0 * std::f32::consts::PI))
+        .collect();
+    let date_inputs = periods.len() * 2;
+    let time_func: ClockFunc = Box::new(move |t, dest| {
+        for &period in periods.iter() {
+            push_clock_period(period, t, dest)
+        }
+    });
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        let vs = VarStore::new(device);
+        let lstm = StockLSTMDesc {
+            additional_inputs,
+            date_inputs,
+            stocks,
+            hidden,
+            layers,
+            heads: targets,
+            ..Default::default()
+        }
+        .build(&vs);
+        let predictor = Predictor::new(lstm, device, time_func, average_decay, range_decay);
+        StockburnPredictor {
+            vs,
+            predictor,
+            pending: vec![None; stocks],
+        }
+    }));
+    match result {
+        Ok(predictor) => Box::into_raw(Box::new(predictor)),
+        Err(_) => {
+            fail(STOCKBURN_FAILURE, "Building the model failed");
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Load model weights saved by a `VarStore` from a file, resetting the predictor's state. Weights which do not fit the
+/// predictor's model are rejected, with `stockburn_last_error` describing each mismatch. If the weights were saved
+/// with their preprocessing by `train::save_checkpoint`, ticks are then scaled and quantile transformed as in training
+/// rather than with the decays the predictor was created with.
+///
+/// # Safety
+/// `predictor` must be a live handle returned by `stockburn_predictor_new`, and `path` a nul-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn stockburn_predictor_load(
+    predictor: *mut StockburnPredictor,
+    path: *const c_char,
+) -> c_int {
+    let predictor = match predictor.as_mut() {
+        Some(predictor) => predictor,
+        None => return fail(STOCKBURN_INVALID_ARGUMENT, "Null predictor"),
+    };
+    if path.is_null() {
+        return fail(STOCKBURN_INVALID_ARGUMENT, "Null path");
+    }
+    let path = match CStr::from_ptr(path).to_str() {
+        Ok(path) => path,
+        Err(err) => return fail(STOCKBURN_INVALID_ARGUMENT, format!("Invalid path: {}", err)),
+    };
+    let result =
+        std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| -> anyhow::Result<()> {
+            load_weights(&mut predictor.vs, &predictor.predictor.lstm, path)?;
+            if let Some(preprocessing) = read_preprocessing(path)? {
+                if let Some(quantiles) = &preprocessing.quantiles {
+                    if quantiles.features() != Tick::NN_FIELDS {
+                        return Err(format_err!(
+                            "Quantile transform has {} features, but ticks have {}",
+                            quantiles.features(),
+                            Tick::NN_FIELDS
+                        ));
+                    }
+                }
+                predictor.predictor.scaler_config = Some(preprocessing.scaler);
+                predictor.predictor.quantiles = preprocessing.quantiles;
+            }
+            Ok(())
+        }));
+    match result {
+        Ok(Ok(())) => {}
+        Ok(Err(err)) => return fail(STOCKBURN_FAILURE, err.to_string()),
+        Err(_) => return fail(STOCKBURN_FAILURE, "Loading weights failed"),
+    }
+    predictor.predictor.reset();
+    for tick in predictor.pending.iter_mut() {
+        *tick = None;
+    }
+    STOCKBURN_OK
+}
+
+/// Push a raw tick for a stock, to be fed in at the next call to `stockburn_predictor_predict`. Pushing a second tick
+/// for the same stock before then replaces the first. The tick's time is given in nanoseconds since the Unix epoch.
+///
+/// # Safety
+/// `predictor` must be a live handle returned by `stockburn_predictor_new`.
+#[no_mangle]
+#[allow(clippy::too_many_arguments)]
+pub unsafe extern "C" fn stockburn_predictor_push_tick(
+    predictor: *mut StockburnPredictor,
+    stock: usize,
+    t_ns: i64,
+    v: f64,
+    vw: f64,
+    o: f64,
+    c: f64,
+    h: f64,
+    l: f64,
+    n: f64,
+) -> c_int {
+    let predictor = match predictor.as_mut() {
+        Some(predictor) => predictor,
+        None => return fail(STOCKBURN_INVALID_ARGUMENT, "Null predictor"),
+    };
+    let stocks = predictor.pending.len();
+    let pending = match predictor.pending.get_mut(stock) {
+        Some(pending) => pending,
+        None => {
+            return fail(
+                STOCKBURN_INVALID_ARGUMENT,
+                format!("Stock {} out of range for {} stocks", stock, stocks),
+            )
+        }
+    };
+    *pending = Some(Tick {
+        t: from_ns(t_ns),
+        v,
+        vw,
+        o,
+        c,
+        h,
+        l,
+        n,
+    });
+    STOCKBURN_OK
+}
+
+/// Get the number of outputs of a predictor, i.e. the number of values written by `stockburn_predictor_predict`, or
+/// zero for a null predictor
+///
+/// # Safety
+/// `predictor` must be null or a live handle returned by `stockburn_predictor_new`.
+#[no_mangle]
+pub unsafe extern "C" fn stockburn_predictor_outputs(
+    predictor: *const StockburnPredictor,
+) -> usize {
+    predictor
+        .as_ref()
+        .map_or(0, |predictor| predictor.predictor.lstm.no_outputs())
+}
+
+/// Feed in the ticks pushed since the last prediction as the timestep at time `t_ns`, in nanoseconds since the Unix
+/// epoch, writing the network's outputs, laid out as in `StockLSTM::targets`, into `out`. Stocks without a pushed tick
+/// are fed in as missing. `out_len` must be at least `stockburn_predictor_outputs`.
+///
+/// # Safety
+/// `predictor` must be a live handle returned by `stockburn_predictor_new`, and `out` must point to `out_len`
+/// writable values.
+#[no_mangle]
+pub unsafe extern "C" fn stockburn_predictor_predict(
+    predictor: *mut StockburnPredictor,
+    t_ns: i64,
+    out: *mut f32,
+    out_len: usize,
+) -> c_int {
+    let predictor = match predictor.as_mut() {
+        Some(predictor) => predictor,
+        None => return fail(STOCKBURN_INVALID_ARGUMENT, "Null predictor"),
+    };
+    if out.is_null() {
+        return fail(STOCKBURN_INVALID_ARGUMENT, "Null output buffer");
+    }
+    let outputs = stockburn_predictor_outputs(predictor);
+    if out_len < outputs {
+        return fail(
+            STOCKBURN_INVALID_ARGUMENT,
+            format!(
+                "Output buffer of length {} too short for {} outputs",
+                out_len, outputs
+            ),
+        );
+    }
+    let StockburnPredictor {
+        predictor, pending, ..
+    } = predictor;
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        predictor.push(from_ns(t_ns), pending, &[]).to_vec()
+    }));
+    for tick in pending.iter_mut() {
+        *tick = None;
+    }
+    match result {
+        Ok(output) => {
+            std::slice::from_raw_parts_mut(out, output.len()).copy_from_slice(&output);
+            STOCKBURN_OK
+        }
+        Err(_) => fail(STOCKBURN_FAILURE, "Prediction failed"),
+    }
+}
+
+/// Free a predictor. Does nothing if `predictor` is null.
+///
+/// # Safety
+/// `predictor` must be null or a live handle returned by `stockburn_predictor_new`, which must not be used again.
+#[no_mangle]
+pub unsafe extern "C" fn stockburn_predictor_free(predictor: *mut StockburnPredictor) {
+    if !predictor.is_null() {
+        drop(Box::from_raw(predictor));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn predictor_roundtrip() {
+        unsafe {
+            let heads = [STOCKBURN_TARGET_CLOSE, STOCKBURN_TARGET_VOLUME];
+            let predictor =
+                stockburn_predictor_new(2, 0, 8, 1, heads.as_ptr(), 2, 60, 0.99, 0.999, 0);
+            assert!(!predictor.is_null());
+            assert_eq!(stockburn_predictor_outputs(predictor), 4);
+            let t_ns = 1_602_509_400_000_000_000;
+            assert_eq!(
+                stockburn_predictor_push_tick(
+                    predictor, 0, t_ns, 100.0, 40.0, 40.0, 40.5, 41.0, 39.5, 10.0
+                ),
+                STOCKBURN_OK
+            );
+            assert_eq!(
+                stockburn_predictor_push_tick(
+                    predictor, 2, t_ns, 100.0, 40.0, 40.0, 40.5, 41.0, 39.5, 10.0
+                ),
+                STOCKBURN_INVALID_ARGUMENT
+            );
+            assert!(!stockburn_last_error().is_null());
+            let mut out = [f32::NAN; 4];
+            assert_eq!(
+                stockburn_predictor_predict(predictor, t_ns, out.as_mut_ptr(), 3),
+                STOCKBURN_INVALID_ARGUMENT
+            );
+            assert_eq!(
+                stockburn_predictor_predict(predictor, t_ns, out.as_mut_ptr(), 4),
+                STOCKBURN_OK
+            );
+            assert!(out.iter().all(|x| x.is_finite()));
+            let missing = CString::new("/nonexistent/weights.ot").unwrap();
+            assert_eq!(
+                stockburn_predictor_load(predictor, missing.as_ptr()),
+                STOCKBURN_FAILURE
+            );
+            stockburn_predictor_free(predictor);
+        }
+    }
+}
@@ -0,0 +1,235 @@
+/*!
+Split and dividend adjustment of historical tick data
+*/
+use super::{files::decompress, polygon::TimestampFormat, Symbol, Tick};
+use chrono::{NaiveDate, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::Read;
+
+/// A kind of corporate action affecting historical prices
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ActionKind {
+    /// A split, giving the number of new shares per old share, e.g. `4.0` for a 4-for-1 split or `0.1` for a
+    /// 1-for-10 reverse split
+    Split(f64),
+    /// A cash dividend, giving the amount paid per share
+    Dividend(f64),
+}
+
+/// A corporate action, taking effect at its ex-date
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CorporateAction {
+    /// The ex-date of this action. Ticks strictly before this time are adjusted
+    pub t: NaiveDateTime,
+    /// The kind of action
+    pub kind: ActionKind,
+}
+
+/// A record of a corporate actions CSV file
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+struct ActionRecord {
+    symbol: String,
+    date: String,
+    action: String,
+    value: f64,
+}
+
+/// Read corporate actions from a CSV file with columns `symbol, date, action, value`, where `action` is either
+/// `split` or `dividend` and `value` is the split ratio or dividend amount. Dates may be in any format supported by
+/// `TimestampFormat::detect` or `%Y-%m-%d`, in which case they are taken to be at midnight UTC.
+///
+/// Records which cannot be parsed are skipped. Each symbol's actions are sorted by ex-date.
+pub fn read_actions<R: Read>(rdr: R) -> BTreeMap<Symbol, Vec<CorporateAction>> {
+    let mut result: BTreeMap<Symbol, Vec<CorporateAction>> = BTreeMap::new();
+    let records = csv::Reader::from_reader(decompress(rdr))
+        .into_deserialize::<ActionRecord>()
+        .filter_map(|record| record.ok());
+    for record in records {
+        let date = record.date.trim();
+        let t = NaiveDate::parse_from_str(date, "%Y-%m-%d")
+            .map(|date| date.and_hms(0, 0, 0))
+            .ok()
+            .or_else(|| TimestampFormat::detect(date)?.parse(date));
+        let kind = match record.action.trim().to_lowercase().as_str() {
+            "split" => ActionKind::Split(record.value),
+            "dividend" => ActionKind::Dividend(record.value),
+            _ => continue,
+        };
+        if let Some(t) = t {
+            result
+                .entry(Symbol(record.symbol.trim().to_owned()))
+                .or_default()
+                .push(CorporateAction { t, kind });
+        }
+    }
+    for actions in result.values_mut() {
+        actions.sort_by_key(|action| action.t)
+    }
+    result
+}
+
+/// Adjust a time-sorted series of ticks for a set of corporate actions, so that prices are comparable with those
+/// after the last action.
+///
+/// For each split, prices before its ex-date are divided by the split ratio and volumes multiplied by it. For each
+/// dividend, prices before its ex-date are multiplied by `1 - dividend / close`, where `close` is the unadjusted close
+/// of the last tick before the ex-date. Trade counts are left as is.
+pub fn adjust(ticks: &mut [Tick], actions: &[CorporateAction]) {
+    let mut actions = actions.to_vec();
+    actions.sort_by(|l, r| r.t.cmp(&l.t));
+    let mut actions = actions.iter().peekable();
+    let mut price_factor = 1.0;
+    let mut volume_factor = 1.0;
+    for tick in ticks.iter_mut().rev() {
+        while let Some(action) = actions.peek() {
+            if tick.t >= action.t {
+                break;
+            }
+            match action.kind {
+                ActionKind::Split(ratio) if ratio > 0.0 => {
+                    price_factor /= ratio;
+                    volume_factor *= ratio;
+                }
+                ActionKind::Dividend(amount) if tick.c > 0.0 && amount < tick.c => {
+                    price_factor *= 1.0 - amount / tick.c;
+                }
+                _ => {}
+            }
+            actions.next();
+        }
+        tick.o *= price_factor;
+        tick.h *= price_factor;
+        tick.l *= price_factor;
+        tick.c *= price_factor;
+        tick.vw *= price_factor;
+        tick.v *= volume_factor;
+    }
+}
+
+/// Adjust every symbol's ticks for its corporate actions, if any
+pub fn adjust_all(
+    data: &mut BTreeMap<Symbol, Vec<Tick>>,
+    actions: &BTreeMap<Symbol, Vec<CorporateAction>>,
+) {
+    for (symbol, ticks) in data.iter_mut() {
+        if let Some(actions) = actions.get(symbol) {
+            adjust(ticks, actions)
+        }
+    }
+}
+
+/// Fetching corporate actions from Polygon's reference API, enabled by the `polygon-api` feature
+#[cfg(feature = "polygon-api")]
+pub mod polygon {
+    use super::*;
+    use anyhow::{format_err, Result};
+
+    /// The base URL of Polygon's API
+    pub const POLYGON_API_URL: &str = "https://api.polygon.io";
+
+    #[derive(Debug, Clone, Deserialize)]
+    struct Response<T> {
+        results: Vec<T>,
+    }
+
+    #[derive(Debug, Clone, Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct Split {
+        ex_date: String,
+        tofactor: f64,
+        forfactor: f64,
+    }
+
+    #[derive(Debug, Clone, Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct Dividend {
+        ex_date: String,
+        amount: f64,
+    }
+
+    fn get<T: serde::de::DeserializeOwned>(url: &str, api_key: &str) -> Result<Vec<T>> {
+        let response = ureq::get(url).query("apiKey", api_key).call();
+        if let Some(err) = response.synthetic_error() {
+            return Err(format_err!("Error requesting {}: {}", url, err));
+        }
+        if !response.ok() {
+            return Err(format_err!(
+                "Request to {} failed with status {}",
+                url,
+                response.status()
+            ));
+        }
+        Ok(response.into_json_deserialize::<Response<T>>()?.results)
+    }
+
+    fn ex_date(date: &str) -> Result<NaiveDateTime> {
+        Ok(NaiveDate::parse_from_str(date, "%Y-%m-%d")?.and_hms(0, 0, 0))
+    }
+
+    /// Fetch the splits and dividends of a symbol from Polygon's reference API, sorted by ex-date
+    pub fn fetch_actions(api_key: &str, symbol: &Symbol) -> Result<Vec<CorporateAction>> {
+        let mut actions = Vec::new();
+        let splits: Vec<Split> = get(
+            &format!("{}/v2/reference/splits/{}", POLYGON_API_URL, symbol),
+            api_key,
+        )?;
+        for split in splits {
+            actions.push(CorporateAction {
+                t: ex_date(&split.ex_date)?,
+                kind: ActionKind::Split(split.tofactor / split.forfactor),
+            })
+        }
+        let dividends: Vec<Dividend> = get(
+            &format!("{}/v2/reference/dividends/{}", POLYGON_API_URL, symbol),
+            api_key,
+        )?;
+        for dividend in dividends {
+            actions.push(CorporateAction {
+                t: ex_date(&dividend.ex_date)?,
+                kind: ActionKind::Dividend(dividend.amount),
+            })
+        }
+        actions.sort_by_key(|action| action.t);
+        Ok(actions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    #[test]
+    fn split_and_dividend() {
+        let data = "symbol,date,action,value
+AAPL,2020-10-13,split,4
+AAPL,2020-10-12,dividend,10
+AAPL,2020-10-14,merger,1
+";
+        let actions = read_actions(data.as_bytes());
+        let actions = &actions[&Symbol::from("AAPL")];
+        assert_eq!(actions.len(), 2);
+        let start = NaiveDate::from_ymd(2020, 10, 11).and_hms(14, 30, 0);
+        let mut ticks: Vec<Tick> = [100.0, 90.0, 22.5]
+            .iter()
+            .enumerate()
+            .map(|(i, &c)| Tick {
+                t: start + Duration::days(i as i64),
+                v: 1.0,
+                vw: c,
+                o: c,
+                c,
+                h: c,
+                l: c,
+                n: 1.0,
+            })
+            .collect();
+        adjust(&mut ticks, actions);
+        assert!((ticks[0].c - 22.5).abs() < 1e-9);
+        assert_eq!(ticks[0].v, 4.0);
+        assert!((ticks[1].c - 22.5).abs() < 1e-9);
+        assert_eq!(ticks[2].c, 22.5);
+        assert_eq!(ticks[2].v, 1.0);
+    }
+}
@@ -0,0 +1,266 @@
+/*!
+A client for the [Alpaca](https://alpaca.markets/) market data API, enabled by the `alpaca` feature
+*/
+use super::{Symbol, Tick};
+use anyhow::{format_err, Result};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::net::TcpStream;
+use tungstenite::{client::AutoStream, Message, WebSocket};
+
+/// The base URL of Alpaca's market data API
+pub const ALPACA_DATA_URL: &str = "https://data.alpaca.markets/v1";
+
+/// The URL of Alpaca's market data stream
+pub const ALPACA_STREAM_URL: &str = "wss://data.alpaca.markets/stream";
+
+/// The maximum number of bars Alpaca returns per request
+pub const ALPACA_BAR_LIMIT: usize = 1000;
+
+/// The size of the bars to request from Alpaca
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum Timeframe {
+    /// One minute bars
+    Minute,
+    /// Five minute bars
+    FiveMinutes,
+    /// Fifteen minute bars
+    FifteenMinutes,
+    /// Daily bars
+    Day,
+}
+
+impl Timeframe {
+    /// Get the name Alpaca uses for this timeframe
+    pub fn name(&self) -> &'static str {
+        match self {
+            Timeframe::Minute => "1Min",
+            Timeframe::FiveMinutes => "5Min",
+            Timeframe::FifteenMinutes => "15Min",
+            Timeframe::Day => "1D",
+        }
+    }
+}
+
+/// A bar, as returned by Alpaca's historical bars endpoint
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+struct Bar {
+    t: i64,
+    o: f64,
+    h: f64,
+    l: f64,
+    c: f64,
+    v: f64,
+}
+
+impl Bar {
+    /// Convert this bar to a tick. Alpaca's bars do not include a volume weighted average price or trade count, so
+    /// the former is estimated by the typical price and the latter is set to zero.
+    fn tick(&self) -> Option<Tick> {
+        Some(Tick {
+            t: NaiveDateTime::from_timestamp_opt(self.t, 0)?,
+            v: self.v,
+            vw: (self.h + self.l + self.c) / 3.0,
+            o: self.o,
+            c: self.c,
+            h: self.h,
+            l: self.l,
+            n: 0.0,
+        })
+    }
+}
+
+/// An aggregate minute bar, as sent by Alpaca's `AM` stream
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+struct MinuteBar {
+    #[serde(rename = "T")]
+    symbol: String,
+    v: f64,
+    vw: f64,
+    o: f64,
+    c: f64,
+    h: f64,
+    l: f64,
+    /// The start of the bar, in milliseconds since the Unix epoch
+    s: i64,
+}
+
+impl MinuteBar {
+    /// Convert this bar to a tick. The trade count is not sent, and so is set to zero.
+    fn tick(&self) -> Option<Tick> {
+        let secs = self.s.div_euclid(1000);
+        let nanos = self.s.rem_euclid(1000) * 1_000_000;
+        Some(Tick {
+            t: NaiveDateTime::from_timestamp_opt(secs, nanos as u32)?,
+            v: self.v,
+            vw: self.vw,
+            o: self.o,
+            c: self.c,
+            h: self.h,
+            l: self.l,
+            n: 0.0,
+        })
+    }
+}
+
+/// A client for Alpaca's market data API, authenticated by an API key pair
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct AlpacaClient {
+    /// The API key ID
+    pub key_id: String,
+    /// The API secret key
+    pub secret_key: String,
+    /// The base URL of the market data API
+    pub data_url: String,
+    /// The URL of the market data stream
+    pub stream_url: String,
+}
+
+impl AlpacaClient {
+    /// Create a new client from an API key pair
+    pub fn new(key_id: &str, secret_key: &str) -> AlpacaClient {
+        AlpacaClient {
+            key_id: key_id.to_owned(),
+            secret_key: secret_key.to_owned(),
+            data_url: ALPACA_DATA_URL.to_owned(),
+            stream_url: ALPACA_STREAM_URL.to_owned(),
+        }
+    }
+    /// Create a new client from the `APCA_API_KEY_ID` and `APCA_API_SECRET_KEY` environment variables
+    pub fn from_env() -> Result<AlpacaClient> {
+        let key_id = std::env::var("APCA_API_KEY_ID")?;
+        let secret_key = std::env::var("APCA_API_SECRET_KEY")?;
+        Ok(AlpacaClient::new(&key_id, &secret_key))
+    }
+    /// Fetch a single page of at most `ALPACA_BAR_LIMIT` historical bars per symbol, starting at `start`
+    fn bars_page(
+        &self,
+        symbols: &str,
+        timeframe: Timeframe,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> Result<BTreeMap<String, Vec<Bar>>> {
+        let url = format!("{}/bars/{}", self.data_url, timeframe.name());
+        let response = ureq::get(&url)
+            .set("APCA-API-KEY-ID", &self.key_id)
+            .set("APCA-API-SECRET-KEY", &self.secret_key)
+            .query("symbols", symbols)
+            .query("start", &start.to_rfc3339())
+            .query("end", &end.to_rfc3339())
+            .query("limit", &ALPACA_BAR_LIMIT.to_string())
+            .call();
+        if let Some(err) = response.synthetic_error() {
+            return Err(format_err!("Error requesting Alpaca bars: {}", err));
+        }
+        if !response.ok() {
+            return Err(format_err!(
+                "Alpaca bars request failed with status {}: {}",
+                response.status(),
+                response.into_string().unwrap_or_default()
+            ));
+        }
+        Ok(response.into_json_deserialize()?)
+    }
+    /// Fetch the historical bars of a set of symbols between two times, paging through results as necessary
+    pub fn bars(
+        &self,
+        symbols: &[Symbol],
+        timeframe: Timeframe,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> Result<BTreeMap<Symbol, Vec<Tick>>> {
+        let mut result = BTreeMap::new();
+        for symbol in symbols {
+            let mut ticks: Vec<Tick> = Vec::new();
+            let mut page_start = start;
+            loop {
+                let mut page = self.bars_page(&symbol.0, timeframe, page_start, end)?;
+                let bars = page.remove(&symbol.0).unwrap_or_default();
+                let full = bars.len() >= ALPACA_BAR_LIMIT;
+                ticks.extend(bars.iter().filter_map(Bar::tick));
+                match ticks.last() {
+                    Some(last) if full && last.t > page_start.naive_utc() => {
+                        page_start = DateTime::from_utc(last.t, Utc) + chrono::Duration::seconds(1)
+                    }
+                    _ => break,
+                }
+            }
+            result.insert(symbol.clone(), ticks);
+        }
+        Ok(result)
+    }
+    /// Connect to Alpaca's market data stream and subscribe to live minute bars for a set of symbols
+    pub fn stream_minute_bars(&self, symbols: &[Symbol]) -> Result<MinuteBarStream> {
+        let (mut socket, _response) = tungstenite::connect(self.stream_url.as_str())?;
+        socket.write_message(Message::Text(
+            json!({
+                "action": "authenticate",
+                "data": { "key_id": self.key_id, "secret_key": self.secret_key }
+            })
+            .to_string(),
+        ))?;
+        let streams: Vec<String> = symbols
+            .iter()
+            .map(|symbol| format!("AM.{}", symbol))
+            .collect();
+        socket.write_message(Message::Text(
+            json!({ "action": "listen", "data": { "streams": streams } }).to_string(),
+        ))?;
+        Ok(MinuteBarStream { socket })
+    }
+}
+
+/// A stream of live minute bars from Alpaca, yielding each bar's symbol and tick as it arrives
+#[derive(Debug)]
+pub struct MinuteBarStream {
+    socket: WebSocket<AutoStream>,
+}
+
+impl MinuteBarStream {
+    /// Get the underlying TCP stream, e.g. to set a read timeout
+    pub fn tcp_stream(&self) -> &TcpStream {
+        match self.socket.get_ref() {
+            AutoStream::Plain(stream) => stream,
+            AutoStream::Tls(stream) => stream.get_ref(),
+        }
+    }
+}
+
+impl Iterator for MinuteBarStream {
+    type Item = Result<(Symbol, Tick)>;
+
+    fn next(&mut self) -> Option<Result<(Symbol, Tick)>> {
+        loop {
+            let message = match self.socket.read_message() {
+                Ok(Message::Text(message)) => message,
+                Ok(Message::Close(_)) => return None,
+                Ok(_) => continue,
+                Err(tungstenite::Error::ConnectionClosed) => return None,
+                Err(err) => return Some(Err(err.into())),
+            };
+            let message: serde_json::Value = match serde_json::from_str(&message) {
+                Ok(message) => message,
+                Err(err) => return Some(Err(err.into())),
+            };
+            let stream = message["stream"].as_str().unwrap_or("");
+            if stream == "authorization" && message["data"]["status"] != "authorized" {
+                return Some(Err(format_err!(
+                    "Alpaca stream authorization failed: {}",
+                    message["data"]
+                )));
+            }
+            if !stream.starts_with("AM.") {
+                continue;
+            }
+            let bar: MinuteBar = match serde_json::from_value(message["data"].clone()) {
+                Ok(bar) => bar,
+                Err(err) => return Some(Err(err.into())),
+            };
+            if let Some(tick) = bar.tick() {
+                return Some(Ok((Symbol(bar.symbol), tick)));
+            }
+        }
+    }
+}
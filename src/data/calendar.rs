@@ -0,0 +1,331 @@
+/*!
+A US equity market calendar, and date inputs flagging special bars: the first and last bars of a session, the day
+before a holiday and triple witching Fridays
+*/
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveDateTime, NaiveTime, Utc, Weekday};
+
+/// The number of date inputs pushed by `session_flags`
+pub const SESSION_FLAGS: usize = 4;
+
+/// Get the date of Easter Sunday in a given year, by the anonymous Gregorian algorithm
+fn easter(year: i32) -> NaiveDate {
+    let a = year % 19;
+    let b = year / 100;
+    let c = year % 100;
+    let d = b / 4;
+    let e = b % 4;
+    let f = (b + 8) / 25;
+    let g = (b - f + 1) / 3;
+    let h = (19 * a + b - d - g + 15) % 30;
+    let i = c / 4;
+    let k = c % 4;
+    let l = (32 + 2 * e + 2 * i - h - k) % 7;
+    let m = (a + 11 * h + 22 * l) / 451;
+    let month = (h + l - 7 * m + 114) / 31;
+    let day = (h + l - 7 * m + 114) % 31 + 1;
+    NaiveDate::from_ymd(year, month as u32, day as u32)
+}
+
+/// Get the `n`th (counting from one) given weekday of a month
+fn nth_weekday(year: i32, month: u32, weekday: Weekday, n: u32) -> NaiveDate {
+    NaiveDate::from_weekday_of_month(year, month, weekday, n as u8)
+}
+
+/// Get the last given weekday of a month
+fn last_weekday(year: i32, month: u32, weekday: Weekday) -> NaiveDate {
+    let mut date = nth_weekday(year, month, weekday, 4);
+    while (date + Duration::weeks(1)).month() == month {
+        date = date + Duration::weeks(1);
+    }
+    date
+}
+
+/// Get the date a fixed-date holiday is observed on: the preceding Friday if it falls on a Saturday, or the following
+/// Monday if it falls on a Sunday
+fn observed(date: NaiveDate) -> NaiveDate {
+    match date.weekday() {
+        Weekday::Sat => date.pred(),
+        Weekday::Sun => date.succ(),
+        _ => date,
+    }
+}
+
+/// Get the US equity market (NYSE and NASDAQ) full-day holidays in a given year, sorted by date
+pub fn market_holidays(year: i32) -> Vec<NaiveDate> {
+    let mut holidays = Vec::with_capacity(10);
+    // New Year's Day is not observed on the preceding Friday when it falls on a Saturday
+    let new_year = NaiveDate::from_ymd(year, 1, 1);
+    if new_year.weekday() != Weekday::Sat {
+        holidays.push(observed(new_year));
+    }
+    holidays.push(nth_weekday(year, 1, Weekday::Mon, 3));
+    holidays.push(nth_weekday(year, 2, Weekday::Mon, 3));
+    holidays.push(easter(year) - Duration::days(2));
+    holidays.push(last_weekday(year, 5, Weekday::Mon));
+    if year >= 2022 {
+        holidays.push(observed(NaiveDate::from_ymd(year, 6, 19)));
+    }
+    holidays.push(observed(NaiveDate::from_ymd(year, 7, 4)));
+    holidays.push(nth_weekday(year, 9, Weekday::Mon, 1));
+    holidays.push(nth_weekday(year, 11, Weekday::Thu, 4));
+    holidays.push(observed(NaiveDate::from_ymd(year, 12, 25)));
+    holidays.sort();
+    holidays
+}
+
+/// Check if a date is a US equity market holiday
+pub fn is_market_holiday(date: NaiveDate) -> bool {
+    market_holidays(date.year()).contains(&date)
+}
+
+/// Check if a date is a US equity market trading day, i.e. a weekday which is not a holiday
+pub fn is_trading_day(date: NaiveDate) -> bool {
+    !matches!(date.weekday(), Weekday::Sat | Weekday::Sun) && !is_market_holiday(date)
+}
+
+/// Check if a date is a weekday before a market holiday, i.e. the market is closed on the next weekday
+pub fn is_day_before_holiday(date: NaiveDate) -> bool {
+    let mut next = date.succ();
+    while matches!(next.weekday(), Weekday::Sat | Weekday::Sun) {
+        next = next.succ();
+    }
+    is_market_holiday(next)
+}
+
+/// Check if a UTC time falls under US daylight saving time, which starts at 2 AM local time on the second Sunday of
+/// March and ends at 2 AM local time on the first Sunday of November (the first Sunday of April and the last Sunday of
+/// October before 2007)
+fn is_new_york_dst(time: NaiveDateTime) -> bool {
+    let year = time.year();
+    let (start, end) = if year >= 2007 {
+        (
+            nth_weekday(year, 3, Weekday::Sun, 2),
+            nth_weekday(year, 11, Weekday::Sun, 1),
+        )
+    } else {
+        (
+            nth_weekday(year, 4, Weekday::Sun, 1),
+            last_weekday(year, 10, Weekday::Sun),
+        )
+    };
+    // 2 AM local time is 7 AM UTC under standard time, and 6 AM UTC under daylight saving time
+    time >= start.and_hms(7, 0, 0) && time < end.and_hms(6, 0, 0)
+}
+
+/// Convert a UTC time to exchange (New York) local time
+pub fn exchange_time(time: NaiveDateTime) -> NaiveDateTime {
+    let offset = if is_new_york_dst(time) { 4 } else { 5 };
+    time - Duration::hours(offset)
+}
+
+/// Convert an exchange (New York) local time to UTC. Local times which are skipped or repeated when clocks change are
+/// all outside trading hours, and are resolved to either meaning.
+pub fn utc_time(time: NaiveDateTime) -> NaiveDateTime {
+    let standard = time + Duration::hours(5);
+    if is_new_york_dst(standard) {
+        time + Duration::hours(4)
+    } else {
+        standard
+    }
+}
+
+/// Get the exchange local opening time of the regular NASDAQ session
+pub fn regular_open() -> NaiveTime {
+    NaiveTime::from_hms(9, 30, 0)
+}
+
+/// Get the exchange local closing time of the regular NASDAQ session
+pub fn regular_close() -> NaiveTime {
+    NaiveTime::from_hms(16, 0, 0)
+}
+
+/// Get the UTC opening and closing times of the regular NASDAQ session on a given (exchange) date, or `None` if it is
+/// not a trading day
+pub fn regular_session(date: NaiveDate) -> Option<(NaiveDateTime, NaiveDateTime)> {
+    if !is_trading_day(date) {
+        return None;
+    }
+    Some((
+        utc_time(date.and_time(regular_open())),
+        utc_time(date.and_time(regular_close())),
+    ))
+}
+
+/// Check if a date is a triple witching day: the third Friday of March, June, September or December, on which stock
+/// options, stock index futures and stock index options expire together
+pub fn is_triple_witching(date: NaiveDate) -> bool {
+    date.month() % 3 == 0 && date == nth_weekday(date.year(), date.month(), Weekday::Fri, 3)
+}
+
+/// Push the `SESSION_FLAGS` binary date inputs of a bar, given its date and time of day in the session's timezone
+/// and the date flags of that date
+fn push_session_flags(
+    t: NaiveTime,
+    open: NaiveTime,
+    close: NaiveTime,
+    interval: Duration,
+    (before_holiday, triple_witching): (bool, bool),
+    dest: &mut Vec<f32>,
+) {
+    let flag = |set: bool| if set { 1.0 } else { 0.0 };
+    let first = t >= open && t - open < interval;
+    let last = t <= close && close - t < interval;
+    dest.push(flag(first));
+    dest.push(flag(last));
+    dest.push(flag(before_holiday));
+    dest.push(flag(triple_witching));
+}
+
+/// Get the date flags of a date, reusing those of the previous call if it was for the same date, since computing
+/// them builds the market holidays of the year and consecutive bars almost always share a date
+fn date_flags(date: NaiveDate, cache: &mut Option<(NaiveDate, (bool, bool))>) -> (bool, bool) {
+    match cache {
+        Some((cached, flags)) if *cached == date => *flags,
+        _ => {
+            let flags = (is_day_before_holiday(date), is_triple_witching(date));
+            *cache = Some((date, flags));
+            flags
+        }
+    }
+}
+
+/// A time function pushing `SESSION_FLAGS` binary date inputs for bars of a given length in a session with given
+/// (UTC) opening and closing times, which are `1.0` if a flag is set and `0.0` otherwise: whether the bar is the first
+/// of its session, whether it is the last, whether it is on the day before a holiday, and whether it is on a triple
+/// witching day. As for `SessionTimes`, the last bar of a session is the one starting at the closing time.
+pub fn session_flags(
+    open: NaiveTime,
+    close: NaiveTime,
+    interval: Duration,
+) -> (
+    usize,
+    impl FnMut(DateTime<Utc>, &mut Vec<f32>) + Send + Sync + Copy,
+) {
+    let mut cache = None;
+    (
+        SESSION_FLAGS,
+        move |time: DateTime<Utc>, dest: &mut Vec<f32>| {
+            let (date, t) = (time.date().naive_utc(), time.time());
+            let flags = date_flags(date, &mut cache);
+            push_session_flags(t, open, close, interval, flags, dest);
+        },
+    )
+}
+
+/// Session flags for bars of a given length during regular NASDAQ trading hours, 9:30 AM to 4 PM New York time,
+/// whatever the time of year. Unlike `session_flags`, bars are placed in their exchange local date and time.
+pub fn nasdaq_session_flags(
+    interval: Duration,
+) -> (
+    usize,
+    impl FnMut(DateTime<Utc>, &mut Vec<f32>) + Send + Sync + Copy,
+) {
+    let (open, close) = (regular_open(), regular_close());
+    let mut cache = None;
+    (
+        SESSION_FLAGS,
+        move |time: DateTime<Utc>, dest: &mut Vec<f32>| {
+            let local = exchange_time(time.naive_utc());
+            let flags = date_flags(local.date(), &mut cache);
+            push_session_flags(local.time(), open, close, interval, flags, dest);
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn holidays_2020() {
+        let holidays: Vec<_> = market_holidays(2020)
+            .into_iter()
+            .map(|date| (date.month(), date.day()))
+            .collect();
+        assert_eq!(
+            holidays,
+            vec![
+                (1, 1),
+                (1, 20),
+                (2, 17),
+                (4, 10),
+                (5, 25),
+                (7, 3),
+                (9, 7),
+                (11, 26),
+                (12, 25)
+            ]
+        );
+        assert!(!is_trading_day(NaiveDate::from_ymd(2020, 7, 3)));
+        assert!(is_day_before_holiday(NaiveDate::from_ymd(2020, 7, 2)));
+        assert!(is_day_before_holiday(NaiveDate::from_ymd(2020, 9, 4)));
+        assert!(is_triple_witching(NaiveDate::from_ymd(2020, 9, 18)));
+        assert!(!is_triple_witching(NaiveDate::from_ymd(2020, 10, 16)));
+    }
+
+    #[test]
+    fn exchange_times() {
+        let utc = |y, mo, d, h, mi| NaiveDate::from_ymd(y, mo, d).and_hms(h, mi, 0);
+        // Standard time, daylight saving time, and either side of the 2020 clock changes
+        assert_eq!(
+            exchange_time(utc(2020, 1, 6, 14, 30)),
+            utc(2020, 1, 6, 9, 30)
+        );
+        assert_eq!(
+            exchange_time(utc(2020, 9, 18, 13, 30)),
+            utc(2020, 9, 18, 9, 30)
+        );
+        assert_eq!(
+            exchange_time(utc(2020, 3, 8, 6, 59)),
+            utc(2020, 3, 8, 1, 59)
+        );
+        assert_eq!(exchange_time(utc(2020, 3, 8, 7, 0)), utc(2020, 3, 8, 3, 0));
+        assert_eq!(
+            exchange_time(utc(2020, 11, 1, 5, 59)),
+            utc(2020, 11, 1, 1, 59)
+        );
+        assert_eq!(
+            exchange_time(utc(2020, 11, 1, 6, 0)),
+            utc(2020, 11, 1, 1, 0)
+        );
+        // Before 2007, daylight saving time started in April
+        assert_eq!(
+            exchange_time(utc(2005, 3, 21, 14, 30)),
+            utc(2005, 3, 21, 9, 30)
+        );
+        assert_eq!(
+            regular_session(NaiveDate::from_ymd(2020, 9, 18)),
+            Some((utc(2020, 9, 18, 13, 30), utc(2020, 9, 18, 20, 0)))
+        );
+        assert_eq!(
+            regular_session(NaiveDate::from_ymd(2020, 12, 18)),
+            Some((utc(2020, 12, 18, 14, 30), utc(2020, 12, 18, 21, 0)))
+        );
+        assert_eq!(regular_session(NaiveDate::from_ymd(2020, 7, 3)), None);
+    }
+
+    #[test]
+    fn session_boundaries_are_flagged() {
+        let (n, mut flags) = nasdaq_session_flags(Duration::minutes(1));
+        let mut dest = Vec::new();
+        // Triple witching days under daylight saving time (September) and standard time (December)
+        for (day, offset) in &[((9, 18), 4), ((12, 18), 5)] {
+            for (h, m, expected) in &[
+                (9, 30, [1.0, 0.0]),
+                (9, 31, [0.0, 0.0]),
+                (15, 59, [0.0, 0.0]),
+                (16, 0, [0.0, 1.0]),
+            ] {
+                dest.clear();
+                flags(
+                    Utc.ymd(2020, day.0, day.1).and_hms(h + offset, *m, 0),
+                    &mut dest,
+                );
+                assert_eq!(dest.len(), n);
+                assert_eq!(dest[..2], expected[..]);
+                assert_eq!(dest[2..], [0.0, 1.0]);
+            }
+        }
+    }
+}
@@ -0,0 +1,202 @@
+/*!
+Sanitation of raw tick data, which may be unsorted, duplicated or inconsistent
+*/
+use super::Tick;
+use serde::{Deserialize, Serialize};
+
+/// How to resolve distinct ticks with the same timestamp
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum MergePolicy {
+    /// Keep the first tick, in the original order
+    KeepFirst,
+    /// Keep the last tick, in the original order
+    KeepLast,
+    /// Aggregate the ticks into a single bar, taking the first open, last close, highest high and lowest low,
+    /// summing volumes and trade counts, and volume-weighting the average price
+    Aggregate,
+}
+
+impl Default for MergePolicy {
+    fn default() -> MergePolicy {
+        MergePolicy::Aggregate
+    }
+}
+
+/// A report of the corrections made while sanitizing ticks
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Default, Serialize, Deserialize)]
+pub struct SanitizeReport {
+    /// The number of ticks which had an earlier timestamp than the tick before them
+    pub out_of_order: usize,
+    /// The number of exact duplicate ticks dropped
+    pub exact_duplicates: usize,
+    /// The number of ticks with a duplicate timestamp merged into another tick
+    pub merged: usize,
+    /// The number of ticks whose high or low was widened to contain their open and close
+    pub ohlc_fixed: usize,
+}
+
+impl SanitizeReport {
+    /// Whether any corrections were made
+    pub fn is_clean(&self) -> bool {
+        *self == SanitizeReport::default()
+    }
+}
+
+/// Check if two ticks are exact duplicates, considering NaN values equal to each other
+pub fn same_tick(left: &Tick, right: &Tick) -> bool {
+    let same = |l: f64, r: f64| l == r || (l.is_nan() && r.is_nan());
+    left.t == right.t
+        && same(left.o, right.o)
+        && same(left.h, right.h)
+        && same(left.l, right.l)
+        && same(left.c, right.c)
+        && same(left.v, right.v)
+        && same(left.vw, right.vw)
+        && same(left.n, right.n)
+}
+
+/// Merge a tick into an earlier tick with the same timestamp
+fn merge(into: &mut Tick, tick: &Tick, policy: MergePolicy) {
+    match policy {
+        MergePolicy::KeepFirst => {}
+        MergePolicy::KeepLast => *into = *tick,
+        MergePolicy::Aggregate => {
+            let v = into.v + tick.v;
+            if v > 0.0 {
+                into.vw = (into.vw * into.v + tick.vw * tick.v) / v;
+            }
+            into.v = v;
+            into.c = tick.c;
+            into.h = into.h.max(tick.h);
+            into.l = into.l.min(tick.l);
+            into.n += tick.n;
+        }
+    }
+}
+
+/// Sanitize ticks with the default merge policy. See `sanitize_with`.
+pub fn sanitize(ticks: &mut Vec<Tick>) -> SanitizeReport {
+    sanitize_with(ticks, MergePolicy::default())
+}
+
+/// Sanitize ticks in place, returning a report of the corrections made. Ticks are
+/// - sorted by timestamp, keeping ticks with equal timestamps in their original order
+/// - deduplicated, dropping exact duplicates of an earlier tick, where missing (NaN) values match each other
+/// - merged according to a merge policy where distinct ticks share a timestamp
+/// - corrected so that `l <= o, c <= h`, by widening the high and low. Missing highs and lows are left missing.
+pub fn sanitize_with(ticks: &mut Vec<Tick>, policy: MergePolicy) -> SanitizeReport {
+    let mut report = SanitizeReport {
+        out_of_order: ticks.windows(2).filter(|w| w[1].t < w[0].t).count(),
+        ..SanitizeReport::default()
+    };
+    ticks.sort_by_key(|tick| tick.t);
+    let mut sanitized: Vec<Tick> = Vec::with_capacity(ticks.len());
+    // The index in `ticks` of the first tick with the current timestamp
+    let mut group_start = 0;
+    for (i, tick) in ticks.iter().enumerate() {
+        match sanitized.last_mut() {
+            Some(last) if last.t == tick.t => {
+                if ticks[group_start..i]
+                    .iter()
+                    .any(|earlier| same_tick(earlier, tick))
+                {
+                    report.exact_duplicates += 1;
+                } else {
+                    merge(last, tick, policy);
+                    report.merged += 1;
+                }
+            }
+            _ => {
+                group_start = i;
+                sanitized.push(*tick);
+            }
+        }
+    }
+    for tick in sanitized.iter_mut() {
+        // Comparisons with missing values are false, so that they are neither widened nor counted
+        let high = tick.h.max(tick.o).max(tick.c);
+        let low = tick.l.min(tick.o).min(tick.c);
+        let (widen_high, widen_low) = (high > tick.h, low < tick.l);
+        if widen_high {
+            tick.h = high;
+        }
+        if widen_low {
+            tick.l = low;
+        }
+        if widen_high || widen_low {
+            report.ohlc_fixed += 1;
+        }
+    }
+    *ticks = sanitized;
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, NaiveDate};
+
+    #[test]
+    fn sanitation_report() {
+        let start = NaiveDate::from_ymd(2020, 10, 12).and_hms(14, 30, 0);
+        let tick = |minute: i64, c: f64, v: f64| Tick {
+            t: start + Duration::minutes(minute),
+            v,
+            vw: c,
+            o: 1.0,
+            c,
+            h: 2.0,
+            l: 1.0,
+            n: 1.0,
+        };
+        let mut ticks = vec![
+            tick(1, 1.5, 1.0),
+            tick(0, 1.5, 1.0),
+            tick(1, 1.5, 1.0),
+            tick(1, 1.8, 3.0),
+            tick(2, 3.0, 1.0),
+        ];
+        let report = sanitize(&mut ticks);
+        assert_eq!(
+            report,
+            SanitizeReport {
+                out_of_order: 1,
+                exact_duplicates: 1,
+                merged: 1,
+                ohlc_fixed: 1,
+            }
+        );
+        assert_eq!(ticks.len(), 3);
+        assert_eq!(ticks[1].v, 4.0);
+        assert_eq!(ticks[1].c, 1.8);
+        assert!((ticks[1].vw - 1.725).abs() < 1e-12);
+        assert_eq!(ticks[2].h, 3.0);
+    }
+
+    #[test]
+    fn missing_values_are_deduplicated() {
+        let t = NaiveDate::from_ymd(2020, 10, 12).and_hms(14, 30, 0);
+        let tick = Tick {
+            t,
+            o: 1.0,
+            h: f64::NAN,
+            l: 1.0,
+            c: 1.5,
+            v: 2.0,
+            vw: f64::NAN,
+            n: f64::NAN,
+        };
+        let mut ticks = vec![tick, tick, tick];
+        let report = sanitize(&mut ticks);
+        assert_eq!(
+            report,
+            SanitizeReport {
+                exact_duplicates: 2,
+                ..SanitizeReport::default()
+            }
+        );
+        assert_eq!(ticks.len(), 1);
+        assert_eq!(ticks[0].v, 2.0);
+        assert!(ticks[0].h.is_nan());
+    }
+}
@@ -0,0 +1,263 @@
+/*!
+Time-aligned tick data for many symbols
+*/
+use super::{Symbol, SymbolRegistry, Target, TargetKind, Tick};
+use crate::CpuFloat;
+use chrono::NaiveDateTime;
+use num::NumCast;
+use std::collections::BTreeMap;
+
+/// Tick data for a set of symbols, aligned to a unified time index
+#[derive(Debug, Clone, PartialEq)]
+pub struct Dataset<F = CpuFloat> {
+    /// The symbols in this dataset, in stock order
+    pub symbols: Vec<Symbol>,
+    /// Each stock's ticks, sorted by time
+    pub ticks: Vec<Vec<Tick<F>>>,
+    /// Each stock's raw ticks, one for each of its (e.g. scaled) ticks, if they differ
+    raw: Option<Vec<Vec<Tick<F>>>>,
+    /// Every time at which any stock has a tick, sorted
+    times: Vec<NaiveDateTime>,
+    /// For each time and stock, in row-major order, the index of the stock's tick at that time, if any
+    alignment: Vec<Option<usize>>,
+}
+
+impl<F: Copy> Dataset<F> {
+    /// Create a dataset from each symbol's ticks, which are sorted by time. If a symbol has several ticks at the same
+    /// time, only the first is used.
+    pub fn new(data: BTreeMap<Symbol, Vec<Tick<F>>>) -> Dataset<F> {
+        let (symbols, ticks) = data.into_iter().unzip();
+        Dataset::from_ticks(symbols, ticks)
+    }
+    /// Create a dataset from a list of symbols and their ticks, in stock order
+    pub fn from_ticks(symbols: Vec<Symbol>, mut ticks: Vec<Vec<Tick<F>>>) -> Dataset<F> {
+        assert_eq!(symbols.len(), ticks.len(), "Every stock needs a symbol!");
+        for stock in ticks.iter_mut() {
+            stock.sort_by_key(|tick| tick.t);
+        }
+        let mut times: Vec<NaiveDateTime> = ticks.iter().flatten().map(|tick| tick.t).collect();
+        times.sort();
+        times.dedup();
+        let stocks = ticks.len();
+        let mut alignment = vec![None; times.len() * stocks];
+        for (stock, stock_ticks) in ticks.iter().enumerate() {
+            let mut row = 0;
+            for (ix, tick) in stock_ticks.iter().enumerate() {
+                // Both sequences are sorted, so the row of each tick is found by a merge
+                while times[row] < tick.t {
+                    row += 1;
+                }
+                let entry = &mut alignment[row * stocks + stock];
+                if entry.is_none() {
+                    *entry = Some(ix)
+                }
+            }
+        }
+        Dataset {
+            symbols,
+            ticks,
+            raw: None,
+            times,
+            alignment,
+        }
+    }
+    /// Attach the raw ticks each stock's ticks were scaled from, one for one, which changes are computed from. Panics
+    /// if the raw ticks are not at the same times as the ticks.
+    pub fn with_raw(mut self, mut raw: Vec<Vec<Tick<F>>>) -> Dataset<F> {
+        assert_eq!(raw.len(), self.stocks(), "Every stock needs raw ticks!");
+        for (raw, ticks) in raw.iter_mut().zip(self.ticks.iter()) {
+            raw.sort_by_key(|tick| tick.t);
+            assert!(
+                raw.len() == ticks.len()
+                    && raw.iter().zip(ticks).all(|(raw, tick)| raw.t == tick.t),
+                "Raw ticks must be at the same times as the ticks!"
+            );
+        }
+        self.raw = Some(raw);
+        self
+    }
+    /// Get the number of stocks in this dataset
+    pub fn stocks(&self) -> usize {
+        self.ticks.len()
+    }
+    /// Get a registry mapping this dataset's symbols to their stock indices, returning an error if a symbol is repeated
+    pub fn registry(&self) -> anyhow::Result<SymbolRegistry> {
+        SymbolRegistry::new(self.symbols.clone())
+    }
+    /// Get the number of rows, i.e. distinct times, in this dataset
+    pub fn len(&self) -> usize {
+        self.times.len()
+    }
+    /// Whether this dataset has no rows
+    pub fn is_empty(&self) -> bool {
+        self.times.is_empty()
+    }
+    /// Get the time index of this dataset
+    pub fn times(&self) -> &[NaiveDateTime] {
+        &self.times
+    }
+    /// Get the tick of a stock in a row, if the stock has a tick at that row's time
+    pub fn tick(&self, row: usize, stock: usize) -> Option<&Tick<F>> {
+        let ix = (*self.alignment.get(row * self.stocks() + stock)?)?;
+        self.ticks[stock].get(ix)
+    }
+    /// Get the raw tick of a stock in a row, falling back to its tick if the dataset has no raw ticks
+    pub fn raw_tick(&self, row: usize, stock: usize) -> Option<&Tick<F>> {
+        let ix = (*self.alignment.get(row * self.stocks() + stock)?)?;
+        self.raw.as_ref().unwrap_or(&self.ticks)[stock].get(ix)
+    }
+    /// Whether each stock has a tick in a row
+    pub fn present(&self, row: usize) -> impl Iterator<Item = bool> + '_ {
+        (0..self.stocks()).map(move |stock| self.tick(row, stock).is_some())
+    }
+    /// Get a window of consecutive rows, truncated to the end of the dataset
+    pub fn window(&self, start: usize, len: usize) -> Window<F> {
+        let start = start.min(self.len());
+        Window {
+            dataset: self,
+            start,
+            len: len.min(self.len() - start),
+        }
+    }
+    /// Iterate over windows of `len` rows, with the start of each window `stride` rows after the last. The final
+    /// window may be shorter than `len`.
+    pub fn windows(&self, len: usize, stride: usize) -> impl Iterator<Item = Window<F>> + '_ {
+        assert!(stride > 0, "Windows must have a positive stride!");
+        (0..self.len())
+            .step_by(stride)
+            .map(move |start| self.window(start, len))
+    }
+}
+
+/// A window of consecutive rows of a dataset
+#[derive(Debug)]
+pub struct Window<'a, F = CpuFloat> {
+    /// The underlying dataset
+    pub dataset: &'a Dataset<F>,
+    /// The first row of this window
+    pub start: usize,
+    /// The number of rows in this window
+    pub len: usize,
+}
+
+impl<'a, F> Clone for Window<'a, F> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<'a, F> Copy for Window<'a, F> {}
+
+impl<'a, F: Copy> Window<'a, F> {
+    /// Get the time index of this window
+    pub fn times(&self) -> &'a [NaiveDateTime] {
+        &self.dataset.times[self.start..self.start + self.len]
+    }
+    /// Get the tick of a stock in a row of this window. Rows past the end of the window, but not the dataset, may also
+    /// be accessed, e.g. to get the targets of the last row.
+    pub fn tick(&self, row: usize, stock: usize) -> Option<&'a Tick<F>> {
+        self.dataset.tick(self.start + row, stock)
+    }
+    /// Get the raw tick of a stock in a row of this window, falling back to its tick. See `Dataset::with_raw`.
+    pub fn raw_tick(&self, row: usize, stock: usize) -> Option<&'a Tick<F>> {
+        self.dataset.raw_tick(self.start + row, stock)
+    }
+    /// Get the target of a stock in a row of this window, taken from the tick `shift` rows ahead. Levels are taken from
+    /// the ticks, so that they are predicted in the same (e.g. scaled) units as the inputs, while changes are computed
+    /// from the raw ticks, so that they are real returns.
+    pub fn target(
+        &self,
+        row: usize,
+        stock: usize,
+        shift: usize,
+        target: Target,
+        kind: TargetKind,
+    ) -> Option<f32>
+    where
+        F: NumCast,
+    {
+        if kind == TargetKind::Level {
+            self.tick(row + shift, stock)?
+                .target_of_kind(self.tick(row, stock), target, kind)
+        } else {
+            self.raw_tick(row + shift, stock)?.target_of_kind(
+                self.raw_tick(row, stock),
+                target,
+                kind,
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, NaiveDate};
+
+    #[test]
+    fn alignment() {
+        let start = NaiveDate::from_ymd(2020, 10, 12).and_hms(14, 30, 0);
+        let ticks = |minutes: &[i64]| -> Vec<Tick> {
+            minutes
+                .iter()
+                .map(|&m| Tick {
+                    t: start + Duration::minutes(m),
+                    v: m as f64,
+                    vw: 0.0,
+                    o: 0.0,
+                    c: 0.0,
+                    h: 0.0,
+                    l: 0.0,
+                    n: 0.0,
+                })
+                .collect()
+        };
+        let mut data = BTreeMap::new();
+        data.insert(Symbol::from("AMD"), ticks(&[0, 5]));
+        data.insert(Symbol::from("MSFT"), ticks(&[3, 5, 6]));
+        let dataset = Dataset::new(data);
+        assert_eq!(dataset.len(), 4);
+        assert_eq!(dataset.present(1).collect::<Vec<_>>(), vec![false, true]);
+        assert_eq!(dataset.tick(2, 0).unwrap().v, 5.0);
+        assert_eq!(dataset.tick(2, 1).unwrap().v, 5.0);
+        let windows: Vec<_> = dataset.windows(3, 2).collect();
+        assert_eq!(windows.len(), 2);
+        assert_eq!(windows[1].len, 2);
+        assert_eq!(windows[0].tick(3, 1).unwrap().v, 6.0);
+        assert!(windows[1].tick(2, 0).is_none());
+    }
+
+    #[test]
+    fn changes_are_computed_from_raw_ticks() {
+        let t = NaiveDate::from_ymd(2020, 10, 12).and_hms(14, 30, 0);
+        let tick = |minute: i64, c: f64| Tick {
+            t: t + Duration::minutes(minute),
+            v: 0.0,
+            vw: c,
+            o: c,
+            c,
+            h: c,
+            l: c,
+            n: 0.0,
+        };
+        let scaled = vec![vec![tick(0, 0.0), tick(1, 0.5)]];
+        let raw = vec![vec![tick(1, 11.0), tick(0, 10.0)]];
+        let dataset = Dataset::from_ticks(vec!["A".into()], scaled.clone());
+        let window = dataset.window(0, 1);
+        assert_eq!(
+            window.target(0, 0, 1, Target::Close, TargetKind::Return),
+            None
+        );
+        let dataset = dataset.with_raw(raw);
+        let window = dataset.window(0, 1);
+        assert_eq!(
+            window.target(0, 0, 1, Target::Close, TargetKind::Level),
+            Some(0.5)
+        );
+        let change = window
+            .target(0, 0, 1, Target::Close, TargetKind::Return)
+            .unwrap();
+        assert!((change - 0.1).abs() < 1e-6);
+        assert_eq!(window.tick(1, 0), Some(&scaled[0][1]));
+    }
+}
@@ -0,0 +1,318 @@
+/*!
+A configurable, reproducible generator of fake tick data for several correlated stocks
+*/
+use super::{
+    make_tick, HestonGen, NASDAQDays, SessionTimes, SpreadNoise, Volume, TRADING_YEAR_SECS,
+};
+use crate::data::{Dataset, Symbol, Tick};
+use chrono::{Date, DateTime, Duration, NaiveDate, NaiveDateTime, Utc};
+use rand::{distributions::Distribution, rngs::StdRng, Rng, SeedableRng};
+use rand_distr::{Normal, StandardNormal};
+use std::collections::BTreeMap;
+
+/// The number of seconds in a regular NASDAQ trading session
+pub const SESSION_SECS: f64 = 6.5 * 60.0 * 60.0;
+
+/// The volatility regime of a fake dataset, setting the Heston variance parameters of every stock
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum VolatilityRegime {
+    /// A quiet market, with an annualized volatility of about 10%
+    Calm,
+    /// A typical market, with an annualized volatility of about 20%
+    Normal,
+    /// A stressed market, with an annualized volatility of about 40% which swings violently
+    Turbulent,
+    /// Custom variance parameters
+    Custom {
+        /// The long-run mean annualized variance
+        theta: f64,
+        /// The volatility of the variance
+        xi: f64,
+    },
+}
+
+impl VolatilityRegime {
+    /// Get the long-run mean variance and volatility of variance of this regime
+    pub fn params(&self) -> (f64, f64) {
+        match *self {
+            VolatilityRegime::Calm => (0.01, 0.2),
+            VolatilityRegime::Normal => (0.04, 0.6),
+            VolatilityRegime::Turbulent => (0.16, 1.2),
+            VolatilityRegime::Custom { theta, xi } => (theta, xi),
+        }
+    }
+}
+
+impl Default for VolatilityRegime {
+    fn default() -> VolatilityRegime {
+        VolatilityRegime::Normal
+    }
+}
+
+/// A named, reusable recipe for fake bars of several stocks during NASDAQ trading hours.
+///
+/// Each stock follows a Heston stochastic volatility model with volume bursts. Price shocks are correlated through a
+/// common market factor, and prices may jump at random. Given a seed, the generated data is fully deterministic.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FakeDataBuilder {
+    /// The name of this recipe
+    pub name: String,
+    /// The symbols to generate data for
+    pub symbols: Vec<Symbol>,
+    /// The first date to generate data for. Non-trading days are skipped
+    pub start: Date<Utc>,
+    /// The length of each bar
+    pub interval: Duration,
+    /// The number of trading sessions to generate
+    pub sessions: usize,
+    /// The initial price of every stock
+    pub price: f64,
+    /// The annual drift of every stock
+    pub drift: f64,
+    /// The volatility regime
+    pub regime: VolatilityRegime,
+    /// The average number of price jumps of each stock per session
+    pub jump_rate: f64,
+    /// The standard deviation of the log return of a price jump
+    pub jump_size: f64,
+    /// The correlation between the price shocks of any two stocks, between 0 and 1
+    pub correlation: f64,
+    /// The annual rate at which log prices revert to the log of the initial price
+    pub reversion: f64,
+    /// Market-wide log returns applied to every stock at the first bar ending at or after a given time, sorted by time
+    pub shocks: Vec<(NaiveDateTime, f64)>,
+    /// Half the bid-ask spread, relative to the latent price
+    pub half_spread: f64,
+    /// The standard deviation of microstructure noise, relative to the latent price
+    pub noise: f64,
+    /// The seed of the random number generator
+    pub seed: u64,
+}
+
+impl Default for FakeDataBuilder {
+    fn default() -> FakeDataBuilder {
+        FakeDataBuilder {
+            name: String::from("default"),
+            symbols: vec![Symbol::from("FAKE")],
+            start: Date::from_utc(NaiveDate::from_ymd(2020, 10, 12), Utc),
+            interval: Duration::minutes(1),
+            sessions: 1,
+            price: 40.0,
+            drift: 0.05,
+            regime: VolatilityRegime::Normal,
+            jump_rate: 0.0,
+            jump_size: 0.0,
+            correlation: 0.0,
+            reversion: 0.0,
+            shocks: Vec::new(),
+            half_spread: 0.0,
+            noise: 0.0,
+            seed: 0,
+        }
+    }
+}
+
+impl FakeDataBuilder {
+    /// Create a new recipe with a given name and default options
+    pub fn new(name: &str) -> FakeDataBuilder {
+        FakeDataBuilder {
+            name: name.to_owned(),
+            ..FakeDataBuilder::default()
+        }
+    }
+    /// Set the symbols to generate data for
+    pub fn symbols<I, S>(mut self, symbols: I) -> FakeDataBuilder
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<Symbol>,
+    {
+        self.symbols = symbols.into_iter().map(Into::into).collect();
+        self
+    }
+    /// Generate data for `n` stocks, named `FAKE0` to `FAKE{n - 1}`
+    pub fn stocks(self, n: usize) -> FakeDataBuilder {
+        self.symbols((0..n).map(|i| Symbol(format!("FAKE{}", i))))
+    }
+    /// Set the first date to generate data for
+    pub fn start(mut self, start: NaiveDate) -> FakeDataBuilder {
+        self.start = Date::from_utc(start, Utc);
+        self
+    }
+    /// Set the length of each bar
+    pub fn interval(mut self, interval: Duration) -> FakeDataBuilder {
+        assert!(
+            interval > Duration::zero(),
+            "Bar interval must be positive!"
+        );
+        self.interval = interval;
+        self
+    }
+    /// Set the number of trading sessions to generate
+    pub fn sessions(mut self, sessions: usize) -> FakeDataBuilder {
+        self.sessions = sessions;
+        self
+    }
+    /// Set the initial price of every stock
+    pub fn price(mut self, price: f64) -> FakeDataBuilder {
+        self.price = price;
+        self
+    }
+    /// Set the annual drift of every stock
+    pub fn drift(mut self, drift: f64) -> FakeDataBuilder {
+        self.drift = drift;
+        self
+    }
+    /// Set the volatility regime
+    pub fn regime(mut self, regime: VolatilityRegime) -> FakeDataBuilder {
+        self.regime = regime;
+        self
+    }
+    /// Add price jumps, occurring `rate` times per session on average, with log returns of standard deviation `size`
+    pub fn jumps(mut self, rate: f64, size: f64) -> FakeDataBuilder {
+        self.jump_rate = rate.max(0.0);
+        self.jump_size = size.max(0.0);
+        self
+    }
+    /// Set the correlation between the price shocks of any two stocks, clamped between 0 and 1
+    pub fn correlation(mut self, correlation: f64) -> FakeDataBuilder {
+        self.correlation = correlation.max(0.0).min(1.0);
+        self
+    }
+    /// Make log prices revert to the log of the initial price at a given annual rate
+    pub fn mean_reversion(mut self, rate: f64) -> FakeDataBuilder {
+        self.reversion = rate.max(0.0);
+        self
+    }
+    /// Schedule a market-wide shock, moving every stock by a given log return at the first bar ending at or after `t`
+    pub fn shock(mut self, t: NaiveDateTime, log_return: f64) -> FakeDataBuilder {
+        self.shocks.push((t, log_return));
+        self.shocks.sort_by_key(|shock| shock.0);
+        self
+    }
+    /// Overlay a relative half-spread and microstructure noise on every price. See `SpreadNoise`.
+    pub fn spread(mut self, half_spread: f64, noise: f64) -> FakeDataBuilder {
+        self.half_spread = half_spread;
+        self.noise = noise.max(0.0);
+        self
+    }
+    /// Set the seed of the random number generator
+    pub fn seed(mut self, seed: u64) -> FakeDataBuilder {
+        self.seed = seed;
+        self
+    }
+    /// Get the bar start times of this recipe's sessions
+    pub fn times(&self) -> Vec<DateTime<Utc>> {
+        let interval = self.interval;
+        NASDAQDays(self.start)
+            .take(self.sessions)
+            .flat_map(move |date| SessionTimes::nasdaq(date, interval))
+            .collect()
+    }
+    /// Generate each symbol's ticks
+    pub fn build(&self) -> BTreeMap<Symbol, Vec<Tick>> {
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        let (theta, xi) = self.regime.params();
+        let mut gens: Vec<_> = self
+            .symbols
+            .iter()
+            .map(|_| {
+                let mut heston = HestonGen::new(StdRng::seed_from_u64(rng.gen()), self.price);
+                heston.variance = theta;
+                heston.theta = theta;
+                heston.xi = xi;
+                heston.drift = self.drift;
+                let spread = SpreadNoise::new(
+                    (),
+                    StdRng::seed_from_u64(rng.gen()),
+                    self.half_spread,
+                    self.noise,
+                );
+                (heston, spread)
+            })
+            .collect();
+        let jump = Normal::new(0.0, self.jump_size).unwrap();
+        let market_weight = self.correlation.sqrt();
+        let own_weight = (1.0 - self.correlation).sqrt();
+        let times = self.times();
+        let mut ticks = vec![Vec::with_capacity(times.len()); gens.len()];
+        let mut closes = vec![self.price; gens.len()];
+        let mut prices = vec![[0.0; 4]; gens.len()];
+        let mut volumes = vec![[Volume { v: 0.0, n: 0.0 }; 4]; gens.len()];
+        let mut shocks = self.shocks.iter().peekable();
+        for w in times.windows(2) {
+            let t = w[1].naive_utc();
+            while let Some((_, log_return)) = shocks.peek().filter(|shock| shock.0 <= t) {
+                for (heston, _) in gens.iter_mut() {
+                    heston.price *= log_return.exp();
+                }
+                shocks.next();
+            }
+            let dt = (w[1] - w[0]) / 4;
+            // Like the Heston generator, jumps only happen during trading, so overnight gaps are capped at an hour
+            let secs = dt
+                .to_std()
+                .expect("Duration out of bounds!")
+                .as_secs_f64()
+                .min(60.0 * 60.0);
+            let jump_probability = self.jump_rate * secs / SESSION_SECS;
+            let reversion = self.reversion * secs / TRADING_YEAR_SECS;
+            for quarter in 0..4 {
+                let market: f64 = rng.sample(StandardNormal);
+                for (stock, (heston, spread)) in gens.iter_mut().enumerate() {
+                    if rng.gen::<f64>() < jump_probability {
+                        heston.price *= jump.sample(&mut rng).exp();
+                    }
+                    heston.price *= (-reversion * (heston.price / self.price).ln()).exp();
+                    let own: f64 = rng.sample(StandardNormal);
+                    let (mut price, volume) =
+                        heston.next_with_shock(dt, market_weight * market + own_weight * own);
+                    spread.overlay(&mut price);
+                    prices[stock][quarter] = price;
+                    volumes[stock][quarter] = volume;
+                }
+            }
+            for (stock, ticks) in ticks.iter_mut().enumerate() {
+                ticks.push(make_tick(
+                    t,
+                    &prices[stock],
+                    &volumes[stock],
+                    &mut closes[stock],
+                ))
+            }
+        }
+        self.symbols.iter().cloned().zip(ticks).collect()
+    }
+    /// Generate a time-aligned dataset
+    pub fn dataset(&self) -> Dataset {
+        Dataset::new(self.build())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seeded_builds_are_reproducible() {
+        let builder = FakeDataBuilder::new("test")
+            .stocks(3)
+            .sessions(2)
+            .interval(Duration::minutes(5))
+            .regime(VolatilityRegime::Turbulent)
+            .jumps(2.0, 0.05)
+            .correlation(0.5)
+            .spread(0.001, 0.0001)
+            .seed(42);
+        let data = builder.build();
+        assert_eq!(data.len(), 3);
+        // Two sessions of 79 bar starts each, with a tick for every bar start but the first
+        assert!(data.values().all(|ticks| ticks.len() == 2 * 79 - 1));
+        for tick in data.values().flatten() {
+            assert!(tick.l <= tick.o && tick.o <= tick.h);
+            assert!(tick.l <= tick.c && tick.c <= tick.h);
+            assert!(tick.c > 0.0);
+        }
+        assert_eq!(data, builder.build());
+        assert_ne!(data, builder.clone().seed(43).build());
+    }
+}
@@ -0,0 +1,665 @@
+/*!
+Generate fake tick data, for testing purposes
+*/
+use super::Tick;
+use chrono::{
+    naive::{NaiveDate, NaiveDateTime},
+    Date, DateTime, Datelike, Duration, TimeZone, Timelike, Utc, Weekday,
+};
+use rand::{distributions::Distribution, Rng, thread_rng};
+use rand_distr::{Normal, Poisson, StandardNormal};
+use std::iter::Peekable;
+
+pub mod builder;
+pub mod scenarios;
+
+pub use builder::{FakeDataBuilder, VolatilityRegime};
+
+/// Generate decent looking fake minute bars using a provided RNG
+pub fn cubic_fake_ticks() -> impl Iterator<Item = Tick> {
+    fake_ticks(Duration::minutes(1))
+}
+
+/// Generate decent looking fake bars of a given length, e.g. between one second and one hour, during NASDAQ trading
+/// hours. The price jitter is scaled with the square root of the bar length, so that prices at every bar length
+/// follow the same random walk.
+pub fn fake_ticks(interval: Duration) -> impl Iterator<Item = Tick> {
+    let minutes = interval
+        .to_std()
+        .expect("Bar interval out of bounds!")
+        .as_secs_f64()
+        / 60.0;
+    let price_gen = DistGen2 {
+        rng: thread_rng(),
+        price: 40.0,
+        jitter: Normal::new(0.0, 0.1 * minutes.sqrt()).unwrap(),
+        vel: 1e-7,
+        acc: 1e-15,
+        jerk: Normal::new(0.0, 1e-19).unwrap(),
+    };
+    let volume_gen = VolumeGen {
+        rng: thread_rng(),
+        average: Normal::new(200.0, 100.0).unwrap(),
+        no_trades: Normal::new(0.03, 0.05).unwrap(),
+    };
+    let time_gen = NASDAQDays(Date::from_utc(NaiveDate::from_ymd(2020, 10, 10), Utc))
+        .map(move |date| SessionTimes::nasdaq(date, interval))
+        .flatten()
+        .peekable();
+    TickGen {
+        price_gen,
+        volume_gen,
+        time_gen,
+        close: 0.0,
+    }
+}
+
+/// Generate tick data using a price generator, volume generator and a time generator
+#[derive(Debug, Clone)]
+pub struct TickGen<D, P, V>
+where
+    D: Iterator<Item = DateTime<Utc>>,
+    P: TimedGen<Item = f64>,
+    V: TimedGen<Item = Volume>,
+{
+    /// The time generator in use
+    pub time_gen: Peekable<D>,
+    /// The price generator in use
+    pub price_gen: P,
+    /// The volume generator in use
+    pub volume_gen: V,
+    /// The previous closing price
+    pub close: f64,
+}
+
+/// A volume, number-of-trades pair
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Volume {
+    /// The volume of a tick
+    pub v: f64,
+    /// The number of trades of a tick
+    pub n: f64,
+}
+
+impl<D, P, V> Iterator for TickGen<D, P, V>
+where
+    D: Iterator<Item = DateTime<Utc>>,
+    P: TimedGen<Item = f64>,
+    V: TimedGen<Item = Volume>,
+{
+    type Item = Tick<f64>;
+    fn next(&mut self) -> Option<Tick<f64>> {
+        let old_time = self.time_gen.next()?;
+        let t = *self.time_gen.peek()?;
+        let dt = t - old_time;
+        let volumes = [
+            self.volume_gen.next_after(dt / 4)?,
+            self.volume_gen.next_after(dt / 4)?,
+            self.volume_gen.next_after(dt / 4)?,
+            self.volume_gen.next_after(dt / 4)?,
+        ];
+        if volumes.iter().map(|v| v.v).sum::<f64>() == 0.0 {
+            // Zero volume special case
+            return Some(flat_tick(t.naive_utc(), self.close));
+        }
+        let prices = [
+            self.price_gen.next_after(dt / 4)?,
+            self.price_gen.next_after(dt / 4)?,
+            self.price_gen.next_after(dt / 4)?,
+            self.price_gen.next_after(dt / 4)?,
+        ];
+        Some(make_tick(t.naive_utc(), &prices, &volumes, &mut self.close))
+    }
+}
+
+/// Make a tick with zero volume, with every price at the previous close
+fn flat_tick(t: NaiveDateTime, close: f64) -> Tick<f64> {
+    Tick {
+        t,
+        v: 0.0,
+        vw: close,
+        o: close,
+        h: close,
+        l: close,
+        c: close,
+        n: 0.0,
+    }
+}
+
+/// Make a tick from the prices and volumes of each quarter of its duration, updating the previous close
+fn make_tick(
+    t: NaiveDateTime,
+    prices: &[f64; 4],
+    volumes: &[Volume; 4],
+    close: &mut f64,
+) -> Tick<f64> {
+    use std::cmp::Ordering::*;
+    let v = volumes.iter().map(|v| v.v).sum();
+    if v == 0.0 {
+        return flat_tick(t, *close);
+    }
+    let o = prices[0];
+    let h = *prices
+        .iter()
+        .max_by(|l, r| l.partial_cmp(r).unwrap_or(Less))
+        .expect("Nonempty");
+    let l = *prices
+        .iter()
+        .min_by(|l, r| l.partial_cmp(r).unwrap_or(Greater))
+        .expect("Nonempty");
+    let c = prices[3];
+    *close = c;
+    let mut vw: f64 = volumes
+        .iter()
+        .zip(prices.iter())
+        .map(|(v, p)| v.v * p)
+        .sum();
+    vw /= v;
+    let n = volumes.iter().map(|v| v.n).sum();
+    Tick {
+        t,
+        o,
+        h,
+        l,
+        c,
+        v,
+        vw,
+        n,
+    }
+}
+
+/// A trait implemented by timed generators
+pub trait TimedGen {
+    /// The this object generates
+    type Item;
+    /// Generate a value, jumping forward a given duration
+    fn next_after(&mut self, after: Duration) -> Option<Self::Item>;
+}
+
+/// Generate numbers using a time-weighted second-order random walk
+#[derive(Debug, Copy, Clone)]
+pub struct DistGen2<R, P, J> {
+    /// The RNG used by this random walk
+    pub rng: R,
+    /// The current price
+    pub price: f64,
+    /// The price jitter distribution
+    pub jitter: P,
+    /// The current price velocity
+    pub vel: f64,
+    /// The current price acceleration
+    pub acc: f64,
+    /// The jerk distribution
+    pub jerk: J,
+}
+
+impl<R, P, J> TimedGen for DistGen2<R, P, J>
+where
+    R: Rng,
+    P: Distribution<f64>,
+    J: Distribution<f64>,
+{
+    type Item = f64;
+    fn next_after(&mut self, after: Duration) -> Option<f64> {
+        let after = after
+            .to_std()
+            .expect("Duration out of bounds!")
+            .as_secs_f64();
+        self.acc += after * self.jerk.sample(&mut self.rng);
+        self.vel += after * self.acc;
+        self.price += after * self.vel + self.jitter.sample(&mut self.rng);
+        Some(self.price)
+    }
+}
+
+/// Generate random volumes by generating random numbers of trades (given a number of seconds),
+/// and then generating random average trade sizes
+#[derive(Debug, Copy, Clone)]
+pub struct VolumeGen<R, N, A> {
+    /// The RNG used to generate these values
+    pub rng: R,
+    /// The distribution for the average trade size
+    pub average: A,
+    /// The distribution for the average number of trades / second
+    pub no_trades: N,
+}
+
+impl<R, N, A> TimedGen for VolumeGen<R, N, A>
+where
+    R: Rng,
+    N: Distribution<f64>,
+    A: Distribution<f64>,
+{
+    type Item = Volume;
+    fn next_after(&mut self, after: Duration) -> Option<Volume> {
+        let after = after
+            .to_std()
+            .expect("Duration out of bounds!")
+            .as_secs_f64();
+        let no_trades = self.no_trades.sample(&mut self.rng).max(0.0) * after;
+        let volume = no_trades * self.average.sample(&mut self.rng).max(0.0);
+        let n = no_trades.round();
+        let v = if n == 0.0 { 0.0 } else { volume };
+        Some(Volume { v, n })
+    }
+}
+
+/// The number of seconds in a trading year of 252 days of 6.5 hours, used to express stochastic volatility parameters
+/// in annual terms
+pub const TRADING_YEAR_SECS: f64 = 252.0 * 6.5 * 60.0 * 60.0;
+
+/// Generate prices using the Heston stochastic volatility model, together with volumes whose rate of trading rises
+/// with volatility, producing volatility clustering and correlated volume bursts.
+///
+/// Rates are annual, with time measured in trading years of `TRADING_YEAR_SECS` seconds. Gaps of more than an hour,
+/// such as overnight and over weekends, are treated as an hour, so that prices only move while trading.
+#[derive(Debug, Copy, Clone)]
+pub struct HestonGen<R, A> {
+    /// The RNG used by this generator
+    pub rng: R,
+    /// The current price
+    pub price: f64,
+    /// The current annualized variance of returns
+    pub variance: f64,
+    /// The annual drift of the price
+    pub drift: f64,
+    /// The rate at which the variance reverts to its long-run mean
+    pub kappa: f64,
+    /// The long-run mean variance
+    pub theta: f64,
+    /// The volatility of the variance
+    pub xi: f64,
+    /// The correlation between price and variance shocks, typically negative
+    pub rho: f64,
+    /// The average number of trades per second when the variance is at its long-run mean
+    pub trade_rate: f64,
+    /// The elasticity of the trade rate with respect to the variance relative to its long-run mean
+    pub burst: f64,
+    /// The distribution for the average trade size
+    pub average: A,
+}
+
+impl<R: Rng> HestonGen<R, Normal<f64>> {
+    /// Create a new Heston generator with typical parameters for a large-cap stock, starting at its long-run variance
+    pub fn new(rng: R, price: f64) -> HestonGen<R, Normal<f64>> {
+        HestonGen {
+            rng,
+            price,
+            variance: 0.04,
+            drift: 0.05,
+            kappa: 3.0,
+            theta: 0.04,
+            xi: 0.6,
+            rho: -0.7,
+            trade_rate: 0.5,
+            burst: 1.0,
+            average: Normal::new(200.0, 100.0).unwrap(),
+        }
+    }
+}
+
+impl<R, A> TimedGen for HestonGen<R, A>
+where
+    R: Rng,
+    A: Distribution<f64>,
+{
+    type Item = (f64, Volume);
+    fn next_after(&mut self, after: Duration) -> Option<(f64, Volume)> {
+        let z_price = self.rng.sample(StandardNormal);
+        Some(self.next_with_shock(after, z_price))
+    }
+}
+
+impl<R, A> HestonGen<R, A>
+where
+    R: Rng,
+    A: Distribution<f64>,
+{
+    /// Generate a price and volume, jumping forward a given duration, using a given standard normal price shock.
+    /// Sharing part of the price shock between generators correlates their returns.
+    pub fn next_with_shock(&mut self, after: Duration, z_price: f64) -> (f64, Volume) {
+        let secs = after
+            .to_std()
+            .expect("Duration out of bounds!")
+            .as_secs_f64()
+            .min(60.0 * 60.0);
+        let dt = secs / TRADING_YEAR_SECS;
+        let z_independent: f64 = self.rng.sample(StandardNormal);
+        let z_variance = self.rho * z_price + (1.0 - self.rho * self.rho).sqrt() * z_independent;
+        // Full truncation Euler scheme: negative variances are treated as zero
+        let variance = self.variance.max(0.0);
+        self.price *= ((self.drift - variance / 2.0) * dt + (variance * dt).sqrt() * z_price).exp();
+        self.variance += self.kappa * (self.theta - variance) * dt
+            + self.xi * (variance * dt).sqrt() * z_variance;
+        let relative = if self.theta > 0.0 {
+            (self.variance.max(0.0) / self.theta).powf(self.burst)
+        } else {
+            1.0
+        };
+        let rate = self.trade_rate * relative * secs;
+        let n = if rate > 0.0 {
+            let rng = &mut self.rng;
+            Poisson::new(rate)
+                .map(|poisson| -> u64 { poisson.sample(rng) })
+                .unwrap_or(0) as f64
+        } else {
+            0.0
+        };
+        let v = if n == 0.0 {
+            0.0
+        } else {
+            n * self.average.sample(&mut self.rng).max(1.0)
+        };
+        (self.price, Volume { v, n })
+    }
+}
+
+/// Generate tick data using a joint price and volume generator and a time generator
+#[derive(Debug, Clone)]
+pub struct JointTickGen<D, G>
+where
+    D: Iterator<Item = DateTime<Utc>>,
+    G: TimedGen<Item = (f64, Volume)>,
+{
+    /// The time generator in use
+    pub time_gen: Peekable<D>,
+    /// The joint price and volume generator in use
+    pub gen: G,
+    /// The previous closing price
+    pub close: f64,
+}
+
+impl<D, G> Iterator for JointTickGen<D, G>
+where
+    D: Iterator<Item = DateTime<Utc>>,
+    G: TimedGen<Item = (f64, Volume)>,
+{
+    type Item = Tick<f64>;
+    fn next(&mut self) -> Option<Tick<f64>> {
+        let old_time = self.time_gen.next()?;
+        let t = *self.time_gen.peek()?;
+        let dt = t - old_time;
+        let quarters = [
+            self.gen.next_after(dt / 4)?,
+            self.gen.next_after(dt / 4)?,
+            self.gen.next_after(dt / 4)?,
+            self.gen.next_after(dt / 4)?,
+        ];
+        let prices = [quarters[0].0, quarters[1].0, quarters[2].0, quarters[3].0];
+        let volumes = [quarters[0].1, quarters[1].1, quarters[2].1, quarters[3].1];
+        Some(make_tick(t.naive_utc(), &prices, &volumes, &mut self.close))
+    }
+}
+
+/// Generate fake bars of a given length during NASDAQ trading hours using the Heston stochastic volatility model
+pub fn heston_fake_ticks(interval: Duration) -> impl Iterator<Item = Tick> {
+    let gen = HestonGen::new(thread_rng(), 40.0);
+    let time_gen = NASDAQDays(Date::from_utc(NaiveDate::from_ymd(2020, 10, 10), Utc))
+        .map(move |date| SessionTimes::nasdaq(date, interval))
+        .flatten()
+        .peekable();
+    JointTickGen {
+        time_gen,
+        close: gen.price,
+        gen,
+    }
+}
+
+/// A generated value containing a price, which microstructure noise can be overlaid on
+pub trait HasPrice {
+    /// Get a mutable reference to the price
+    fn price_mut(&mut self) -> &mut f64;
+}
+
+impl HasPrice for f64 {
+    fn price_mut(&mut self) -> &mut f64 {
+        self
+    }
+}
+
+impl HasPrice for (f64, Volume) {
+    fn price_mut(&mut self) -> &mut f64 {
+        &mut self.0
+    }
+}
+
+/// Overlay a bid-ask spread and microstructure noise on the latent prices of a generator, so that each generated price
+/// is a trade at either the bid or the ask, perturbed by noise. This widens high-low ranges and makes the volume
+/// weighted average price deviate from the latent price, which remains available as ground truth.
+#[derive(Debug, Copy, Clone)]
+pub struct SpreadNoise<G, R, N> {
+    /// The generator of latent prices
+    pub gen: G,
+    /// The RNG used to pick trade sides and noise
+    pub rng: R,
+    /// Half the bid-ask spread, relative to the latent price
+    pub half_spread: f64,
+    /// The distribution of noise, relative to the latent price
+    pub noise: N,
+    /// The last latent price generated
+    pub latent: f64,
+}
+
+impl<G, R: Rng> SpreadNoise<G, R, Normal<f64>> {
+    /// Overlay a given relative half-spread and normally distributed relative noise on a generator
+    pub fn new(gen: G, rng: R, half_spread: f64, noise: f64) -> SpreadNoise<G, R, Normal<f64>> {
+        SpreadNoise {
+            gen,
+            rng,
+            half_spread,
+            noise: Normal::new(0.0, noise.max(0.0)).unwrap(),
+            latent: f64::NAN,
+        }
+    }
+}
+
+impl<G, R, N> TimedGen for SpreadNoise<G, R, N>
+where
+    G: TimedGen,
+    G::Item: HasPrice,
+    R: Rng,
+    N: Distribution<f64>,
+{
+    type Item = G::Item;
+    fn next_after(&mut self, after: Duration) -> Option<G::Item> {
+        let mut item = self.gen.next_after(after)?;
+        self.overlay(item.price_mut());
+        Some(item)
+    }
+}
+
+impl<G, R, N> SpreadNoise<G, R, N>
+where
+    R: Rng,
+    N: Distribution<f64>,
+{
+    /// Overlay the spread and noise on a latent price in place, recording it as the last latent price
+    pub fn overlay(&mut self, price: &mut f64) {
+        self.latent = *price;
+        let side = if self.rng.gen::<bool>() { 1.0 } else { -1.0 };
+        *price *= 1.0 + side * self.half_spread + self.noise.sample(&mut self.rng);
+    }
+}
+
+/// Generate NASDAQ trading days starting at a given date
+#[derive(Debug, Copy, Clone, Eq, PartialEq, PartialOrd, Ord, Hash)]
+pub struct NASDAQDays(pub Date<Utc>);
+
+/// Check if a naive UTC date is a NASDAQ trading day
+pub fn naive_utc_is_nasdaq_trading_day(date: NaiveDate) -> bool {
+    match date.weekday() {
+        Weekday::Sat => false,
+        Weekday::Sun => false,
+        _ => true,
+    }
+}
+
+/// Check if a date is a NASDAQ trading day
+#[inline]
+pub fn is_nasdaq_trading_day<Tz: TimeZone>(date: Date<Tz>) -> bool {
+    naive_utc_is_nasdaq_trading_day(date.naive_utc())
+}
+
+/// Check if a naive UTC datetime is within NASDAQ trading hours
+#[inline]
+pub fn naitve_utc_is_nasdaq_trading_time(datetime: NaiveDateTime) -> bool {
+    if !naive_utc_is_nasdaq_trading_day(datetime.date()) {
+        return false;
+    }
+    match datetime.hour() {
+        14 => datetime.minute() >= 30,
+        15..=20 => true,
+        21 => datetime.minute() == 0,
+        _ => false,
+    }
+}
+
+/// Check if a time is within NASDAQ trading hours
+pub fn is_nasdaq_trading_time<Tz: TimeZone>(datetime: DateTime<Tz>) -> bool {
+    naitve_utc_is_nasdaq_trading_time(datetime.naive_utc())
+}
+
+impl Iterator for NASDAQDays {
+    type Item = Date<Utc>;
+    fn next(&mut self) -> Option<Date<Utc>> {
+        while !is_nasdaq_trading_day(self.0) {
+            self.0 = self.0.succ()
+        }
+        let result = self.0;
+        self.0 = self.0.succ();
+        Some(result)
+    }
+}
+
+/// Generate NASDAQ trading minutes on a given date, starting at a given time. See `SessionTimes` for other bar
+/// lengths.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, PartialOrd, Ord, Hash)]
+pub struct NASDAQMinutes(pub DateTime<Utc>);
+
+impl NASDAQMinutes {
+    /// Create a new `NASDAQMinutes` iterator for a given date
+    pub fn for_date(date: Date<Utc>) -> NASDAQMinutes {
+        NASDAQMinutes(date.and_hms(14, 30, 00))
+    }
+}
+
+impl Iterator for NASDAQMinutes {
+    type Item = DateTime<Utc>;
+    fn next(&mut self) -> Option<DateTime<Utc>> {
+        if !is_nasdaq_trading_time(self.0) {
+            return None;
+        }
+        let result = self.0;
+        self.0 = self.0 + Duration::minutes(1);
+        Some(result)
+    }
+}
+
+/// Generate the start times of bars of a fixed length during a trading session, including the closing time
+#[derive(Debug, Copy, Clone, Eq, PartialEq, PartialOrd, Ord, Hash)]
+pub struct SessionTimes {
+    /// The next time to generate
+    pub t: DateTime<Utc>,
+    /// The closing time of the session
+    pub close: DateTime<Utc>,
+    /// The length of each bar
+    pub interval: Duration,
+}
+
+impl SessionTimes {
+    /// Create a new `SessionTimes` iterator between an opening and closing time
+    pub fn new(open: DateTime<Utc>, close: DateTime<Utc>, interval: Duration) -> SessionTimes {
+        assert!(
+            interval > Duration::zero(),
+            "Bar interval must be positive!"
+        );
+        SessionTimes {
+            t: open,
+            close,
+            interval,
+        }
+    }
+    /// Create a new `SessionTimes` iterator for NASDAQ trading hours on a given date, which are empty if the date is
+    /// not a trading day
+    pub fn nasdaq(date: Date<Utc>, interval: Duration) -> SessionTimes {
+        let open = date.and_hms(14, 30, 00);
+        let close = if is_nasdaq_trading_day(date) {
+            date.and_hms(21, 00, 00)
+        } else {
+            open - interval
+        };
+        SessionTimes::new(open, close, interval)
+    }
+}
+
+impl Iterator for SessionTimes {
+    type Item = DateTime<Utc>;
+    fn next(&mut self) -> Option<DateTime<Utc>> {
+        if self.t > self.close {
+            return None;
+        }
+        let result = self.t;
+        self.t = self.t + self.interval;
+        Some(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn session_times_match_minutes() {
+        let date = Date::from_utc(NaiveDate::from_ymd(2020, 10, 12), Utc);
+        let minutes: Vec<_> = NASDAQMinutes::for_date(date).collect();
+        let session: Vec<_> = SessionTimes::nasdaq(date, Duration::minutes(1)).collect();
+        assert_eq!(minutes, session);
+        assert_eq!(
+            SessionTimes::nasdaq(date, Duration::seconds(1)).count(),
+            390 * 60 + 1
+        );
+        let saturday = Date::from_utc(NaiveDate::from_ymd(2020, 10, 10), Utc);
+        assert_eq!(
+            SessionTimes::nasdaq(saturday, Duration::hours(1)).count(),
+            0
+        );
+    }
+
+    #[test]
+    fn spread_widens_ranges() {
+        let date = Date::from_utc(NaiveDate::from_ymd(2020, 10, 12), Utc);
+        let flat = DistGen2 {
+            rng: thread_rng(),
+            price: 40.0,
+            jitter: Normal::new(0.0, 0.0).unwrap(),
+            vel: 0.0,
+            acc: 0.0,
+            jerk: Normal::new(0.0, 0.0).unwrap(),
+        };
+        let volume_gen = VolumeGen {
+            rng: thread_rng(),
+            average: Normal::new(200.0, 0.0).unwrap(),
+            no_trades: Normal::new(1.0, 0.0).unwrap(),
+        };
+        let mut ticks = TickGen {
+            time_gen: SessionTimes::nasdaq(date, Duration::minutes(1)).peekable(),
+            price_gen: SpreadNoise::new(flat, thread_rng(), 0.001, 0.0),
+            volume_gen,
+            close: 0.0,
+        };
+        for tick in ticks.by_ref().take(100) {
+            assert!(tick.h <= 40.04 + 1e-9 && tick.l >= 39.96 - 1e-9);
+            assert!(tick.h >= tick.l);
+        }
+        assert_eq!(ticks.price_gen.latent, 40.0);
+    }
+
+    #[test]
+    fn heston_ticks_are_valid() {
+        for tick in heston_fake_ticks(Duration::minutes(1)).take(1000) {
+            assert!(tick.l <= tick.o && tick.o <= tick.h);
+            assert!(tick.l <= tick.c && tick.c <= tick.h);
+            assert!(tick.v >= 0.0 && tick.c > 0.0);
+        }
+    }
+}
@@ -0,0 +1,71 @@
+/*!
+Canonical, deterministic fake datasets with known dynamics, for integration tests and benchmarks.
+
+Every scenario covers four stocks over the five trading sessions of the week of 2020-10-12, in minute bars.
+*/
+use super::{FakeDataBuilder, VolatilityRegime};
+use chrono::NaiveDate;
+
+/// The number of stocks in each scenario
+pub const SCENARIO_STOCKS: usize = 4;
+
+/// The number of trading sessions in each scenario
+pub const SCENARIO_SESSIONS: usize = 5;
+
+/// The initial price of every stock in each scenario
+pub const SCENARIO_PRICE: f64 = 40.0;
+
+/// The log return of the crash in the `crash` scenario
+pub const CRASH_LOG_RETURN: f64 = -0.25;
+
+/// The common base of every scenario
+fn base(name: &str, seed: u64) -> FakeDataBuilder {
+    FakeDataBuilder::new(name)
+        .stocks(SCENARIO_STOCKS)
+        .sessions(SCENARIO_SESSIONS)
+        .start(NaiveDate::from_ymd(2020, 10, 12))
+        .price(SCENARIO_PRICE)
+        .seed(seed)
+}
+
+/// Calm, correlated stocks with a strong upward drift, gaining about 10% over the scenario
+pub fn trend() -> FakeDataBuilder {
+    base("trend", 1)
+        .regime(VolatilityRegime::Calm)
+        .drift(5.0)
+        .correlation(0.5)
+}
+
+/// Driftless stocks which revert quickly to their initial price, with a half-life of about a third of a session
+pub fn mean_revert() -> FakeDataBuilder {
+    base("mean-revert", 2).drift(0.0).mean_reversion(500.0)
+}
+
+/// Turbulent, highly correlated stocks with random jumps, which all crash by `CRASH_LOG_RETURN` at 17:00 UTC on the
+/// third session
+pub fn crash() -> FakeDataBuilder {
+    base("crash", 3)
+        .regime(VolatilityRegime::Turbulent)
+        .drift(0.0)
+        .correlation(0.8)
+        .jumps(1.0, 0.02)
+        .shock(
+            NaiveDate::from_ymd(2020, 10, 14).and_hms(17, 0, 0),
+            CRASH_LOG_RETURN,
+        )
+}
+
+/// Stocks whose prices never move, but which still trade
+pub fn flat() -> FakeDataBuilder {
+    base("flat", 4)
+        .regime(VolatilityRegime::Custom {
+            theta: 0.0,
+            xi: 0.0,
+        })
+        .drift(0.0)
+}
+
+/// Get every scenario
+pub fn all() -> Vec<FakeDataBuilder> {
+    vec![trend(), mean_revert(), crash(), flat()]
+}
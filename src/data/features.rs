@@ -0,0 +1,313 @@
+/*!
+Derived per-stock features computed from raw (unscaled) ticks, packaged as auxiliary series to be passed to the network
+as additional inputs
+*/
+use super::calendar::{exchange_time, is_trading_day};
+use super::multirate::AuxiliarySeries;
+use super::Tick;
+use chrono::{Duration, NaiveDate};
+use num::NumCast;
+use std::collections::VecDeque;
+
+/// The number of features pushed by `SessionAnchors::push`
+pub const INTRADAY_FEATURES: usize = 8;
+/// The number of features pushed by `RollingVolatility::push`
+pub const VOLATILITY_FEATURES: usize = 2;
+
+/// Get the log ratio of a price to a reference price, or 0 if either is not positive and finite
+fn log_ratio(price: f64, reference: f64) -> f32 {
+    let ratio = (price / reference).ln();
+    if price > 0.0 && reference > 0.0 && ratio.is_finite() {
+        ratio as f32
+    } else {
+        0.0
+    }
+}
+
+/// Session-anchored intraday price structure: the open, high, low and close of each tick as log ratios to the session's
+/// VWAP so far and to the session's opening price, which are scale free. Anchors reset at the first tick of each
+/// trading day in exchange local time, as given by the market calendar, and ticks on days the market is closed get
+/// zero features.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct SessionAnchors {
+    /// The trading day of the current session, if any
+    session: Option<NaiveDate>,
+    /// The opening price of the current session
+    open: f64,
+    /// The sum of VWAP times volume over the current session
+    price_volume: f64,
+    /// The total volume of the current session
+    volume: f64,
+    /// The latest close of the current session, used as its VWAP while it has no volume
+    close: f64,
+}
+
+impl SessionAnchors {
+    /// Create anchors before any session
+    pub fn new() -> SessionAnchors {
+        SessionAnchors::default()
+    }
+    /// Get the names of the features pushed by `push`
+    pub fn names() -> Vec<String> {
+        ["o", "h", "l", "c"]
+            .iter()
+            .flat_map(|field| {
+                vec![
+                    format!("{}_to_session_vwap", field),
+                    format!("{}_to_session_open", field),
+                ]
+            })
+            .collect()
+    }
+    /// Get the VWAP of the current session so far, if any
+    pub fn vwap(&self) -> Option<f64> {
+        if self.session.is_none() {
+            None
+        } else if self.volume > 0.0 {
+            Some(self.price_volume / self.volume)
+        } else {
+            Some(self.close)
+        }
+    }
+    /// Update the anchors with a raw tick, and push its features. Guaranteed to write `INTRADAY_FEATURES` data
+    /// points.
+    pub fn push<F: Copy + NumCast>(&mut self, tick: &Tick<F>, dest: &mut Vec<f32>) {
+        let value = |x: F| -> f64 { NumCast::from(x).unwrap_or(f64::NAN) };
+        let date = exchange_time(tick.t).date();
+        if !is_trading_day(date) {
+            self.session = None;
+            dest.extend(std::iter::repeat(0.0).take(INTRADAY_FEATURES));
+            return;
+        }
+        let (o, h, l, c) = (value(tick.o), value(tick.h), value(tick.l), value(tick.c));
+        if self.session != Some(date) {
+            *self = SessionAnchors {
+                session: Some(date),
+                open: o,
+                ..SessionAnchors::default()
+            };
+        }
+        let (vw, v) = (value(tick.vw), value(tick.v));
+        if vw.is_finite() && v.is_finite() && v > 0.0 {
+            self.price_volume += vw * v;
+            self.volume += v;
+        }
+        if c.is_finite() {
+            self.close = c;
+        }
+        let vwap = self.vwap().expect("In a session");
+        for price in &[o, h, l, c] {
+            dest.push(log_ratio(*price, vwap));
+            dest.push(log_ratio(*price, self.open));
+        }
+    }
+}
+
+/// Get the session-anchored intraday features of a stock's raw ticks, sorted by time, as an auxiliary series. Each
+/// tick's features are available from its own time, and are carried forward to rows where the stock has no tick.
+pub fn intraday_series<F: Copy + NumCast>(ticks: &[Tick<F>]) -> AuxiliarySeries {
+    let mut anchors = SessionAnchors::new();
+    let rows = ticks
+        .iter()
+        .map(|tick| {
+            let mut row = Vec::with_capacity(INTRADAY_FEATURES);
+            anchors.push(tick, &mut row);
+            (tick.t, row)
+        })
+        .collect();
+    AuxiliarySeries::new(SessionAnchors::names(), rows, Duration::zero())
+        .expect("INTRADAY_FEATURES values per row")
+}
+
+/// Rolling volatility estimates: the realized volatility, i.e. the root sum of squared log close-to-close returns over
+/// the last `window` ticks, and the average true range (ATR) by Wilder's smoothing over `window` ticks, relative to the
+/// close so as to be scale free. Estimates are only meaningful for raw ticks. The true range of the next tick is also
+/// available as a prediction target, as `Target::TrueRange`, which is only relative to the close, and computed from raw
+/// ticks, as a change.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RollingVolatility {
+    /// The number of ticks estimates are taken over
+    pub window: usize,
+    /// The log returns of the last `window` ticks
+    returns: VecDeque<f64>,
+    /// The sum of the squares of `returns`
+    sum_squares: f64,
+    /// The average true range so far, if any
+    atr: Option<f64>,
+    /// The previous tick's close, if any
+    previous_close: Option<f64>,
+}
+
+impl RollingVolatility {
+    /// Create estimators over windows of `window` ticks
+    pub fn new(window: usize) -> RollingVolatility {
+        let window = window.max(1);
+        RollingVolatility {
+            window,
+            returns: VecDeque::with_capacity(window),
+            sum_squares: 0.0,
+            atr: None,
+            previous_close: None,
+        }
+    }
+    /// Get the names of the features pushed by `push`
+    pub fn names() -> Vec<String> {
+        vec![
+            "realized_volatility".to_string(),
+            "atr_to_close".to_string(),
+        ]
+    }
+    /// Get the current realized volatility, if any returns have been seen
+    pub fn realized_volatility(&self) -> Option<f64> {
+        if self.returns.is_empty() {
+            None
+        } else {
+            Some(self.sum_squares.max(0.0).sqrt())
+        }
+    }
+    /// Get the current average true range, if any ticks have been seen
+    pub fn atr(&self) -> Option<f64> {
+        self.atr
+    }
+    /// Update the estimates with a raw tick, ignoring ticks with an invalid close, and push its features. Guaranteed
+    /// to write `VOLATILITY_FEATURES` data points.
+    pub fn push<F: Copy + NumCast>(&mut self, tick: &Tick<F>, dest: &mut Vec<f32>) {
+        let value = |x: F| -> f64 { NumCast::from(x).unwrap_or(f64::NAN) };
+        let (h, l, c) = (value(tick.h), value(tick.l), value(tick.c));
+        if c.is_finite() && c > 0.0 {
+            let (high, low) = match self.previous_close {
+                Some(previous) => (h.max(previous), l.min(previous)),
+                None => (h, l),
+            };
+            let true_range = high - low;
+            if true_range.is_finite() {
+                let alpha = 1.0 / self.window as f64;
+                self.atr = Some(match self.atr {
+                    Some(atr) => atr + alpha * (true_range - atr),
+                    None => true_range,
+                });
+            }
+            if let Some(previous) = self.previous_close {
+                let log_return = (c / previous).ln();
+                if self.returns.len() == self.window {
+                    let oldest = self.returns.pop_front().expect("Window is full");
+                    self.sum_squares -= oldest * oldest;
+                }
+                self.returns.push_back(log_return);
+                self.sum_squares += log_return * log_return;
+                // Rounding errors accumulate in the running sum, which matters once it is close to zero
+                if self.sum_squares < 1e-12 {
+                    self.sum_squares = self.returns.iter().map(|r| r * r).sum();
+                }
+            }
+            self.previous_close = Some(c);
+        }
+        let close = self.previous_close.unwrap_or(0.0);
+        dest.push(self.realized_volatility().unwrap_or(0.0) as f32);
+        dest.push(match self.atr {
+            Some(atr) if close > 0.0 => (atr / close) as f32,
+            _ => 0.0,
+        });
+    }
+}
+
+/// Get the rolling volatility features of a stock's raw ticks over windows of `window` ticks, sorted by time, as an
+/// auxiliary series. Each tick's features are available from its own time, and are carried forward to rows where the
+/// stock has no tick.
+pub fn volatility_series<F: Copy + NumCast>(ticks: &[Tick<F>], window: usize) -> AuxiliarySeries {
+    let mut volatility = RollingVolatility::new(window);
+    let rows = ticks
+        .iter()
+        .map(|tick| {
+            let mut row = Vec::with_capacity(VOLATILITY_FEATURES);
+            volatility.push(tick, &mut row);
+            (tick.t, row)
+        })
+        .collect();
+    AuxiliarySeries::new(RollingVolatility::names(), rows, Duration::zero())
+        .expect("VOLATILITY_FEATURES values per row")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn anchors_reset_each_session() {
+        let tick = |d: u32, m: u32, o: f64, c: f64, v: f64| Tick {
+            t: NaiveDate::from_ymd(2020, 10, d).and_hms(14, 30 + m, 0),
+            o,
+            h: o.max(c),
+            l: o.min(c),
+            c,
+            v,
+            vw: (o + c) / 2.0,
+            n: 1.0,
+        };
+        let ticks = vec![
+            tick(9, 0, 100.0, 102.0, 10.0),
+            tick(9, 1, 102.0, 104.0, 30.0),
+            tick(10, 0, 50.0, 50.0, 0.0),
+            tick(12, 0, 110.0, 110.0, 0.0),
+            Tick {
+                t: NaiveDate::from_ymd(2020, 10, 13).and_hms(0, 30, 0),
+                ..tick(12, 0, 121.0, 121.0, 0.0)
+            },
+        ];
+        let series = intraday_series(&ticks);
+        assert_eq!(series.width(), INTRADAY_FEATURES);
+        assert_eq!(series.names[6], "c_to_session_vwap");
+
+        // The session VWAP after the second tick is (101 * 10 + 103 * 30) / 40 = 102.5
+        let second = series.row_at(ticks[1].t).unwrap();
+        assert!((second[6] - (104.0f64 / 102.5).ln() as f32).abs() < 1e-6);
+        assert!((second[7] - (104.0f64 / 100.0).ln() as f32).abs() < 1e-6);
+        // Saturday is not a trading day
+        assert_eq!(
+            series.row_at(ticks[2].t).unwrap(),
+            &[0.0; INTRADAY_FEATURES][..]
+        );
+        // Monday's session starts afresh, with its close as its VWAP before any volume
+        assert_eq!(
+            series.row_at(ticks[3].t).unwrap(),
+            &[0.0; INTRADAY_FEATURES][..]
+        );
+        // Monday evening in New York is Tuesday in UTC, but still anchored to Monday's open
+        let evening = series.row_at(ticks[4].t).unwrap();
+        assert!((evening[7] - 1.1f64.ln() as f32).abs() < 1e-6);
+    }
+
+    #[test]
+    fn rolling_volatility() {
+        let tick = |m: i64, h: f64, l: f64, c: f64| Tick {
+            t: NaiveDate::from_ymd(2020, 10, 9).and_hms(14, 30, 0) + Duration::minutes(m),
+            o: c,
+            h,
+            l,
+            c,
+            v: 1.0,
+            vw: c,
+            n: 1.0,
+        };
+        let ticks = vec![
+            tick(0, 101.0, 99.0, 100.0),
+            tick(1, 112.0, 104.0, 110.0),
+            tick(2, 111.0, 98.0, 99.0),
+            tick(3, 99.0, 99.0, 99.0),
+        ];
+        let series = volatility_series(&ticks, 2);
+        assert_eq!(series.names, RollingVolatility::names());
+        let row = |ix: usize| series.row_at(ticks[ix].t).unwrap().to_vec();
+        assert_eq!(row(0)[0], 0.0);
+        assert!((row(0)[1] - 0.02).abs() < 1e-6);
+
+        // True ranges are 2, 12 (from the previous close of 100) and 13, smoothed as 2, 7 and 10
+        let (up, down) = (1.1f64.ln(), 0.9f64.ln());
+        assert!((row(1)[0] - up.abs() as f32).abs() < 1e-6);
+        assert!((row(1)[1] - 7.0 / 110.0).abs() < 1e-6);
+        assert!((row(2)[0] - (up * up + down * down).sqrt() as f32).abs() < 1e-6);
+        assert!((row(2)[1] - 10.0 / 99.0).abs() < 1e-6);
+        // The first return leaves the window
+        assert!((row(3)[0] - down.abs() as f32).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,191 @@
+/*!
+Loading tick data from files, including compressed files
+*/
+use super::clean::{sanitize_with, MergePolicy};
+use super::{polygon::read_ticks_auto, Symbol, Tick};
+use flate2::read::MultiGzDecoder;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
+
+/// The magic bytes at the start of a gzip stream
+pub const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// The magic bytes at the start of a zstd frame
+pub const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// Wrap a Reader so that it is transparently decompressed if it holds gzip or zstd compressed data, detected by its
+/// magic bytes. Uncompressed data is passed through as is.
+pub fn decompress<'a, R: Read + 'a>(rdr: R) -> Box<dyn Read + 'a> {
+    let mut rdr = BufReader::new(rdr);
+    let (gzip, zstd) = match rdr.fill_buf() {
+        Ok(buf) => (buf.starts_with(&GZIP_MAGIC), buf.starts_with(&ZSTD_MAGIC)),
+        Err(_) => (false, false),
+    };
+    if gzip {
+        Box::new(MultiGzDecoder::new(rdr))
+    } else if zstd {
+        match zstd::stream::read::Decoder::with_buffer(rdr) {
+            Ok(decoder) => Box::new(decoder),
+            Err(_) => Box::new(io::empty()),
+        }
+    } else {
+        Box::new(rdr)
+    }
+}
+
+/// Open a file of tick data, transparently decompressing `.csv.gz` and `.csv.zst` files
+pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Box<dyn Read>> {
+    Ok(decompress(File::open(path)?))
+}
+
+/// Check whether a file name matches a shell-style pattern, where `*` matches any sequence of characters and `?`
+/// matches any single character
+pub fn matches_pattern(name: &str, pattern: &str) -> bool {
+    let name: Vec<char> = name.chars().collect();
+    let pattern: Vec<char> = pattern.chars().collect();
+    // The position in the name and pattern to backtrack to on a mismatch after the last `*`
+    let mut backtrack: Option<(usize, usize)> = None;
+    let (mut n, mut p) = (0, 0);
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((n, p));
+                p += 1;
+            }
+            Some('?') => {
+                n += 1;
+                p += 1;
+            }
+            Some(c) if *c == name[n] => {
+                n += 1;
+                p += 1;
+            }
+            _ => {
+                if let Some((star_n, star_p)) = backtrack {
+                    backtrack = Some((star_n + 1, star_p));
+                    n = star_n + 1;
+                    p = star_p + 1;
+                } else {
+                    return false;
+                }
+            }
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// Infer a stock's symbol from the name of a file containing its tick data.
+///
+/// Compression and CSV extensions are stripped, and the last `_`- or `-`-separated component consisting only of
+/// uppercase letters, digits and dots is taken as the symbol, e.g. `small_AMD.csv.gz` yields `AMD`. If there is no
+/// such component, the uppercased file stem is used.
+pub fn infer_symbol<P: AsRef<Path>>(path: P) -> Option<Symbol> {
+    let mut stem = path.as_ref().file_name()?.to_str()?;
+    for extension in &[".gz", ".zst", ".csv"] {
+        if stem.ends_with(extension) {
+            stem = &stem[..stem.len() - extension.len()];
+        }
+    }
+    if stem.is_empty() {
+        return None;
+    }
+    let is_symbol = |part: &&str| {
+        !part.is_empty()
+            && part.chars().any(|c| c.is_ascii_uppercase())
+            && part
+                .chars()
+                .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '.')
+    };
+    let symbol = stem
+        .rsplit(|c| c == '_' || c == '-')
+        .find(is_symbol)
+        .map(Symbol::from)
+        .unwrap_or_else(|| Symbol(stem.to_uppercase()));
+    Some(symbol)
+}
+
+/// Load tick data from a list of files, inferring the symbol of each from its file name.
+///
+/// Ticks from files with the same symbol are merged. Each symbol's ticks are then sanitized, keeping only the first
+/// tick read at any given time.
+pub fn load_files<I, P>(paths: I) -> io::Result<BTreeMap<Symbol, Vec<Tick>>>
+where
+    I: IntoIterator<Item = P>,
+    P: AsRef<Path>,
+{
+    let span = debug_span!("load_files");
+    let _guard = span.enter();
+    let mut result: BTreeMap<Symbol, Vec<Tick>> = BTreeMap::new();
+    for path in paths {
+        let path = path.as_ref();
+        let symbol = infer_symbol(path).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Cannot infer a symbol from {:?}", path),
+            )
+        })?;
+        let ticks = read_ticks_auto(open(path)?);
+        debug!("Read {} ticks for {} from {:?}", ticks.len(), symbol, path);
+        result.entry(symbol).or_default().extend(ticks);
+    }
+    for (symbol, ticks) in result.iter_mut() {
+        let report = sanitize_with(ticks, MergePolicy::KeepFirst);
+        if !report.is_clean() {
+            warn!("Sanitized ticks for {}: {:?}", symbol, report);
+        }
+    }
+    Ok(result)
+}
+
+/// Load tick data from every file in a directory whose name matches a shell-style pattern (e.g. `*.csv.gz`),
+/// inferring the symbol of each from its file name. See `load_files`.
+pub fn load_dir<P: AsRef<Path>>(path: P, pattern: &str) -> io::Result<BTreeMap<Symbol, Vec<Tick>>> {
+    let mut paths = Vec::new();
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        let matches = entry
+            .file_name()
+            .to_str()
+            .map(|name| matches_pattern(name, pattern))
+            .unwrap_or(false);
+        if matches && entry.file_type()?.is_file() {
+            paths.push(entry.path());
+        }
+    }
+    paths.sort();
+    load_files(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::{write::GzEncoder, Compression};
+    use std::io::Write;
+
+    #[test]
+    fn decompression_roundtrip() {
+        let data = b"t,v,vw,o,c,h,l,n\n";
+        let mut gzip = GzEncoder::new(Vec::new(), Compression::default());
+        gzip.write_all(data).unwrap();
+        let gzip = gzip.finish().unwrap();
+        let zstd = zstd::stream::encode_all(&data[..], 0).unwrap();
+        for input in &[&data[..], &gzip[..], &zstd[..]] {
+            let mut output = Vec::new();
+            decompress(*input).read_to_end(&mut output).unwrap();
+            assert_eq!(output, data);
+        }
+    }
+
+    #[test]
+    fn patterns_and_symbols() {
+        assert!(matches_pattern("small_AMD.csv", "*.csv"));
+        assert!(matches_pattern("AMD.csv.gz", "*.csv*"));
+        assert!(matches_pattern("AMD.csv", "A?D*"));
+        assert!(!matches_pattern("AMD.csv.gz", "*.csv"));
+        assert_eq!(infer_symbol("data/small_AMD.csv"), Some("AMD".into()));
+        assert_eq!(infer_symbol("BRK.B-2020.csv.zst"), Some("BRK.B".into()));
+        assert_eq!(infer_symbol("aapl.csv"), Some("AAPL".into()));
+    }
+}
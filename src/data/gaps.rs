@@ -0,0 +1,91 @@
+/*!
+Features describing the spacing of ticks: the gap since each stock's previous tick, and whether it spans a session
+boundary
+*/
+use super::dataset::Dataset;
+use chrono::NaiveDateTime;
+
+/// The number of gap features computed for each stock in each row
+pub const GAP_FEATURES: usize = 2;
+
+/// Compute the gap features of a stock at time `t`, given the time of its previous tick, if any: the gap since the
+/// previous tick in minutes, scaled by `ln(1 + x)`, and `1.0` if the previous tick was on a different (UTC) day, and
+/// hence in a different US trading session, or `0.0` otherwise. Both features are zero without a previous tick.
+pub fn gap_features(previous: Option<NaiveDateTime>, t: NaiveDateTime) -> [f32; GAP_FEATURES] {
+    match previous {
+        Some(previous) => {
+            let minutes = (t - previous).num_milliseconds().max(0) as f32 / 60_000.0;
+            let boundary = if previous.date() != t.date() {
+                1.0
+            } else {
+                0.0
+            };
+            [minutes.ln_1p(), boundary]
+        }
+        None => [0.0, 0.0],
+    }
+}
+
+impl<F: Copy> Dataset<F> {
+    /// Compute the gap features of every stock in every row, as rows of `GAP_FEATURES * stocks` values laid out stock
+    /// by stock. A stock's features in a row describe the time since its last tick strictly before that row, so that
+    /// stocks without a tick in a row report how long they have been quiet. See `gap_features`.
+    ///
+    /// Rows are aligned with the rows of the dataset, and hence with the rows of batches made from its ticks, so they
+    /// can be fed to a network as additional inputs.
+    pub fn gap_features(&self) -> Vec<Vec<f32>> {
+        let stocks = self.stocks();
+        let mut previous: Vec<Option<NaiveDateTime>> = vec![None; stocks];
+        let mut rows = Vec::with_capacity(self.len());
+        for (row, t) in self.times().iter().enumerate() {
+            let mut features = Vec::with_capacity(GAP_FEATURES * stocks);
+            for (stock, previous) in previous.iter_mut().enumerate() {
+                features.extend_from_slice(&gap_features(*previous, *t));
+                if let Some(tick) = self.tick(row, stock) {
+                    *previous = Some(tick.t);
+                }
+            }
+            rows.push(features);
+        }
+        rows
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::Tick;
+    use chrono::{Duration, NaiveDate};
+
+    #[test]
+    fn gaps_and_boundaries() {
+        let t = NaiveDate::from_ymd(2020, 10, 12).and_hms(19, 58, 0);
+        let tick = |t| Tick {
+            t,
+            v: 1.0,
+            vw: 1.0,
+            o: 1.0,
+            c: 1.0,
+            h: 1.0,
+            l: 1.0,
+            n: 1.0,
+        };
+        let next_day = NaiveDate::from_ymd(2020, 10, 13).and_hms(13, 30, 0);
+        let dataset = Dataset::from_ticks(
+            vec!["A".into(), "B".into()],
+            vec![
+                vec![tick(t), tick(t + Duration::minutes(1)), tick(next_day)],
+                vec![tick(t)],
+            ],
+        );
+        let features = dataset.gap_features();
+        assert_eq!(features.len(), 3);
+        assert_eq!(features[0], vec![0.0; 4]);
+        assert_eq!(features[1], vec![1f32.ln_1p(), 0.0, 1f32.ln_1p(), 0.0]);
+        let overnight = |from: NaiveDateTime| ((next_day - from).num_minutes() as f32).ln_1p();
+        assert_eq!(
+            features[2],
+            vec![overnight(t + Duration::minutes(1)), 1.0, overnight(t), 1.0]
+        );
+    }
+}
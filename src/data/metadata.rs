@@ -0,0 +1,194 @@
+/*!
+Static metadata about each symbol, such as its sector, read from a CSV file, and its encoding as static input features
+broadcast to every row of the symbol's inputs
+*/
+use super::{files::decompress, Symbol};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::Read;
+
+/// Static metadata about a symbol
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SymbolMetadata {
+    /// The sector the symbol belongs to, if known
+    pub sector: Option<String>,
+    /// The symbol's market capitalization, in dollars, if known
+    pub market_cap: Option<f64>,
+    /// The symbol's average bid-ask spread, in basis points of its price, if known
+    pub spread_bps: Option<f64>,
+}
+
+/// A record of a symbol metadata CSV file
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+struct MetadataRecord {
+    symbol: String,
+    #[serde(default)]
+    sector: Option<String>,
+    #[serde(default)]
+    market_cap: Option<f64>,
+    #[serde(default)]
+    spread_bps: Option<f64>,
+}
+
+/// Read symbol metadata from a CSV file with columns `symbol, sector, market_cap, spread_bps`. Columns other than
+/// `symbol` may be missing or empty, and unknown columns are ignored.
+///
+/// Records which cannot be parsed are skipped. If a symbol appears more than once, its last record is used.
+pub fn read_metadata<R: Read>(rdr: R) -> BTreeMap<Symbol, SymbolMetadata> {
+    let records = csv::Reader::from_reader(decompress(rdr))
+        .into_deserialize::<MetadataRecord>()
+        .filter_map(|record| record.ok());
+    let nonempty = |value: Option<String>| {
+        value
+            .map(|value| value.trim().to_owned())
+            .filter(|value| !value.is_empty())
+    };
+    records
+        .map(|record| {
+            (
+                Symbol(record.symbol.trim().to_owned()),
+                SymbolMetadata {
+                    sector: nonempty(record.sector),
+                    market_cap: record.market_cap.filter(|cap| cap.is_finite()),
+                    spread_bps: record.spread_bps.filter(|spread| spread.is_finite()),
+                },
+            )
+        })
+        .collect()
+}
+
+/// Get the sector of each of a list of symbols, if known
+pub fn sectors<'a>(
+    symbols: impl IntoIterator<Item = &'a Symbol>,
+    metadata: &BTreeMap<Symbol, SymbolMetadata>,
+) -> Vec<Option<String>> {
+    symbols
+        .into_iter()
+        .map(|symbol| metadata.get(symbol)?.sector.clone())
+        .collect()
+}
+
+/// The default upper bounds of market capitalization buckets, separating micro, small, mid, large and mega caps
+pub const DEFAULT_MARKET_CAP_BUCKETS: [f64; 4] = [3e8, 2e9, 1e10, 2e11];
+
+/// How symbol metadata is encoded as static features: a one-hot encoding of the symbol's market capitalization bucket,
+/// a one-hot encoding of its sector, and its log average spread. Unknown metadata is encoded as zeros.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StaticEncoding {
+    /// The upper bound of each market capitalization bucket but the last, in increasing order
+    pub market_cap_buckets: Vec<f64>,
+    /// The sectors to encode, in order. Symbols in other sectors have no sector feature set.
+    pub sectors: Vec<String>,
+}
+
+impl StaticEncoding {
+    /// Encode every sector appearing in a set of metadata, in sorted order, with the default market capitalization
+    /// buckets
+    pub fn new(metadata: &BTreeMap<Symbol, SymbolMetadata>) -> StaticEncoding {
+        let mut sectors: Vec<String> = metadata
+            .values()
+            .filter_map(|metadata| metadata.sector.clone())
+            .collect();
+        sectors.sort();
+        sectors.dedup();
+        StaticEncoding {
+            market_cap_buckets: DEFAULT_MARKET_CAP_BUCKETS.to_vec(),
+            sectors,
+        }
+    }
+    /// Get the number of static features of each symbol
+    pub fn width(&self) -> usize {
+        self.market_cap_buckets.len() + 1 + self.sectors.len() + 1
+    }
+    /// Get the name of each static feature
+    pub fn names(&self) -> Vec<String> {
+        let mut names = Vec::with_capacity(self.width());
+        names.extend(
+            (0..=self.market_cap_buckets.len()).map(|ix| format!("market_cap_bucket{}", ix)),
+        );
+        names.extend(
+            self.sectors
+                .iter()
+                .map(|sector| format!("sector_{}", sector)),
+        );
+        names.push("log_spread_bps".to_string());
+        names
+    }
+    /// Push the static features of a symbol with given metadata, if any, to an input vector. Guaranteed to write
+    /// `width` data points.
+    pub fn push_features(&self, metadata: Option<&SymbolMetadata>, dest: &mut Vec<f32>) {
+        let start = dest.len();
+        dest.extend(std::iter::repeat(0.0).take(self.width()));
+        let metadata = match metadata {
+            Some(metadata) => metadata,
+            None => return,
+        };
+        let features = &mut dest[start..];
+        let buckets = self.market_cap_buckets.len() + 1;
+        if let Some(cap) = metadata.market_cap {
+            let bucket = self
+                .market_cap_buckets
+                .iter()
+                .take_while(|bound| cap > **bound)
+                .count();
+            features[bucket] = 1.0;
+        }
+        if let Some(sector) = &metadata.sector {
+            if let Some(ix) = self.sectors.iter().position(|known| known == sector) {
+                features[buckets + ix] = 1.0;
+            }
+        }
+        if let Some(spread) = metadata.spread_bps {
+            features[buckets + self.sectors.len()] = spread.max(0.0).ln_1p() as f32;
+        }
+    }
+    /// Get the static features of each of a list of symbols, e.g. for `SymbolSampler::with_static_features`. Static
+    /// features are only informative when rows of different symbols are fed through the same weights, as they are when
+    /// sampling symbols for a single stock network: fed to a multi-stock network, they would be the same on every row.
+    pub fn features<'a>(
+        &self,
+        symbols: impl IntoIterator<Item = &'a Symbol>,
+        metadata: &BTreeMap<Symbol, SymbolMetadata>,
+    ) -> Vec<Vec<f32>> {
+        symbols
+            .into_iter()
+            .map(|symbol| {
+                let mut features = Vec::with_capacity(self.width());
+                self.push_features(metadata.get(symbol), &mut features);
+                features
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn metadata_is_read() {
+        let csv = "symbol,name,sector,market_cap,spread_bps\nAMD,Advanced Micro Devices,Technology,,\n\
+            XOM,Exxon, ,1.7e11,\nJPM,JPMorgan,Financials,3e11,1.5\nAMD,AMD,Semiconductors,9.8e10,2\n";
+        let metadata = read_metadata(csv.as_bytes());
+        assert_eq!(metadata.len(), 3);
+        let symbols = [
+            Symbol::from("AMD"),
+            Symbol::from("XOM"),
+            Symbol::from("NVDA"),
+        ];
+        assert_eq!(
+            sectors(symbols.iter(), &metadata),
+            vec![Some("Semiconductors".to_string()), None, None]
+        );
+
+        let encoding = StaticEncoding::new(&metadata);
+        assert_eq!(encoding.sectors, vec!["Financials", "Semiconductors"]);
+        assert_eq!(encoding.width(), 8);
+        assert_eq!(encoding.names().len(), 8);
+        let features = encoding.features(symbols.iter(), &metadata);
+        assert_eq!(features[0][..7], [0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 1.0]);
+        assert!((features[0][7] - 3f32.ln()).abs() < 1e-6);
+        assert_eq!(features[1], vec![0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0]);
+        assert_eq!(features[2], vec![0.0; 8]);
+    }
+}
@@ -5,12 +5,54 @@ use crate::*;
 use chrono::{DateTime, Duration, NaiveDate, NaiveDateTime, Utc};
 use num::{Float, NumCast};
 use serde::{Deserialize, Serialize};
+use std::fmt::{self, Display, Formatter};
 use ta::{Close, High, Low, Open, Volume};
 use util::to_ns;
 
+pub mod adjust;
+#[cfg(feature = "alpaca")]
+pub mod alpaca;
+pub mod calendar;
+pub mod clean;
+pub mod dataset;
 pub mod fake;
+pub mod features;
+pub mod files;
+pub mod gaps;
+pub mod metadata;
+pub mod multirate;
 pub mod polygon;
+pub mod predictions;
+pub mod quote;
+pub mod registry;
 pub mod scale;
+pub mod schema;
+pub mod store;
+pub mod targets;
+pub mod trades;
+pub mod yahoo;
+
+pub use dataset::Dataset;
+pub use files::{load_dir, load_files};
+pub use registry::SymbolRegistry;
+pub use targets::{PredictedTick, TargetSpec};
+
+/// A stock's ticker symbol
+#[derive(Debug, Clone, Eq, PartialEq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Symbol(pub String);
+
+impl Display for Symbol {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        write!(fmt, "{}", self.0)
+    }
+}
+
+impl From<&str> for Symbol {
+    fn from(symbol: &str) -> Symbol {
+        Symbol(symbol.to_owned())
+    }
+}
 
 /// Tick data for a stock
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
@@ -36,12 +78,18 @@ pub struct Tick<F = CpuFloat> {
 impl Tick {
     /// The number of fields a tick feeds into a neural network. Time is *not* fed in.
     pub const NN_FIELDS: usize = 7; // (v, vw, o, c, h, l, n)
+    /// The names of the fields a tick feeds into a neural network, in the order written by `push_tick`
+    pub const NN_FIELD_NAMES: [&str; Tick::NN_FIELDS] = ["o", "h", "l", "c", "v", "vw", "n"];
 }
 
 impl<F> Tick<F>
 where
     F: Copy + NumCast,
 {
+    /// Get the fields a tick feeds into a neural network, in the order of `Tick::NN_FIELD_NAMES`
+    pub fn nn_fields(&self) -> [F; Tick::NN_FIELDS] {
+        [self.o, self.h, self.l, self.c, self.v, self.vw, self.n]
+    }
     /// Push a tick's data points to an input vector. Guaranteed to write `NN_FIELDS` data points
     pub fn push_tick(&self, input: &mut Vec<f32>) {
         input.push(NumCast::from(self.o).unwrap_or(0.0));
@@ -57,8 +105,213 @@ where
         Prediction {
             c: self.c,
             v: self.v,
+            kind: TargetKind::Level,
         }
     }
+    /// Get the value of a prediction target for this tick
+    pub fn target(&self, target: Target) -> f32 {
+        let value = |x: F| -> f32 { NumCast::from(x).unwrap_or(0.0) };
+        match target {
+            Target::Close => value(self.c),
+            Target::Volume => value(self.v),
+            Target::Volatility => value(self.h) - value(self.l),
+            Target::Open => value(self.o),
+            Target::High => value(self.h),
+            Target::Low => value(self.l),
+            Target::Vwap => value(self.vw),
+            Target::Trades => value(self.n),
+            Target::TrueRange => self.true_range(None),
+        }
+    }
+    /// Get the true range of this tick given the tick before it, if any: the range from the lower of its low and the
+    /// previous close to the higher of its high and the previous close, which includes gaps between ticks. Without a
+    /// previous tick, this is the high-low range.
+    pub fn true_range(&self, previous: Option<&Tick<F>>) -> f32 {
+        let value = |x: F| -> f32 { NumCast::from(x).unwrap_or(0.0) };
+        let (h, l) = (value(self.h), value(self.l));
+        match previous {
+            Some(previous) => {
+                let previous_close = value(previous.c);
+                h.max(previous_close) - l.min(previous_close)
+            }
+            None => h - l,
+        }
+    }
+    /// Get the value of a prediction target of a given kind for this tick, given the tick before it, if any. Returns
+    /// `None` if a change is requested without a previous tick, or if either close is zero or missing, e.g. a gap
+    /// filled with zeros. Changes are only meaningful for raw ticks: see `Window::target`. Cross-sectional kinds give
+    /// the same change as `Return`, to be transformed across stocks with `TargetKind::cross_section`.
+    pub fn target_of_kind(
+        &self,
+        previous: Option<&Tick<F>>,
+        target: Target,
+        kind: TargetKind,
+    ) -> Option<f32> {
+        if kind == TargetKind::Level {
+            return Some(match target {
+                Target::TrueRange => self.true_range(previous),
+                target => self.target(target),
+            });
+        }
+        let value = |x: F| -> f32 { NumCast::from(x).unwrap_or(0.0) };
+        let previous = previous?;
+        let previous_close = value(previous.c);
+        let close = value(self.c);
+        if !(previous_close.is_normal() && close.is_normal()) {
+            return None;
+        }
+        let result = match target {
+            Target::Volume => value(self.v).ln_1p() - value(previous.v).ln_1p(),
+            Target::Trades => value(self.n).ln_1p() - value(previous.n).ln_1p(),
+            Target::Volatility => (value(self.h) - value(self.l)) / previous_close,
+            Target::TrueRange => self.true_range(Some(previous)) / previous_close,
+            price => self.target(price) / previous_close - 1.0,
+        };
+        Some(result)
+    }
+}
+
+/// A quantity which a network can be trained to predict for each stock
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Target {
+    /// The closing price of the next tick
+    Close,
+    /// The volume of the next tick
+    Volume,
+    /// The realized volatility of the next tick, estimated by its high-low range. As a level, this is the range of the
+    /// input ticks, so that for scaled ticks it is in scaled units; as a change, it is computed from raw ticks relative
+    /// to the previous close.
+    Volatility,
+    /// The opening price of the next tick
+    Open,
+    /// The high price of the next tick
+    High,
+    /// The low price of the next tick
+    Low,
+    /// The volume weighted average price of the next tick
+    Vwap,
+    /// The number of trades during the next tick
+    Trades,
+    /// The true range of the next tick, i.e. its high-low range extended to the previous close, whose expectation is
+    /// the average true range (ATR). Useful as an auxiliary volatility target for a multi-task network. Like
+    /// `Volatility`, as a level this is in the units of the input ticks, which should be unscaled for a true range.
+    TrueRange,
+}
+
+impl Target {
+    /// The targets corresponding to the fields of a `Prediction`
+    pub const PREDICTION: &'static [Target] = &[Target::Close, Target::Volume];
+
+    /// Get the name of this target
+    pub fn name(&self) -> &'static str {
+        match self {
+            Target::Close => "close",
+            Target::Volume => "volume",
+            Target::Volatility => "volatility",
+            Target::Open => "open",
+            Target::High => "high",
+            Target::Low => "low",
+            Target::Vwap => "vwap",
+            Target::Trades => "trades",
+            Target::TrueRange => "true_range",
+        }
+    }
+    /// Get the target predicting a tick field, given its name as in `Tick::NN_FIELD_NAMES`, or a target by its name
+    pub fn parse(name: &str) -> Option<Target> {
+        let target = match name.trim() {
+            "o" | "open" => Target::Open,
+            "h" | "high" => Target::High,
+            "l" | "low" => Target::Low,
+            "c" | "close" => Target::Close,
+            "v" | "volume" => Target::Volume,
+            "vw" | "vwap" => Target::Vwap,
+            "n" | "trades" => Target::Trades,
+            "volatility" => Target::Volatility,
+            "true_range" | "atr" => Target::TrueRange,
+            _ => return None,
+        };
+        Some(target)
+    }
+}
+
+/// How a target is expressed: as a level, or as a change relative to the previous tick
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TargetKind {
+    /// The target's value at the next tick, e.g. the next closing price
+    Level,
+    /// The target's change from the current tick to the next, which is comparable across symbols: the return
+    /// `c_{t+1} / c_t - 1` of the close, and of the other prices relative to the current close, the log change
+    /// `ln(1 + v_{t+1}) - ln(1 + v_t)` of the volume and number of trades, and the high-low range of the next tick
+    /// relative to the current close.
+    ///
+    /// Returns are computed from the ticks being batched, so these should be unscaled, or scaled by a positive factor.
+    Return,
+    /// The target's cross-sectional rank among the stocks with a tick at the next row, by the change it is targeted
+    /// with as a `Return`, scaled to `[-1, 1]` with ties sharing their average rank. A lone stock ranks at zero.
+    Rank,
+    /// The target's cross-sectional z-score among the stocks with a tick at the next row, by the change it is
+    /// targeted with as a `Return`, or zero if every stock's change is equal.
+    ZScore,
+}
+
+impl TargetKind {
+    /// Whether targets of this kind compare each stock against the others with a tick at the same time
+    pub fn is_cross_sectional(self) -> bool {
+        match self {
+            TargetKind::Level | TargetKind::Return => false,
+            TargetKind::Rank | TargetKind::ZScore => true,
+        }
+    }
+    /// Get the name of this kind
+    pub fn name(self) -> &'static str {
+        match self {
+            TargetKind::Level => "level",
+            TargetKind::Return => "return",
+            TargetKind::Rank => "rank",
+            TargetKind::ZScore => "zscore",
+        }
+    }
+    /// Transform the targets of every stock at a time, as computed by `Tick::target_of_kind`, into targets of this
+    /// kind in place, leaving missing targets missing. Does nothing for kinds which are not cross-sectional.
+    pub fn cross_section(self, values: &mut [Option<f32>]) {
+        let present: Vec<f32> = values.iter().filter_map(|value| *value).collect();
+        let n = present.len();
+        match self {
+            TargetKind::Level | TargetKind::Return => {}
+            TargetKind::Rank => {
+                for value in values.iter_mut() {
+                    if let Some(x) = value {
+                        if n < 2 {
+                            *x = 0.0;
+                            continue;
+                        }
+                        let below = present.iter().filter(|y| **y < *x).count();
+                        let equal = present.iter().filter(|y| **y == *x).count();
+                        let rank = below as f32 + (equal - 1) as f32 / 2.0;
+                        *x = 2.0 * rank / (n - 1) as f32 - 1.0;
+                    }
+                }
+            }
+            TargetKind::ZScore => {
+                let mean = present.iter().sum::<f32>() / n.max(1) as f32;
+                let variance =
+                    present.iter().map(|x| (x - mean) * (x - mean)).sum::<f32>() / n.max(1) as f32;
+                let std = variance.sqrt();
+                for value in values.iter_mut() {
+                    if let Some(x) = value {
+                        *x = if std > 0.0 { (*x - mean) / std } else { 0.0 };
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Default for TargetKind {
+    fn default() -> TargetKind {
+        TargetKind::Level
+    }
 }
 
 /// Push clock, with a period measured in seconds / 2 pi
@@ -76,6 +329,45 @@ where
     dest.push(cos_dt);
 }
 
+/// Get default clock periods for bars of a given length: periods of 5, 10, 30 and 60 bars, where shorter than a day,
+/// followed by daily, weekly, monthly and yearly periods
+pub fn default_clock_periods(interval: Duration) -> Vec<Duration> {
+    let mut periods: Vec<Duration> = [5, 10, 30, 60]
+        .iter()
+        .map(|&bars| interval * bars)
+        .filter(|&period| period < Duration::days(1))
+        .collect();
+    periods.extend_from_slice(&[
+        Duration::days(1),
+        Duration::weeks(1),
+        Duration::weeks(4),
+        Duration::days(365),
+    ]);
+    periods
+}
+
+/// Parse a duration given as a positive integer followed by a unit: `s` (seconds), `m` (minutes), `h` (hours), `d`
+/// (days) or `w` (weeks), e.g. `30m` or `1d`
+pub fn parse_duration(duration: &str) -> Option<Duration> {
+    let duration = duration.trim();
+    let split = duration.find(|c: char| !c.is_ascii_digit())?;
+    let (count, unit) = duration.split_at(split);
+    let count: i64 = count.parse().ok().filter(|&count| count > 0)?;
+    match unit {
+        "s" => Some(Duration::seconds(count)),
+        "m" => Some(Duration::minutes(count)),
+        "h" => Some(Duration::hours(count)),
+        "d" => Some(Duration::days(count)),
+        "w" => Some(Duration::weeks(count)),
+        _ => None,
+    }
+}
+
+/// Parse a comma-separated list of durations, e.g. clock periods such as `5m,30m,1h,1d,1w`. See `parse_duration`.
+pub fn parse_durations(durations: &str) -> Option<Vec<Duration>> {
+    durations.split(',').map(parse_duration).collect()
+}
+
 /// Push a set of clocks, with duration periods
 pub fn clocks<'a, F>(
     durations: &'a [Duration],
@@ -98,6 +390,27 @@ where
 )
 }
 
+/// Compose two time functions, each paired with the number of date inputs it pushes as returned by `clocks`, into a
+/// time function pushing the inputs of the first followed by those of the second
+pub fn compose_time_funcs<F, A, B>(
+    first: (usize, A),
+    second: (usize, B),
+) -> (usize, impl FnMut(DateTime<Utc>, &mut Vec<F>))
+where
+    A: FnMut(DateTime<Utc>, &mut Vec<F>),
+    B: FnMut(DateTime<Utc>, &mut Vec<F>),
+{
+    let (first_inputs, mut first) = first;
+    let (second_inputs, mut second) = second;
+    (
+        first_inputs + second_inputs,
+        move |time, dest: &mut Vec<F>| {
+            first(time, dest);
+            second(time, dest);
+        },
+    )
+}
+
 impl<F> Open for Tick<F>
 where
     F: Copy + Into<f64>,
@@ -149,11 +462,15 @@ where
 }
 
 /// A predicted tick
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Prediction<F = CpuFloat> {
-    /// Predicted closing price
+    /// Predicted closing price, or return if `kind` is `TargetKind::Return`
     pub c: F,
-    /// Predicted volume
+    /// Predicted volume, or log volume change if `kind` is `TargetKind::Return`
     pub v: F,
+    /// How the predicted fields are expressed
+    #[serde(default)]
+    pub kind: TargetKind,
 }
 
 impl<F> Close for Prediction<F>
@@ -181,6 +498,31 @@ impl Prediction {
     pub const NN_FIELDS: usize = 2;
 }
 
+impl<F: Copy> Prediction<F> {
+    /// Create a prediction with every field set to the same value
+    pub fn splat(value: F) -> Prediction<F> {
+        Prediction {
+            c: value,
+            v: value,
+            kind: TargetKind::Level,
+        }
+    }
+    /// Get the return-space prediction of the change from one tick to another, as targeted with `TargetKind::Return`
+    pub fn change(previous: &Tick<F>, next: &Tick<F>) -> Option<Prediction<F>>
+    where
+        F: Float,
+    {
+        if previous.c == F::zero() {
+            return None;
+        }
+        Some(Prediction {
+            c: next.c / previous.c - F::one(),
+            v: next.v.ln_1p() - previous.v.ln_1p(),
+            kind: TargetKind::Return,
+        })
+    }
+}
+
 impl<F> Prediction<F>
 where
     F: Copy + NumCast,
@@ -191,3 +533,120 @@ where
         input.push(NumCast::from(self.v).unwrap_or(0.0));
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn duration_parsing() {
+        assert_eq!(parse_duration("30s"), Some(Duration::seconds(30)));
+        assert_eq!(parse_duration(" 1w"), Some(Duration::weeks(1)));
+        assert_eq!(parse_duration("0m"), None);
+        assert_eq!(parse_duration("5"), None);
+        assert_eq!(parse_duration("m"), None);
+        assert_eq!(parse_duration("5y"), None);
+        assert_eq!(
+            parse_durations("5m,1h, 1d"),
+            Some(vec![
+                Duration::minutes(5),
+                Duration::hours(1),
+                Duration::days(1)
+            ])
+        );
+        assert_eq!(parse_durations("5m,,1d"), None);
+    }
+
+    #[test]
+    fn return_targets() {
+        let t = NaiveDate::from_ymd(2020, 10, 12).and_hms(14, 30, 0);
+        let previous = Tick {
+            t,
+            v: 99.0,
+            vw: 20.0,
+            o: 20.0,
+            c: 20.0,
+            h: 20.0,
+            l: 20.0,
+            n: 1.0,
+        };
+        let next = Tick {
+            t: t + Duration::minutes(1),
+            v: 199.0,
+            c: 21.0,
+            h: 22.0,
+            l: 19.0,
+            ..previous
+        };
+        let close = next.target_of_kind(Some(&previous), Target::Close, TargetKind::Return);
+        assert!((close.unwrap() - 0.05).abs() < 1e-6);
+        let volume = next.target_of_kind(Some(&previous), Target::Volume, TargetKind::Return);
+        assert!((volume.unwrap() - 2f32.ln()).abs() < 1e-6);
+        let range = next.target_of_kind(Some(&previous), Target::Volatility, TargetKind::Return);
+        assert_eq!(range, Some(0.15));
+        let gapped = Tick { l: 20.5, ..next };
+        let true_range =
+            gapped.target_of_kind(Some(&previous), Target::TrueRange, TargetKind::Level);
+        assert_eq!(true_range, Some(2.0));
+        assert_eq!(gapped.target(Target::TrueRange), 1.5);
+        assert_eq!(
+            next.target_of_kind(None, Target::Close, TargetKind::Return),
+            None
+        );
+        let missing = Tick { c: 0.0, ..next };
+        assert_eq!(
+            missing.target_of_kind(Some(&previous), Target::Close, TargetKind::Return),
+            None
+        );
+        assert_eq!(
+            next.target_of_kind(None, Target::Close, TargetKind::Level),
+            Some(21.0)
+        );
+        let change = Prediction::change(&previous, &next).unwrap();
+        assert_eq!(change.kind, TargetKind::Return);
+        assert!((change.c - 0.05).abs() < 1e-12);
+    }
+
+    #[test]
+    fn cross_sectional_targets() {
+        let mut ranks = vec![Some(0.02), None, Some(-0.01), Some(0.02), Some(0.0)];
+        TargetKind::Rank.cross_section(&mut ranks);
+        let expected = [
+            Some(2.0 / 3.0),
+            None,
+            Some(-1.0),
+            Some(2.0 / 3.0),
+            Some(-1.0 / 3.0),
+        ];
+        for (rank, expected) in ranks.iter().zip(expected.iter()) {
+            assert_eq!(rank.is_some(), expected.is_some());
+            assert!((rank.unwrap_or(0.0) - expected.unwrap_or(0.0)).abs() < 1e-6);
+        }
+        let mut lone = vec![None, Some(0.5)];
+        TargetKind::Rank.cross_section(&mut lone);
+        assert_eq!(lone, vec![None, Some(0.0)]);
+        let mut scores = vec![Some(1.0), Some(3.0), None];
+        TargetKind::ZScore.cross_section(&mut scores);
+        assert_eq!(scores, vec![Some(-1.0), Some(1.0), None]);
+        let mut returns = vec![Some(1.0), Some(3.0)];
+        TargetKind::Return.cross_section(&mut returns);
+        assert_eq!(returns, vec![Some(1.0), Some(3.0)]);
+    }
+
+    #[test]
+    fn composed_time_funcs() {
+        let periods = [Duration::days(1)];
+        let (n, mut time_func) = compose_time_funcs(
+            clocks::<f32>(&periods),
+            calendar::nasdaq_session_flags(Duration::minutes(1)),
+        );
+        assert_eq!(n, 2 + calendar::SESSION_FLAGS);
+        let mut dest = Vec::new();
+        time_func(
+            DateTime::from_utc(NaiveDate::from_ymd(2020, 10, 12).and_hms(13, 30, 0), Utc),
+            &mut dest,
+        );
+        assert_eq!(dest.len(), n);
+        assert_eq!(dest[2..], [1.0, 0.0, 0.0, 0.0]);
+    }
+}
@@ -0,0 +1,241 @@
+/*!
+Auxiliary series at coarser rates than the ticks they inform, e.g. daily indicators attached to every intraday bar,
+forward-aligned so that each bar only sees values which were available at its time
+*/
+use super::{Dataset, Tick};
+use anyhow::format_err;
+use chrono::{DateTime, Duration, NaiveDate, NaiveDateTime, Utc};
+use num::NumCast;
+
+/// A series of feature rows at a coarser rate than the ticks they are attached to. Each row is attached to every tick
+/// from the time it becomes available until the next row becomes available.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuxiliarySeries {
+    /// The name of each feature
+    pub names: Vec<String>,
+    /// The time each row becomes available, sorted
+    times: Vec<NaiveDateTime>,
+    /// The features of each row
+    values: Vec<Vec<f32>>,
+}
+
+impl AuxiliarySeries {
+    /// Create a series from rows of features observed at given times, which become available a given delay after they
+    /// are observed, e.g. a day after the start of a daily bar. If several rows become available at the same time,
+    /// the last is used. Returns an error if a row does not have one value per name.
+    pub fn new(
+        names: Vec<String>,
+        mut rows: Vec<(NaiveDateTime, Vec<f32>)>,
+        delay: Duration,
+    ) -> anyhow::Result<AuxiliarySeries> {
+        if let Some((t, row)) = rows.iter().find(|(_, row)| row.len() != names.len()) {
+            return Err(format_err!(
+                "Row at {} has {} values, but {} features were named",
+                t,
+                row.len(),
+                names.len()
+            ));
+        }
+        // A stable sort keeps rows available at the same time in their original order, so the last can be kept
+        rows.sort_by_key(|(t, _)| *t);
+        let mut times: Vec<NaiveDateTime> = Vec::with_capacity(rows.len());
+        let mut values: Vec<Vec<f32>> = Vec::with_capacity(rows.len());
+        for (t, row) in rows {
+            let t = t + delay;
+            if times.last() == Some(&t) {
+                *values.last_mut().expect("One row per time") = row;
+            } else {
+                times.push(t);
+                values.push(row);
+            }
+        }
+        Ok(AuxiliarySeries {
+            names,
+            times,
+            values,
+        })
+    }
+    /// Create a series of daily rows, each attached to every tick on its date. The features of a date must only use
+    /// information available before that date's first tick, e.g. indicators computed from the previous day's close.
+    pub fn daily(
+        names: Vec<String>,
+        rows: Vec<(NaiveDate, Vec<f32>)>,
+    ) -> anyhow::Result<AuxiliarySeries> {
+        let rows = rows
+            .into_iter()
+            .map(|(date, row)| (date.and_hms(0, 0, 0), row))
+            .collect();
+        AuxiliarySeries::new(names, rows, Duration::zero())
+    }
+    /// Create a series of the features of daily bars: each day's close return, high-low range relative to its close,
+    /// and log volume. Each day's features become available at the start of the next day, so that intraday ticks
+    /// never see the close of their own day.
+    pub fn from_daily_bars<F>(bars: &[Tick<F>]) -> AuxiliarySeries
+    where
+        F: Copy + NumCast,
+    {
+        let value = |x: F| -> f32 { NumCast::from(x).unwrap_or(0.0) };
+        let mut previous_close = None;
+        let rows = bars
+            .iter()
+            .map(|bar| {
+                let (c, h, l, v) = (value(bar.c), value(bar.h), value(bar.l), value(bar.v));
+                let ret = match previous_close {
+                    Some(previous) if previous > 0.0 => c / previous - 1.0,
+                    _ => 0.0,
+                };
+                let range = if c > 0.0 { (h - l) / c } else { 0.0 };
+                previous_close = Some(c);
+                (
+                    bar.t.date().and_hms(0, 0, 0),
+                    vec![ret, range, v.max(0.0).ln_1p()],
+                )
+            })
+            .collect();
+        let names = vec![
+            "daily_return".to_string(),
+            "daily_range".to_string(),
+            "daily_log_volume".to_string(),
+        ];
+        AuxiliarySeries::new(names, rows, Duration::days(1)).expect("Three values per row")
+    }
+    /// Get the number of features in each row
+    pub fn width(&self) -> usize {
+        self.names.len()
+    }
+    /// Get the number of rows in this series
+    pub fn len(&self) -> usize {
+        self.times.len()
+    }
+    /// Whether this series has no rows
+    pub fn is_empty(&self) -> bool {
+        self.times.is_empty()
+    }
+    /// Get the latest row available at a given time, if any
+    pub fn row_at(&self, t: NaiveDateTime) -> Option<&[f32]> {
+        let ix = match self.times.binary_search(&t) {
+            Ok(ix) => ix,
+            Err(0) => return None,
+            Err(ix) => ix - 1,
+        };
+        Some(&self.values[ix])
+    }
+    /// Push the latest row available at a given time to an input vector, or zeros if none is available yet.
+    /// Guaranteed to write `width` data points.
+    pub fn push_row<F: NumCast>(&self, t: NaiveDateTime, dest: &mut Vec<F>) {
+        match self.row_at(t) {
+            Some(row) => dest.extend(
+                row.iter()
+                    .map(|value| NumCast::from(*value).expect("Features fit in F")),
+            ),
+            None => dest.extend(
+                std::iter::repeat_with(|| NumCast::from(0.0).expect("Zero fits in F"))
+                    .take(self.width()),
+            ),
+        }
+    }
+    /// Get the index of the latest row available at each of a sorted sequence of times, if any
+    pub fn align(&self, times: &[NaiveDateTime]) -> Vec<Option<usize>> {
+        let mut next = 0;
+        times
+            .iter()
+            .map(|t| {
+                // Both sequences are sorted, so the latest available row is found by a merge
+                while next < self.times.len() && self.times[next] <= *t {
+                    next += 1;
+                }
+                next.checked_sub(1)
+            })
+            .collect()
+    }
+    /// Get a time function pushing the latest row available at each time, paired with the number of date inputs it
+    /// pushes as returned by `clocks`, e.g. to compose with clocks using `compose_time_funcs` when streaming ticks
+    /// into a `Predictor`
+    pub fn time_func<F: NumCast>(&self) -> (usize, impl FnMut(DateTime<Utc>, &mut Vec<F>) + '_) {
+        (
+            self.width(),
+            move |time: DateTime<Utc>, dest: &mut Vec<F>| self.push_row(time.naive_utc(), dest),
+        )
+    }
+}
+
+impl<F: Copy> Dataset<F> {
+    /// Get the additional inputs of each row of this dataset from a set of auxiliary series, concatenating the latest
+    /// row of each series available at the row's time, zero filled where none is available yet, as taken by `Batches`
+    pub fn auxiliary_inputs(&self, series: &[AuxiliarySeries]) -> Vec<Vec<f32>> {
+        let width = series.iter().map(AuxiliarySeries::width).sum();
+        let mut inputs = vec![Vec::with_capacity(width); self.len()];
+        for series in series {
+            for (input, ix) in inputs.iter_mut().zip(series.align(self.times())) {
+                match ix {
+                    Some(ix) => input.extend_from_slice(&series.values[ix]),
+                    None => input.extend(std::iter::repeat(0.0).take(series.width())),
+                }
+            }
+        }
+        inputs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::Symbol;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn daily_features_are_forward_aligned() {
+        let day = |d: u32| NaiveDate::from_ymd(2020, 10, d);
+        let bar = |d: u32, c: f64| Tick {
+            t: day(d).and_hms(4, 0, 0),
+            o: c,
+            h: c * 1.1,
+            l: c * 0.9,
+            c,
+            v: 0.0,
+            vw: c,
+            n: 1.0,
+        };
+        let daily = AuxiliarySeries::from_daily_bars(&[bar(9, 100.0), bar(12, 110.0)]);
+        assert_eq!(daily.len(), 2);
+        assert_eq!(daily.width(), 3);
+
+        // Friday's features are first seen on Saturday, and carried through to Monday's close
+        assert_eq!(daily.row_at(day(9).and_hms(20, 0, 0)), None);
+        let friday = daily.row_at(day(12).and_hms(14, 30, 0)).unwrap();
+        assert_eq!(friday[0], 0.0);
+        assert!((friday[1] - 0.2).abs() < 1e-6);
+        let monday = daily.row_at(day(13).and_hms(14, 30, 0)).unwrap();
+        assert!((monday[0] - 0.1).abs() < 1e-6);
+
+        let minute = |d: u32, h: u32| Tick {
+            t: day(d).and_hms(h, 30, 0),
+            ..bar(d, 1.0)
+        };
+        let mut data = BTreeMap::new();
+        data.insert(
+            Symbol::from("AMD"),
+            vec![
+                minute(9, 14),
+                minute(12, 14),
+                minute(12, 15),
+                minute(13, 14),
+            ],
+        );
+        let dataset = Dataset::new(data);
+        let inputs = dataset.auxiliary_inputs(&[daily.clone()]);
+        assert_eq!(inputs[0], vec![0.0; 3]);
+        assert_eq!(inputs[1], friday);
+        assert_eq!(inputs[2], friday);
+        assert_eq!(inputs[3], monday);
+
+        let (width, mut time_func) = daily.time_func::<f32>();
+        let mut dest = Vec::new();
+        time_func(
+            DateTime::from_utc(day(13).and_hms(14, 30, 0), Utc),
+            &mut dest,
+        );
+        assert_eq!(width, 3);
+        assert_eq!(dest, monday);
+    }
+}
@@ -1,16 +1,114 @@
 /*!
 [Polygon](https://polygon.io/)-specific data processing code
 */
-use super::Tick;
-use chrono::NaiveDateTime;
-use csv;
+use super::{files::decompress, quote::Quote, trades::Trade, Tick};
+use chrono::{DateTime, NaiveDateTime};
+use csv::{self, StringRecord};
 use std::io::{Read, Write};
 use std::str::FromStr;
 
 /// The polygon DateTime format
 pub const POLYGON_DATETIME: &str = "%Y-%m-%d %H:%M:%S";
 
-/// Read polygon tick data from a Reader
+/// A format for the timestamps of tick data
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub enum TimestampFormat {
+    /// A `strftime`-style format for a UTC timestamp, such as `POLYGON_DATETIME`
+    Strftime(String),
+    /// An RFC 3339 timestamp, converted to UTC
+    Rfc3339,
+    /// Seconds since the Unix epoch
+    EpochSeconds,
+    /// Milliseconds since the Unix epoch, as used by Polygon's v2 API
+    EpochMillis,
+    /// Nanoseconds since the Unix epoch
+    EpochNanos,
+}
+
+/// The format `chrono` uses to serialize `NaiveDateTime`s
+pub const ISO_DATETIME: &str = "%Y-%m-%dT%H:%M:%S%.f";
+
+impl TimestampFormat {
+    /// Parse a timestamp in this format
+    pub fn parse(&self, timestamp: &str) -> Option<NaiveDateTime> {
+        let timestamp = timestamp.trim();
+        let from_epoch = |units_per_sec: i64| {
+            let units = i64::from_str(timestamp)
+                .ok()
+                .or_else(|| f64::from_str(timestamp).ok().map(|units| units as i64))?;
+            let secs = units.div_euclid(units_per_sec);
+            let nanos = units.rem_euclid(units_per_sec) * (1_000_000_000 / units_per_sec);
+            NaiveDateTime::from_timestamp_opt(secs, nanos as u32)
+        };
+        match self {
+            TimestampFormat::Strftime(format) => {
+                NaiveDateTime::parse_from_str(timestamp, format).ok()
+            }
+            TimestampFormat::Rfc3339 => DateTime::parse_from_rfc3339(timestamp)
+                .ok()
+                .map(|t| t.naive_utc()),
+            TimestampFormat::EpochSeconds => from_epoch(1),
+            TimestampFormat::EpochMillis => from_epoch(1_000),
+            TimestampFormat::EpochNanos => from_epoch(1_000_000_000),
+        }
+    }
+    /// Detect the format of a timestamp, if it is in a supported format.
+    ///
+    /// Epoch timestamps are told apart by magnitude, assuming they lie between 1973 and 2286.
+    pub fn detect(timestamp: &str) -> Option<TimestampFormat> {
+        let timestamp = timestamp.trim();
+        if let Ok(units) = f64::from_str(timestamp) {
+            let units = units.abs();
+            return if units >= 1e17 {
+                Some(TimestampFormat::EpochNanos)
+            } else if units >= 1e11 {
+                Some(TimestampFormat::EpochMillis)
+            } else {
+                Some(TimestampFormat::EpochSeconds)
+            };
+        }
+        let candidates = [
+            TimestampFormat::Rfc3339,
+            TimestampFormat::Strftime(POLYGON_DATETIME.to_owned()),
+            TimestampFormat::Strftime(ISO_DATETIME.to_owned()),
+        ];
+        candidates
+            .iter()
+            .find(|format| format.parse(timestamp).is_some())
+            .cloned()
+    }
+}
+
+/// Parse a tick from a CSV record whose first field is the timestamp, followed by `v, vw, o, c, h, l, n`
+fn parse_tick(record: &StringRecord, format: &TimestampFormat) -> Option<Tick> {
+    let mut record = record.iter();
+    let first = record.next()?;
+    let t = format.parse(first)?;
+    let mut tick = Tick {
+        t,
+        v: f64::NAN,
+        vw: f64::NAN,
+        o: f64::NAN,
+        c: f64::NAN,
+        h: f64::NAN,
+        l: f64::NAN,
+        n: f64::NAN,
+    };
+    for (i, field) in record.enumerate().take(7) {
+        match i {
+            0 => tick.v = f64::from_str(field).unwrap_or(f64::NAN),
+            1 => tick.vw = f64::from_str(field).unwrap_or(f64::NAN),
+            2 => tick.o = f64::from_str(field).unwrap_or(f64::NAN),
+            3 => tick.c = f64::from_str(field).unwrap_or(f64::NAN),
+            4 => tick.h = f64::from_str(field).unwrap_or(f64::NAN),
+            5 => tick.l = f64::from_str(field).unwrap_or(f64::NAN),
+            _ => tick.n = f64::from_str(field).unwrap_or(f64::NAN),
+        }
+    }
+    Some(tick)
+}
+
+/// Read polygon tick data from a Reader, which may be gzip or zstd compressed
 pub fn read_ticks<R: Read>(rdr: R, date_format: Option<&str>) -> Vec<Tick> {
     let date_format = if let Some(format) = date_format {
         format
@@ -19,42 +117,138 @@ pub fn read_ticks<R: Read>(rdr: R, date_format: Option<&str>) -> Vec<Tick> {
             .filter_map(|result| result.ok())
             .collect();
     };
-    csv::Reader::from_reader(rdr)
+    read_ticks_with_format(rdr, &TimestampFormat::Strftime(date_format.to_owned()))
+}
+
+/// Read polygon tick data with timestamps in a given format from a Reader
+pub fn read_ticks_with_format<R: Read>(rdr: R, format: &TimestampFormat) -> Vec<Tick> {
+    csv::Reader::from_reader(decompress(rdr))
         .into_records()
-        .filter_map(|result| {
-            let record = result.ok()?;
-            let mut record = record.iter();
-            let first = record.next()?;
-            let t = NaiveDateTime::parse_from_str(first, date_format).ok()?;
-            let mut tick = Tick {
-                t,
-                v: f64::NAN,
-                vw: f64::NAN,
-                o: f64::NAN,
-                c: f64::NAN,
-                h: f64::NAN,
-                l: f64::NAN,
-                n: f64::NAN,
-            };
-            for (i, field) in record.enumerate().take(7) {
-                match i {
-                    0 => tick.v = f64::from_str(field).unwrap_or(f64::NAN),
-                    1 => tick.vw = f64::from_str(field).unwrap_or(f64::NAN),
-                    2 => tick.o = f64::from_str(field).unwrap_or(f64::NAN),
-                    3 => tick.c = f64::from_str(field).unwrap_or(f64::NAN),
-                    4 => tick.h = f64::from_str(field).unwrap_or(f64::NAN),
-                    5 => tick.l = f64::from_str(field).unwrap_or(f64::NAN),
-                    _ => tick.n = f64::from_str(field).unwrap_or(f64::NAN),
-                }
-            }
-            Some(tick)
+        .filter_map(|result| parse_tick(&result.ok()?, format))
+        .collect()
+}
+
+/// Read polygon tick data from a Reader, detecting the timestamp format from the first record
+pub fn read_ticks_auto<R: Read>(rdr: R) -> Vec<Tick> {
+    let mut records = csv::Reader::from_reader(decompress(rdr))
+        .into_records()
+        .filter_map(|result| result.ok())
+        .peekable();
+    let format = records
+        .peek()
+        .and_then(|record| TimestampFormat::detect(record.get(0)?));
+    if let Some(format) = format {
+        records
+            .filter_map(|record| parse_tick(&record, &format))
+            .collect()
+    } else {
+        Vec::new()
+    }
+}
+
+/// Find the index of the first matching column for each field, given the names each field may have, in order of
+/// preference. Column names are case sensitive, since e.g. Polygon's `p` and `P` differ.
+fn resolve_columns(headers: &StringRecord, fields: &[&[&str]]) -> Option<Vec<usize>> {
+    fields
+        .iter()
+        .map(|names| {
+            names
+                .iter()
+                .find_map(|name| headers.iter().position(|header| header.trim() == *name))
         })
         .collect()
 }
 
+/// The column names of each field of a Polygon quote, in order of preference: Polygon's v2 API uses single letter
+/// names, whereas its flat files use descriptive names
+const QUOTE_COLUMNS: [&[&str]; 5] = [
+    &["sip_timestamp", "t"],
+    &["bid_price", "p"],
+    &["ask_price", "P"],
+    &["bid_size", "s"],
+    &["ask_size", "S"],
+];
+
+/// Read Polygon NBBO quote data from a Reader, which may be gzip or zstd compressed, detecting the timestamp format
+/// from the first record. Records which cannot be parsed are skipped.
+pub fn read_quotes<R: Read>(rdr: R) -> Vec<Quote> {
+    let mut rdr = csv::Reader::from_reader(decompress(rdr));
+    let headers = match rdr.headers() {
+        Ok(headers) => headers.clone(),
+        Err(_) => return Vec::new(),
+    };
+    let columns = match resolve_columns(&headers, &QUOTE_COLUMNS) {
+        Some(columns) => columns,
+        None => return Vec::new(),
+    };
+    let mut records = rdr
+        .into_records()
+        .filter_map(|result| result.ok())
+        .peekable();
+    let format = match records
+        .peek()
+        .and_then(|record| TimestampFormat::detect(record.get(columns[0])?))
+    {
+        Some(format) => format,
+        None => return Vec::new(),
+    };
+    let parse_quote = |record: StringRecord| -> Option<Quote> {
+        let field = |i: usize| f64::from_str(record.get(columns[i])?.trim()).ok();
+        Some(Quote {
+            t: format.parse(record.get(columns[0])?)?,
+            bid: field(1)?,
+            ask: field(2)?,
+            bid_size: field(3)?,
+            ask_size: field(4)?,
+        })
+    };
+    records.filter_map(parse_quote).collect()
+}
+
+/// The column names of each field of a Polygon trade, in order of preference
+const TRADE_COLUMNS: [&[&str]; 3] = [&["sip_timestamp", "t"], &["price", "p"], &["size", "s"]];
+
+/// Read raw Polygon trade data from a Reader, which may be gzip or zstd compressed, detecting the timestamp format
+/// from the first record. Trades are read lazily, so that large archives can be aggregated into bars on the fly.
+/// Records which cannot be parsed are skipped.
+pub fn read_trades<'a, R: Read + 'a>(rdr: R) -> impl Iterator<Item = Trade> + 'a {
+    let mut rdr = csv::Reader::from_reader(decompress(rdr));
+    let columns = rdr
+        .headers()
+        .ok()
+        .and_then(|headers| resolve_columns(headers, &TRADE_COLUMNS));
+    let mut records = rdr
+        .into_records()
+        .filter_map(|result| result.ok())
+        .peekable();
+    let format = match &columns {
+        Some(columns) => records
+            .peek()
+            .and_then(|record| TimestampFormat::detect(record.get(columns[0])?)),
+        None => None,
+    };
+    // Without columns or a timestamp format, no trades can be read
+    let records = if format.is_some() {
+        Some(records)
+    } else {
+        None
+    };
+    records.into_iter().flatten().filter_map(move |record| {
+        let columns = columns.as_ref()?;
+        let field = |i: usize| f64::from_str(record.get(columns[i])?.trim()).ok();
+        Some(Trade {
+            t: format.as_ref()?.parse(record.get(columns[0])?)?,
+            price: field(1)?,
+            size: field(2)?,
+        })
+    })
+}
+
 /// Deserialize tick data
-pub fn deserialize_ticks<R: Read>(rdr: R) -> impl Iterator<Item = Result<Tick, csv::Error>> {
-    csv::Reader::from_reader(rdr).into_deserialize()
+pub fn deserialize_ticks<'a, R: Read + 'a>(
+    rdr: R,
+) -> impl Iterator<Item = Result<Tick, csv::Error>> + 'a {
+    csv::Reader::from_reader(decompress(rdr)).into_deserialize()
 }
 
 /// Write tick data to a Writer
@@ -72,3 +266,39 @@ where
     }
     Ok(written)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    #[test]
+    fn timestamp_detection() {
+        let t = NaiveDate::from_ymd(2020, 10, 12).and_hms(14, 30, 0);
+        for timestamp in &[
+            "1602513000",
+            "1602513000000",
+            "1602513000000000000",
+            "2020-10-12T10:30:00-04:00",
+            "2020-10-12 14:30:00",
+            "2020-10-12T14:30:00",
+        ] {
+            let format = TimestampFormat::detect(timestamp).unwrap();
+            assert_eq!(format.parse(timestamp), Some(t), "{:?}", format);
+        }
+        assert_eq!(TimestampFormat::detect("yesterday"), None);
+    }
+
+    #[test]
+    fn quote_parsing() {
+        let data = "t,y,p,x,s,P,X,S\n1602513000000000000,0,9.5,11,3,10.5,12,1\n";
+        let quotes = read_quotes(data.as_bytes());
+        assert_eq!(quotes.len(), 1);
+        assert_eq!(
+            quotes[0].t,
+            NaiveDate::from_ymd(2020, 10, 12).and_hms(14, 30, 0)
+        );
+        assert_eq!(quotes[0].bid, 9.5);
+        assert_eq!(quotes[0].ask_size, 1.0);
+    }
+}
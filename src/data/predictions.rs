@@ -0,0 +1,354 @@
+/*!
+Writing streams of predictions, together with their symbol, horizon and realized values, to CSV or
+[Parquet](https://parquet.apache.org/) files with a stable schema, for consumption by backtesting and analysis tools.
+Parquet output is enabled by the `parquet` feature.
+*/
+use super::{Prediction, Symbol, TargetKind};
+use anyhow::format_err;
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufWriter, Read, Write};
+use std::path::Path;
+
+/// The columns of a predictions file, in order. Columns are only ever appended to this list, so readers may rely on
+/// the position of existing columns.
+pub const COLUMNS: [&str; 8] = [
+    "symbol", "t", "horizon", "kind", "c", "v", "actual_c", "actual_v",
+];
+
+/// A prediction of a symbol's tick some rows ahead, together with the realized tick, if known
+#[derive(Debug, Clone, PartialEq)]
+pub struct PredictionRecord {
+    /// The symbol predicted
+    pub symbol: Symbol,
+    /// The time of the last tick the prediction was made from
+    pub t: NaiveDateTime,
+    /// How many rows ahead of `t` the predicted tick is
+    pub horizon: usize,
+    /// The prediction
+    pub prediction: Prediction,
+    /// The realized value of the prediction, expressed like the prediction, if known
+    pub actual: Option<Prediction>,
+}
+
+/// A row of a predictions file, with one field per column of `COLUMNS`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct PredictionRow {
+    symbol: Symbol,
+    t: NaiveDateTime,
+    horizon: usize,
+    kind: TargetKind,
+    c: f64,
+    v: f64,
+    actual_c: Option<f64>,
+    actual_v: Option<f64>,
+}
+
+impl From<&PredictionRecord> for PredictionRow {
+    fn from(record: &PredictionRecord) -> PredictionRow {
+        PredictionRow {
+            symbol: record.symbol.clone(),
+            t: record.t,
+            horizon: record.horizon,
+            kind: record.prediction.kind,
+            c: record.prediction.c,
+            v: record.prediction.v,
+            actual_c: record.actual.map(|actual| actual.c),
+            actual_v: record.actual.map(|actual| actual.v),
+        }
+    }
+}
+
+impl From<PredictionRow> for PredictionRecord {
+    fn from(row: PredictionRow) -> PredictionRecord {
+        let kind = row.kind;
+        PredictionRecord {
+            symbol: row.symbol,
+            t: row.t,
+            horizon: row.horizon,
+            prediction: Prediction {
+                c: row.c,
+                v: row.v,
+                kind,
+            },
+            actual: match (row.actual_c, row.actual_v) {
+                (Some(c), Some(v)) => Some(Prediction { c, v, kind }),
+                _ => None,
+            },
+        }
+    }
+}
+
+/// The format of a predictions file
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum PredictionFormat {
+    /// CSV with a header row
+    Csv,
+    /// Parquet, which requires the `parquet` feature
+    Parquet,
+}
+
+impl PredictionFormat {
+    /// Guess the format of a file from its extension, defaulting to CSV
+    pub fn from_path<P: AsRef<Path>>(path: P) -> PredictionFormat {
+        match path.as_ref().extension().and_then(|ext| ext.to_str()) {
+            Some("parquet") | Some("pq") => PredictionFormat::Parquet,
+            _ => PredictionFormat::Csv,
+        }
+    }
+}
+
+/// Write prediction records to a CSV writer, with a header row naming the `COLUMNS`. Missing actual values are
+/// written as empty fields.
+pub fn write_csv<W, I>(wtr: W, records: I) -> anyhow::Result<()>
+where
+    W: Write,
+    I: IntoIterator<Item = PredictionRecord>,
+{
+    let mut wtr = csv::WriterBuilder::new()
+        .has_headers(false)
+        .from_writer(wtr);
+    wtr.write_record(&COLUMNS)?;
+    for record in records {
+        wtr.serialize(PredictionRow::from(&record))?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+/// Read prediction records written by `write_csv`
+pub fn read_csv<R: Read>(rdr: R) -> anyhow::Result<Vec<PredictionRecord>> {
+    let mut rdr = csv::Reader::from_reader(rdr);
+    let mut records = Vec::new();
+    for row in rdr.deserialize() {
+        let row: PredictionRow = row?;
+        records.push(row.into());
+    }
+    Ok(records)
+}
+
+/// The Parquet schema of a predictions file, with the `COLUMNS` in order
+#[cfg(feature = "parquet")]
+const PARQUET_SCHEMA: &str = "
+message prediction {
+    REQUIRED BYTE_ARRAY symbol (UTF8);
+    REQUIRED INT64 t (TIMESTAMP_MILLIS);
+    REQUIRED INT64 horizon;
+    REQUIRED BYTE_ARRAY kind (UTF8);
+    REQUIRED DOUBLE c;
+    REQUIRED DOUBLE v;
+    OPTIONAL DOUBLE actual_c;
+    OPTIONAL DOUBLE actual_v;
+}
+";
+
+/// Write prediction records to a Parquet file as a single row group, with times in milliseconds since the Unix epoch
+#[cfg(feature = "parquet")]
+pub fn write_parquet<I>(file: File, records: I) -> anyhow::Result<()>
+where
+    I: IntoIterator<Item = PredictionRecord>,
+{
+    use parquet::column::writer::ColumnWriter;
+    use parquet::data_type::ByteArray;
+    use parquet::file::properties::WriterProperties;
+    use parquet::file::writer::{FileWriter, RowGroupWriter, SerializedFileWriter};
+    use parquet::schema::parser::parse_message_type;
+    use std::sync::Arc;
+
+    let rows: Vec<PredictionRow> = records.into_iter().map(|record| (&record).into()).collect();
+    let schema = Arc::new(parse_message_type(PARQUET_SCHEMA)?);
+    let properties = Arc::new(WriterProperties::builder().build());
+    let mut writer = SerializedFileWriter::new(file, schema, properties)?;
+    let mut row_group = writer.next_row_group()?;
+    let mut column = 0;
+    while let Some(mut column_writer) = row_group.next_column()? {
+        match (&mut column_writer, COLUMNS[column]) {
+            (ColumnWriter::ByteArrayColumnWriter(writer), name) => {
+                let values: Vec<ByteArray> = rows
+                    .iter()
+                    .map(|row| match name {
+                        "symbol" => ByteArray::from(row.symbol.0.as_str()),
+                        _ => ByteArray::from(row.kind.name()),
+                    })
+                    .collect();
+                writer.write_batch(&values, None, None)?;
+            }
+            (ColumnWriter::Int64ColumnWriter(writer), name) => {
+                let values: Vec<i64> = rows
+                    .iter()
+                    .map(|row| match name {
+                        "t" => row.t.timestamp_millis(),
+                        _ => row.horizon as i64,
+                    })
+                    .collect();
+                writer.write_batch(&values, None, None)?;
+            }
+            (ColumnWriter::DoubleColumnWriter(writer), name) => {
+                let values: Vec<Option<f64>> = rows
+                    .iter()
+                    .map(|row| match name {
+                        "c" => Some(row.c),
+                        "v" => Some(row.v),
+                        "actual_c" => row.actual_c,
+                        _ => row.actual_v,
+                    })
+                    .collect();
+                let present: Vec<f64> = values.iter().flatten().copied().collect();
+                if name.starts_with("actual") {
+                    let levels: Vec<i16> =
+                        values.iter().map(|value| value.is_some() as i16).collect();
+                    writer.write_batch(&present, Some(&levels), None)?;
+                } else {
+                    writer.write_batch(&present, None, None)?;
+                }
+            }
+            (_, name) => return Err(format_err!("Unexpected type of column {}", name)),
+        }
+        row_group.close_column(column_writer)?;
+        column += 1;
+    }
+    writer.close_row_group(row_group)?;
+    writer.close()?;
+    Ok(())
+}
+
+/// Write prediction records to a file in a given format, e.g. one guessed by `PredictionFormat::from_path`
+pub fn write<P, I>(path: P, records: I, format: PredictionFormat) -> anyhow::Result<()>
+where
+    P: AsRef<Path>,
+    I: IntoIterator<Item = PredictionRecord>,
+{
+    let path = path.as_ref();
+    let file = File::create(path)
+        .map_err(|err| format_err!("Error creating predictions file {:?}: {}", path, err))?;
+    match format {
+        PredictionFormat::Csv => write_csv(BufWriter::new(file), records),
+        #[cfg(feature = "parquet")]
+        PredictionFormat::Parquet => write_parquet(file, records),
+        #[cfg(not(feature = "parquet"))]
+        PredictionFormat::Parquet => Err(format_err!(
+            "Writing {:?} as Parquet requires the `parquet` feature",
+            path
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    /// Get a prediction of a return which was realized, and a prediction of a level which was not
+    fn records() -> Vec<PredictionRecord> {
+        let t = NaiveDate::from_ymd(2020, 10, 12).and_hms(14, 30, 0);
+        vec![
+            PredictionRecord {
+                symbol: Symbol::from("AMD"),
+                t,
+                horizon: 5,
+                prediction: Prediction {
+                    c: 0.01,
+                    v: -0.5,
+                    kind: TargetKind::Return,
+                },
+                actual: Some(Prediction {
+                    c: 0.02,
+                    v: 0.25,
+                    kind: TargetKind::Return,
+                }),
+            },
+            PredictionRecord {
+                symbol: Symbol::from("NVDA"),
+                t,
+                horizon: 1,
+                prediction: Prediction {
+                    c: 540.5,
+                    v: 1000.0,
+                    kind: TargetKind::Level,
+                },
+                actual: None,
+            },
+        ]
+    }
+
+    #[test]
+    fn csv_roundtrip() {
+        let records = records();
+        let mut csv = Vec::new();
+        write_csv(&mut csv, records.clone()).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let mut lines = csv.lines();
+        assert_eq!(lines.next(), Some(COLUMNS.join(",").as_str()));
+        assert_eq!(
+            lines.next(),
+            Some("AMD,2020-10-12T14:30:00,5,return,0.01,-0.5,0.02,0.25")
+        );
+        assert_eq!(
+            lines.next(),
+            Some("NVDA,2020-10-12T14:30:00,1,level,540.5,1000.0,,")
+        );
+        assert_eq!(read_csv(csv.as_bytes()).unwrap(), records);
+        assert_eq!(
+            PredictionFormat::from_path("out/predictions.parquet"),
+            PredictionFormat::Parquet
+        );
+    }
+
+    #[cfg(feature = "parquet")]
+    #[test]
+    fn parquet_roundtrip() {
+        use parquet::file::reader::{FileReader, SerializedFileReader};
+        use parquet::record::Field;
+
+        let records = records();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("predictions.parquet");
+        write(&path, records.clone(), PredictionFormat::Parquet).unwrap();
+        let reader = SerializedFileReader::new(File::open(&path).unwrap()).unwrap();
+        let rows: Vec<Vec<(String, Field)>> = reader
+            .get_row_iter(None)
+            .unwrap()
+            .map(|row| {
+                row.get_column_iter()
+                    .map(|(name, field)| (name.clone(), field.clone()))
+                    .collect()
+            })
+            .collect();
+        assert_eq!(rows.len(), 2);
+        for row in rows.iter() {
+            let names: Vec<&str> = row.iter().map(|(name, _)| name.as_str()).collect();
+            assert_eq!(names, COLUMNS);
+        }
+        let t = Field::TimestampMillis(records[0].t.timestamp_millis() as u64);
+        let fields = |row: &[(String, Field)]| -> Vec<Field> {
+            row.iter().map(|(_, field)| field.clone()).collect()
+        };
+        assert_eq!(
+            fields(&rows[0]),
+            vec![
+                Field::Str("AMD".into()),
+                t.clone(),
+                Field::Long(5),
+                Field::Str("return".into()),
+                Field::Double(0.01),
+                Field::Double(-0.5),
+                Field::Double(0.02),
+                Field::Double(0.25),
+            ]
+        );
+        assert_eq!(
+            fields(&rows[1]),
+            vec![
+                Field::Str("NVDA".into()),
+                t,
+                Field::Long(1),
+                Field::Str("level".into()),
+                Field::Double(540.5),
+                Field::Double(1000.0),
+                Field::Null,
+                Field::Null,
+            ]
+        );
+    }
+}
@@ -0,0 +1,150 @@
+/*!
+Level-1 quote data, and features derived from it
+*/
+use super::Tick;
+use crate::CpuFloat;
+use chrono::NaiveDateTime;
+use num::{Float, NumCast};
+use serde::{Deserialize, Serialize};
+
+/// A level-1 (best bid and offer) quote for a stock
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Quote<F = CpuFloat> {
+    /// This quote's timestamp in UTC
+    pub t: NaiveDateTime,
+    /// The best bid price
+    pub bid: F,
+    /// The best ask price
+    pub ask: F,
+    /// The size available at the best bid
+    pub bid_size: F,
+    /// The size available at the best ask
+    pub ask_size: F,
+}
+
+impl<F: Float> Quote<F> {
+    /// Get the bid-ask spread of this quote
+    pub fn spread(&self) -> F {
+        self.ask - self.bid
+    }
+    /// Get the midpoint of this quote
+    pub fn midpoint(&self) -> F {
+        (self.ask + self.bid) / (F::one() + F::one())
+    }
+}
+
+/// Microstructure features of the quotes during a tick
+#[derive(Debug, Copy, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct QuoteFeatures {
+    /// The spread of the last quote
+    pub spread: f32,
+    /// The midpoint of the last quote
+    pub midpoint: f32,
+    /// The spread of the last quote relative to its midpoint
+    pub relative_spread: f32,
+    /// The imbalance `(bid_size - ask_size) / (bid_size + ask_size)` of the last quote
+    pub imbalance: f32,
+}
+
+impl QuoteFeatures {
+    /// The number of fields quote features feed into a neural network
+    pub const NN_FIELDS: usize = 4; // (spread, midpoint, relative_spread, imbalance)
+
+    /// Compute the features of a quote
+    pub fn new<F: Float>(quote: &Quote<F>) -> QuoteFeatures {
+        let value = |x: F| -> f32 { NumCast::from(x).unwrap_or(0.0) };
+        let spread = value(quote.spread());
+        let midpoint = value(quote.midpoint());
+        let total_size = value(quote.bid_size + quote.ask_size);
+        QuoteFeatures {
+            spread,
+            midpoint,
+            relative_spread: if midpoint != 0.0 {
+                spread / midpoint
+            } else {
+                0.0
+            },
+            imbalance: if total_size != 0.0 {
+                value(quote.bid_size - quote.ask_size) / total_size
+            } else {
+                0.0
+            },
+        }
+    }
+    /// Push these features to an input vector. Guaranteed to write `NN_FIELDS` data points
+    pub fn push_features(&self, input: &mut Vec<f32>) {
+        input.push(self.spread);
+        input.push(self.midpoint);
+        input.push(self.relative_spread);
+        input.push(self.imbalance);
+    }
+}
+
+/// Compute the quote features of each tick in a time-sorted series, given time-sorted quotes for the same stock.
+///
+/// Each tick uses the last quote before the next tick, i.e. the quote prevailing at the end of its bar, carrying
+/// quotes forward over ticks without any. Ticks before the first quote get default (zero) features.
+pub fn quote_features<T, Q: Float>(ticks: &[Tick<T>], quotes: &[Quote<Q>]) -> Vec<QuoteFeatures> {
+    let mut result = Vec::with_capacity(ticks.len());
+    let mut quotes = quotes.iter().peekable();
+    let mut last: Option<&Quote<Q>> = None;
+    // The end of each tick's bar is the start of the next tick's, and the last tick's bar has no end
+    let ends = ticks
+        .iter()
+        .skip(1)
+        .map(|tick| Some(tick.t))
+        .chain(std::iter::once(None))
+        .take(ticks.len());
+    for end in ends {
+        while let Some(quote) = quotes.peek() {
+            if end.map(|end| quote.t >= end).unwrap_or(false) {
+                break;
+            }
+            last = quotes.next();
+        }
+        result.push(last.map(QuoteFeatures::new).unwrap_or_default());
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, NaiveDate};
+
+    #[test]
+    fn features_follow_ticks() {
+        let start = NaiveDate::from_ymd(2020, 10, 12).and_hms(14, 30, 0);
+        let ticks: Vec<Tick> = (0..3)
+            .map(|i| Tick {
+                t: start + Duration::minutes(i),
+                v: 1.0,
+                vw: 10.0,
+                o: 10.0,
+                c: 10.0,
+                h: 10.0,
+                l: 10.0,
+                n: 1.0,
+            })
+            .collect();
+        let quote = |seconds: i64, bid: f64, ask: f64| Quote {
+            t: start + Duration::seconds(seconds),
+            bid,
+            ask,
+            bid_size: 300.0,
+            ask_size: 100.0,
+        };
+        let quotes = [
+            quote(10, 9.0, 11.0),
+            quote(50, 9.5, 10.5),
+            quote(130, 9.0, 10.0),
+        ];
+        let features = quote_features(&ticks, &quotes);
+        assert_eq!(features.len(), 3);
+        assert_eq!(features[0].spread, 1.0);
+        assert_eq!(features[1].spread, 1.0);
+        assert_eq!(features[0].imbalance, 0.5);
+        assert_eq!(features[2].midpoint, 9.5);
+        assert_eq!(features[2].relative_spread, 1.0 / 9.5);
+    }
+}
@@ -0,0 +1,127 @@
+/*!
+A registry of the symbols a network is trained on, mapping each ticker to its stock index, i.e. the position of its
+columns in the network's inputs and outputs
+*/
+use super::Symbol;
+use anyhow::format_err;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+
+/// An ordered set of symbols, the `ix`th of which is stock `ix`. Serialized as a list of symbols in stock order.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(try_from = "Vec<Symbol>", into = "Vec<Symbol>")]
+pub struct SymbolRegistry {
+    /// The symbols, in stock order
+    symbols: Vec<Symbol>,
+    /// The stock index of each symbol
+    index: HashMap<Symbol, usize>,
+}
+
+impl PartialEq for SymbolRegistry {
+    fn eq(&self, other: &SymbolRegistry) -> bool {
+        self.symbols == other.symbols
+    }
+}
+
+impl Eq for SymbolRegistry {}
+
+impl SymbolRegistry {
+    /// Create a registry of symbols in stock order, returning an error if a symbol is repeated
+    pub fn new(symbols: Vec<Symbol>) -> anyhow::Result<SymbolRegistry> {
+        let mut registry = SymbolRegistry::default();
+        for symbol in symbols {
+            if registry.index(&symbol).is_some() {
+                return Err(format_err!("Symbol {} is registered twice", symbol));
+            }
+            registry.push(symbol);
+        }
+        Ok(registry)
+    }
+    /// Get the number of registered symbols
+    pub fn len(&self) -> usize {
+        self.symbols.len()
+    }
+    /// Whether no symbols are registered
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+    /// Get the registered symbols, in stock order
+    pub fn symbols(&self) -> &[Symbol] {
+        &self.symbols
+    }
+    /// Get the stock index of a symbol, if it is registered
+    pub fn index(&self, symbol: &Symbol) -> Option<usize> {
+        self.index.get(symbol).copied()
+    }
+    /// Get the symbol of a stock index, if any
+    pub fn symbol(&self, ix: usize) -> Option<&Symbol> {
+        self.symbols.get(ix)
+    }
+    /// Register a symbol as the next stock, e.g. one added by `train::grow_stocks`, returning its stock index. A symbol
+    /// which is already registered keeps its index.
+    pub fn push(&mut self, symbol: Symbol) -> usize {
+        if let Some(ix) = self.index(&symbol) {
+            return ix;
+        }
+        let ix = self.symbols.len();
+        self.index.insert(symbol.clone(), ix);
+        self.symbols.push(symbol);
+        ix
+    }
+    /// Label a sequence of per-stock values, e.g. the decoded predictions of a network, with their symbols. Values
+    /// past the last registered stock are dropped.
+    pub fn label<T>(&self, values: impl IntoIterator<Item = T>) -> BTreeMap<Symbol, T> {
+        self.symbols.iter().cloned().zip(values).collect()
+    }
+    /// Arrange values keyed by symbol, e.g. the latest tick of each symbol, in stock order, with `None` for registered
+    /// symbols without a value. Values of unregistered symbols are ignored.
+    pub fn arrange<T: Clone>(&self, values: &BTreeMap<Symbol, T>) -> Vec<Option<T>> {
+        self.symbols
+            .iter()
+            .map(|symbol| values.get(symbol).cloned())
+            .collect()
+    }
+}
+
+impl TryFrom<Vec<Symbol>> for SymbolRegistry {
+    type Error = anyhow::Error;
+
+    fn try_from(symbols: Vec<Symbol>) -> anyhow::Result<SymbolRegistry> {
+        SymbolRegistry::new(symbols)
+    }
+}
+
+impl From<SymbolRegistry> for Vec<Symbol> {
+    fn from(registry: SymbolRegistry) -> Vec<Symbol> {
+        registry.symbols
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn registry_maps_symbols_to_stocks() {
+        let mut registry =
+            SymbolRegistry::new(vec![Symbol::from("AMD"), Symbol::from("NVDA")]).unwrap();
+        assert_eq!(registry.index(&Symbol::from("NVDA")), Some(1));
+        assert_eq!(registry.push(Symbol::from("INTC")), 2);
+        assert_eq!(registry.push(Symbol::from("AMD")), 0);
+        assert_eq!(registry.symbol(2), Some(&Symbol::from("INTC")));
+
+        let labelled = registry.label(vec![1.0, 2.0, 3.0]);
+        assert_eq!(labelled[&Symbol::from("INTC")], 3.0);
+        let mut ticks = BTreeMap::new();
+        ticks.insert(Symbol::from("NVDA"), 5);
+        ticks.insert(Symbol::from("TSLA"), 7);
+        assert_eq!(registry.arrange(&ticks), vec![None, Some(5), None]);
+
+        let json = serde_json::to_string(&registry).unwrap();
+        assert_eq!(json, r#"["AMD","NVDA","INTC"]"#);
+        let parsed: SymbolRegistry = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, registry);
+        assert!(serde_json::from_str::<SymbolRegistry>(r#"["AMD","AMD"]"#).is_err());
+    }
+}
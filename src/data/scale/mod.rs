@@ -5,8 +5,14 @@ use super::Tick;
 use crate::{util::to_s, CpuFloat};
 use chrono::{DateTime, Duration, NaiveDateTime, Utc};
 use num::Float;
+use serde::{Deserialize, Serialize};
 use std::fmt::Debug;
 
+pub mod quantile;
+pub mod robust;
+pub use quantile::{QuantileFitter, QuantileTransformer};
+pub use robust::RobustScaler;
+
 /// A window for exponential scaling
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub struct ExpScaler<F = CpuFloat> {
@@ -72,6 +78,103 @@ where
     }
 }
 
+/// The default average decay parameter of an exponential scaler
+pub const DEFAULT_AVERAGE_DECAY: f64 = 0.999;
+/// The default range decay parameter of an exponential scaler
+pub const DEFAULT_RANGE_DECAY: f64 = 0.999;
+
+/// The decay parameters of an exponential scaler
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScalerParams<F = CpuFloat> {
+    /// The exponential moving average's decay parameter, per second
+    pub average_decay: F,
+    /// The range decay parameter, per update
+    pub range_decay: F,
+}
+
+impl<F: Copy + Float> ScalerParams<F> {
+    /// Create scaler parameters from decay parameters
+    pub fn new(average_decay: F, range_decay: F) -> ScalerParams<F> {
+        ScalerParams {
+            average_decay,
+            range_decay,
+        }
+    }
+    /// Create an exponential scaler with these parameters and a given starting value
+    pub fn start(&self, start: F) -> ExpScaler<F> {
+        ExpScaler::start(start, self.average_decay, self.range_decay)
+    }
+}
+
+impl<F: Float> Default for ScalerParams<F> {
+    fn default() -> ScalerParams<F> {
+        ScalerParams {
+            average_decay: F::from(DEFAULT_AVERAGE_DECAY).expect("Decay is representable"),
+            range_decay: F::from(DEFAULT_RANGE_DECAY).expect("Decay is representable"),
+        }
+    }
+}
+
+/// The parameters of the scaler of each field of a tick, since e.g. volume and trade counts move far faster than
+/// prices and call for faster decay
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TickScalerConfig<F = CpuFloat> {
+    /// The opening price scaler's parameters
+    pub o: ScalerParams<F>,
+    /// The high price scaler's parameters
+    pub h: ScalerParams<F>,
+    /// The low price scaler's parameters
+    pub l: ScalerParams<F>,
+    /// The closing price scaler's parameters
+    pub c: ScalerParams<F>,
+    /// The volume scaler's parameters
+    pub v: ScalerParams<F>,
+    /// The VWAP scaler's parameters
+    pub vw: ScalerParams<F>,
+    /// The trade count scaler's parameters
+    pub n: ScalerParams<F>,
+    /// The number of initial ticks which only update the scaler without being emitted, since scaled values are
+    /// meaningless while the range is still near zero
+    #[serde(default)]
+    pub warmup: usize,
+}
+
+impl<F: Copy + Float> TickScalerConfig<F> {
+    /// Use the same decay parameters for every field
+    pub fn uniform(average_decay: F, range_decay: F) -> TickScalerConfig<F> {
+        TickScalerConfig::uniform_params(ScalerParams::new(average_decay, range_decay))
+    }
+    /// Use the same parameters for every field
+    pub fn uniform_params(params: ScalerParams<F>) -> TickScalerConfig<F> {
+        TickScalerConfig::split(params, params)
+    }
+    /// Use one set of parameters for prices, i.e. `o`, `h`, `l`, `c` and `vw`, and another for volume and trade counts,
+    /// i.e. `v` and `n`
+    pub fn split(prices: ScalerParams<F>, activity: ScalerParams<F>) -> TickScalerConfig<F> {
+        TickScalerConfig {
+            o: prices,
+            h: prices,
+            l: prices,
+            c: prices,
+            v: activity,
+            vw: prices,
+            n: activity,
+            warmup: 0,
+        }
+    }
+    /// Only update scalers on their first `warmup` ticks, without emitting them
+    pub fn with_warmup(mut self, warmup: usize) -> TickScalerConfig<F> {
+        self.warmup = warmup;
+        self
+    }
+}
+
+impl<F: Float> Default for TickScalerConfig<F> {
+    fn default() -> TickScalerConfig<F> {
+        TickScalerConfig::uniform_params(ScalerParams::default())
+    }
+}
+
 /// An exponential scaler for stock market ticks
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub struct TickExpScaler<F> {
@@ -91,6 +194,8 @@ pub struct TickExpScaler<F> {
     pub vw: ExpScaler<F>,
     /// The scaler for the number of trades
     pub n: ExpScaler<F>,
+    /// The number of remaining warm-up ticks, which only update the scaler
+    pub warmup: usize,
 }
 
 impl<F> TickExpScaler<F> {
@@ -108,6 +213,7 @@ impl<F> TickExpScaler<F> {
             v: base.clone(),
             vw: base.clone(),
             n: base.clone(),
+            warmup: 0,
         }
     }
     /// Create a scaler with the given starting tick
@@ -122,6 +228,31 @@ impl<F> TickExpScaler<F> {
         scaler.set_start(tick);
         scaler
     }
+    /// Create a scaler with the given starting tick and the default parameters, `DEFAULT_AVERAGE_DECAY` and
+    /// `DEFAULT_RANGE_DECAY`, for every field
+    pub fn with_default_start(tick: Tick<F>) -> TickExpScaler<F>
+    where
+        F: Float,
+    {
+        TickExpScaler::with_config(tick, &TickScalerConfig::default())
+    }
+    /// Create a scaler with the given starting tick, with each field's scaler parameterized by a config
+    pub fn with_config(tick: Tick<F>, config: &TickScalerConfig<F>) -> TickExpScaler<F>
+    where
+        F: Float,
+    {
+        TickExpScaler {
+            t: tick.t,
+            o: config.o.start(tick.o),
+            h: config.h.start(tick.h),
+            l: config.l.start(tick.l),
+            c: config.c.start(tick.c),
+            v: config.v.start(tick.v),
+            vw: config.vw.start(tick.vw),
+            n: config.n.start(tick.n),
+            warmup: config.warmup,
+        }
+    }
     /// Set the starting tick for a tick scaler
     pub fn set_start(&mut self, tick: Tick<F>) {
         self.t = tick.t;
@@ -174,4 +305,116 @@ impl<F: Float + Copy> TickExpScaler<F> {
         self.update(tick);
         scaled_tick
     }
+    /// Whether this scaler is still warming up, i.e. whether `warm_tick` will not emit the next tick
+    #[inline]
+    pub fn warming_up(&self) -> bool {
+        self.warmup > 0
+    }
+    /// Feed a tick into the scaler, returning the scaled tick, or `None` if the scaler is still warming up
+    #[inline]
+    pub fn warm_tick(&mut self, tick: Tick<F>) -> Option<Tick<F>> {
+        if self.warming_up() {
+            self.warmup -= 1;
+            self.update(tick);
+            None
+        } else {
+            Some(self.tick(tick))
+        }
+    }
+}
+
+/// Scale a series of ticks, starting each field's scaler from the first tick, and dropping the ticks of the warm-up
+/// period
+pub fn scale_ticks<F: Float>(ticks: &[Tick<F>], config: &TickScalerConfig<F>) -> Vec<Tick<F>> {
+    let mut scaler = match ticks.first() {
+        Some(first) => TickExpScaler::with_config(*first, config),
+        None => return Vec::new(),
+    };
+    ticks
+        .iter()
+        .filter_map(|tick| scaler.warm_tick(*tick))
+        .collect()
+}
+
+/// Scale a series of ticks like `scale_ticks`, pairing each scaled tick with the raw tick it was scaled from, which
+/// return targets are computed from
+pub fn scale_ticks_with_raw<F: Float>(
+    ticks: &[Tick<F>],
+    config: &TickScalerConfig<F>,
+) -> Vec<(Tick<F>, Tick<F>)> {
+    let mut scaler = match ticks.first() {
+        Some(first) => TickExpScaler::with_config(*first, config),
+        None => return Vec::new(),
+    };
+    ticks
+        .iter()
+        .filter_map(|tick| Some((scaler.warm_tick(*tick)?, *tick)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    #[test]
+    fn fields_decay_with_their_own_parameters() {
+        let t = NaiveDate::from_ymd(2020, 10, 12).and_hms(14, 30, 0);
+        let tick = Tick {
+            t,
+            o: 10.0,
+            h: 10.0,
+            l: 10.0,
+            c: 10.0,
+            v: 10.0,
+            vw: 10.0,
+            n: 10.0,
+        };
+        assert_eq!(
+            TickExpScaler::with_default_start(tick),
+            TickExpScaler::with_start(tick, DEFAULT_AVERAGE_DECAY, DEFAULT_RANGE_DECAY)
+        );
+        let config =
+            TickScalerConfig::split(ScalerParams::new(0.999, 0.999), ScalerParams::new(0.5, 0.9));
+        let mut scaler = TickExpScaler::with_config(tick, &config);
+        assert_eq!(scaler.c, ExpScaler::start(10.0, 0.999, 0.999));
+        assert_eq!(scaler.v, ExpScaler::start(10.0, 0.5, 0.9));
+        let later = Tick {
+            t: t + Duration::seconds(1),
+            o: 20.0,
+            h: 20.0,
+            l: 20.0,
+            c: 20.0,
+            v: 20.0,
+            vw: 20.0,
+            n: 20.0,
+        };
+        scaler.update(later);
+        assert!((scaler.v.average - 15.0).abs() < 1e-9);
+        assert!(scaler.c.average < 10.1);
+        assert_eq!(scaler.v, scaler.n);
+        assert_eq!(scaler.c, scaler.vw);
+    }
+
+    #[test]
+    fn warmup_ticks_are_dropped() {
+        let t = NaiveDate::from_ymd(2020, 10, 12).and_hms(14, 30, 0);
+        let ticks: Vec<Tick> = (0..5)
+            .map(|ix| Tick {
+                t: t + Duration::minutes(ix),
+                o: 10.0 + ix as f64,
+                h: 10.0 + ix as f64,
+                l: 10.0 + ix as f64,
+                c: 10.0 + ix as f64,
+                v: 10.0,
+                vw: 10.0,
+                n: 10.0,
+            })
+            .collect();
+        let config = TickScalerConfig::default();
+        let scaled = scale_ticks(&ticks, &config.with_warmup(2));
+        assert_eq!(scaled.len(), 3);
+        assert_eq!(scaled[0].t, ticks[2].t);
+        assert_eq!(&scale_ticks(&ticks, &config)[2..], &scaled[..]);
+    }
 }
@@ -0,0 +1,343 @@
+/*!
+Quantile (Gauss-rank) transforms, mapping each feature to an approximately standard normal distribution through the
+quantiles of its values on a training set.
+
+Fitting takes two passes over the training data: the first feeds every row to a `QuantileFitter`, which keeps a bounded
+reservoir sample of each feature, and the quantiles of the samples then give a `QuantileTransformer` applied on the
+second pass, and at inference. The transformer is serializable, to be stored with a checkpoint's
+`train::Preprocessing`.
+
+Transforms fitted to scaled ticks with `QuantileTransformer::fit_ticks` are the last step of scaling: `transform_tick`
+maps each field of a scaled tick, pooled over stocks, before it is batched or fed to a `predict::Predictor`.
+*/
+use crate::data::Tick;
+use crate::CpuFloat;
+use anyhow::format_err;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+/// The default number of quantiles stored per feature
+pub const DEFAULT_QUANTILES: usize = 1000;
+/// The default number of values sampled per feature to compute quantiles
+pub const DEFAULT_SUBSAMPLE: usize = 100_000;
+/// The probability at which transformed values are clipped, bounding them within about 5.2 standard deviations
+pub const CLIP_PROBABILITY: f64 = 1e-7;
+
+/// Collect reservoir samples of each feature of the rows of a training set, to fit a `QuantileTransformer`
+#[derive(Debug, Clone)]
+pub struct QuantileFitter {
+    /// The maximum number of values sampled per feature
+    subsample: usize,
+    /// The values sampled for each feature
+    samples: Vec<Vec<CpuFloat>>,
+    /// The number of finite values seen for each feature
+    seen: Vec<usize>,
+}
+
+impl QuantileFitter {
+    /// Create a fitter for rows of `features` values, sampling at most `subsample` values per feature
+    pub fn new(features: usize, subsample: usize) -> QuantileFitter {
+        QuantileFitter {
+            subsample: subsample.max(1),
+            samples: vec![Vec::new(); features],
+            seen: vec![0; features],
+        }
+    }
+    /// Get the number of features of each row
+    pub fn features(&self) -> usize {
+        self.samples.len()
+    }
+    /// Add a row of features, ignoring NaN and infinite values. Panics if the row has the wrong number of features.
+    pub fn push<R: Rng>(&mut self, row: &[CpuFloat], rng: &mut R) {
+        assert_eq!(row.len(), self.features(), "Wrong number of features");
+        let subsample = self.subsample;
+        for ((value, samples), seen) in row
+            .iter()
+            .zip(self.samples.iter_mut())
+            .zip(self.seen.iter_mut())
+        {
+            if !value.is_finite() {
+                continue;
+            }
+            *seen += 1;
+            if samples.len() < subsample {
+                samples.push(*value);
+            } else {
+                let ix = rng.gen_range(0, *seen);
+                if ix < subsample {
+                    samples[ix] = *value;
+                }
+            }
+        }
+    }
+    /// Compute `quantiles` evenly spaced quantiles of each feature's samples. Returns an error if a feature has no
+    /// finite values.
+    pub fn finish(self, quantiles: usize) -> anyhow::Result<QuantileTransformer> {
+        let quantiles = quantiles.max(2);
+        let references = self
+            .samples
+            .into_iter()
+            .enumerate()
+            .map(|(feature, mut samples)| {
+                if samples.is_empty() {
+                    return Err(format_err!("Feature {} has no finite values", feature));
+                }
+                samples.sort_by(|l, r| l.partial_cmp(r).expect("Samples are finite"));
+                let last = (samples.len() - 1) as f64;
+                Ok((0..quantiles)
+                    .map(|ix| {
+                        let position = ix as f64 / (quantiles - 1) as f64 * last;
+                        let (lower, upper) = (position.floor(), position.ceil());
+                        let (low, high) = (samples[lower as usize], samples[upper as usize]);
+                        low + (high - low) * (position - lower)
+                    })
+                    .collect())
+            })
+            .collect::<anyhow::Result<_>>()?;
+        Ok(QuantileTransformer { references })
+    }
+}
+
+/// A transform mapping each feature to an approximately standard normal distribution, by the evenly spaced quantiles
+/// of its values on a training set
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QuantileTransformer {
+    /// The quantiles of each feature, in increasing order
+    pub references: Vec<Vec<CpuFloat>>,
+}
+
+impl QuantileTransformer {
+    /// Fit a transform to rows of `features` values in one call, when the training set fits in memory
+    pub fn fit<'a, I, R>(
+        rows: I,
+        features: usize,
+        quantiles: usize,
+        subsample: usize,
+        rng: &mut R,
+    ) -> anyhow::Result<QuantileTransformer>
+    where
+        I: IntoIterator<Item = &'a [CpuFloat]>,
+        R: Rng,
+    {
+        let mut fitter = QuantileFitter::new(features, subsample);
+        for row in rows {
+            fitter.push(row, rng);
+        }
+        fitter.finish(quantiles)
+    }
+    /// Fit a transform to the fields of scaled ticks, in the order of `Tick::NN_FIELD_NAMES`, pooled over stocks, to be
+    /// applied with `transform_tick`
+    pub fn fit_ticks<'a, I, R>(
+        ticks: I,
+        quantiles: usize,
+        subsample: usize,
+        rng: &mut R,
+    ) -> anyhow::Result<QuantileTransformer>
+    where
+        I: IntoIterator<Item = &'a Tick>,
+        R: Rng,
+    {
+        let mut fitter = QuantileFitter::new(Tick::NN_FIELDS, subsample);
+        for tick in ticks {
+            fitter.push(&tick.nn_fields(), rng);
+        }
+        fitter.finish(quantiles)
+    }
+    /// Get the number of features transformed
+    pub fn features(&self) -> usize {
+        self.references.len()
+    }
+    /// Get the position in `[0, 1]` of a value of a feature among that feature's quantiles, interpolating linearly
+    /// between quantiles and averaging over ties
+    pub fn probability(&self, feature: usize, value: CpuFloat) -> CpuFloat {
+        let references = &self.references[feature];
+        let last = references.len() - 1;
+        let search = |inclusive: bool| {
+            references
+                .binary_search_by(|reference| {
+                    if *reference < value || (inclusive && *reference == value) {
+                        std::cmp::Ordering::Less
+                    } else {
+                        std::cmp::Ordering::Greater
+                    }
+                })
+                .unwrap_err()
+        };
+        let (lower, upper) = (search(false), search(true));
+        let rank = if lower < upper {
+            (lower + upper - 1) as f64 / 2.0
+        } else if lower == 0 {
+            0.0
+        } else if lower > last {
+            last as f64
+        } else {
+            let (low, high) = (references[lower - 1], references[lower]);
+            (lower - 1) as f64 + (value - low) / (high - low)
+        };
+        rank / last as f64
+    }
+    /// Transform a value of a feature, returning 0 for NaN and infinities
+    pub fn transform_value(&self, feature: usize, value: CpuFloat) -> CpuFloat {
+        if !value.is_finite() {
+            return 0.0;
+        }
+        let probability = self
+            .probability(feature, value)
+            .max(CLIP_PROBABILITY)
+            .min(1.0 - CLIP_PROBABILITY);
+        normal_quantile(probability)
+    }
+    /// Transform a row of features in place. Panics if the row has the wrong number of features.
+    pub fn transform(&self, row: &mut [CpuFloat]) {
+        assert_eq!(row.len(), self.features(), "Wrong number of features");
+        for (feature, value) in row.iter_mut().enumerate() {
+            *value = self.transform_value(feature, *value);
+        }
+    }
+    /// Transform the fields of a scaled tick with a transform fitted by `fit_ticks`, leaving missing (NaN) fields
+    /// missing. Panics if the transform does not have `Tick::NN_FIELDS` features.
+    pub fn transform_tick(&self, tick: &Tick) -> Tick {
+        assert_eq!(self.features(), Tick::NN_FIELDS, "Not a transform of ticks");
+        let mut fields = tick.nn_fields();
+        for (feature, value) in fields.iter_mut().enumerate() {
+            if value.is_finite() {
+                *value = self.transform_value(feature, *value);
+            }
+        }
+        let [o, h, l, c, v, vw, n] = fields;
+        Tick {
+            t: tick.t,
+            o,
+            h,
+            l,
+            c,
+            v,
+            vw,
+            n,
+        }
+    }
+}
+
+/// The quantile function of the standard normal distribution, by Acklam's rational approximation, with a relative
+/// error below `1.2e-9` on `(0, 1)`
+pub fn normal_quantile(p: f64) -> f64 {
+    const A: [f64; 6] = [
+        -3.969683028665376e+01,
+        2.209460984245205e+02,
+        -2.759285104469687e+02,
+        1.383577518672690e+02,
+        -3.066479806614716e+01,
+        2.506628277459239e+00,
+    ];
+    const B: [f64; 5] = [
+        -5.447609879822406e+01,
+        1.615858368580409e+02,
+        -1.556989798598866e+02,
+        6.680131188771972e+01,
+        -1.328068155288572e+01,
+    ];
+    const C: [f64; 6] = [
+        -7.784894002430293e-03,
+        -3.223964580411365e-01,
+        -2.400758277161838e+00,
+        -2.549732539343734e+00,
+        4.374664141464968e+00,
+        2.938163982698783e+00,
+    ];
+    const D: [f64; 4] = [
+        7.784695709041462e-03,
+        3.224671290700398e-01,
+        2.445134137142996e+00,
+        3.754408661907416e+00,
+    ];
+    const LOW: f64 = 0.02425;
+    if p <= 0.0 {
+        return f64::NEG_INFINITY;
+    } else if p >= 1.0 {
+        return f64::INFINITY;
+    }
+    let tail = |q: f64| {
+        (((((C[0] * q + C[1]) * q + C[2]) * q + C[3]) * q + C[4]) * q + C[5])
+            / ((((D[0] * q + D[1]) * q + D[2]) * q + D[3]) * q + 1.0)
+    };
+    if p < LOW {
+        tail((-2.0 * p.ln()).sqrt())
+    } else if p > 1.0 - LOW {
+        -tail((-2.0 * (1.0 - p).ln()).sqrt())
+    } else {
+        let q = p - 0.5;
+        let r = q * q;
+        (((((A[0] * r + A[1]) * r + A[2]) * r + A[3]) * r + A[4]) * r + A[5]) * q
+            / (((((B[0] * r + B[1]) * r + B[2]) * r + B[3]) * r + B[4]) * r + 1.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    #[test]
+    fn skewed_features_are_gaussianized() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let rows: Vec<[f64; 2]> = (0..10000)
+            .map(|ix| [(ix as f64 / 1000.0).exp(), (ix % 2) as f64])
+            .collect();
+        let transformer =
+            QuantileTransformer::fit(rows.iter().map(|row| &row[..]), 2, 101, 20000, &mut rng)
+                .unwrap();
+        assert!(transformer.transform_value(0, (5.0f64).exp()).abs() < 1e-3);
+        assert!(
+            (transformer.transform_value(0, (7.0f64).exp()) - normal_quantile(0.7)).abs() < 1e-2
+        );
+        assert_eq!(
+            transformer.transform_value(0, 1e9),
+            normal_quantile(1.0 - CLIP_PROBABILITY)
+        );
+        assert_eq!(transformer.transform_value(0, f64::NAN), 0.0);
+        assert!((normal_quantile(0.975) - 1.959964).abs() < 1e-6);
+        let (zero, one) = (
+            transformer.transform_value(1, 0.0),
+            transformer.transform_value(1, 1.0),
+        );
+        assert!(zero < 0.0 && (zero + one).abs() < 1e-9);
+
+        let json = serde_json::to_string(&transformer).unwrap();
+        assert_eq!(
+            serde_json::from_str::<QuantileTransformer>(&json).unwrap(),
+            transformer
+        );
+    }
+
+    #[test]
+    fn tick_fields_are_transformed() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let t = chrono::NaiveDate::from_ymd(2020, 10, 12).and_hms(14, 30, 0);
+        let ticks: Vec<Tick> = (0..1000)
+            .map(|ix| {
+                let x = ix as f64;
+                Tick {
+                    t,
+                    o: x,
+                    h: x,
+                    l: x,
+                    c: x,
+                    v: -x,
+                    vw: x,
+                    n: x,
+                }
+            })
+            .collect();
+        let transformer = QuantileTransformer::fit_ticks(&ticks, 101, 1000, &mut rng).unwrap();
+        let median = Tick {
+            vw: f64::NAN,
+            ..ticks[500]
+        };
+        let transformed = transformer.transform_tick(&median);
+        assert_eq!(transformed.t, t);
+        assert!(transformed.c.abs() < 1e-2 && transformed.v.abs() < 1e-2);
+        assert!(transformed.vw.is_nan());
+        assert!(transformer.transform_tick(&ticks[900]).c > 1.0);
+        assert!(transformer.transform_tick(&ticks[900]).v < -1.0);
+    }
+}
@@ -0,0 +1,230 @@
+/*!
+Outlier-resistant scaling by a rolling median and median absolute deviation (MAD), estimated in constant memory with the
+P² algorithm of Jain and Chlamtac
+*/
+use super::clip;
+use crate::CpuFloat;
+use num::Float;
+
+/// The ratio of the standard deviation of a normal distribution to its MAD
+pub const MAD_TO_STD: f64 = 1.4826;
+
+/// A streaming estimate of a quantile by the P² algorithm, using five markers rather than storing observations
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct P2Quantile<F = CpuFloat> {
+    /// The quantile being estimated, in `[0, 1]`
+    p: F,
+    /// The marker heights; the first `count` are the observations so far, in order, until five have been made
+    heights: [F; 5],
+    /// The marker positions
+    positions: [F; 5],
+    /// The desired marker positions
+    desired: [F; 5],
+    /// The increments of the desired marker positions per observation
+    increments: [F; 5],
+    /// The number of observations so far
+    count: usize,
+}
+
+impl<F: Float> P2Quantile<F> {
+    /// Create an estimator of the `p`-quantile, e.g. `0.5` for the median
+    pub fn new(p: F) -> P2Quantile<F> {
+        let f = |x: f64| F::from(x).expect("Small constants are representable");
+        let two = f(2.0);
+        P2Quantile {
+            p,
+            heights: [F::zero(); 5],
+            positions: [f(0.0), f(1.0), f(2.0), f(3.0), f(4.0)],
+            desired: [f(0.0), two * p, f(4.0) * p, two + two * p, f(4.0)],
+            increments: [f(0.0), p / two, p, (F::one() + p) / two, F::one()],
+            count: 0,
+        }
+    }
+    /// Get the number of observations so far
+    pub fn count(&self) -> usize {
+        self.count
+    }
+    /// Get the current estimate of the quantile, or `None` if there have been no observations
+    pub fn estimate(&self) -> Option<F> {
+        match self.count {
+            0 => None,
+            count if count < 5 => {
+                let ix = (self.p * F::from(count - 1)?).round().to_usize()?;
+                Some(self.heights[ix.min(count - 1)])
+            }
+            _ => Some(self.heights[2]),
+        }
+    }
+    /// Add an observation, ignoring NaN
+    pub fn observe(&mut self, x: F) {
+        if x.is_nan() {
+            return;
+        }
+        if self.count < 5 {
+            let mut ix = self.count;
+            while ix > 0 && self.heights[ix - 1] > x {
+                self.heights[ix] = self.heights[ix - 1];
+                ix -= 1;
+            }
+            self.heights[ix] = x;
+            self.count += 1;
+            return;
+        }
+        self.count += 1;
+
+        // Find the cell containing the observation, extending the extreme markers if necessary
+        let q = &mut self.heights;
+        let cell = if x < q[0] {
+            q[0] = x;
+            0
+        } else if x >= q[4] {
+            q[4] = x;
+            3
+        } else {
+            (1..5).find(|&ix| x < q[ix]).expect("x < q[4]") - 1
+        };
+        for position in self.positions[cell + 1..].iter_mut() {
+            *position = *position + F::one();
+        }
+        for (desired, increment) in self.desired.iter_mut().zip(self.increments.iter()) {
+            *desired = *desired + *increment;
+        }
+
+        // Move the middle markers towards their desired positions
+        for ix in 1..4 {
+            let n = &mut self.positions;
+            let offset = self.desired[ix] - n[ix];
+            if (offset >= F::one() && n[ix + 1] - n[ix] > F::one())
+                || (offset <= -F::one() && n[ix - 1] - n[ix] < -F::one())
+            {
+                let d = offset.signum();
+                let parabolic = q[ix]
+                    + d / (n[ix + 1] - n[ix - 1])
+                        * ((n[ix] - n[ix - 1] + d) * (q[ix + 1] - q[ix]) / (n[ix + 1] - n[ix])
+                            + (n[ix + 1] - n[ix] - d) * (q[ix] - q[ix - 1]) / (n[ix] - n[ix - 1]));
+                q[ix] = if q[ix - 1] < parabolic && parabolic < q[ix + 1] {
+                    parabolic
+                } else {
+                    let neighbour = if d > F::zero() { ix + 1 } else { ix - 1 };
+                    q[ix] + d * (q[neighbour] - q[ix]) / (n[neighbour] - n[ix])
+                };
+                n[ix] = n[ix] + d;
+            }
+        }
+    }
+}
+
+/// A pair of estimators of the median and MAD of the same observations
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct MedianMad<F> {
+    median: P2Quantile<F>,
+    mad: P2Quantile<F>,
+}
+
+impl<F: Float> MedianMad<F> {
+    fn new() -> MedianMad<F> {
+        let half = F::from(0.5).expect("0.5 is representable");
+        MedianMad {
+            median: P2Quantile::new(half),
+            mad: P2Quantile::new(half),
+        }
+    }
+    fn observe(&mut self, x: F) {
+        self.median.observe(x);
+        let median = self.median.estimate().expect("Just observed");
+        self.mad.observe((x - median).abs());
+    }
+}
+
+/// A scaler standardizing values by a rolling median and MAD, which unlike `ExpScaler` barely reacts to single bad
+/// prints. Estimates are rolled over every `window` observations: scaling uses estimators fed at least the last
+/// `window`, and at most the last `2 * window`, observations, so memory use is constant.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct RobustScaler<F = CpuFloat> {
+    /// The number of observations after which estimates are rolled over
+    pub window: usize,
+    /// The number of standard deviations, estimated from the MAD, beyond which scaled values are clipped
+    pub clip: F,
+    /// The estimators used for scaling
+    current: MedianMad<F>,
+    /// The estimators of the most recent observations, which replace the current estimators once full
+    next: MedianMad<F>,
+}
+
+impl<F: Float> RobustScaler<F> {
+    /// Create a scaler rolling over its estimates every `window` observations, and clipping scaled values at `clip`
+    /// standard deviations
+    pub fn new(window: usize, clip: F) -> RobustScaler<F> {
+        RobustScaler {
+            window: window.max(1),
+            clip,
+            current: MedianMad::new(),
+            next: MedianMad::new(),
+        }
+    }
+    /// Get the current estimate of the median, if any values have been observed
+    pub fn median(&self) -> Option<F> {
+        self.current.median.estimate()
+    }
+    /// Get the current estimate of the MAD, if any values have been observed
+    pub fn mad(&self) -> Option<F> {
+        self.current.mad.estimate()
+    }
+    /// Scale a value by the current estimates, returning 0 for NaN and infinities, and before the MAD is positive
+    pub fn scale(&self, val: F) -> F {
+        if !val.is_finite() {
+            return F::zero();
+        }
+        let (median, mad) = match (self.median(), self.mad()) {
+            (Some(median), Some(mad)) if mad > F::zero() => (median, mad),
+            _ => return F::zero(),
+        };
+        let std = mad * F::from(MAD_TO_STD).expect("Constant is representable");
+        clip((val - median) / std, self.clip)
+    }
+    /// Update the estimates with a value, ignoring NaN and infinities
+    pub fn update(&mut self, val: F) {
+        if !val.is_finite() {
+            return;
+        }
+        self.current.observe(val);
+        if self.current.median.count() > self.window {
+            self.next.observe(val);
+            if self.next.median.count() >= self.window {
+                self.current = self.next;
+                self.next = MedianMad::new();
+            }
+        }
+    }
+    /// Feed a value into the scaler, and return the scaled value
+    pub fn tick(&mut self, val: F) -> F {
+        let scaled = self.scale(val);
+        self.update(val);
+        scaled
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn single_outliers_barely_move_estimates() {
+        let mut quantile = P2Quantile::new(0.5);
+        for x in (0..1001).map(|ix| (ix * 379 % 1001) as f64) {
+            quantile.observe(x);
+        }
+        assert!((quantile.estimate().unwrap() - 500.0).abs() < 10.0);
+
+        let mut scaler = RobustScaler::new(200, 3.0);
+        for ix in 0..1000 {
+            scaler.update(100.0 + (ix as f64).sin());
+        }
+        let (median, mad) = (scaler.median().unwrap(), scaler.mad().unwrap());
+        assert!((median - 100.0).abs() < 0.25);
+        assert_eq!(scaler.tick(1e6), 3.0);
+        assert!((scaler.median().unwrap() - median).abs() < 0.1);
+        assert!((scaler.mad().unwrap() - mad).abs() < 0.1);
+        assert_eq!(scaler.scale(f64::NAN), 0.0);
+    }
+}
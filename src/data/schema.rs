@@ -0,0 +1,200 @@
+/*!
+Configurable column mappings for reading tick data in arbitrary CSV formats
+*/
+use super::{files::decompress, polygon::TimestampFormat, Tick};
+use csv::{ReaderBuilder, StringRecord};
+use std::io::{self, Read};
+use std::str::FromStr;
+
+/// A reference to a column of a CSV file
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub enum Column {
+    /// A column with a given header, matched ignoring case and surrounding whitespace
+    Name(String),
+    /// A column at a given zero-based index
+    Index(usize),
+}
+
+impl From<&str> for Column {
+    fn from(name: &str) -> Column {
+        Column::Name(name.to_owned())
+    }
+}
+
+impl From<usize> for Column {
+    fn from(index: usize) -> Column {
+        Column::Index(index)
+    }
+}
+
+impl Column {
+    /// Resolve this column to an index, given a file's headers, if any
+    fn resolve(&self, headers: Option<&StringRecord>) -> Option<usize> {
+        match self {
+            Column::Index(index) => Some(*index),
+            Column::Name(name) => headers?
+                .iter()
+                .position(|header| header.trim().eq_ignore_ascii_case(name.trim())),
+        }
+    }
+}
+
+/// A mapping from the columns of a CSV file to the fields of a tick.
+///
+/// Fields without a column, or whose column is missing from a file, are read as NaN.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct CsvSchema {
+    /// The timestamp column
+    pub t: Column,
+    /// The volume column
+    pub v: Option<Column>,
+    /// The volume weighted average price column
+    pub vw: Option<Column>,
+    /// The opening price column
+    pub o: Option<Column>,
+    /// The closing price column
+    pub c: Option<Column>,
+    /// The high price column
+    pub h: Option<Column>,
+    /// The low price column
+    pub l: Option<Column>,
+    /// The number of trades column
+    pub n: Option<Column>,
+    /// The timestamp format. If `None`, the format is detected from the first record
+    pub timestamp: Option<TimestampFormat>,
+    /// Whether the file starts with a header row. Columns can only be referred to by name if it does
+    pub has_headers: bool,
+}
+
+impl CsvSchema {
+    /// The schema of Polygon tick data, as read by `polygon::read_ticks_auto`
+    pub fn polygon() -> CsvSchema {
+        CsvSchema {
+            t: Column::Index(0),
+            v: Some(Column::Index(1)),
+            vw: Some(Column::Index(2)),
+            o: Some(Column::Index(3)),
+            c: Some(Column::Index(4)),
+            h: Some(Column::Index(5)),
+            l: Some(Column::Index(6)),
+            n: Some(Column::Index(7)),
+            timestamp: None,
+            has_headers: true,
+        }
+    }
+}
+
+/// The resolved column indices of a schema's fields, in the order `t, v, vw, o, c, h, l, n`
+struct Indices([Option<usize>; 8]);
+
+impl Indices {
+    fn parse_tick(&self, record: &StringRecord, format: &TimestampFormat) -> Option<Tick> {
+        let t = format.parse(record.get(self.0[0]?)?)?;
+        let field = |i: usize| {
+            self.0[i]
+                .and_then(|ix| record.get(ix))
+                .and_then(|field| f64::from_str(field.trim()).ok())
+                .unwrap_or(f64::NAN)
+        };
+        Some(Tick {
+            t,
+            v: field(1),
+            vw: field(2),
+            o: field(3),
+            c: field(4),
+            h: field(5),
+            l: field(6),
+            n: field(7),
+        })
+    }
+}
+
+/// Read tick data in the format described by a schema from a Reader, which may be gzip or zstd compressed.
+///
+/// Records which cannot be parsed are skipped. Returns an error if the timestamp column cannot be found.
+pub fn read_ticks_with_schema<R: Read>(
+    rdr: R,
+    schema: &CsvSchema,
+) -> Result<Vec<Tick>, csv::Error> {
+    let mut rdr = ReaderBuilder::new()
+        .has_headers(schema.has_headers)
+        .flexible(true)
+        .from_reader(decompress(rdr));
+    let headers = if schema.has_headers {
+        Some(rdr.headers()?.clone())
+    } else {
+        None
+    };
+    let resolve =
+        |column: Option<&Column>| column.and_then(|column| column.resolve(headers.as_ref()));
+    let t = resolve(Some(&schema.t)).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Timestamp column {:?} not found", schema.t),
+        )
+    })?;
+    let indices = Indices([
+        Some(t),
+        resolve(schema.v.as_ref()),
+        resolve(schema.vw.as_ref()),
+        resolve(schema.o.as_ref()),
+        resolve(schema.c.as_ref()),
+        resolve(schema.h.as_ref()),
+        resolve(schema.l.as_ref()),
+        resolve(schema.n.as_ref()),
+    ]);
+    let mut records = rdr
+        .into_records()
+        .filter_map(|result| result.ok())
+        .peekable();
+    let format = match &schema.timestamp {
+        Some(format) => format.clone(),
+        None => match records
+            .peek()
+            .and_then(|record| TimestampFormat::detect(record.get(t)?))
+        {
+            Some(format) => format,
+            None => return Ok(Vec::new()),
+        },
+    };
+    Ok(records
+        .filter_map(|record| indices.parse_tick(&record, &format))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    #[test]
+    fn vendor_schema() {
+        let data = "Close,Volume,Time\n10.5,200,2020-10-12T14:30:00Z\n";
+        let schema = CsvSchema {
+            t: "time".into(),
+            v: Some("Volume".into()),
+            vw: None,
+            o: Some(Column::Name("Open".into())),
+            c: Some(0.into()),
+            h: None,
+            l: None,
+            n: None,
+            timestamp: None,
+            has_headers: true,
+        };
+        let ticks = read_ticks_with_schema(data.as_bytes(), &schema).unwrap();
+        assert_eq!(ticks.len(), 1);
+        assert_eq!(
+            ticks[0].t,
+            NaiveDate::from_ymd(2020, 10, 12).and_hms(14, 30, 0)
+        );
+        assert_eq!(ticks[0].c, 10.5);
+        assert_eq!(ticks[0].v, 200.0);
+        assert!(ticks[0].o.is_nan());
+        let missing = CsvSchema {
+            t: "date".into(),
+            ..schema
+        };
+        assert!(read_ticks_with_schema(data.as_bytes(), &missing).is_err());
+    }
+}
@@ -0,0 +1,250 @@
+/*!
+A compact, fixed-width binary cache format for tick data, which can be memory mapped and read without copying.
+
+A cache file consists of
+- the magic bytes `CACHE_MAGIC`
+- the number of symbols, as a little-endian `u64`
+- an index entry for each symbol, consisting of its name padded with zeros to `SYMBOL_LEN` bytes, followed by the
+  index of its first tick and its number of ticks as little-endian `u64`s
+- the ticks of every symbol, in index order, as `RawTick`s
+*/
+use super::super::{Symbol, Tick};
+use chrono::NaiveDateTime;
+use memmap::Mmap;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, Write};
+use std::mem::{align_of, size_of};
+use std::ops::Range;
+use std::path::Path;
+
+/// The magic bytes at the start of a tick cache file, including the format version
+pub const CACHE_MAGIC: [u8; 8] = *b"SBTICK01";
+
+/// The maximum length, in bytes, of a symbol in a tick cache file
+pub const SYMBOL_LEN: usize = 16;
+
+/// The size, in bytes, of an index entry
+const ENTRY_LEN: usize = SYMBOL_LEN + 16;
+
+/// A tick, in the fixed-width little-endian layout of a cache file
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[repr(C)]
+pub struct RawTick {
+    /// This tick's timestamp, in nanoseconds since the Unix epoch
+    pub t: i64,
+    /// The volume traded this tick
+    pub v: f64,
+    /// The volume weighted average price of this tick
+    pub vw: f64,
+    /// The opening price of this tick
+    pub o: f64,
+    /// The closing price of this tick
+    pub c: f64,
+    /// The high price of this tick
+    pub h: f64,
+    /// The low price of this tick
+    pub l: f64,
+    /// The number of trades which occured during this tick
+    pub n: f64,
+}
+
+impl From<Tick> for RawTick {
+    fn from(tick: Tick) -> RawTick {
+        RawTick {
+            t: tick.t.timestamp_nanos(),
+            v: tick.v,
+            vw: tick.vw,
+            o: tick.o,
+            c: tick.c,
+            h: tick.h,
+            l: tick.l,
+            n: tick.n,
+        }
+    }
+}
+
+impl From<RawTick> for Tick {
+    fn from(raw: RawTick) -> Tick {
+        Tick {
+            t: NaiveDateTime::from_timestamp(
+                raw.t.div_euclid(1_000_000_000),
+                raw.t.rem_euclid(1_000_000_000) as u32,
+            ),
+            v: raw.v,
+            vw: raw.vw,
+            o: raw.o,
+            c: raw.c,
+            h: raw.h,
+            l: raw.l,
+            n: raw.n,
+        }
+    }
+}
+
+impl RawTick {
+    /// Write this tick in little-endian byte order
+    fn write<W: Write>(&self, wtr: &mut W) -> io::Result<()> {
+        wtr.write_all(&self.t.to_le_bytes())?;
+        for field in &[self.v, self.vw, self.o, self.c, self.h, self.l, self.n] {
+            wtr.write_all(&field.to_le_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Write tick data for a set of symbols in the tick cache format
+pub fn write_cache<W: Write>(mut wtr: W, data: &BTreeMap<Symbol, Vec<Tick>>) -> io::Result<()> {
+    wtr.write_all(&CACHE_MAGIC)?;
+    wtr.write_all(&(data.len() as u64).to_le_bytes())?;
+    let mut start = 0;
+    for (symbol, ticks) in data {
+        let name = symbol.0.as_bytes();
+        if name.len() > SYMBOL_LEN || name.contains(&0) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Symbol {:?} cannot be written to a tick cache", symbol.0),
+            ));
+        }
+        let mut padded = [0; SYMBOL_LEN];
+        padded[..name.len()].copy_from_slice(name);
+        wtr.write_all(&padded)?;
+        wtr.write_all(&(start as u64).to_le_bytes())?;
+        wtr.write_all(&(ticks.len() as u64).to_le_bytes())?;
+        start += ticks.len();
+    }
+    for ticks in data.values() {
+        for tick in ticks {
+            RawTick::from(*tick).write(&mut wtr)?;
+        }
+    }
+    wtr.flush()
+}
+
+/// A memory mapped tick cache file
+#[derive(Debug)]
+pub struct TickCache {
+    map: Mmap,
+    index: BTreeMap<Symbol, Range<usize>>,
+    ticks_offset: usize,
+}
+
+impl TickCache {
+    /// Memory map a tick cache file, reading its index
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<TickCache> {
+        if cfg!(target_endian = "big") {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "Tick caches can only be mapped on little-endian targets",
+            ));
+        }
+        let file = File::open(path)?;
+        // Safety: the cache file must not be modified while mapped
+        let map = unsafe { Mmap::map(&file)? };
+        if map.len() < 16 || map[..8] != CACHE_MAGIC {
+            return Err(invalid_data("Not a tick cache file"));
+        }
+        let read_u64 = |offset: usize| {
+            let mut bytes = [0; 8];
+            bytes.copy_from_slice(&map[offset..offset + 8]);
+            u64::from_le_bytes(bytes) as usize
+        };
+        let symbols = read_u64(8);
+        let ticks_offset = symbols
+            .checked_mul(ENTRY_LEN)
+            .and_then(|len| len.checked_add(16))
+            .filter(|&offset| offset <= map.len())
+            .ok_or_else(|| invalid_data("Truncated tick cache index"))?;
+        let total = (map.len() - ticks_offset) / size_of::<RawTick>();
+        let mut index = BTreeMap::new();
+        for i in 0..symbols {
+            let entry = 16 + i * ENTRY_LEN;
+            let name = &map[entry..entry + SYMBOL_LEN];
+            let name = &name[..name.iter().position(|&b| b == 0).unwrap_or(SYMBOL_LEN)];
+            let name = std::str::from_utf8(name).map_err(|_| invalid_data("Invalid symbol"))?;
+            let start = read_u64(entry + SYMBOL_LEN);
+            let len = read_u64(entry + SYMBOL_LEN + 8);
+            if start
+                .checked_add(len)
+                .map(|end| end > total)
+                .unwrap_or(true)
+            {
+                return Err(invalid_data("Truncated tick cache data"));
+            }
+            index.insert(Symbol::from(name), start..start + len);
+        }
+        Ok(TickCache {
+            map,
+            index,
+            ticks_offset,
+        })
+    }
+    /// Iterate over the symbols in this cache, in order
+    pub fn symbols(&self) -> impl Iterator<Item = &Symbol> {
+        self.index.keys()
+    }
+    /// Get the ticks of a symbol without copying them, if it is in this cache
+    pub fn ticks(&self, symbol: &Symbol) -> Option<&[RawTick]> {
+        let range = self.index.get(symbol)?;
+        let ptr = self.map[self.ticks_offset..].as_ptr();
+        // The map is page aligned and the header is a multiple of 8 bytes long, so this should never fail
+        assert_eq!(ptr as usize % align_of::<RawTick>(), 0);
+        // Safety: `open` checked that the range is in bounds, and every bit pattern is a valid `RawTick`
+        let ticks = unsafe {
+            std::slice::from_raw_parts(
+                (ptr as *const RawTick).add(range.start),
+                range.end - range.start,
+            )
+        };
+        Some(ticks)
+    }
+    /// Copy every symbol's ticks out of this cache, in the same form as `load_dir`
+    pub fn load(&self) -> BTreeMap<Symbol, Vec<Tick>> {
+        self.index
+            .keys()
+            .map(|symbol| {
+                let ticks = self.ticks(symbol).unwrap_or(&[]);
+                let ticks = ticks.iter().map(|&raw| Tick::from(raw)).collect();
+                (symbol.clone(), ticks)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, NaiveDate};
+    use std::io::BufWriter;
+
+    #[test]
+    fn cache_roundtrip() {
+        let start = NaiveDate::from_ymd(2020, 10, 12).and_hms(14, 30, 0);
+        let mut data = BTreeMap::new();
+        for (s, symbol) in ["AMD", "MSFT"].iter().enumerate() {
+            let ticks: Vec<Tick> = (0..5 + s)
+                .map(|i| Tick {
+                    t: start + Duration::minutes(i as i64),
+                    v: (i + s) as f64,
+                    vw: 1.5,
+                    o: 1.0,
+                    c: 2.0,
+                    h: 3.0,
+                    l: 0.5,
+                    n: 7.0,
+                })
+                .collect();
+            data.insert(Symbol::from(*symbol), ticks);
+        }
+        let file = tempfile::NamedTempFile::new().unwrap();
+        write_cache(BufWriter::new(file.reopen().unwrap()), &data).unwrap();
+        let cache = TickCache::open(file.path()).unwrap();
+        assert_eq!(cache.symbols().count(), 2);
+        assert_eq!(cache.ticks(&Symbol::from("MSFT")).unwrap()[5].v, 6.0);
+        assert_eq!(cache.load(), data);
+    }
+}
@@ -0,0 +1,6 @@
+/*!
+Persistent stores for tick data, which can be queried without re-parsing CSV files
+*/
+pub mod binary;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
@@ -0,0 +1,182 @@
+/*!
+A [SQLite](https://sqlite.org/) tick store, enabled by the `sqlite` feature
+*/
+use super::super::{files, polygon::read_ticks_auto, Symbol, Tick};
+use chrono::NaiveDateTime;
+use rusqlite::{params, Connection, Result, Row, NO_PARAMS};
+use std::path::Path;
+
+/// The schema of the tick table. Ticks are keyed, and hence indexed, by symbol and time in nanoseconds since the
+/// Unix epoch. NaN fields are stored as `NULL`.
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS ticks (
+    symbol TEXT NOT NULL,
+    t INTEGER NOT NULL,
+    v REAL,
+    vw REAL,
+    o REAL,
+    c REAL,
+    h REAL,
+    l REAL,
+    n REAL,
+    PRIMARY KEY (symbol, t)
+) WITHOUT ROWID;
+";
+
+/// A store of tick data for many symbols, backed by a SQLite database
+#[derive(Debug)]
+pub struct SqliteStore {
+    /// The connection to the underlying database
+    pub conn: Connection,
+}
+
+fn to_nanos(t: NaiveDateTime) -> i64 {
+    t.timestamp_nanos()
+}
+
+fn from_nanos(nanos: i64) -> NaiveDateTime {
+    NaiveDateTime::from_timestamp(
+        nanos.div_euclid(1_000_000_000),
+        nanos.rem_euclid(1_000_000_000) as u32,
+    )
+}
+
+fn tick_from_row(row: &Row) -> Result<Tick> {
+    let field = |i: usize| -> Result<f64> { Ok(row.get::<_, Option<f64>>(i)?.unwrap_or(f64::NAN)) };
+    Ok(Tick {
+        t: from_nanos(row.get(0)?),
+        v: field(1)?,
+        vw: field(2)?,
+        o: field(3)?,
+        c: field(4)?,
+        h: field(5)?,
+        l: field(6)?,
+        n: field(7)?,
+    })
+}
+
+impl SqliteStore {
+    /// Open a store at a path, creating it if it does not exist
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<SqliteStore> {
+        SqliteStore::from_connection(Connection::open(path)?)
+    }
+    /// Open a new, empty in-memory store
+    pub fn open_in_memory() -> Result<SqliteStore> {
+        SqliteStore::from_connection(Connection::open_in_memory()?)
+    }
+    /// Use an existing connection as a store, creating the tick table if it does not exist
+    pub fn from_connection(conn: Connection) -> Result<SqliteStore> {
+        conn.execute_batch(SCHEMA)?;
+        Ok(SqliteStore { conn })
+    }
+    /// Insert ticks for a symbol in a single transaction, replacing any existing ticks at the same times.
+    /// On success, return how many ticks were written
+    pub fn insert<I>(&mut self, symbol: &Symbol, ticks: I) -> Result<usize>
+    where
+        I: IntoIterator<Item = Tick>,
+    {
+        let tx = self.conn.transaction()?;
+        let mut written = 0;
+        {
+            let mut stmt = tx.prepare_cached(
+                "INSERT OR REPLACE INTO ticks (symbol, t, v, vw, o, c, h, l, n)
+                 VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            )?;
+            for tick in ticks {
+                stmt.execute(params![
+                    symbol.0,
+                    to_nanos(tick.t),
+                    tick.v,
+                    tick.vw,
+                    tick.o,
+                    tick.c,
+                    tick.h,
+                    tick.l,
+                    tick.n
+                ])?;
+                written += 1;
+            }
+        }
+        tx.commit()?;
+        Ok(written)
+    }
+    /// Bulk-import tick data files in any format supported by `polygon::read_ticks_auto`, inferring each file's
+    /// symbol from its name. On success, return how many ticks were written
+    pub fn import_files<I, P>(&mut self, paths: I) -> anyhow::Result<usize>
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<Path>,
+    {
+        let mut written = 0;
+        for path in paths {
+            let path = path.as_ref();
+            let symbol = files::infer_symbol(path)
+                .ok_or_else(|| anyhow::format_err!("Cannot infer a symbol from {:?}", path))?;
+            written += self.insert(&symbol, read_ticks_auto(files::open(path)?))?;
+        }
+        Ok(written)
+    }
+    /// Get every symbol with ticks in the store, in order
+    pub fn symbols(&self) -> Result<Vec<Symbol>> {
+        let mut stmt = self
+            .conn
+            .prepare_cached("SELECT DISTINCT symbol FROM ticks ORDER BY symbol")?;
+        let symbols = stmt.query_map(NO_PARAMS, |row| Ok(Symbol(row.get(0)?)))?;
+        symbols.collect()
+    }
+    /// Get the ticks of a symbol with times in the range `[start, end)`, sorted by time
+    pub fn ticks(
+        &self,
+        symbol: &Symbol,
+        start: NaiveDateTime,
+        end: NaiveDateTime,
+    ) -> Result<Vec<Tick>> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT t, v, vw, o, c, h, l, n FROM ticks
+             WHERE symbol = ? AND t >= ? AND t < ? ORDER BY t",
+        )?;
+        let ticks = stmt.query_map(
+            params![symbol.0, to_nanos(start), to_nanos(end)],
+            tick_from_row,
+        )?;
+        ticks.collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, NaiveDate};
+
+    #[test]
+    fn insert_and_query() {
+        let mut store = SqliteStore::open_in_memory().unwrap();
+        let start = NaiveDate::from_ymd(2020, 10, 12).and_hms(14, 30, 0);
+        let ticks: Vec<Tick> = (0..10)
+            .map(|i| Tick {
+                t: start + Duration::minutes(i),
+                v: i as f64,
+                vw: f64::NAN,
+                o: 1.0,
+                c: 2.0,
+                h: 3.0,
+                l: 0.5,
+                n: 7.0,
+            })
+            .collect();
+        let symbol = Symbol::from("AMD");
+        assert_eq!(store.insert(&symbol, ticks.iter().copied()).unwrap(), 10);
+        assert_eq!(store.symbols().unwrap(), vec![symbol.clone()]);
+        let queried = store
+            .ticks(
+                &symbol,
+                start + Duration::minutes(2),
+                start + Duration::minutes(5),
+            )
+            .unwrap();
+        assert_eq!(queried.len(), 3);
+        assert_eq!(queried[0].t, ticks[2].t);
+        assert_eq!(queried[2].v, 4.0);
+        assert!(queried[0].vw.is_nan());
+    }
+}
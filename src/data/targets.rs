@@ -0,0 +1,155 @@
+/*!
+Configurable sets of prediction targets, generalizing the close and volume fields of a `Prediction`
+*/
+use super::{Prediction, Target, TargetKind, Tick};
+use num::NumCast;
+use serde::{Deserialize, Serialize};
+
+/// The targets predicted for each stock, in output order, e.g. a full OHLC bar for strategies placing bracket orders
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct TargetSpec {
+    /// The targets predicted, which should be distinct
+    pub targets: Vec<Target>,
+    /// How the targets are expressed
+    #[serde(default)]
+    pub kind: TargetKind,
+}
+
+impl TargetSpec {
+    /// Create a specification predicting the levels of a set of targets
+    pub fn new(targets: Vec<Target>) -> TargetSpec {
+        TargetSpec {
+            targets,
+            kind: TargetKind::Level,
+        }
+    }
+    /// Predict the fields of a `Prediction`: the close and volume
+    pub fn prediction() -> TargetSpec {
+        TargetSpec::new(Target::PREDICTION.to_vec())
+    }
+    /// Predict a full OHLC bar
+    pub fn ohlc() -> TargetSpec {
+        TargetSpec::new(vec![Target::Open, Target::High, Target::Low, Target::Close])
+    }
+    /// Predict every field a tick feeds into a neural network, in the order of `Tick::NN_FIELD_NAMES`
+    pub fn all_fields() -> TargetSpec {
+        let targets = Tick::NN_FIELD_NAMES
+            .iter()
+            .map(|field| Target::parse(field).expect("Tick fields are targets"))
+            .collect();
+        TargetSpec::new(targets)
+    }
+    /// Parse a comma-separated list of tick fields or target names, e.g. `o,h,l,c` or `close,volatility`. Returns
+    /// `None` if a name is invalid or repeated.
+    pub fn parse(spec: &str) -> Option<TargetSpec> {
+        let mut targets = Vec::new();
+        for name in spec.split(',') {
+            let target = Target::parse(name)?;
+            if targets.contains(&target) {
+                return None;
+            }
+            targets.push(target);
+        }
+        Some(TargetSpec::new(targets))
+    }
+    /// Set how the targets are expressed
+    pub fn with_kind(mut self, kind: TargetKind) -> TargetSpec {
+        self.kind = kind;
+        self
+    }
+    /// Get the number of values predicted for each stock, generalizing `Prediction::NN_FIELDS`
+    pub fn nn_fields(&self) -> usize {
+        self.targets.len()
+    }
+    /// Push the targets of a tick, given the tick before it, to an output vector, zero filling targets which cannot be
+    /// computed. Guaranteed to write `nn_fields` data points. Cross-sectional kinds push the change they rank, as
+    /// they depend on the other stocks' ticks; see `TargetKind::cross_section`.
+    pub fn push_targets<F>(&self, tick: &Tick<F>, previous: Option<&Tick<F>>, output: &mut Vec<f32>)
+    where
+        F: Copy + NumCast,
+    {
+        for target in self.targets.iter() {
+            let value = tick.target_of_kind(previous, *target, self.kind);
+            output.push(value.unwrap_or(0.0))
+        }
+    }
+    /// Decode the outputs of a network predicting these targets as heads, laid out as in `StockLSTM::targets`, into a
+    /// prediction for each stock. Returns `None` if the number of outputs is not a multiple of `nn_fields`.
+    pub fn decode(&self, outputs: &[f32]) -> Option<Vec<PredictedTick>> {
+        let fields = self.nn_fields();
+        if fields == 0 || outputs.len() % fields != 0 {
+            return None;
+        }
+        let stocks = outputs.len() / fields;
+        let predictions = (0..stocks)
+            .map(|stock| PredictedTick {
+                kind: self.kind,
+                values: self
+                    .targets
+                    .iter()
+                    .enumerate()
+                    .map(|(head, target)| (*target, outputs[head * stocks + stock]))
+                    .collect(),
+            })
+            .collect();
+        Some(predictions)
+    }
+}
+
+impl Default for TargetSpec {
+    fn default() -> TargetSpec {
+        TargetSpec::prediction()
+    }
+}
+
+/// The predicted values of a set of targets for a stock's next tick
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PredictedTick {
+    /// How the predicted values are expressed
+    pub kind: TargetKind,
+    /// Each target's predicted value, in output order
+    pub values: Vec<(Target, f32)>,
+}
+
+impl PredictedTick {
+    /// Get the predicted value of a target, if it was predicted
+    pub fn get(&self, target: Target) -> Option<f32> {
+        self.values
+            .iter()
+            .find(|(predicted, _)| *predicted == target)
+            .map(|(_, value)| *value)
+    }
+    /// Get the predicted close and volume as a `Prediction`, if both were predicted
+    pub fn prediction(&self) -> Option<Prediction<f32>> {
+        Some(Prediction {
+            c: self.get(Target::Close)?,
+            v: self.get(Target::Volume)?,
+            kind: self.kind,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn specs_parse_and_decode() {
+        assert_eq!(TargetSpec::parse("o,h,l,c"), Some(TargetSpec::ohlc()));
+        assert_eq!(
+            TargetSpec::parse("c, volume"),
+            Some(TargetSpec::prediction())
+        );
+        assert_eq!(TargetSpec::parse("c,close"), None);
+        assert_eq!(TargetSpec::parse("c,x"), None);
+        assert_eq!(TargetSpec::all_fields().nn_fields(), Tick::NN_FIELDS);
+        let spec = TargetSpec::prediction();
+        let decoded = spec.decode(&[1.0, 2.0, 10.0, 20.0]).unwrap();
+        assert_eq!(decoded.len(), 2);
+        assert_eq!(decoded[1].get(Target::Close), Some(2.0));
+        assert_eq!(decoded[1].get(Target::Volume), Some(20.0));
+        assert_eq!(decoded[0].get(Target::High), None);
+        assert_eq!(decoded[0].prediction().unwrap().v, 10.0);
+        assert_eq!(spec.decode(&[1.0, 2.0, 3.0]), None);
+    }
+}
@@ -0,0 +1,150 @@
+/*!
+Trade-level data, and its aggregation into bars
+*/
+use super::Tick;
+use crate::CpuFloat;
+use chrono::{Duration, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+
+/// A single trade of a stock
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Trade<F = CpuFloat> {
+    /// This trade's timestamp in UTC
+    pub t: NaiveDateTime,
+    /// The price of this trade
+    pub price: F,
+    /// The number of shares traded
+    pub size: F,
+}
+
+/// Get the start of the bar of a given length containing a time, with bars aligned to the Unix epoch
+pub fn bar_start(t: NaiveDateTime, interval: Duration) -> NaiveDateTime {
+    let interval = interval.num_nanoseconds().expect("Bar interval too long");
+    assert!(interval > 0, "Bar interval must be positive!");
+    let nanos = t.timestamp_nanos();
+    let start = nanos - nanos.rem_euclid(interval);
+    NaiveDateTime::from_timestamp(
+        start.div_euclid(1_000_000_000),
+        start.rem_euclid(1_000_000_000) as u32,
+    )
+}
+
+/// Aggregates time-sorted trades into bars of a fixed length
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct BarAggregator {
+    /// The length of each bar
+    pub interval: Duration,
+    /// The bar currently being aggregated, if any
+    current: Option<Tick>,
+    /// The total value traded in the current bar, used to compute its volume weighted average price
+    value: f64,
+}
+
+impl BarAggregator {
+    /// Create a new aggregator for bars of a given length, e.g. one second or one minute
+    pub fn new(interval: Duration) -> BarAggregator {
+        BarAggregator {
+            interval,
+            current: None,
+            value: 0.0,
+        }
+    }
+    /// Add a trade to the current bar. If the trade starts a new bar, return the previous bar, which is complete.
+    ///
+    /// Trades are assumed to be sorted by time: a trade before the current bar is aggregated into it.
+    pub fn push(&mut self, trade: Trade) -> Option<Tick> {
+        let start = bar_start(trade.t, self.interval);
+        match &mut self.current {
+            Some(bar) if bar.t >= start => {
+                bar.h = bar.h.max(trade.price);
+                bar.l = bar.l.min(trade.price);
+                bar.c = trade.price;
+                bar.v += trade.size;
+                bar.n += 1.0;
+                self.value += trade.price * trade.size;
+                bar.vw = if bar.v > 0.0 {
+                    self.value / bar.v
+                } else {
+                    trade.price
+                };
+                None
+            }
+            _ => {
+                self.value = trade.price * trade.size;
+                self.current.replace(Tick {
+                    t: start,
+                    v: trade.size,
+                    vw: trade.price,
+                    o: trade.price,
+                    c: trade.price,
+                    h: trade.price,
+                    l: trade.price,
+                    n: 1.0,
+                })
+            }
+        }
+    }
+    /// Return the current bar, if any, leaving the aggregator empty
+    pub fn finish(&mut self) -> Option<Tick> {
+        self.value = 0.0;
+        self.current.take()
+    }
+}
+
+/// An iterator over the bars aggregated from an iterator of time-sorted trades
+#[derive(Debug, Clone)]
+pub struct Bars<I> {
+    trades: I,
+    aggregator: BarAggregator,
+}
+
+impl<I: Iterator<Item = Trade>> Iterator for Bars<I> {
+    type Item = Tick;
+
+    fn next(&mut self) -> Option<Tick> {
+        for trade in &mut self.trades {
+            if let Some(bar) = self.aggregator.push(trade) {
+                return Some(bar);
+            }
+        }
+        self.aggregator.finish()
+    }
+}
+
+/// Aggregate time-sorted trades into bars of a given length on the fly. Bars without trades are skipped.
+pub fn aggregate<I>(trades: I, interval: Duration) -> Bars<I::IntoIter>
+where
+    I: IntoIterator<Item = Trade>,
+{
+    Bars {
+        trades: trades.into_iter(),
+        aggregator: BarAggregator::new(interval),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::polygon::read_trades;
+
+    #[test]
+    fn minute_bars() {
+        let data = "t,p,s
+1602513000000000000,10.0,100
+1602513030000000000,12.0,300
+1602513059000000000,9.0,100
+1602513125000000000,11.0,50
+";
+        let bars: Vec<Tick> =
+            aggregate(read_trades(data.as_bytes()), Duration::minutes(1)).collect();
+        assert_eq!(bars.len(), 2);
+        assert_eq!(bars[0].o, 10.0);
+        assert_eq!(bars[0].h, 12.0);
+        assert_eq!(bars[0].l, 9.0);
+        assert_eq!(bars[0].c, 9.0);
+        assert_eq!(bars[0].v, 500.0);
+        assert_eq!(bars[0].vw, 11.0);
+        assert_eq!(bars[0].n, 3.0);
+        assert_eq!(bars[1].t - bars[0].t, Duration::minutes(2));
+    }
+}
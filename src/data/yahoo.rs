@@ -0,0 +1,102 @@
+/*!
+[Yahoo Finance](https://finance.yahoo.com/) daily history processing code
+*/
+use super::{files::decompress, Tick};
+use chrono::NaiveDate;
+use serde::Deserialize;
+use std::io::Read;
+
+/// The Yahoo Finance date format
+pub const YAHOO_DATE: &str = "%Y-%m-%d";
+
+/// A record of Yahoo Finance's daily history CSV format. Missing values are written as `null`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+struct YahooRecord {
+    #[serde(rename = "Date")]
+    date: String,
+    #[serde(rename = "Open", deserialize_with = "csv::invalid_option")]
+    open: Option<f64>,
+    #[serde(rename = "High", deserialize_with = "csv::invalid_option")]
+    high: Option<f64>,
+    #[serde(rename = "Low", deserialize_with = "csv::invalid_option")]
+    low: Option<f64>,
+    #[serde(rename = "Close", deserialize_with = "csv::invalid_option")]
+    close: Option<f64>,
+    #[serde(
+        rename = "Adj Close",
+        default,
+        deserialize_with = "csv::invalid_option"
+    )]
+    adj_close: Option<f64>,
+    #[serde(rename = "Volume", deserialize_with = "csv::invalid_option")]
+    volume: Option<f64>,
+}
+
+impl YahooRecord {
+    /// Convert this record to a tick, returning `None` if it has a missing price or an invalid date
+    fn tick(&self, adjust: bool) -> Option<Tick> {
+        let t = NaiveDate::parse_from_str(self.date.trim(), YAHOO_DATE)
+            .ok()?
+            .and_hms(0, 0, 0);
+        let (mut o, mut h, mut l, mut c) = (self.open?, self.high?, self.low?, self.close?);
+        let mut v = self.volume.unwrap_or(0.0);
+        if adjust {
+            if let Some(adj_close) = self.adj_close {
+                if c != 0.0 {
+                    let ratio = adj_close / c;
+                    o *= ratio;
+                    h *= ratio;
+                    l *= ratio;
+                    c = adj_close;
+                    v /= ratio;
+                }
+            }
+        }
+        Some(Tick {
+            t,
+            v,
+            vw: (h + l + c) / 3.0,
+            o,
+            c,
+            h,
+            l,
+            n: 0.0,
+        })
+    }
+}
+
+/// Read Yahoo Finance daily history, with columns `Date, Open, High, Low, Close, Adj Close, Volume`, from a Reader
+/// which may be gzip or zstd compressed.
+///
+/// Each tick is timestamped at midnight UTC on its date. If `adjust` is set, prices are scaled by the ratio of the
+/// adjusted to the raw close, and volume by its inverse, so that splits and dividends do not appear as jumps. Yahoo
+/// does not provide a volume weighted average price, so it is estimated by the typical price `(h + l + c) / 3`, and
+/// the number of trades is set to zero. Days with missing prices, such as holidays, are skipped.
+pub fn read_ticks<R: Read>(rdr: R, adjust: bool) -> Vec<Tick> {
+    csv::Reader::from_reader(decompress(rdr))
+        .into_deserialize::<YahooRecord>()
+        .filter_map(|record| record.ok()?.tick(adjust))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn adjusted_close() {
+        let data = "Date,Open,High,Low,Close,Adj Close,Volume
+2020-10-12,20.0,22.0,18.0,20.0,10.0,1000
+2020-10-13,null,null,null,null,null,null
+";
+        let raw = read_ticks(data.as_bytes(), false);
+        let adjusted = read_ticks(data.as_bytes(), true);
+        assert_eq!(raw.len(), 1);
+        assert_eq!(raw[0].t, NaiveDate::from_ymd(2020, 10, 12).and_hms(0, 0, 0));
+        assert_eq!(raw[0].c, 20.0);
+        assert_eq!(raw[0].vw, 20.0);
+        assert_eq!(adjusted[0].h, 11.0);
+        assert_eq!(adjusted[0].c, 10.0);
+        assert_eq!(adjusted[0].v, 2000.0);
+    }
+}
@@ -0,0 +1,304 @@
+/*!
+Calibration analysis of probabilistic predictions: PIT histograms, coverage of nominal intervals and the CRPS
+*/
+use super::standard_normal_cdf;
+use crate::predict::Estimate;
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Display, Formatter};
+
+/// A predictive distribution of a single value
+pub trait Predictive {
+    /// The cumulative distribution function at a value
+    fn cdf(&self, x: f64) -> f64;
+    /// The central interval with a given nominal coverage, e.g. `0.9` for the interval between the 5% and 95%
+    /// quantiles. Returns `None` if the distribution does not determine it.
+    fn interval(&self, coverage: f64) -> Option<(f64, f64)>;
+    /// The continuous ranked probability score of an observed value: the integrated squared difference between the
+    /// cumulative distribution function and the step function at the observation. Lower is better.
+    fn crps(&self, x: f64) -> f64;
+}
+
+/// A Gaussian predictive distribution, e.g. a mean and standard deviation estimated by Monte Carlo dropout
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GaussianForecast {
+    /// The mean
+    pub mean: f64,
+    /// The standard deviation
+    pub std: f64,
+}
+
+impl From<Estimate> for GaussianForecast {
+    fn from(estimate: Estimate) -> GaussianForecast {
+        GaussianForecast {
+            mean: f64::from(estimate.mean),
+            std: f64::from(estimate.std),
+        }
+    }
+}
+
+impl Predictive for GaussianForecast {
+    fn cdf(&self, x: f64) -> f64 {
+        if self.std > 0.0 {
+            standard_normal_cdf((x - self.mean) / self.std)
+        } else if x >= self.mean {
+            1.0
+        } else {
+            0.0
+        }
+    }
+    fn interval(&self, coverage: f64) -> Option<(f64, f64)> {
+        if coverage.is_nan() || coverage <= 0.0 || coverage >= 1.0 {
+            return None;
+        }
+        let z = standard_normal_quantile((1.0 + coverage) / 2.0);
+        Some((self.mean - z * self.std, self.mean + z * self.std))
+    }
+    fn crps(&self, x: f64) -> f64 {
+        if self.std <= 0.0 {
+            return (x - self.mean).abs();
+        }
+        let z = (x - self.mean) / self.std;
+        let density = (-z * z / 2.0).exp() / (2.0 * std::f64::consts::PI).sqrt();
+        let inv_sqrt_pi = 1.0 / std::f64::consts::PI.sqrt();
+        self.std * (z * (2.0 * standard_normal_cdf(z) - 1.0) + 2.0 * density - inv_sqrt_pi)
+    }
+}
+
+/// The tolerance within which quantile levels are considered equal, since e.g. `(1.0 - 0.8) / 2.0 != 0.1`
+const LEVEL_TOLERANCE: f64 = 1e-9;
+
+/// A predictive distribution given by its quantiles at several levels, e.g. the outputs of quantile regression.
+///
+/// The distribution is interpolated linearly between quantiles, and assumed to have no mass beyond the lowest and
+/// highest quantiles, so observations outside them land in the extreme bins of a PIT histogram.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QuantileForecast {
+    /// The levels of the quantiles, strictly increasing within `(0, 1)`
+    pub levels: Vec<f64>,
+    /// The quantile at each level, in nondecreasing order
+    pub values: Vec<f64>,
+}
+
+impl QuantileForecast {
+    /// Create a forecast from the quantiles at a set of increasing levels. Crossing quantiles are sorted, as is
+    /// common when quantile heads are trained independently. Returns `None` if the levels are not strictly increasing
+    /// within `(0, 1)` or there is not one value per level.
+    pub fn new(levels: Vec<f64>, mut values: Vec<f64>) -> Option<QuantileForecast> {
+        let increasing = levels.windows(2).all(|pair| pair[0] < pair[1]);
+        let in_range = levels.iter().all(|level| *level > 0.0 && *level < 1.0);
+        if levels.is_empty() || levels.len() != values.len() || !increasing || !in_range {
+            return None;
+        }
+        values.sort_by(|left, right| left.partial_cmp(right).unwrap_or(std::cmp::Ordering::Equal));
+        Some(QuantileForecast { levels, values })
+    }
+    /// Interpolate the quantile at a level between the lowest and highest levels, up to rounding error
+    fn quantile(&self, level: f64) -> Option<f64> {
+        let (first, last) = (self.levels[0], self.levels[self.levels.len() - 1]);
+        if level < first - LEVEL_TOLERANCE || level > last + LEVEL_TOLERANCE {
+            return None;
+        }
+        let level = level.max(first).min(last);
+        let upper = self.levels.iter().position(|l| *l >= level)?;
+        if upper == 0 {
+            return Some(self.values[0]);
+        }
+        let (l0, l1) = (self.levels[upper - 1], self.levels[upper]);
+        let (v0, v1) = (self.values[upper - 1], self.values[upper]);
+        Some(v0 + (v1 - v0) * (level - l0) / (l1 - l0))
+    }
+}
+
+impl Predictive for QuantileForecast {
+    fn cdf(&self, x: f64) -> f64 {
+        let upper = match self.values.iter().position(|v| *v > x) {
+            None => return 1.0,
+            Some(0) => return 0.0,
+            Some(upper) => upper,
+        };
+        let (v0, v1) = (self.values[upper - 1], self.values[upper]);
+        let (l0, l1) = (self.levels[upper - 1], self.levels[upper]);
+        l0 + (l1 - l0) * (x - v0) / (v1 - v0)
+    }
+    fn interval(&self, coverage: f64) -> Option<(f64, f64)> {
+        let lower = self.quantile((1.0 - coverage) / 2.0)?;
+        let upper = self.quantile((1.0 + coverage) / 2.0)?;
+        Some((lower, upper))
+    }
+    /// Approximated as twice the mean quantile (pinball) loss over the forecast's levels
+    fn crps(&self, x: f64) -> f64 {
+        let pinball: f64 = self
+            .levels
+            .iter()
+            .zip(self.values.iter())
+            .map(|(level, value)| {
+                let below = if x < *value { 1.0 } else { 0.0 };
+                (below - level) * (value - x)
+            })
+            .sum();
+        2.0 * pinball / self.levels.len() as f64
+    }
+}
+
+/// Invert the standard normal cumulative distribution function by bisection
+fn standard_normal_quantile(p: f64) -> f64 {
+    let (mut low, mut high) = (-10.0, 10.0);
+    for _ in 0..64 {
+        let mid = (low + high) / 2.0;
+        if standard_normal_cdf(mid) < p {
+            low = mid;
+        } else {
+            high = mid;
+        }
+    }
+    (low + high) / 2.0
+}
+
+/// Accumulated calibration statistics of probabilistic predictions against observations
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Calibration {
+    /// The number of probability integral transform (PIT) values, i.e. predicted CDFs at the observations, in each of
+    /// a number of equal bins of `[0, 1]`. Calibrated predictions have a flat histogram.
+    pub pit: Vec<u64>,
+    /// The nominal coverages of the central intervals checked
+    pub nominal: Vec<f64>,
+    /// The number of observations within the central interval of each nominal coverage
+    pub covered: Vec<u64>,
+    /// The number of predictions determining the central interval of each nominal coverage
+    pub intervals: Vec<u64>,
+    /// The sum of the CRPS of every observation
+    pub crps_sum: f64,
+    /// The number of observations
+    pub samples: u64,
+}
+
+impl Calibration {
+    /// Create empty statistics with a number of PIT histogram bins, checking central intervals of the given nominal
+    /// coverages
+    pub fn new(bins: usize, nominal: &[f64]) -> Calibration {
+        Calibration {
+            pit: vec![0; bins.max(1)],
+            nominal: nominal.to_vec(),
+            covered: vec![0; nominal.len()],
+            intervals: vec![0; nominal.len()],
+            crps_sum: 0.0,
+            samples: 0,
+        }
+    }
+    /// Add a prediction and its observation. Non-finite observations are ignored.
+    pub fn push<P: Predictive + ?Sized>(&mut self, forecast: &P, actual: f64) {
+        if !actual.is_finite() {
+            return;
+        }
+        let pit = forecast.cdf(actual);
+        let bins = self.pit.len();
+        let bin = ((pit * bins as f64) as usize).min(bins - 1);
+        self.pit[bin] += 1;
+        for (ix, coverage) in self.nominal.iter().enumerate() {
+            if let Some((lower, upper)) = forecast.interval(*coverage) {
+                self.intervals[ix] += 1;
+                if lower <= actual && actual <= upper {
+                    self.covered[ix] += 1;
+                }
+            }
+        }
+        self.crps_sum += forecast.crps(actual);
+        self.samples += 1;
+    }
+    /// Get the fraction of PIT values in each bin, which is `1 / bins` for every bin if predictions are calibrated
+    pub fn pit_histogram(&self) -> Vec<f64> {
+        let total = self.samples.max(1) as f64;
+        self.pit.iter().map(|count| *count as f64 / total).collect()
+    }
+    /// Get the empirical coverage of the central interval of each nominal coverage, as pairs of nominal and empirical
+    /// coverage
+    pub fn coverage(&self) -> Vec<(f64, f64)> {
+        self.nominal
+            .iter()
+            .zip(self.covered.iter().zip(self.intervals.iter()))
+            .map(|(nominal, (covered, intervals))| {
+                (*nominal, *covered as f64 / (*intervals).max(1) as f64)
+            })
+            .collect()
+    }
+    /// Get the mean CRPS, or zero if no predictions were pushed
+    pub fn crps(&self) -> f64 {
+        self.crps_sum / self.samples.max(1) as f64
+    }
+}
+
+impl Display for Calibration {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        writeln!(fmt, "PIT histogram ({} samples):", self.samples)?;
+        let bins = self.pit.len() as f64;
+        for (ix, fraction) in self.pit_histogram().into_iter().enumerate() {
+            writeln!(
+                fmt,
+                "  [{:.2}, {:.2}): {:.4} (expected {:.4})",
+                ix as f64 / bins,
+                (ix + 1) as f64 / bins,
+                fraction,
+                1.0 / bins
+            )?;
+        }
+        for (nominal, empirical) in self.coverage() {
+            writeln!(
+                fmt,
+                "coverage of {:.0}% intervals = {:.4}",
+                100.0 * nominal,
+                empirical
+            )?;
+        }
+        write!(fmt, "CRPS = {:.6}", self.crps())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gaussian_and_quantile_calibration() {
+        let forecast = GaussianForecast {
+            mean: 1.0,
+            std: 2.0,
+        };
+        let (lower, upper) = forecast.interval(0.95).unwrap();
+        assert!((upper - 1.0 - 2.0 * 1.959_964).abs() < 1e-5);
+        assert!((lower + upper - 2.0).abs() < 1e-9);
+        // The CRPS of a standard normal at its mean is (sqrt(2) - 1) / sqrt(pi)
+        let standard = GaussianForecast {
+            mean: 0.0,
+            std: 1.0,
+        };
+        let expected = (2.0f64.sqrt() - 1.0) / std::f64::consts::PI.sqrt();
+        assert!((standard.crps(0.0) - expected).abs() < 1e-6);
+
+        // Observations at evenly spaced quantiles of the forecast give a flat PIT histogram
+        let mut calibration = Calibration::new(4, &[0.5]);
+        for ix in 0..8 {
+            let level = (ix as f64 + 0.5) / 8.0;
+            calibration.push(&standard, standard_normal_quantile(level));
+        }
+        assert_eq!(calibration.pit, vec![2, 2, 2, 2]);
+        assert_eq!(calibration.coverage(), vec![(0.5, 0.5)]);
+
+        let quantiles = QuantileForecast::new(vec![0.1, 0.5, 0.9], vec![2.0, 0.0, -2.0]).unwrap();
+        assert_eq!(quantiles.values, vec![-2.0, 0.0, 2.0]);
+        assert!((quantiles.cdf(1.0) - 0.7).abs() < 1e-12);
+        assert_eq!(quantiles.cdf(-3.0), 0.0);
+        assert_eq!(quantiles.interval(0.8), Some((-2.0, 2.0)));
+        assert_eq!(quantiles.interval(0.9), None);
+        // Pinball losses of 0.1 * 1, 0.5 * 1 and 0.1 * 3 at the observation -1
+        assert!((quantiles.crps(-1.0) - 2.0 * 0.9 / 3.0).abs() < 1e-12);
+        assert!(QuantileForecast::new(vec![0.5, 0.1], vec![0.0, 1.0]).is_none());
+    }
+
+    #[test]
+    fn empty_calibration() {
+        let calibration = Calibration::new(4, &[0.5]);
+        assert_eq!(calibration.pit_histogram(), vec![0.0; 4]);
+        assert_eq!(calibration.coverage(), vec![(0.5, 0.0)]);
+        assert_eq!(calibration.crps(), 0.0);
+    }
+}
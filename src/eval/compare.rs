@@ -0,0 +1,322 @@
+/*!
+Side by side evaluation of several checkpoints on the same validation data, for model selection
+*/
+use super::{standard_normal_cdf, tensor_to_vec};
+use crate::data::{dataset::Dataset, Target};
+use crate::lstm::StockLSTM;
+use crate::train::{load_checkpoint, read_preprocessing, Preprocessing};
+use anyhow::format_err;
+use chrono::{DateTime, Utc};
+use num::NumCast;
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Display, Formatter};
+use std::path::Path;
+use tch::nn::RNN;
+use tch::{Device, Kind, Tensor};
+
+/// A paired test of whether a model's squared errors differ from a baseline's on the same samples, using the normal
+/// approximation to the distribution of the mean of their differences. Samples are treated as independent.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PairedTest {
+    /// The mean of the model's squared errors minus the baseline's, which is negative if the model is more accurate
+    pub mean_difference: f64,
+    /// The mean difference divided by its standard error
+    pub statistic: f64,
+    /// The two-sided p-value of the hypothesis that the model and the baseline are equally accurate
+    pub p_value: f64,
+}
+
+impl PairedTest {
+    /// Test a model's squared errors against a baseline's on the same samples. Returns `None` if there are fewer than
+    /// two samples, the series have different lengths, or their differences have no variance.
+    pub fn new(baseline: &[f64], model: &[f64]) -> Option<PairedTest> {
+        if baseline.len() != model.len() || baseline.len() < 2 {
+            return None;
+        }
+        let n = baseline.len() as f64;
+        let differences: Vec<f64> = model
+            .iter()
+            .zip(baseline)
+            .map(|(model, baseline)| model - baseline)
+            .collect();
+        let mean = differences.iter().sum::<f64>() / n;
+        let variance = differences
+            .iter()
+            .map(|difference| (difference - mean).powi(2))
+            .sum::<f64>()
+            / (n - 1.0);
+        if variance <= 0.0 || !variance.is_finite() {
+            return None;
+        }
+        let statistic = mean / (variance / n).sqrt();
+        Some(PairedTest {
+            mean_difference: mean,
+            statistic,
+            p_value: 2.0 * (1.0 - standard_normal_cdf(statistic.abs())),
+        })
+    }
+}
+
+/// The accuracy of a model over a set of batches
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModelReport {
+    /// The name of the model, e.g. the file name of its checkpoint
+    pub name: String,
+    /// The mean squared error over every output
+    pub mse: f64,
+    /// The mean squared error of each head
+    pub head_mse: Vec<(Target, f64)>,
+    /// The mean squared error over the outputs of each row of each batch, in order
+    pub row_errors: Vec<f64>,
+    /// The test of this model's row errors against the baseline's, if any
+    pub test: Option<PairedTest>,
+}
+
+impl ModelReport {
+    /// Evaluate a model on batches of inputs and outputs, without dropout or gradients. Each batch is run from a zero
+    /// state.
+    pub fn evaluate(
+        name: impl Into<String>,
+        lstm: &StockLSTM,
+        batches: &[(Tensor, Tensor)],
+        device: Device,
+    ) -> ModelReport {
+        let targets = lstm.targets();
+        let mut head_sums = vec![0.0; targets.len()];
+        let mut row_errors = Vec::new();
+        tch::no_grad(|| {
+            for (input, output) in batches {
+                let (input, output) = (input.to_device(device), output.to_device(device));
+                let (yhat, _) = lstm.forward_t(&input, &lstm.zero_state(input.size()[0]), false);
+                let diff = &yhat - &output;
+                let rows = tensor_to_vec(&(&diff * &diff).mean1(&[-1], false, Kind::Float));
+                let head_losses = lstm.head_losses(&yhat, &output, None);
+                for (sum, loss) in head_sums.iter_mut().zip(head_losses) {
+                    *sum += f64::from(loss) * rows.len() as f64;
+                }
+                row_errors.extend(rows);
+            }
+        });
+        let total_rows = row_errors.len().max(1) as f64;
+        ModelReport {
+            name: name.into(),
+            mse: row_errors.iter().sum::<f64>() / total_rows,
+            head_mse: targets
+                .into_iter()
+                .zip(head_sums)
+                .map(|(target, sum)| (target, sum / total_rows))
+                .collect(),
+            row_errors,
+            test: None,
+        }
+    }
+}
+
+/// A side by side comparison of several models evaluated on the same batches
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comparison {
+    /// The reports of each model, with the first serving as the baseline of significance tests
+    pub reports: Vec<ModelReport>,
+}
+
+impl Comparison {
+    /// Compare models' reports, testing each model's errors against the first's if `significance` is set
+    pub fn new(mut reports: Vec<ModelReport>, significance: bool) -> Comparison {
+        if significance {
+            if let Some((baseline, rest)) = reports.split_first_mut() {
+                for report in rest {
+                    report.test = PairedTest::new(&baseline.row_errors, &report.row_errors);
+                }
+            }
+        }
+        Comparison { reports }
+    }
+    /// Get the report of the model with the lowest mean squared error, if any
+    pub fn best(&self) -> Option<&ModelReport> {
+        self.reports
+            .iter()
+            .filter(|report| !report.mse.is_nan())
+            .min_by(|left, right| left.mse.partial_cmp(&right.mse).expect("Not NaN"))
+    }
+}
+
+impl Display for Comparison {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        let width = self
+            .reports
+            .iter()
+            .map(|report| report.name.len())
+            .max()
+            .unwrap_or(0)
+            .max(5);
+        write!(fmt, "{:<width$} {:>12}", "model", "mse", width = width)?;
+        if let Some(first) = self.reports.first() {
+            for (target, _) in first.head_mse.iter() {
+                write!(fmt, " {:>12}", format!("{:?}", target).to_lowercase())?;
+            }
+        }
+        write!(fmt, " {:>10} {:>10}", "z", "p")?;
+        for report in self.reports.iter() {
+            write!(
+                fmt,
+                "\n{:<width$} {:>12.6e}",
+                report.name,
+                report.mse,
+                width = width
+            )?;
+            for (_, mse) in report.head_mse.iter() {
+                write!(fmt, " {:>12.6e}", mse)?;
+            }
+            match report.test {
+                Some(test) => write!(fmt, " {:>10.4} {:>10.4}", test.statistic, test.p_value)?,
+                None => write!(fmt, " {:>10} {:>10}", "-", "-")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// List the ways in which a model's inputs, their preprocessing and its outputs differ from a reference model's, such
+/// that they cannot be evaluated on the same batches
+fn incomparabilities(
+    reference: &StockLSTM,
+    reference_preprocessing: &Preprocessing,
+    lstm: &StockLSTM,
+    preprocessing: &Preprocessing,
+) -> Vec<String> {
+    let mut differences = Vec::new();
+    if preprocessing.scaler != reference_preprocessing.scaler {
+        differences.push(format!(
+            "scaler {:?}, expected {:?}",
+            preprocessing.scaler, reference_preprocessing.scaler
+        ));
+    }
+    if preprocessing.quantiles != reference_preprocessing.quantiles {
+        differences.push(
+            match (&preprocessing.quantiles, &reference_preprocessing.quantiles) {
+                (Some(_), Some(_)) => "a different quantile transform",
+                (Some(_), None) => "a quantile transform, expected none",
+                (None, _) => "no quantile transform, expected one",
+            }
+            .to_string(),
+        );
+    }
+    if lstm.input_layout() != reference.input_layout() {
+        differences.push(format!(
+            "{} inputs, expected {}",
+            lstm.no_inputs(),
+            reference.no_inputs()
+        ));
+    }
+    if lstm.targets() != reference.targets() {
+        differences.push(format!(
+            "heads {:?}, expected {:?}",
+            lstm.targets(),
+            reference.targets()
+        ));
+    }
+    if lstm.desc.target_kind != reference.desc.target_kind {
+        differences.push(format!(
+            "target kind {:?}, expected {:?}",
+            lstm.desc.target_kind, reference.desc.target_kind
+        ));
+    }
+    if lstm.desc.target_horizon != reference.desc.target_horizon {
+        differences.push(format!(
+            "target horizon {}, expected {}",
+            lstm.desc.target_horizon, reference.desc.target_horizon
+        ));
+    }
+    differences
+}
+
+/// Load several checkpoints saved by `train::save_checkpoint` and evaluate them on consecutive batches of the same
+/// dataset, optionally testing whether each model's errors differ significantly from the first's.
+///
+/// Every checkpoint must have the same inputs, preprocessing and targets, and the dataset must have been preprocessed
+/// in the same way, as read by `train::read_preprocessing`, with raw ticks attached for targets computed from them.
+/// Checkpoints saved without their preprocessing are taken to use the default. Batches are built once, with zero
+/// additional inputs.
+pub fn compare<P, F, DF>(
+    checkpoints: &[P],
+    dataset: &Dataset<F>,
+    time_func: DF,
+    batch_size: usize,
+    sequence_length: usize,
+    device: Device,
+    significance: bool,
+) -> anyhow::Result<Comparison>
+where
+    P: AsRef<Path>,
+    F: Copy + NumCast,
+    DF: FnMut(DateTime<Utc>, &mut Vec<f32>),
+{
+    let mut models = Vec::with_capacity(checkpoints.len());
+    for path in checkpoints {
+        let path = path.as_ref();
+        let (vs, lstm) = load_checkpoint(path, device)?;
+        let preprocessing = read_preprocessing(path)?.unwrap_or_default();
+        if let Some((_, _, reference, reference_preprocessing)) = models.first() {
+            let differences =
+                incomparabilities(reference, reference_preprocessing, &lstm, &preprocessing);
+            if !differences.is_empty() {
+                return Err(format_err!(
+                    "Checkpoint {:?} cannot be compared to {:?}: it has {}",
+                    path,
+                    checkpoints[0].as_ref(),
+                    differences.join(", ")
+                ));
+            }
+        }
+        let name = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_else(|| path.display().to_string());
+        models.push((name, vs, lstm, preprocessing));
+    }
+    let batches: Vec<(Tensor, Tensor)> = match models.first() {
+        Some((_, _, reference, _)) => reference
+            .batches(dataset, &[], time_func, batch_size, sequence_length)
+            .collect(),
+        None => Vec::new(),
+    };
+    let reports = models
+        .iter()
+        .map(|(name, _vs, lstm, _)| ModelReport::evaluate(name.as_str(), lstm, &batches, device))
+        .collect();
+    Ok(Comparison::new(reports, significance))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paired_tests_and_best_models() {
+        let baseline = [1.0, 2.0, 3.0, 4.0];
+        let better = [0.5, 1.0, 2.0, 3.5];
+        let test = PairedTest::new(&baseline, &better).unwrap();
+        // Differences -0.5, -1, -1, -0.5 have mean -0.75 and standard error sqrt(1 / 12) / 2
+        assert_eq!(test.mean_difference, -0.75);
+        assert!((test.statistic + 0.75 / (1.0f64 / 12.0).sqrt() * 2.0).abs() < 1e-9);
+        assert!(test.p_value < 0.01);
+        assert!(PairedTest::new(&baseline, &baseline).is_none());
+        assert!(PairedTest::new(&baseline, &better[..3]).is_none());
+
+        let report = |name: &str, row_errors: &[f64]| ModelReport {
+            name: name.into(),
+            mse: row_errors.iter().sum::<f64>() / row_errors.len() as f64,
+            head_mse: vec![(Target::Close, 0.0)],
+            row_errors: row_errors.to_vec(),
+            test: None,
+        };
+        let comparison = Comparison::new(
+            vec![report("baseline", &baseline), report("better", &better)],
+            true,
+        );
+        assert_eq!(comparison.best().unwrap().name, "better");
+        assert!(comparison.reports[0].test.is_none());
+        assert_eq!(comparison.reports[1].test, Some(test));
+        assert_eq!(comparison.to_string().lines().count(), 3);
+    }
+}
@@ -0,0 +1,143 @@
+/*!
+The Diebold-Mariano test of equal predictive accuracy of two forecasts, e.g. a model against a persistence baseline
+*/
+use super::standard_normal_cdf;
+use serde::{Deserialize, Serialize};
+
+/// The loss applied to forecast errors before comparing them
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum ErrorLoss {
+    /// The squared error
+    Squared,
+    /// The absolute error
+    Absolute,
+}
+
+impl ErrorLoss {
+    /// Apply this loss to a forecast error
+    pub fn apply(self, error: f64) -> f64 {
+        match self {
+            ErrorLoss::Squared => error * error,
+            ErrorLoss::Absolute => error.abs(),
+        }
+    }
+}
+
+impl Default for ErrorLoss {
+    fn default() -> ErrorLoss {
+        ErrorLoss::Squared
+    }
+}
+
+/// The result of a Diebold-Mariano test comparing a model's forecast errors against a baseline's over the same times
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DieboldMariano {
+    /// The number of forecasts compared
+    pub samples: usize,
+    /// The mean of the model's losses minus the baseline's, which is negative if the model is more accurate
+    pub mean_difference: f64,
+    /// The Diebold-Mariano statistic, with the Harvey, Leybourne and Newbold (1997) small sample correction
+    pub statistic: f64,
+    /// The two-sided p-value of the hypothesis that the model and the baseline are equally accurate, from the
+    /// standard normal distribution
+    pub p_value: f64,
+}
+
+impl DieboldMariano {
+    /// Test a model's forecast errors against a baseline's, given the forecast horizon in steps. The loss
+    /// differentials of `h`-step forecasts are autocorrelated up to lag `h - 1`, which the variance of their mean
+    /// accounts for.
+    ///
+    /// Returns `None` if the series have different lengths, there are no more samples than the horizon, the horizon
+    /// is zero, or the estimated variance is not positive.
+    pub fn new(
+        model_errors: &[f64],
+        baseline_errors: &[f64],
+        horizon: usize,
+        loss: ErrorLoss,
+    ) -> Option<DieboldMariano> {
+        if model_errors.len() != baseline_errors.len() {
+            return None;
+        }
+        let differentials: Vec<f64> = model_errors
+            .iter()
+            .zip(baseline_errors)
+            .map(|(model, baseline)| loss.apply(*model) - loss.apply(*baseline))
+            .collect();
+        DieboldMariano::from_differentials(&differentials, horizon)
+    }
+    /// Test a series of loss differentials, i.e. a model's losses minus a baseline's, of forecasts `horizon` steps
+    /// ahead. See `new`.
+    pub fn from_differentials(differentials: &[f64], horizon: usize) -> Option<DieboldMariano> {
+        let samples = differentials.len();
+        if horizon == 0 || samples <= horizon {
+            return None;
+        }
+        let n = samples as f64;
+        let mean = differentials.iter().sum::<f64>() / n;
+        let autocovariance = |lag: usize| {
+            differentials[lag..]
+                .iter()
+                .zip(differentials.iter())
+                .map(|(later, earlier)| (later - mean) * (earlier - mean))
+                .sum::<f64>()
+                / n
+        };
+        let long_run_variance =
+            autocovariance(0) + 2.0 * (1..horizon).map(autocovariance).sum::<f64>();
+        if long_run_variance <= 0.0 || !long_run_variance.is_finite() {
+            return None;
+        }
+        let h = horizon as f64;
+        let correction = ((n + 1.0 - 2.0 * h + h * (h - 1.0) / n) / n).sqrt();
+        let statistic = correction * mean / (long_run_variance / n).sqrt();
+        Some(DieboldMariano {
+            samples,
+            mean_difference: mean,
+            statistic,
+            p_value: 2.0 * (1.0 - standard_normal_cdf(statistic.abs())),
+        })
+    }
+    /// Whether the model is significantly more accurate than the baseline at a given significance level, e.g. `0.05`
+    pub fn model_better(&self, level: f64) -> bool {
+        self.mean_difference < 0.0 && self.p_value < level
+    }
+}
+
+/// Compute the errors of the persistence forecast, which predicts that each value of a series equals the value
+/// `horizon` steps before it. The result is aligned with `series[horizon..]`.
+pub fn persistence_errors(series: &[f64], horizon: usize) -> Vec<f64> {
+    series
+        .iter()
+        .skip(horizon)
+        .zip(series.iter())
+        .map(|(actual, previous)| actual - previous)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diebold_mariano_statistic() {
+        // Differentials 1, -1, 2, 0 have mean 0.5 and variance 1.25, so the uncorrected statistic is
+        // 0.5 / sqrt(1.25 / 4), corrected by a factor of sqrt(3 / 4)
+        let test = DieboldMariano::from_differentials(&[1.0, -1.0, 2.0, 0.0], 1).unwrap();
+        let expected = 0.5 / (1.25f64 / 4.0).sqrt() * 0.75f64.sqrt();
+        assert_eq!(test.samples, 4);
+        assert!((test.statistic - expected).abs() < 1e-12);
+        assert!(test.p_value > 0.4 && test.p_value < 0.5);
+        assert!(!test.model_better(0.05));
+        assert!(DieboldMariano::from_differentials(&[1.0, 1.0, 1.0], 1).is_none());
+        assert!(DieboldMariano::from_differentials(&[1.0, 2.0], 2).is_none());
+
+        let series = [1.0, 2.0, 4.0, 3.0, 5.0, 4.0, 6.0, 5.0];
+        let baseline = persistence_errors(&series, 1);
+        assert_eq!(baseline, vec![1.0, 2.0, -1.0, 2.0, -1.0, 2.0, -1.0]);
+        let model: Vec<f64> = baseline.iter().map(|error| error * 0.1 + 0.01).collect();
+        let test = DieboldMariano::new(&model, &baseline, 1, ErrorLoss::Absolute).unwrap();
+        assert!(test.mean_difference < 0.0);
+        assert!(test.model_better(0.05));
+    }
+}
@@ -0,0 +1,236 @@
+/*!
+Evaluation utilities for predictions
+*/
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Display, Formatter};
+use tch::{Device, Kind, Tensor};
+
+pub mod calibration;
+pub mod compare;
+pub mod dm;
+pub use calibration::{Calibration, GaussianForecast, Predictive, QuantileForecast};
+pub use compare::{compare, Comparison, ModelReport, PairedTest};
+pub use dm::{persistence_errors, DieboldMariano, ErrorLoss};
+
+/// Copy the entries of a tensor of any shape, kind and device to a flat vector
+pub fn tensor_to_vec(tensor: &Tensor) -> Vec<f64> {
+    let flat = tensor
+        .to_device(Device::Cpu)
+        .to_kind(Kind::Double)
+        .reshape(&[-1]);
+    Vec::<f64>::from(&flat)
+}
+
+/// The cumulative distribution function of the standard normal distribution, accurate to about `1e-7`
+pub fn standard_normal_cdf(x: f64) -> f64 {
+    // Abramowitz and Stegun's approximation 7.1.26 of the error function
+    let z = x.abs() / std::f64::consts::SQRT_2;
+    let t = 1.0 / (1.0 + 0.327_591_1 * z);
+    let coefficients = [
+        0.254_829_592,
+        -0.284_496_736,
+        1.421_413_741,
+        -1.453_152_027,
+        1.061_405_429,
+    ];
+    let poly = t * coefficients.iter().rev().fold(0.0, |acc, c| acc * t + c);
+    let erf = 1.0 - poly * (-z * z).exp();
+    if x >= 0.0 {
+        0.5 * (1.0 + erf)
+    } else {
+        0.5 * (1.0 - erf)
+    }
+}
+
+/// The direction of a return
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Direction {
+    /// A return above the flat threshold
+    Up = 0,
+    /// A return within the flat threshold
+    Flat = 1,
+    /// A return below the negative flat threshold
+    Down = 2,
+}
+
+impl Direction {
+    /// All directions, in index order
+    pub const ALL: [Direction; 3] = [Direction::Up, Direction::Flat, Direction::Down];
+
+    /// Classify a return, given the absolute threshold below which it is considered flat
+    pub fn classify(ret: f64, flat: f64) -> Direction {
+        if ret > flat {
+            Direction::Up
+        } else if ret < -flat {
+            Direction::Down
+        } else {
+            Direction::Flat
+        }
+    }
+}
+
+/// A confusion matrix of predicted against actual return directions
+#[derive(Debug, Copy, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct ConfusionMatrix {
+    /// The absolute threshold below which a return is considered flat
+    pub flat: f64,
+    /// The number of samples for each predicted direction (row) and actual direction (column)
+    pub counts: [[u64; 3]; 3],
+    /// The total absolute actual return of samples whose direction was predicted correctly
+    pub right_weight: f64,
+    /// The total absolute actual return of all samples
+    pub total_weight: f64,
+}
+
+impl ConfusionMatrix {
+    /// Create a new, empty confusion matrix with a given flat threshold
+    pub fn new(flat: f64) -> ConfusionMatrix {
+        ConfusionMatrix {
+            flat,
+            ..Default::default()
+        }
+    }
+    /// Add a predicted and actual return to the matrix. Non-finite values are ignored.
+    pub fn push(&mut self, predicted: f64, actual: f64) {
+        if !predicted.is_finite() || !actual.is_finite() {
+            return;
+        }
+        let predicted_dir = Direction::classify(predicted, self.flat);
+        let actual_dir = Direction::classify(actual, self.flat);
+        self.counts[predicted_dir as usize][actual_dir as usize] += 1;
+        self.total_weight += actual.abs();
+        if predicted_dir == actual_dir {
+            self.right_weight += actual.abs();
+        }
+    }
+    /// Add every pair of corresponding entries in a tensor of predicted returns and a tensor of actual returns
+    pub fn push_tensors(&mut self, predicted: &Tensor, actual: &Tensor) {
+        let predicted = tensor_to_vec(predicted);
+        let actual = tensor_to_vec(actual);
+        for (predicted, actual) in predicted.into_iter().zip(actual) {
+            self.push(predicted, actual)
+        }
+    }
+    /// Add every entry of a tensor of predicted returns, or of predicted changes with the same sign, e.g. predicted
+    /// minus current levels, against the actual return between corresponding raw closes and the raw closes they
+    /// target, as given by `BatchBuffer::closes` and `BatchBuffer::next_closes`. Entries where either close is missing
+    /// (zero) are skipped.
+    pub fn push_returns(&mut self, predicted: &Tensor, closes: &Tensor, next_closes: &Tensor) {
+        let predicted = tensor_to_vec(predicted);
+        let closes = tensor_to_vec(closes);
+        let next_closes = tensor_to_vec(next_closes);
+        for ((predicted, close), next_close) in predicted.into_iter().zip(closes).zip(next_closes) {
+            if close > 0.0 && next_close > 0.0 {
+                self.push(predicted, next_close / close - 1.0)
+            }
+        }
+    }
+    /// Get the number of samples with a given predicted and actual direction
+    pub fn count(&self, predicted: Direction, actual: Direction) -> u64 {
+        self.counts[predicted as usize][actual as usize]
+    }
+    /// Get the total number of samples
+    pub fn total(&self) -> u64 {
+        self.counts.iter().flatten().sum()
+    }
+    /// Get the number of samples whose direction was predicted correctly
+    pub fn right(&self) -> u64 {
+        Direction::ALL.iter().map(|&dir| self.count(dir, dir)).sum()
+    }
+    /// Get the fraction of samples whose direction was predicted correctly, or zero if there are none
+    pub fn hit_rate(&self) -> f64 {
+        fraction(self.right() as f64, self.total() as f64)
+    }
+    /// Get the fraction of samples predicted to move in a direction which actually did, or zero if none were
+    pub fn precision(&self, direction: Direction) -> f64 {
+        let predicted: u64 = self.counts[direction as usize].iter().sum();
+        fraction(self.count(direction, direction) as f64, predicted as f64)
+    }
+    /// Get the fraction of samples moving in a direction which were predicted to, or zero if none moved in it
+    pub fn recall(&self, direction: Direction) -> f64 {
+        let actual: u64 = self.counts.iter().map(|row| row[direction as usize]).sum();
+        fraction(self.count(direction, direction) as f64, actual as f64)
+    }
+    /// Get the accuracy weighted by the absolute actual return, i.e. the fraction of the total absolute movement
+    /// whose direction was predicted correctly, or zero if nothing moved
+    pub fn profit_weighted_accuracy(&self) -> f64 {
+        fraction(self.right_weight, self.total_weight)
+    }
+}
+
+/// Divide a part by a whole, giving zero rather than NaN for an empty whole
+fn fraction(part: f64, whole: f64) -> f64 {
+    if whole > 0.0 {
+        part / whole
+    } else {
+        0.0
+    }
+}
+
+impl Display for ConfusionMatrix {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        writeln!(fmt, "predicted \\ actual:      up     flat     down")?;
+        for &predicted in Direction::ALL.iter() {
+            writeln!(
+                fmt,
+                "{:>18}: {:>8} {:>8} {:>8} (precision = {:.4})",
+                format!("{:?}", predicted).to_lowercase(),
+                self.count(predicted, Direction::Up),
+                self.count(predicted, Direction::Flat),
+                self.count(predicted, Direction::Down),
+                self.precision(predicted)
+            )?;
+        }
+        write!(
+            fmt,
+            "hit rate = {:.4}, profit weighted accuracy = {:.4}",
+            self.hit_rate(),
+            self.profit_weighted_accuracy()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn confusion_matrix_counts() {
+        let mut matrix = ConfusionMatrix::new(0.01);
+        matrix.push(0.5, 1.0);
+        matrix.push(0.5, -3.0);
+        matrix.push(-0.5, -1.0);
+        matrix.push(0.0, 0.001);
+        matrix.push(f64::NAN, 1.0);
+        assert_eq!(matrix.total(), 4);
+        assert_eq!(matrix.right(), 3);
+        assert_eq!(matrix.count(Direction::Up, Direction::Down), 1);
+        assert_eq!(matrix.precision(Direction::Up), 0.5);
+        assert_eq!(matrix.recall(Direction::Down), 0.5);
+        assert!((matrix.profit_weighted_accuracy() - 2.001 / 5.001).abs() < 1e-12);
+    }
+
+    #[test]
+    fn confusion_matrix_classifies_returns() {
+        let mut matrix = ConfusionMatrix::new(0.0);
+        // Predicted changes are classified against real returns, skipping entries with a missing close
+        let predicted = Tensor::of_slice(&[0.5f32, -0.5, 0.5, 0.5]);
+        let closes = Tensor::of_slice(&[10.0f32, 10.0, 0.0, 10.0]);
+        let next_closes = Tensor::of_slice(&[11.0f32, 12.0, 11.0, 0.0]);
+        matrix.push_returns(&predicted, &closes, &next_closes);
+        assert_eq!(matrix.total(), 2);
+        assert_eq!(matrix.count(Direction::Up, Direction::Up), 1);
+        assert_eq!(matrix.count(Direction::Down, Direction::Up), 1);
+        assert!((matrix.profit_weighted_accuracy() - 0.1 / 0.3).abs() < 1e-6);
+        // Empty rows give zero precision rather than NaN
+        assert_eq!(matrix.precision(Direction::Flat), 0.0);
+        assert_eq!(ConfusionMatrix::new(0.0).hit_rate(), 0.0);
+    }
+
+    #[test]
+    fn normal_cdf() {
+        assert!((standard_normal_cdf(0.0) - 0.5).abs() < 1e-7);
+        assert!((standard_normal_cdf(1.959_964) - 0.975).abs() < 1e-6);
+        assert!((standard_normal_cdf(-1.0) - 0.158_655_25).abs() < 1e-6);
+    }
+}
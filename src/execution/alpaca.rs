@@ -0,0 +1,218 @@
+/*!
+A broker executing orders through [Alpaca](https://alpaca.markets/)'s trading API, defaulting to paper trading
+*/
+use super::{Account, Broker, BrokerOrder};
+use crate::data::Symbol;
+use crate::trading::{Order, OrderKind, Position, Side, TimeInForce};
+use anyhow::{format_err, Result};
+use serde::Deserialize;
+use serde_json::json;
+
+/// The base URL of Alpaca's paper trading API
+pub const ALPACA_PAPER_URL: &str = "https://paper-api.alpaca.markets/v2";
+
+/// The base URL of Alpaca's live trading API
+pub const ALPACA_LIVE_URL: &str = "https://api.alpaca.markets/v2";
+
+/// An order, as returned by Alpaca. Quantities and prices are sent as strings.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+struct AlpacaOrder {
+    id: String,
+    symbol: String,
+    qty: String,
+    filled_qty: String,
+    filled_avg_price: Option<String>,
+    side: Side,
+    #[serde(rename = "type")]
+    kind: String,
+    limit_price: Option<String>,
+    time_in_force: String,
+    status: String,
+}
+
+/// A position, as returned by Alpaca
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+struct AlpacaPosition {
+    symbol: String,
+    qty: String,
+    avg_entry_price: String,
+    market_value: String,
+}
+
+/// An account, as returned by Alpaca
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+struct AlpacaAccount {
+    cash: String,
+    equity: String,
+    buying_power: String,
+}
+
+/// Parse a number sent by Alpaca as a string
+fn parse_number(field: &str, value: &str) -> Result<f64> {
+    value
+        .parse()
+        .map_err(|_| format_err!("Invalid {} {:?} returned by Alpaca", field, value))
+}
+
+impl AlpacaOrder {
+    /// Convert this order to a `BrokerOrder`
+    fn into_broker_order(self) -> Result<BrokerOrder> {
+        let kind = match (self.kind.as_str(), &self.limit_price) {
+            ("limit", Some(price)) => OrderKind::Limit(parse_number("limit price", price)?),
+            _ => OrderKind::Market,
+        };
+        let time_in_force = match self.time_in_force.as_str() {
+            "gtc" => TimeInForce::GoodTillCancelled,
+            _ => TimeInForce::Day,
+        };
+        Ok(BrokerOrder {
+            order: Order {
+                symbol: Symbol(self.symbol),
+                qty: parse_number("quantity", &self.qty)?,
+                side: self.side,
+                kind,
+                time_in_force,
+            },
+            filled_qty: parse_number("filled quantity", &self.filled_qty)?,
+            filled_price: match &self.filled_avg_price {
+                Some(price) => Some(parse_number("fill price", price)?),
+                None => None,
+            },
+            status: self.status,
+            id: self.id,
+        })
+    }
+}
+
+/// A broker executing orders through Alpaca's trading API, authenticated by an API key pair
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct AlpacaBroker {
+    /// The API key ID
+    pub key_id: String,
+    /// The API secret key
+    pub secret_key: String,
+    /// The base URL of the trading API, which is the paper trading API unless set otherwise
+    pub trading_url: String,
+}
+
+impl AlpacaBroker {
+    /// Create a new paper trading broker from an API key pair
+    pub fn paper(key_id: &str, secret_key: &str) -> AlpacaBroker {
+        AlpacaBroker {
+            key_id: key_id.to_owned(),
+            secret_key: secret_key.to_owned(),
+            trading_url: ALPACA_PAPER_URL.to_owned(),
+        }
+    }
+    /// Create a new paper trading broker from the `APCA_API_KEY_ID` and `APCA_API_SECRET_KEY` environment variables
+    pub fn from_env() -> Result<AlpacaBroker> {
+        let key_id = std::env::var("APCA_API_KEY_ID")?;
+        let secret_key = std::env::var("APCA_API_SECRET_KEY")?;
+        Ok(AlpacaBroker::paper(&key_id, &secret_key))
+    }
+    /// Build an authenticated request to an endpoint of the trading API
+    fn request(&self, method: &str, endpoint: &str) -> ureq::Request {
+        let url = format!("{}/{}", self.trading_url, endpoint);
+        let mut request = ureq::request(method, &url);
+        request
+            .set("APCA-API-KEY-ID", &self.key_id)
+            .set("APCA-API-SECRET-KEY", &self.secret_key);
+        request
+    }
+    /// Check that a response succeeded, describing the request in errors
+    fn check(response: ureq::Response, what: &str) -> Result<ureq::Response> {
+        if let Some(err) = response.synthetic_error() {
+            return Err(format_err!("Error requesting Alpaca {}: {}", what, err));
+        }
+        if !response.ok() {
+            return Err(format_err!(
+                "Alpaca {} request failed with status {}: {}",
+                what,
+                response.status(),
+                response.into_string().unwrap_or_default()
+            ));
+        }
+        Ok(response)
+    }
+}
+
+impl Broker for AlpacaBroker {
+    fn submit(&mut self, order: &Order) -> Result<BrokerOrder> {
+        let mut body = json!({
+            "symbol": order.symbol.0,
+            "qty": order.qty.to_string(),
+            "side": order.side,
+            "type": "market",
+            "time_in_force": match order.time_in_force {
+                TimeInForce::Day => "day",
+                TimeInForce::GoodTillCancelled => "gtc",
+            },
+        });
+        if let OrderKind::Limit(price) = order.kind {
+            body["type"] = json!("limit");
+            body["limit_price"] = json!(price.to_string());
+        }
+        let response = self.request("POST", "orders").send_json(body);
+        let order: AlpacaOrder = Self::check(response, "order")?.into_json_deserialize()?;
+        order.into_broker_order()
+    }
+    fn cancel(&mut self, id: &str) -> Result<()> {
+        let response = self.request("DELETE", &format!("orders/{}", id)).call();
+        Self::check(response, "cancellation")?;
+        Ok(())
+    }
+    fn positions(&mut self) -> Result<Vec<Position>> {
+        let response = self.request("GET", "positions").call();
+        let positions: Vec<AlpacaPosition> =
+            Self::check(response, "positions")?.into_json_deserialize()?;
+        positions
+            .into_iter()
+            .map(|position| {
+                Ok(Position {
+                    shares: parse_number("position quantity", &position.qty)?,
+                    entry_price: parse_number("entry price", &position.avg_entry_price)?,
+                    market_value: parse_number("market value", &position.market_value)?,
+                    symbol: Symbol(position.symbol),
+                })
+            })
+            .collect()
+    }
+    fn account(&mut self) -> Result<Account> {
+        let response = self.request("GET", "account").call();
+        let account: AlpacaAccount = Self::check(response, "account")?.into_json_deserialize()?;
+        Ok(Account {
+            cash: parse_number("cash", &account.cash)?,
+            equity: parse_number("equity", &account.equity)?,
+            buying_power: parse_number("buying power", &account.buying_power)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn orders_parse() {
+        let order: AlpacaOrder = serde_json::from_str(
+            r#"{
+                "id": "904837e3-3b76-47ec-b432-046db621571b",
+                "symbol": "AMD",
+                "qty": "15",
+                "filled_qty": "15",
+                "filled_avg_price": "82.25",
+                "side": "sell",
+                "type": "limit",
+                "limit_price": "82.00",
+                "time_in_force": "gtc",
+                "status": "filled"
+            }"#,
+        )
+        .unwrap();
+        let order = order.into_broker_order().unwrap();
+        assert_eq!(order.order.shares(), -15.0);
+        assert_eq!(order.order.kind, OrderKind::Limit(82.0));
+        assert_eq!(order.order.time_in_force, TimeInForce::GoodTillCancelled);
+        assert_eq!(order.filled_price, Some(82.25));
+    }
+}
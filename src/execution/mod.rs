@@ -0,0 +1,117 @@
+/*!
+Order execution through brokers, so that predictions can drive actual orders. An implementation against Alpaca's
+trading API is enabled by the `alpaca` feature.
+*/
+use crate::data::Symbol;
+use crate::trading::{Order, Position};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+#[cfg(feature = "alpaca")]
+pub mod alpaca;
+
+/// An order accepted by a broker
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BrokerOrder {
+    /// The broker's ID for the order, used to cancel it
+    pub id: String,
+    /// The order as accepted
+    pub order: Order,
+    /// The number of shares filled so far
+    pub filled_qty: f64,
+    /// The average price of the shares filled so far, if any
+    pub filled_price: Option<f64>,
+    /// The broker's status for the order, e.g. `new`, `filled` or `canceled`
+    pub status: String,
+}
+
+/// The state of a brokerage account
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Account {
+    /// The cash balance
+    pub cash: f64,
+    /// The total value of cash and positions
+    pub equity: f64,
+    /// The buying power available for new orders
+    pub buying_power: f64,
+}
+
+/// A broker, through which orders are executed
+pub trait Broker {
+    /// Submit an order, returning it as accepted by the broker
+    fn submit(&mut self, order: &Order) -> anyhow::Result<BrokerOrder>;
+    /// Cancel an open order, given its ID
+    fn cancel(&mut self, id: &str) -> anyhow::Result<()>;
+    /// Get every open position
+    fn positions(&mut self) -> anyhow::Result<Vec<Position>>;
+    /// Get the state of the account
+    fn account(&mut self) -> anyhow::Result<Account>;
+}
+
+/// Compute the market orders taking a set of positions, by number of shares, to a set of targets, e.g. the positions of
+/// a `Backtest` driven by live predictions. Symbols held but without a target are closed. Trades are rounded towards zero
+/// to whole shares, as by `sizing::weight_to_shares`, so that fractional targets never overshoot, and trades of less than
+/// `min_shares` are skipped.
+pub fn rebalance_orders(
+    positions: &[Position],
+    targets: &BTreeMap<Symbol, f64>,
+    min_shares: f64,
+) -> Vec<Order> {
+    let mut trades: BTreeMap<Symbol, f64> = targets.clone();
+    for position in positions {
+        *trades.entry(position.symbol.clone()).or_insert(0.0) -= position.shares;
+    }
+    trades
+        .into_iter()
+        .map(|(symbol, shares)| (symbol, shares.trunc()))
+        .filter(|(_, shares)| shares.abs() >= min_shares && *shares != 0.0)
+        .map(|(symbol, shares)| Order::market(symbol, shares))
+        .collect()
+}
+
+/// Submit the market orders taking a broker's positions to a set of targets, as computed by `rebalance_orders`,
+/// returning the orders accepted. Stops at the first order rejected.
+pub fn rebalance<B: Broker + ?Sized>(
+    broker: &mut B,
+    targets: &BTreeMap<Symbol, f64>,
+    min_shares: f64,
+) -> anyhow::Result<Vec<BrokerOrder>> {
+    let positions = broker.positions()?;
+    let mut orders = Vec::new();
+    for order in rebalance_orders(&positions, targets, min_shares) {
+        debug!("Submitting {:?}", order);
+        orders.push(broker.submit(&order)?);
+    }
+    Ok(orders)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trading::Side;
+
+    #[test]
+    fn rebalancing_trades_the_difference() {
+        let position = |symbol: &str, shares: f64| Position {
+            symbol: Symbol::from(symbol),
+            shares,
+            entry_price: 10.0,
+            market_value: 10.0 * shares,
+        };
+        let positions = vec![position("AMD", 10.0), position("INTC", -5.0)];
+        let mut targets = BTreeMap::new();
+        targets.insert(Symbol::from("AMD"), 4.0);
+        targets.insert(Symbol::from("NVDA"), 2.7);
+        targets.insert(Symbol::from("TSLA"), 0.5);
+        let orders = rebalance_orders(&positions, &targets, 1.0);
+        assert_eq!(orders.len(), 3);
+        assert_eq!(orders[0].symbol, Symbol::from("AMD"));
+        assert_eq!(orders[0].side, Side::Sell);
+        assert_eq!(orders[0].shares(), -6.0);
+        assert_eq!(orders[1].symbol, Symbol::from("INTC"));
+        assert_eq!(orders[1].shares(), 5.0);
+        assert_eq!(orders[2].symbol, Symbol::from("NVDA"));
+        assert_eq!(orders[2].qty, 2.0);
+        assert_eq!(rebalance_orders(&positions, &targets, 0.0).len(), 3);
+    }
+}
@@ -7,8 +7,25 @@ was based off the [Knet](https://github.com/denizyuret/Knet.jl) machine learning
 */
 #![forbid(missing_docs)]
 
+#[macro_use]
+mod logging;
+
+pub mod analysis;
+pub mod backtest;
+#[cfg(feature = "capi")]
+pub mod capi;
 pub mod data;
+pub mod eval;
+pub mod execution;
 pub mod lstm;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+pub mod predict;
+pub mod serve;
+#[cfg(feature = "testing")]
+pub mod testing;
+pub mod trading;
+pub mod train;
 pub mod util;
 
 /// The floating point type to be used for CPU calculations
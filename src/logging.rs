@@ -0,0 +1,63 @@
+/*!
+Logging macros, which emit `tracing` spans and events when the `tracing` feature is enabled and compile to nothing
+otherwise
+*/
+
+#[cfg(feature = "tracing")]
+macro_rules! trace {
+    ($($arg:tt)*) => { tracing::trace!($($arg)*) };
+}
+
+#[cfg(feature = "tracing")]
+macro_rules! debug {
+    ($($arg:tt)*) => { tracing::debug!($($arg)*) };
+}
+
+#[cfg(feature = "tracing")]
+macro_rules! warn {
+    ($($arg:tt)*) => { tracing::warn!($($arg)*) };
+}
+
+#[cfg(feature = "tracing")]
+macro_rules! debug_span {
+    ($($arg:tt)*) => { tracing::debug_span!($($arg)*) };
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! trace {
+    ($($arg:tt)*) => {
+        if false {
+            let _ = format_args!($($arg)*);
+        }
+    };
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! debug {
+    ($($arg:tt)*) => { trace!($($arg)*) };
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! warn {
+    ($($arg:tt)*) => { trace!($($arg)*) };
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! debug_span {
+    ($($arg:tt)*) => {
+        crate::logging::NoSpan
+    };
+}
+
+/// A stand-in for a `tracing::Span` when the `tracing` feature is disabled
+#[cfg(not(feature = "tracing"))]
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct NoSpan;
+
+#[cfg(not(feature = "tracing"))]
+impl NoSpan {
+    /// Enter this span, which does nothing
+    pub(crate) fn enter(&self) -> NoSpan {
+        NoSpan
+    }
+}
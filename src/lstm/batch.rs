@@ -0,0 +1,792 @@
+/*!
+Preallocated batch tensors, which batches of data are written into directly
+*/
+use super::StockLSTM;
+use crate::data::dataset::{Dataset, Window};
+use crate::data::Tick;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use num::NumCast;
+use std::collections::VecDeque;
+use std::iter::Peekable;
+use tch::{Device, Kind, Tensor};
+
+/// A pair of preallocated input and output tensors of a fixed shape, which batches are written into in place.
+///
+/// Reusing a buffer across batches avoids allocating new tensors, and whole-batch intermediate vectors, for every
+/// batch: each row is written into its place in the tensors' storage as soon as it is built. Writing a batch
+/// overwrites the previous batch, including any shallow clones of the tensors, so the previous batch should be done
+/// with (e.g. its backward pass run) before the next is written.
+///
+/// Buffers may be allocated in pinned (page-locked) memory, in which case transfers to a CUDA device are issued
+/// asynchronously, overlapping with computation already queued on the device. See `BatchRing`.
+#[derive(Debug)]
+pub struct BatchBuffer {
+    /// The input tensor, of shape `[batch_size, sequence_length, input_features]`
+    input: Tensor,
+    /// The output tensor, of shape `[batch_size, sequence_length, output_features]`
+    output: Tensor,
+    /// The number of sequences in each batch
+    batch_size: usize,
+    /// The length of each sequence
+    sequence_length: usize,
+    /// The number of input features in each row
+    input_features: usize,
+    /// The number of output features in each row
+    output_features: usize,
+    /// Whether the tensors are allocated in pinned memory
+    pinned: bool,
+    /// Scratch space for the inputs of a row
+    pub(crate) input_row: Vec<f32>,
+    /// Scratch space for the outputs of a row
+    pub(crate) output_row: Vec<f32>,
+    /// The raw close of each stock in each row of the last batch written, in row-major order
+    pub(crate) closes: Vec<f32>,
+    /// The raw close of each stock at the row targeted by each row of the last batch written, in row-major order
+    pub(crate) next_closes: Vec<f32>,
+}
+
+impl BatchBuffer {
+    /// Allocate a zeroed buffer for batches of a given shape
+    pub fn new(
+        batch_size: usize,
+        sequence_length: usize,
+        input_features: usize,
+        output_features: usize,
+    ) -> BatchBuffer {
+        let shape = |features: usize| [batch_size as i64, sequence_length as i64, features as i64];
+        BatchBuffer {
+            input: Tensor::zeros(&shape(input_features), (Kind::Float, Device::Cpu)),
+            output: Tensor::zeros(&shape(output_features), (Kind::Float, Device::Cpu)),
+            batch_size,
+            sequence_length,
+            input_features,
+            output_features,
+            pinned: false,
+            input_row: Vec::with_capacity(input_features),
+            output_row: Vec::with_capacity(output_features),
+            closes: Vec::new(),
+            next_closes: Vec::new(),
+        }
+    }
+    /// Allocate a zeroed buffer for batches of a given shape in pinned memory. Requires CUDA to be available.
+    pub fn pinned(
+        batch_size: usize,
+        sequence_length: usize,
+        input_features: usize,
+        output_features: usize,
+    ) -> BatchBuffer {
+        let mut buffer =
+            BatchBuffer::new(batch_size, sequence_length, input_features, output_features);
+        buffer.input = buffer.input.pin_memory();
+        buffer.output = buffer.output.pin_memory();
+        buffer.pinned = true;
+        buffer
+    }
+    /// Whether this buffer is allocated in pinned memory
+    pub fn is_pinned(&self) -> bool {
+        self.pinned
+    }
+    /// Get the number of sequences in each batch
+    pub fn batch_size(&self) -> usize {
+        self.batch_size
+    }
+    /// Get the length of each sequence
+    pub fn sequence_length(&self) -> usize {
+        self.sequence_length
+    }
+    /// Get the number of input features in each row
+    pub fn input_features(&self) -> usize {
+        self.input_features
+    }
+    /// Get the number of output features in each row
+    pub fn output_features(&self) -> usize {
+        self.output_features
+    }
+    /// Check whether this buffer holds batches of a given shape
+    pub fn has_shape(
+        &self,
+        batch_size: usize,
+        sequence_length: usize,
+        input_features: usize,
+        output_features: usize,
+    ) -> bool {
+        self.batch_size == batch_size
+            && self.sequence_length == sequence_length
+            && self.input_features == input_features
+            && self.output_features == output_features
+    }
+    /// Get the input tensor
+    pub fn input(&self) -> &Tensor {
+        &self.input
+    }
+    /// Get the output tensor
+    pub fn output(&self) -> &Tensor {
+        &self.output
+    }
+    /// Get shallow clones of the input and output tensors, which share this buffer's storage
+    pub fn tensors(&self) -> (Tensor, Tensor) {
+        (self.input.shallow_clone(), self.output.shallow_clone())
+    }
+    /// Get the raw close of each stock in each row of the last batch written, zero for missing ticks, as a tensor of
+    /// shape `[batch_size, sequence_length, stocks]`. Unlike the inputs, these are never scaled, so that real returns
+    /// can be computed from them, e.g. for `SampleWeighting`.
+    pub fn closes(&self) -> Tensor {
+        self.close_tensor(&self.closes)
+    }
+    /// Get the raw close of each stock at the row targeted by each row of the last batch written, i.e. `target_horizon`
+    /// rows ahead, zero for missing ticks, as a tensor of shape `[batch_size, sequence_length, stocks]`. Together with
+    /// `closes`, this gives the real return targeted by each row.
+    pub fn next_closes(&self) -> Tensor {
+        self.close_tensor(&self.next_closes)
+    }
+    /// Reshape a row-major vector of closes of each stock in each row into a tensor
+    fn close_tensor(&self, closes: &[f32]) -> Tensor {
+        let rows = self.batch_size * self.sequence_length;
+        let stocks = closes.len() / rows.max(1);
+        Tensor::of_slice(closes).view([
+            self.batch_size as i64,
+            self.sequence_length as i64,
+            stocks as i64,
+        ])
+    }
+    /// Transfer the input and output tensors to a device. Transfers from pinned memory are non-blocking, so this buffer
+    /// should not be written to again until the transfer is complete, e.g. until a value computed from the transferred
+    /// tensors has been read back. Transfers to the CPU return shallow clones.
+    pub fn to_device(&self, device: Device) -> (Tensor, Tensor) {
+        if device == Device::Cpu {
+            return self.tensors();
+        }
+        (
+            self.input
+                .to_device_(device, Kind::Float, self.pinned, false),
+            self.output
+                .to_device_(device, Kind::Float, self.pinned, false),
+        )
+    }
+    /// Take the input and output tensors out of this buffer
+    pub fn into_tensors(self) -> (Tensor, Tensor) {
+        (self.input, self.output)
+    }
+    /// Copy the scratch row into a row of the tensors, clearing the scratch row. Panics if the scratch row has the
+    /// wrong number of features.
+    pub(crate) fn commit_row(&mut self, row: usize) {
+        assert_eq!(
+            self.input_row.len(),
+            self.input_features,
+            "Wrong number of input features in row!"
+        );
+        assert_eq!(
+            self.output_row.len(),
+            self.output_features,
+            "Wrong number of output features in row!"
+        );
+        let rows = self.batch_size * self.sequence_length;
+        assert!(row < rows, "Row out of bounds!");
+        let input = row * self.input_features..(row + 1) * self.input_features;
+        let output = row * self.output_features..(row + 1) * self.output_features;
+        // The tensors are contiguous CPU float tensors with `rows * features` elements, and the slices do not outlive
+        // this block, so their storage can be viewed as slices of that length
+        unsafe {
+            float_storage(&mut self.input, rows * self.input_features)[input]
+                .copy_from_slice(&self.input_row);
+            float_storage(&mut self.output, rows * self.output_features)[output]
+                .copy_from_slice(&self.output_row);
+        }
+        self.input_row.clear();
+        self.output_row.clear();
+    }
+}
+
+/// A ring of batch buffers which are written to in turn, so that a batch can be written while the transfer of the
+/// previous batch to a device is still in flight.
+///
+/// With `n` buffers, a buffer is only reused after `n - 1` further batches have been written, so the asynchronous
+/// transfer of each batch has until then to complete. Since reading back a loss synchronizes with the device, two
+/// buffers suffice for a typical training loop.
+#[derive(Debug)]
+pub struct BatchRing {
+    /// The buffers in this ring
+    pub buffers: Vec<BatchBuffer>,
+    /// The index of the next buffer to write to
+    next: usize,
+}
+
+impl BatchRing {
+    /// Create a ring of buffers. Panics if there are no buffers.
+    pub fn new(buffers: Vec<BatchBuffer>) -> BatchRing {
+        assert!(
+            !buffers.is_empty(),
+            "A batch ring needs at least one buffer!"
+        );
+        BatchRing { buffers, next: 0 }
+    }
+    /// Get the next buffer to write to, advancing the ring
+    pub fn next_mut(&mut self) -> &mut BatchBuffer {
+        let ix = self.next;
+        self.next = (self.next + 1) % self.buffers.len();
+        &mut self.buffers[ix]
+    }
+}
+
+/// How a final batch with fewer rows than a full batch is handled
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum TailPolicy {
+    /// Zero pad the batch to full size, masking out the padding
+    Pad,
+    /// Drop the batch, counting its rows as dropped
+    Drop,
+    /// Package the batch as fewer sequences of the same length, dropping the rows which do not fill a sequence
+    Reshape,
+}
+
+impl Default for TailPolicy {
+    fn default() -> TailPolicy {
+        TailPolicy::Pad
+    }
+}
+
+/// A time-ordered source of ticks which can be looked ahead into without consuming them, so that the targets of the
+/// last rows of a batch can be read while being left for the next batch
+pub trait TickSource<F> {
+    /// How many ticks ahead this source can look
+    const LOOKAHEAD: usize;
+    /// Peek at the tick `n` ticks ahead, without consuming any ticks. Panics if `n` is not less than `LOOKAHEAD`.
+    fn peek_nth(&mut self, n: usize) -> Option<&Tick<F>>;
+    /// Take the next tick
+    fn next_tick(&mut self) -> Option<Tick<F>>;
+    /// Peek at the raw tick the tick `n` ticks ahead was scaled from, which changes are computed from. Sources of
+    /// unscaled ticks return the tick itself.
+    fn peek_raw_nth(&mut self, n: usize) -> Option<&Tick<F>> {
+        self.peek_nth(n)
+    }
+}
+
+impl<I, F> TickSource<F> for Peekable<I>
+where
+    I: Iterator<Item = Tick<F>>,
+{
+    const LOOKAHEAD: usize = 1;
+    fn peek_nth(&mut self, n: usize) -> Option<&Tick<F>> {
+        assert_eq!(n, 0, "A peekable iterator can only look one tick ahead");
+        self.peek()
+    }
+    fn next_tick(&mut self) -> Option<Tick<F>> {
+        self.next()
+    }
+}
+
+/// An iterator adaptor buffering the ticks it has been asked to look ahead at, for target horizons longer than the one
+/// row a `Peekable` can look ahead. Only the ticks looked ahead at are buffered.
+#[derive(Debug, Clone)]
+pub struct Lookahead<I: Iterator> {
+    /// The underlying iterator
+    iter: I,
+    /// The items looked ahead at but not yet taken, in order
+    buffer: VecDeque<I::Item>,
+}
+
+impl<I: Iterator> Lookahead<I> {
+    /// Wrap an iterator to look ahead into it
+    pub fn new(iter: I) -> Lookahead<I> {
+        Lookahead {
+            iter,
+            buffer: VecDeque::new(),
+        }
+    }
+    /// Peek at the item `n` items ahead, without consuming any items
+    pub fn peek_nth(&mut self, n: usize) -> Option<&I::Item> {
+        while self.buffer.len() <= n {
+            self.buffer.push_back(self.iter.next()?);
+        }
+        self.buffer.get(n)
+    }
+}
+
+impl<I: Iterator> Iterator for Lookahead<I> {
+    type Item = I::Item;
+    fn next(&mut self) -> Option<I::Item> {
+        self.buffer.pop_front().or_else(|| self.iter.next())
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let (lower, upper) = self.iter.size_hint();
+        let buffered = self.buffer.len();
+        (
+            lower.saturating_add(buffered),
+            upper.and_then(|upper| upper.checked_add(buffered)),
+        )
+    }
+}
+
+impl<I: ExactSizeIterator> ExactSizeIterator for Lookahead<I> {}
+
+impl<I, F> TickSource<F> for Lookahead<I>
+where
+    I: Iterator<Item = Tick<F>>,
+{
+    const LOOKAHEAD: usize = usize::MAX;
+    fn peek_nth(&mut self, n: usize) -> Option<&Tick<F>> {
+        Lookahead::peek_nth(self, n)
+    }
+    fn next_tick(&mut self) -> Option<Tick<F>> {
+        self.next()
+    }
+}
+
+/// A tick source over pairs of scaled ticks and the raw ticks they were scaled from, e.g. as returned by
+/// `scale_ticks_with_raw`, so that return targets are computed from raw prices rather than scaled levels
+#[derive(Debug, Clone)]
+pub struct ScaledTicks<I: Iterator>(Lookahead<I>);
+
+impl<I: Iterator> ScaledTicks<I> {
+    /// Look ahead into an iterator of pairs of scaled and raw ticks
+    pub fn new(iter: I) -> ScaledTicks<I> {
+        ScaledTicks(Lookahead::new(iter))
+    }
+}
+
+impl<I: Iterator> Iterator for ScaledTicks<I> {
+    type Item = I::Item;
+    fn next(&mut self) -> Option<I::Item> {
+        self.0.next()
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+impl<I: ExactSizeIterator> ExactSizeIterator for ScaledTicks<I> {}
+
+impl<I, F> TickSource<F> for ScaledTicks<I>
+where
+    I: Iterator<Item = (Tick<F>, Tick<F>)>,
+{
+    const LOOKAHEAD: usize = usize::MAX;
+    fn peek_nth(&mut self, n: usize) -> Option<&Tick<F>> {
+        self.0.peek_nth(n).map(|(scaled, _raw)| scaled)
+    }
+    fn next_tick(&mut self) -> Option<Tick<F>> {
+        self.0.next().map(|(scaled, _raw)| scaled)
+    }
+    fn peek_raw_nth(&mut self, n: usize) -> Option<&Tick<F>> {
+        self.0.peek_nth(n).map(|(_scaled, raw)| raw)
+    }
+}
+
+/// The shape of the batches to package: how many sequences of how many rows, and how to handle a short final batch
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct BatchShape {
+    /// The number of sequences in a batch
+    pub batch_size: usize,
+    /// The number of rows in a sequence
+    pub sequence_length: usize,
+    /// How a final batch with fewer rows than a full batch is handled
+    pub tail: TailPolicy,
+}
+
+/// A batch of inputs and outputs, together with a mask of the rows which hold real data with real targets
+#[derive(Debug)]
+pub struct Batch {
+    /// The inputs, of shape `[batch_size, sequence_length, input_features]`
+    pub input: Tensor,
+    /// The outputs, of shape `[batch_size, sequence_length, output_features]`
+    pub output: Tensor,
+    /// The row mask, of shape `[batch_size, sequence_length, 1]`, which is `1.0` for rows of real data whose targets
+    /// are available and `0.0` for padding. Can be passed directly to a `LossFn`.
+    pub mask: Tensor,
+    /// The number of rows of real data with available targets in this batch, i.e. the number of rows in the mask
+    pub rows: usize,
+}
+
+impl Batch {
+    /// Get the number of sequences in this batch
+    pub fn batch_size(&self) -> usize {
+        self.input.size()[0] as usize
+    }
+    /// Check whether every row of this batch is real data with real targets
+    pub fn is_full(&self) -> bool {
+        self.mask.numel() == self.rows
+    }
+}
+
+/// Zero fill and alignment statistics accumulated over consecutive batches, to catch misaligned clocks, which otherwise
+/// silently degrade training.
+///
+/// The skew of a row is the time since the stalest stock's most recent tick, e.g. 5 minutes for a row in which one
+/// stock last ticked 5 minutes ago, while the others tick on time.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BatchStats {
+    /// The number of batches observed
+    pub batches: usize,
+    /// The number of rows of real data observed
+    pub rows: usize,
+    /// The number of padding rows observed, past the end of the data
+    pub padding: usize,
+    /// The number of rows of real data in which each stock has no tick, and hence is zero filled
+    pub zero_filled: Vec<usize>,
+    /// The number of rows, including padding, in which no stock has a tick
+    pub empty_rows: usize,
+    /// The sum of the skew of every row of real data, in seconds
+    pub total_skew: f64,
+    /// The largest skew of any row of real data, in seconds
+    pub max_skew: i64,
+    /// The time of each stock's most recent tick, if any
+    last_seen: Vec<Option<NaiveDateTime>>,
+}
+
+impl BatchStats {
+    /// Create empty statistics for batches of a given number of stocks
+    pub fn new(stocks: usize) -> BatchStats {
+        BatchStats {
+            batches: 0,
+            rows: 0,
+            padding: 0,
+            zero_filled: vec![0; stocks],
+            empty_rows: 0,
+            total_skew: 0.0,
+            max_skew: 0,
+            last_seen: vec![None; stocks],
+        }
+    }
+    /// Record a batch of `rows` rows packaged from a window, with any rows past the end of the window zero padded.
+    /// Windows are expected in time order, e.g. consecutive windows of a dataset.
+    pub fn observe<F: Copy>(&mut self, window: Window<F>, rows: usize) {
+        assert_eq!(
+            window.dataset.stocks(),
+            self.zero_filled.len(),
+            "Wrong number of stocks!"
+        );
+        self.batches += 1;
+        self.rows += window.len;
+        self.padding += rows.saturating_sub(window.len);
+        self.empty_rows += rows.saturating_sub(window.len);
+        for (row, t) in window.times().iter().enumerate() {
+            let mut advanced = false;
+            for (stock, last_seen) in self.last_seen.iter_mut().enumerate() {
+                if window.tick(row, stock).is_some() {
+                    *last_seen = Some(*t);
+                    advanced = true;
+                } else {
+                    self.zero_filled[stock] += 1;
+                }
+            }
+            if !advanced {
+                self.empty_rows += 1;
+            }
+            if let Some(stalest) = self.last_seen.iter().flatten().min() {
+                let skew = (*t - *stalest).num_seconds();
+                self.total_skew += skew as f64;
+                self.max_skew = self.max_skew.max(skew);
+            }
+        }
+    }
+    /// Get the fraction of rows of real data in which each stock is zero filled
+    pub fn zero_fill_rates(&self) -> Vec<f64> {
+        let rows = self.rows.max(1) as f64;
+        self.zero_filled
+            .iter()
+            .map(|filled| *filled as f64 / rows)
+            .collect()
+    }
+    /// Get the fraction of rows, including padding, in which no stock has a tick
+    pub fn empty_row_rate(&self) -> f64 {
+        self.empty_rows as f64 / (self.rows + self.padding).max(1) as f64
+    }
+    /// Get the mean skew of the rows of real data, in seconds
+    pub fn mean_skew(&self) -> f64 {
+        self.total_skew / self.rows.max(1) as f64
+    }
+}
+
+/// An iterator over consecutive batches of the rows of a dataset, packaged for a network, which knows exactly how many
+/// batches remain. Each batch holds `batch_size * sequence_length` rows, with the final batch zero padded.
+///
+/// Unlike `StockLSTM::make_batches`, this consumes no iterators, so the same dataset can be batched every epoch, and
+/// the number of batches in an epoch is known up front. See `StockLSTM::batches`.
+pub struct Batches<'a, F, DF> {
+    /// The network the batches are packaged for
+    lstm: &'a StockLSTM,
+    /// The dataset being batched
+    dataset: &'a Dataset<F>,
+    /// The additional inputs of each row of the dataset, zero filled if missing
+    additional: &'a [Vec<f32>],
+    /// The function computing the date inputs of each row
+    time_func: DF,
+    /// The number of sequences in each batch
+    batch_size: usize,
+    /// The length of each sequence
+    sequence_length: usize,
+    /// The first row of the next batch
+    next: usize,
+    /// The statistics of the batches so far, if being collected
+    stats: Option<BatchStats>,
+}
+
+impl<'a, F, DF> Batches<'a, F, DF> {
+    /// Batch the rows of a dataset, with additional inputs given row by row, for a network. Panics if the dataset has
+    /// the wrong number of stocks, or if batches would be empty.
+    pub fn new(
+        lstm: &'a StockLSTM,
+        dataset: &'a Dataset<F>,
+        additional: &'a [Vec<f32>],
+        time_func: DF,
+        batch_size: usize,
+        sequence_length: usize,
+    ) -> Batches<'a, F, DF>
+    where
+        F: Copy,
+    {
+        assert_eq!(
+            dataset.stocks(),
+            lstm.stocks,
+            "Wrong number of input stocks!"
+        );
+        assert!(
+            batch_size * sequence_length > 0,
+            "Batches must have at least one row!"
+        );
+        Batches {
+            lstm,
+            dataset,
+            additional,
+            time_func,
+            batch_size,
+            sequence_length,
+            next: 0,
+            stats: None,
+        }
+    }
+    /// Collect zero fill and alignment statistics of the batches yielded from now on
+    pub fn with_stats(mut self) -> Batches<'a, F, DF> {
+        self.stats = Some(BatchStats::new(self.dataset.stocks()));
+        self
+    }
+    /// Get the statistics of the batches yielded so far, if being collected
+    pub fn stats(&self) -> Option<&BatchStats> {
+        self.stats.as_ref()
+    }
+    /// Get the number of rows in each batch
+    pub fn rows(&self) -> usize {
+        self.batch_size * self.sequence_length
+    }
+    /// Get the first row of the next batch, i.e. the number of rows already batched
+    pub fn position(&self) -> usize {
+        self.next
+    }
+}
+
+impl<'a, F, DF> Iterator for Batches<'a, F, DF>
+where
+    F: Copy + NumCast,
+    DF: FnMut(DateTime<Utc>, &mut Vec<f32>),
+{
+    type Item = (Tensor, Tensor);
+
+    fn next(&mut self) -> Option<(Tensor, Tensor)> {
+        if self.next >= self.dataset.len() {
+            return None;
+        }
+        let start = self.next;
+        self.next += self.rows();
+        let window = self.dataset.window(start, self.rows());
+        if let Some(stats) = &mut self.stats {
+            stats.observe(window, self.rows());
+        }
+        let additional = self
+            .additional
+            .get(start..)
+            .unwrap_or(&[])
+            .iter()
+            .map(Vec::as_slice);
+        self.lstm.make_window_batch(
+            additional,
+            &mut self.time_func,
+            window,
+            self.batch_size,
+            self.sequence_length,
+        )
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.dataset.len().saturating_sub(self.next);
+        let batches = (remaining + self.rows() - 1) / self.rows();
+        (batches, Some(batches))
+    }
+}
+
+impl<'a, F, DF> ExactSizeIterator for Batches<'a, F, DF>
+where
+    F: Copy + NumCast,
+    DF: FnMut(DateTime<Utc>, &mut Vec<f32>),
+{
+}
+
+/// View the storage of a contiguous CPU float tensor with `len` elements as a mutable slice. The caller must ensure
+/// that no other references to the storage are live while the slice is.
+unsafe fn float_storage(tensor: &mut Tensor, len: usize) -> &mut [f32] {
+    std::slice::from_raw_parts_mut(tensor.data_ptr() as *mut f32, len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::{Target, Tick};
+    use crate::lstm::StockLSTMDesc;
+    use chrono::{Duration, NaiveDate};
+    use tch::nn::VarStore;
+
+    #[test]
+    fn rows_are_written_in_place() {
+        let mut buffer = BatchBuffer::new(2, 2, 3, 1);
+        let (input, output) = buffer.tensors();
+        buffer.input_row.extend_from_slice(&[1.0, 2.0, 3.0]);
+        buffer.output_row.push(4.0);
+        buffer.commit_row(3);
+        assert_eq!(Vec::<f32>::from(&input.get(1).get(1)), vec![1.0, 2.0, 3.0]);
+        assert_eq!(Vec::<f32>::from(&output.get(1).get(1)), vec![4.0]);
+        assert_eq!(Vec::<f32>::from(&input.get(0).get(0)), vec![0.0; 3]);
+    }
+
+    #[test]
+    fn batches_know_their_length() {
+        let vs = VarStore::new(Device::Cpu);
+        let lstm = StockLSTMDesc {
+            additional_inputs: 1,
+            stocks: 1,
+            hidden: 2,
+            layers: 1,
+            heads: vec![Target::Close],
+            ..Default::default()
+        }
+        .build(&vs);
+        let t = NaiveDate::from_ymd(2020, 10, 12).and_hms(14, 30, 0);
+        let ticks = (0..7)
+            .map(|ix| Tick {
+                t: t + Duration::minutes(ix),
+                v: 0.0,
+                vw: 0.0,
+                o: 0.0,
+                c: ix as f64,
+                h: 0.0,
+                l: 0.0,
+                n: 0.0,
+            })
+            .collect();
+        let dataset = Dataset::from_ticks(vec!["A".into()], vec![ticks]);
+        let additional: Vec<Vec<f32>> = (0..7).map(|ix| vec![ix as f32]).collect();
+        let mut batches =
+            Batches::new(&lstm, &dataset, &additional, |_, _: &mut Vec<f32>| {}, 2, 2);
+        assert_eq!(batches.len(), 2);
+        let (input, output) = batches.next().unwrap();
+        assert_eq!(batches.len(), 1);
+        assert_eq!(
+            Vec::<f32>::from(&input.select(2, 0).view([-1])),
+            vec![0.0, 1.0, 2.0, 3.0]
+        );
+        assert_eq!(
+            Vec::<f32>::from(&output.view([-1])),
+            vec![1.0, 2.0, 3.0, 4.0]
+        );
+        let (input, output) = batches.next().unwrap();
+        assert_eq!(
+            Vec::<f32>::from(&input.select(2, 0).view([-1])),
+            vec![4.0, 5.0, 6.0, 0.0]
+        );
+        assert_eq!(
+            Vec::<f32>::from(&output.view([-1])),
+            vec![5.0, 6.0, 0.0, 0.0]
+        );
+        assert_eq!(batches.len(), 0);
+        assert!(batches.next().is_none());
+    }
+
+    #[test]
+    fn buffers_record_raw_closes() {
+        let vs = VarStore::new(Device::Cpu);
+        let lstm = StockLSTMDesc {
+            stocks: 1,
+            hidden: 2,
+            layers: 1,
+            heads: vec![Target::Close],
+            ..Default::default()
+        }
+        .build(&vs);
+        let t = NaiveDate::from_ymd(2020, 10, 12).and_hms(14, 30, 0);
+        let tick = |minute: i64, c: f64| Tick {
+            t: t + Duration::minutes(minute),
+            v: 0.0,
+            vw: 0.0,
+            o: 0.0,
+            c,
+            h: 0.0,
+            l: 0.0,
+            n: 0.0,
+        };
+        let scaled = vec![vec![tick(0, 0.0), tick(1, 0.1), tick(2, 0.2)]];
+        let raw = vec![vec![tick(0, 10.0), tick(1, 11.0), tick(2, 12.0)]];
+        let dataset = Dataset::from_ticks(vec!["A".into()], scaled).with_raw(raw);
+        let mut buffer = lstm.batch_buffer(1, 3, false);
+        let (input, _) = lstm
+            .make_window_batch_into(
+                std::iter::empty(),
+                |_, _: &mut Vec<f32>| {},
+                dataset.window(0, 2),
+                &mut buffer,
+            )
+            .unwrap();
+        assert_eq!(
+            Vec::<f32>::from(&input.select(2, 3).view([-1])),
+            vec![0.0, 0.1, 0.0]
+        );
+        assert_eq!(buffer.closes().size(), vec![1, 3, 1]);
+        assert_eq!(
+            Vec::<f32>::from(&buffer.closes().view([-1])),
+            vec![10.0, 11.0, 0.0]
+        );
+        assert_eq!(
+            Vec::<f32>::from(&buffer.next_closes().view([-1])),
+            vec![11.0, 12.0, 0.0]
+        );
+    }
+
+    #[test]
+    fn batch_stats_track_alignment() {
+        let t = NaiveDate::from_ymd(2020, 10, 12).and_hms(14, 30, 0);
+        let tick = |minute: i64| Tick {
+            t: t + Duration::minutes(minute),
+            v: 1.0,
+            vw: 1.0,
+            o: 1.0,
+            c: 1.0,
+            h: 1.0,
+            l: 1.0,
+            n: 1.0,
+        };
+        let dataset = Dataset::from_ticks(
+            vec!["A".into(), "B".into()],
+            vec![(0..5).map(tick).collect(), vec![tick(0), tick(3)]],
+        );
+        let mut stats = BatchStats::new(2);
+        for window in dataset.windows(3, 3) {
+            stats.observe(window, 3);
+        }
+        assert_eq!(stats.batches, 2);
+        assert_eq!((stats.rows, stats.padding), (5, 1));
+        assert_eq!(stats.zero_filled, vec![0, 3]);
+        assert_eq!(stats.zero_fill_rates(), vec![0.0, 0.6]);
+        assert_eq!(stats.empty_rows, 1);
+        // B is 1 and 2 minutes stale at minutes 1 and 2, and again 1 minute stale at minute 4
+        assert_eq!(stats.max_skew, 120);
+        assert_eq!(stats.mean_skew(), 240.0 / 5.0);
+    }
+
+    #[test]
+    fn lookahead_leaves_peeked_items() {
+        let mut items = Lookahead::new(0..5);
+        assert_eq!(items.peek_nth(2), Some(&2));
+        assert_eq!(items.len(), 5);
+        assert_eq!(items.next(), Some(0));
+        assert_eq!(items.peek_nth(0), Some(&1));
+        assert_eq!(items.peek_nth(4), None);
+        assert_eq!(items.collect::<Vec<_>>(), vec![1, 2, 3, 4]);
+    }
+}
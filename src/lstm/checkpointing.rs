@@ -0,0 +1,182 @@
+/*!
+Gradient checkpointing: recomputing recurrent activations segment by segment during the backward pass, so that long
+sequences fit in GPU memory at the cost of a second forward pass
+*/
+use super::loss::LossFn;
+use super::StockLSTM;
+use tch::nn::LSTMState;
+use tch::{Kind, Tensor};
+
+/// Make a detached copy of an LSTM state, cut off from the graph which computed it
+fn detach_state(state: &LSTMState) -> LSTMState {
+    LSTMState((state.h().detach(), state.c().detach()))
+}
+
+impl StockLSTM {
+    /// Compute the loss of this network on a batch of inputs and outputs, and accumulate its gradient into this
+    /// network's variables, keeping only the activations of one segment of `segment_length` timesteps alive at a time.
+    ///
+    /// The states at segment boundaries are computed without gradients in a first pass. Segments are then rerun
+    /// with gradients from last to first, backpropagating each segment's share of the loss together with the
+    /// gradient flowing into its final state from the segment after it. Recurrent and variational dropout masks are
+    /// sampled once and shared by both passes and every segment, so the accumulated gradients match those of
+    /// backpropagating `loss_with` with the same masks, up to the noise of ordinary dropout and the temporal activation
+    /// regularization of consecutive hidden states straddling a segment boundary. An optimizer step can be taken
+    /// afterwards as usual.
+    ///
+    /// Returns the loss and the final state. Panics if the segment length is zero or the network is bidirectional,
+    /// since a bidirectional network's backward direction cannot be split into segments.
+    pub fn checkpointed_backward<L: LossFn + ?Sized>(
+        &self,
+        loss_fn: &L,
+        xs: &Tensor,
+        ys: &Tensor,
+        mask: Option<&Tensor>,
+        state: &LSTMState,
+        segment_length: usize,
+    ) -> (f64, LSTMState) {
+        assert!(segment_length > 0, "Segments must have a positive length!");
+        assert!(
+            !self.desc.bidirectional,
+            "Cannot checkpoint a bidirectional network!"
+        );
+        let sequence_length = xs.size()[1];
+        let segment_length = segment_length as i64;
+        let segments: Vec<(i64, i64)> = (0..sequence_length)
+            .step_by(segment_length as usize)
+            .map(|start| (start, segment_length.min(sequence_length - start)))
+            .collect();
+
+        // Step 1: compute the states at segment boundaries, without gradients, under the masks every segment shares
+        let masks = self.dropout_masks(state);
+        let mut states = vec![detach_state(state)];
+        tch::no_grad(|| {
+            for &(start, len) in segments.iter() {
+                let segment = xs.narrow(1, start, len);
+                let previous = &states[states.len() - 1];
+                let (_, state) = match &masks.recurrent {
+                    Some(mask) => self.recurrent_dropout_sequence(&segment, previous, mask),
+                    None => self.hidden_sequence(&segment, previous),
+                };
+                states.push(detach_state(&state));
+            }
+        });
+
+        // Step 2: weight each segment's loss by its share of the loss entries, so the segment losses sum to the loss
+        let mask = mask.map(|mask| mask.to_kind(Kind::Float).expand_as(ys));
+        let total = match &mask {
+            Some(mask) => f64::from(mask.sum(Kind::Float)).max(1.0),
+            None => sequence_length as f64,
+        };
+
+        // Step 3: rerun segments from last to first, backpropagating their losses and the gradients of their states
+        let mut loss = 0.0;
+        let mut state_grads: Option<(Tensor, Tensor)> = None;
+        for (ix, &(start, len)) in segments.iter().enumerate().rev() {
+            let initial = &states[ix];
+            let (h, c) = if ix == 0 {
+                (initial.h(), initial.c())
+            } else {
+                (
+                    initial.h().set_requires_grad(true),
+                    initial.c().set_requires_grad(true),
+                )
+            };
+            let segment_state = LSTMState((h.shallow_clone(), c.shallow_clone()));
+            let segment_mask = mask.as_ref().map(|mask| mask.narrow(1, start, len));
+            let (yhat, penalty, final_state) =
+                self.forward_masked(&xs.narrow(1, start, len), &segment_state, true, &masks);
+            let share = match &segment_mask {
+                Some(mask) => f64::from(mask.sum(Kind::Float)) / total,
+                None => len as f64 / total,
+            };
+            let segment_loss =
+                loss_fn.loss(&yhat, &ys.narrow(1, start, len), segment_mask.as_ref());
+            let segment_loss = match penalty {
+                Some(penalty) => (segment_loss + penalty) * share,
+                None => segment_loss * share,
+            };
+            loss += segment_loss.double_value(&[]);
+            let objective = match &state_grads {
+                Some((grad_h, grad_c)) => {
+                    segment_loss
+                        + (final_state.h() * grad_h).sum(Kind::Float)
+                        + (final_state.c() * grad_c).sum(Kind::Float)
+                }
+                None => segment_loss,
+            };
+            objective.backward();
+            if ix > 0 {
+                state_grads = Some((h.grad().detach(), c.grad().detach()));
+            }
+        }
+        let final_state = states.pop().expect("There is always an initial state");
+        (loss, final_state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::Target;
+    use crate::lstm::{loss::Mse, StockLSTMDesc};
+    use tch::nn::{VarStore, RNN};
+    use tch::Device;
+
+    #[test]
+    fn checkpointed_gradients_match() {
+        checkpointed_gradients_match_with(0.0, 0.0);
+    }
+
+    #[test]
+    fn checkpointed_gradients_match_with_dropout() {
+        checkpointed_gradients_match_with(0.3, 0.5);
+    }
+
+    /// Check that checkpointed gradients match with given variational and recurrent dropout probabilities, sampling
+    /// the same masks in both runs
+    fn checkpointed_gradients_match_with(dropout: f64, recurrent_dropout: f64) {
+        tch::manual_seed(5);
+        let vs = VarStore::new(Device::Cpu);
+        let lstm = StockLSTMDesc {
+            additional_inputs: 2,
+            stocks: 1,
+            hidden: 3,
+            layers: 2,
+            heads: vec![Target::Close],
+            dropout,
+            variational_dropout: true,
+            recurrent_dropout,
+            ..Default::default()
+        }
+        .build(&vs);
+        let xs = Tensor::randn(&[2, 7, lstm.no_inputs() as i64], tch::kind::FLOAT_CPU);
+        let ys = Tensor::randn(&[2, 7, lstm.no_outputs() as i64], tch::kind::FLOAT_CPU);
+        let state = lstm.zero_state(2);
+        let gradients = |vs: &VarStore| -> Vec<Tensor> {
+            let mut variables: Vec<_> = vs.variables().into_iter().collect();
+            variables.sort_by(|left, right| left.0.cmp(&right.0));
+            variables
+                .into_iter()
+                .map(|(_, var)| {
+                    let grad = var.grad().copy();
+                    var.grad().zero_();
+                    grad
+                })
+                .collect()
+        };
+
+        tch::manual_seed(9);
+        let (loss, _) = lstm.loss_with(&Mse, &xs, &ys, None, &state);
+        loss.backward();
+        let expected = gradients(&vs);
+        tch::manual_seed(9);
+        let (checkpointed_loss, _) = lstm.checkpointed_backward(&Mse, &xs, &ys, None, &state, 3);
+        let actual = gradients(&vs);
+
+        assert!((loss.double_value(&[]) - checkpointed_loss).abs() < 1e-5);
+        for (expected, actual) in expected.iter().zip(actual.iter()) {
+            assert!(expected.allclose(actual, 1e-4, 1e-5, false));
+        }
+    }
+}
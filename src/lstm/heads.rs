@@ -0,0 +1,81 @@
+/*!
+Named output heads, each predicting a single target for every stock
+*/
+use super::loss::LossFn;
+use crate::data::Target;
+use tch::nn::{self, Linear, Module, Path};
+use tch::{Kind, Tensor};
+
+/// An output head of a `StockLSTM`, mapping the hidden state to one target per stock
+#[derive(Debug)]
+pub struct Head {
+    /// The target this head predicts
+    pub target: Target,
+    /// This head's linear layer
+    pub linear: Linear,
+    /// The weight of this head's loss in the total loss
+    pub weight: f32,
+}
+
+impl Head {
+    /// Build a new head with unit weight predicting a target for a given number of stocks
+    pub fn new(path: &Path, target: Target, hidden: usize, stocks: usize) -> Head {
+        let linear = nn::linear(
+            &(path / target.name()),
+            hidden as i64,
+            stocks as i64,
+            Default::default(),
+        );
+        Head {
+            target,
+            linear,
+            weight: 1.0,
+        }
+    }
+    /// Get the name of this head
+    pub fn name(&self) -> &'static str {
+        self.target.name()
+    }
+    /// Compute this head's output given a hidden state
+    pub fn forward(&self, hidden: &Tensor) -> Tensor {
+        self.linear.forward(hidden)
+    }
+    /// Compute this head's output given separate features for each stock, of shape `[..., stocks, hidden]`, such as
+    /// those of `SectorBlocks::stock_features`, each stock's output only seeing its own features
+    pub fn forward_per_stock(&self, features: &Tensor) -> Tensor {
+        (features * &self.linear.ws).sum1(&[-1], false, Kind::Float) + &self.linear.bs
+    }
+}
+
+/// Select the columns belonging to the `ix`th head from a tensor of outputs of heads over `stocks` stocks
+///
+/// Tensors whose last dimension is one, such as row masks, are returned as is.
+pub fn head_columns(outputs: &Tensor, ix: usize, stocks: usize) -> Tensor {
+    let last = outputs.dim() as i64 - 1;
+    if outputs.size().last() == Some(&1) {
+        outputs.shallow_clone()
+    } else {
+        outputs.narrow(last, (ix * stocks) as i64, stocks as i64)
+    }
+}
+
+/// Compute the loss of each of a list of heads over `stocks` stocks
+pub fn head_losses<L: LossFn + ?Sized>(
+    heads: &[Head],
+    stocks: usize,
+    loss_fn: &L,
+    yhat: &Tensor,
+    ys: &Tensor,
+    mask: Option<&Tensor>,
+) -> Vec<Tensor> {
+    (0..heads.len())
+        .map(|ix| {
+            let mask = mask.map(|mask| head_columns(mask, ix, stocks));
+            loss_fn.loss(
+                &head_columns(yhat, ix, stocks),
+                &head_columns(ys, ix, stocks),
+                mask.as_ref(),
+            )
+        })
+        .collect()
+}
@@ -0,0 +1,128 @@
+/*!
+Weight initialization strategies for a `StockLSTM`, replacing tch's defaults, which converge slowly early in training
+*/
+use super::StockLSTM;
+use serde::{Deserialize, Serialize};
+use tch::nn::VarStore;
+use tch::{Kind, Tensor};
+
+/// The initialization of a `StockLSTM`'s weights. Every option is disabled by default, leaving tch's initialization.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Default, Serialize, Deserialize)]
+pub struct Initialization {
+    /// Initialize the recurrent (hidden to hidden) weights of each LSTM gate to a random orthogonal matrix
+    #[serde(default)]
+    pub orthogonal_recurrent: bool,
+    /// Initialize the input to hidden weights of each LSTM and the weights of each head by Xavier (Glorot) uniform
+    /// initialization
+    #[serde(default)]
+    pub xavier: bool,
+    /// Initialize the biases of each LSTM to zero, except for the forget gate, whose total bias is set to one so that
+    /// the LSTM remembers by default
+    #[serde(default)]
+    pub unit_forget_bias: bool,
+}
+
+impl Initialization {
+    /// The recommended initialization, enabling every option
+    pub fn recommended() -> Initialization {
+        Initialization {
+            orthogonal_recurrent: true,
+            xavier: true,
+            unit_forget_bias: true,
+        }
+    }
+    /// Whether this initialization leaves every weight as initialized by tch
+    pub fn is_default(&self) -> bool {
+        *self == Initialization::default()
+    }
+    /// Initialize the variables of a freshly built network in place
+    pub(crate) fn apply(&self, vs: &VarStore, lstm: &StockLSTM) {
+        if self.is_default() {
+            return;
+        }
+        let hidden = lstm.desc.hidden as i64;
+        tch::no_grad(|| {
+            for (name, mut var) in vs.variables() {
+                let param = name.rsplit('/').next().unwrap_or(&name);
+                if self.orthogonal_recurrent && param.starts_with("weight_hh") {
+                    let gates: Vec<Tensor> = (0..4).map(|_| orthogonal(hidden, hidden)).collect();
+                    var.copy_(&Tensor::cat(&gates, 0));
+                } else if self.xavier && param.starts_with("weight_ih") {
+                    var.copy_(&xavier_uniform(&var));
+                } else if self.unit_forget_bias && param.starts_with("bias_") {
+                    // The gates are ordered input, forget, cell, output, and the input to hidden and hidden to
+                    // hidden biases are summed, so only the former gets the forget gate's unit bias
+                    let _ = var.zero_();
+                    if param.starts_with("bias_ih") {
+                        let _ = var.narrow(0, hidden, hidden).fill_(1.0);
+                    }
+                }
+            }
+            if self.xavier {
+                for head in lstm.heads.iter() {
+                    let mut ws = head.linear.ws.shallow_clone();
+                    ws.copy_(&xavier_uniform(&ws));
+                }
+            }
+        })
+    }
+}
+
+/// Sample a random matrix with orthonormal columns (or rows, if it is wide), as the Q factor of a Gaussian matrix
+fn orthogonal(rows: i64, columns: i64) -> Tensor {
+    let gaussian = Tensor::randn(
+        &[rows.max(columns), rows.min(columns)],
+        tch::kind::FLOAT_CPU,
+    );
+    let (q, r) = gaussian.qr(true);
+    // Correct the signs of the columns so that the distribution is uniform over orthogonal matrices
+    let q = q * r.diag(0).sign();
+    if rows < columns {
+        q.tr()
+    } else {
+        q
+    }
+}
+
+/// Sample a matrix shaped like a weight tensor of shape `[fan_out, fan_in]` from the Xavier uniform distribution
+fn xavier_uniform(like: &Tensor) -> Tensor {
+    let (fan_out, fan_in) = like.size2().expect("Weights are matrices");
+    let bound = (6.0 / (fan_in + fan_out) as f64).sqrt();
+    Tensor::zeros(&[fan_out, fan_in], (Kind::Float, like.device())).uniform_(-bound, bound)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::Target;
+    use crate::lstm::StockLSTMDesc;
+    use tch::Device;
+
+    #[test]
+    fn recommended_initialization() {
+        let vs = VarStore::new(Device::Cpu);
+        let desc = StockLSTMDesc {
+            stocks: 2,
+            hidden: 4,
+            layers: 2,
+            heads: vec![Target::Close],
+            init: Initialization::recommended(),
+            ..Default::default()
+        };
+        desc.build(&vs);
+        let variables = vs.variables();
+        let recurrent = &variables["weight_hh_l1"];
+        let gate = recurrent.narrow(0, 4, 4);
+        let identity = Tensor::eye(4, tch::kind::FLOAT_CPU);
+        assert!(gate
+            .matmul(&gate.tr())
+            .allclose(&identity, 1e-4, 1e-5, false));
+        let biases = &variables["bias_ih_l0"] + &variables["bias_hh_l0"];
+        let mut expected = vec![0.0f32; 16];
+        expected[4..8].iter_mut().for_each(|bias| *bias = 1.0);
+        assert_eq!(Vec::<f32>::from(&biases), expected);
+        let bound = (6.0f64 / (16 + 14) as f64).sqrt();
+        let input = &variables["weight_ih_l0"];
+        assert!(f64::from(input.abs().max()) <= bound);
+    }
+}
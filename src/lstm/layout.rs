@@ -0,0 +1,141 @@
+/*!
+Descriptions of the columns of a `StockLSTM`'s input and output tensors, mapping them back to meaningful names
+*/
+use super::StockLSTMDesc;
+use crate::data::Tick;
+use std::ops::Range;
+
+/// A named, contiguous range of columns of a tensor, e.g. the fields of one stock's ticks
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct NamedRange {
+    /// The name of this range, e.g. `clocks` or `stock0`
+    pub name: String,
+    /// The columns in this range
+    pub range: Range<usize>,
+    /// The name of each column in this range, e.g. `stock0.c`
+    pub columns: Vec<String>,
+}
+
+impl NamedRange {
+    /// Create a range of columns starting at a given offset, with the given column names
+    pub fn new(name: impl Into<String>, start: usize, columns: Vec<String>) -> NamedRange {
+        NamedRange {
+            name: name.into(),
+            range: start..start + columns.len(),
+            columns,
+        }
+    }
+}
+
+/// The layout of the last dimension of a tensor, as a sequence of named ranges covering every column in order
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Default)]
+pub struct Layout {
+    /// The ranges making up this layout, in column order
+    pub ranges: Vec<NamedRange>,
+}
+
+impl Layout {
+    /// Append a range with the given column names to the end of this layout
+    pub fn push(&mut self, name: impl Into<String>, columns: Vec<String>) {
+        let range = NamedRange::new(name, self.width(), columns);
+        self.ranges.push(range)
+    }
+    /// Get the number of columns in this layout
+    pub fn width(&self) -> usize {
+        self.ranges.last().map(|range| range.range.end).unwrap_or(0)
+    }
+    /// Get the range with a given name, if any
+    pub fn range(&self, name: &str) -> Option<&NamedRange> {
+        self.ranges.iter().find(|range| range.name == name)
+    }
+    /// Get the name of a column, if it is in bounds
+    pub fn column_name(&self, column: usize) -> Option<&str> {
+        let range = self
+            .ranges
+            .iter()
+            .find(|range| range.range.contains(&column))?;
+        Some(&range.columns[column - range.range.start])
+    }
+    /// Get the index of the column with a given name, if any
+    pub fn column(&self, name: &str) -> Option<usize> {
+        self.ranges.iter().find_map(|range| {
+            let ix = range.columns.iter().position(|column| column == name)?;
+            Some(range.range.start + ix)
+        })
+    }
+    /// Iterate over the names of every column, in order
+    pub fn column_names(&self) -> impl Iterator<Item = &str> {
+        self.ranges
+            .iter()
+            .flat_map(|range| range.columns.iter().map(|column| column.as_str()))
+    }
+}
+
+/// Get the name of the `ix`th stock in a layout
+fn stock_name(ix: usize) -> String {
+    format!("stock{}", ix)
+}
+
+impl StockLSTMDesc {
+    /// Get the layout of the input features of the described network: additional inputs, then clocks, then the tick
+    /// fields of each stock
+    pub fn input_layout(&self) -> Layout {
+        let mut layout = Layout::default();
+        let indexed = |prefix: &str, n: usize| -> Vec<String> {
+            (0..n).map(|ix| format!("{}[{}]", prefix, ix)).collect()
+        };
+        layout.push("additional", indexed("additional", self.additional_inputs));
+        layout.push("clocks", indexed("clocks", self.date_inputs));
+        for stock in 0..self.stocks {
+            let name = stock_name(stock);
+            let columns = Tick::NN_FIELD_NAMES
+                .iter()
+                .map(|field| format!("{}.{}", name, field))
+                .collect();
+            layout.push(name, columns);
+        }
+        layout
+    }
+    /// Get the layout of the outputs of the described network: one range per head, named after its target, with one
+    /// column per stock
+    pub fn output_layout(&self) -> Layout {
+        let mut layout = Layout::default();
+        for target in self.heads.iter() {
+            let columns = (0..self.stocks)
+                .map(|stock| format!("{}.{}", target.name(), stock_name(stock)))
+                .collect();
+            layout.push(target.name(), columns);
+        }
+        layout
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::Target;
+
+    #[test]
+    fn layouts_cover_every_column() {
+        let desc = StockLSTMDesc {
+            additional_inputs: 2,
+            date_inputs: 4,
+            stocks: 2,
+            hidden: 8,
+            layers: 1,
+            heads: vec![Target::Close, Target::Volume],
+            ..Default::default()
+        };
+        let input = desc.input_layout();
+        assert_eq!(input.width(), desc.no_inputs());
+        assert_eq!(input.column_names().count(), desc.no_inputs());
+        assert_eq!(input.range("clocks").unwrap().range, 2..6);
+        assert_eq!(input.column_name(1), Some("additional[1]"));
+        assert_eq!(input.column("stock1.c"), Some(6 + Tick::NN_FIELDS + 3));
+        assert_eq!(input.column_name(desc.no_inputs()), None);
+        let output = desc.output_layout();
+        assert_eq!(output.width(), 4);
+        assert_eq!(output.column_name(2), Some("volume.stock0"));
+        assert_eq!(output.range("close").unwrap().range, 0..2);
+    }
+}
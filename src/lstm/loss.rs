@@ -0,0 +1,227 @@
+/*!
+Loss functions for training a `StockLSTM`
+*/
+use tch::{Kind, Tensor};
+
+/// A loss function comparing a tensor of predictions to a tensor of targets
+pub trait LossFn {
+    /// Compute the loss of the predictions `yhat` against the targets `ys`.
+    ///
+    /// If a mask is given, only entries where the mask is nonzero contribute to the loss. The mask must be
+    /// broadcastable to the shape of the predictions. A mask of nonnegative weights gives a weighted mean.
+    fn loss(&self, yhat: &Tensor, ys: &Tensor, mask: Option<&Tensor>) -> Tensor;
+}
+
+impl<F> LossFn for F
+where
+    F: Fn(&Tensor, &Tensor, Option<&Tensor>) -> Tensor,
+{
+    #[inline]
+    fn loss(&self, yhat: &Tensor, ys: &Tensor, mask: Option<&Tensor>) -> Tensor {
+        self(yhat, ys, mask)
+    }
+}
+
+/// Take the mean of an elementwise loss, optionally only over the entries where a mask is nonzero, each weighted by
+/// the mask
+pub fn masked_mean(loss: &Tensor, mask: Option<&Tensor>) -> Tensor {
+    if let Some(mask) = mask {
+        let mask = mask.to_kind(Kind::Float).expand_as(loss);
+        (loss * &mask).sum(Kind::Float) / mask.sum(Kind::Float).clamp_min(1.0)
+    } else {
+        loss.mean(Kind::Float)
+    }
+}
+
+/// The mean squared error
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Default)]
+pub struct Mse;
+
+impl LossFn for Mse {
+    fn loss(&self, yhat: &Tensor, ys: &Tensor, mask: Option<&Tensor>) -> Tensor {
+        let diff = yhat - ys;
+        masked_mean(&(&diff * &diff), mask)
+    }
+}
+
+/// The mean squared error, with each output weighted along the last dimension
+#[derive(Debug)]
+pub struct WeightedMse {
+    /// The weight of each output
+    pub weights: Tensor,
+}
+
+impl LossFn for WeightedMse {
+    fn loss(&self, yhat: &Tensor, ys: &Tensor, mask: Option<&Tensor>) -> Tensor {
+        let weights = self.weights.to_device(yhat.device());
+        let diff = yhat - ys;
+        masked_mean(&(&diff * &diff * weights), mask)
+    }
+}
+
+/// The mean squared error, penalizing over- and under-predictions differently
+///
+/// For example, setting `under` above `over` for a return target penalizes under-predicting drops
+/// less than over-predicting them, and vice versa.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct AsymmetricMse {
+    /// The weight of squared errors where the prediction is above the target
+    pub over: f64,
+    /// The weight of squared errors where the prediction is at or below the target
+    pub under: f64,
+}
+
+impl LossFn for AsymmetricMse {
+    fn loss(&self, yhat: &Tensor, ys: &Tensor, mask: Option<&Tensor>) -> Tensor {
+        let diff = yhat - ys;
+        let over = diff.gt(0.0).to_kind(Kind::Float) * self.over;
+        let under = diff.le(0.0).to_kind(Kind::Float) * self.under;
+        masked_mean(&(&diff * &diff * (over + under)), mask)
+    }
+}
+
+/// A loss function weighting each window of a batch, e.g. by the weights computed by a
+/// `train::weighting::SampleWeighting`.
+///
+/// The underlying loss is averaged over windows weighted by `weights`, then scaled by the mean weight, so that
+/// batches of heavier windows count for more across batches as well as within them.
+#[derive(Debug)]
+pub struct SampleWeighted<'a, L: ?Sized> {
+    /// The underlying loss function
+    pub loss_fn: &'a L,
+    /// The weight of each window, broadcastable to the shape of the predictions, e.g. of shape `[batch, 1, 1]`
+    pub weights: Tensor,
+}
+
+impl<'a, L: LossFn + ?Sized> LossFn for SampleWeighted<'a, L> {
+    fn loss(&self, yhat: &Tensor, ys: &Tensor, mask: Option<&Tensor>) -> Tensor {
+        let weights = self.weights.to_device(yhat.device()).to_kind(Kind::Float);
+        let mask = match mask {
+            Some(mask) => &weights * mask.to_kind(Kind::Float),
+            None => weights.shallow_clone(),
+        };
+        self.loss_fn.loss(yhat, ys, Some(&mask)) * weights.mean(Kind::Float)
+    }
+}
+
+/// Reshape outputs laid out as in `StockLSTM::targets`, i.e. head by head with one column per stock, and a mask
+/// broadcastable to them, into groups of `stocks` columns, one per head and row. Masked out entries have a weight of
+/// zero.
+fn ranking_groups(
+    yhat: &Tensor,
+    ys: &Tensor,
+    mask: Option<&Tensor>,
+    stocks: usize,
+) -> (Tensor, Tensor, Tensor) {
+    let shape = [-1, stocks as i64];
+    let weights = match mask {
+        Some(mask) => mask.to_kind(Kind::Float).expand_as(yhat),
+        None => yhat.ones_like(),
+    };
+    (
+        yhat.reshape(&shape),
+        ys.reshape(&shape),
+        weights.contiguous().reshape(&shape),
+    )
+}
+
+/// A listwise ranking loss for cross-sectional targets, e.g. `TargetKind::Rank`: the cross entropy between the
+/// softmax of the targets and that of the predictions across the stocks of each head and row (ListNet). Only the
+/// order of the predictions within each row matters, up to a shift.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ListwiseRanking {
+    /// The number of stocks ranked against each other
+    pub stocks: usize,
+    /// The temperature of the softmax of the targets, where lower temperatures concentrate on the top stocks
+    pub temperature: f64,
+}
+
+impl LossFn for ListwiseRanking {
+    fn loss(&self, yhat: &Tensor, ys: &Tensor, mask: Option<&Tensor>) -> Tensor {
+        let (yhat, ys, weights) = ranking_groups(yhat, ys, mask, self.stocks);
+        // Masked out stocks are pushed out of both softmaxes
+        let excluded = (weights.ones_like() - weights.gt(0.0).to_kind(Kind::Float)) * -1e9;
+        let target = (ys / self.temperature + &excluded).softmax(-1, Kind::Float);
+        let predicted = (yhat + &excluded).log_softmax(-1, Kind::Float);
+        let loss = -(target * predicted * &weights).sum1(&[-1], false, Kind::Float);
+        // Only rows ranking at least two stocks carry any information
+        let ranked = weights.gt(0.0).sum1(&[-1], false, Kind::Float).ge(2.0);
+        masked_mean(&loss, Some(&ranked))
+    }
+}
+
+/// A pairwise ranking loss for cross-sectional targets: the logistic loss of ordering each pair of stocks of a head
+/// and row the way their targets are ordered (RankNet), with pairs weighted by the product of their weights
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct PairwiseRanking {
+    /// The number of stocks ranked against each other
+    pub stocks: usize,
+}
+
+impl LossFn for PairwiseRanking {
+    fn loss(&self, yhat: &Tensor, ys: &Tensor, mask: Option<&Tensor>) -> Tensor {
+        let (yhat, ys, weights) = ranking_groups(yhat, ys, mask, self.stocks);
+        let margin = yhat.unsqueeze(2) - yhat.unsqueeze(1);
+        let order = (ys.unsqueeze(2) - ys.unsqueeze(1)).sign();
+        // Ties carry no ordering, so have no weight
+        let pairs = weights.unsqueeze(2) * weights.unsqueeze(1) * order.abs();
+        // A numerically stable softplus of the negated, ordered margin
+        let x = -(order * margin);
+        let loss = x.relu() + (-x.abs()).exp().log1p();
+        masked_mean(&loss, Some(&pairs))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn asymmetric_mse_weights_sides() {
+        let yhat = Tensor::of_slice(&[1.0f32, -1.0]);
+        let ys = Tensor::of_slice(&[0.0f32, 0.0]);
+        let loss_fn = AsymmetricMse {
+            over: 2.0,
+            under: 0.5,
+        };
+        let loss = f64::from(loss_fn.loss(&yhat, &ys, None));
+        assert!((loss - 1.25).abs() < 1e-6);
+        let mask = Tensor::of_slice(&[1.0f32, 0.0]);
+        let loss = f64::from(loss_fn.loss(&yhat, &ys, Some(&mask)));
+        assert!((loss - 2.0).abs() < 1e-6);
+        assert_eq!(f64::from(Mse.loss(&yhat, &ys, None)), 1.0);
+
+        // Weights of 3 and 1 give a weighted mean of 1.625, scaled by the mean weight of 2
+        let weighted = SampleWeighted {
+            loss_fn: &loss_fn,
+            weights: Tensor::of_slice(&[3.0f32, 1.0]),
+        };
+        let loss = f64::from(weighted.loss(&yhat, &ys, None));
+        assert!((loss - 3.25).abs() < 1e-6);
+    }
+
+    #[test]
+    fn ranking_losses_prefer_correct_order() {
+        // A row of three stocks, with the targets' order predicted correctly, then reversed
+        let ys = Tensor::of_slice(&[-1.0f32, 0.0, 1.0]);
+        let right = Tensor::of_slice(&[-0.5f32, 0.0, 0.5]);
+        let wrong = Tensor::of_slice(&[0.5f32, 0.0, -0.5]);
+        let listwise = ListwiseRanking {
+            stocks: 3,
+            temperature: 1.0,
+        };
+        let pairwise = PairwiseRanking { stocks: 3 };
+        for loss_fn in [&listwise as &dyn LossFn, &pairwise].iter() {
+            let right = f64::from(loss_fn.loss(&right, &ys, None));
+            let wrong = f64::from(loss_fn.loss(&wrong, &ys, None));
+            assert!(right < wrong);
+        }
+        // Adding a constant to every prediction of a row does not change its ranking loss
+        let shifted = f64::from(listwise.loss(&(&right + 3.0), &ys, None));
+        assert!((shifted - f64::from(listwise.loss(&right, &ys, None))).abs() < 1e-5);
+        // A masked out stock is ignored, leaving one pair, ordered incorrectly
+        let mask = Tensor::of_slice(&[1.0f32, 0.0, 1.0]);
+        let masked = f64::from(pairwise.loss(&wrong, &ys, Some(&mask)));
+        assert!((masked - (1.0f64 + 1.0f64.exp()).ln()).abs() < 1e-5);
+    }
+}
@@ -2,12 +2,35 @@
 The LSTM implementation: a rather direct translation of https://gitlab.com/tekne/stock-lstm
 */
 
-use crate::data::{Prediction, Tick};
-use chrono::{DateTime, NaiveDateTime, Utc};
+use crate::data::{
+    dataset::{Dataset, Window},
+    PredictedTick, Symbol, Target, TargetKind, TargetSpec, Tick,
+};
+use anyhow::format_err;
+use chrono::{DateTime, Utc};
 use num::NumCast;
-use std::iter::Peekable;
-use tch::nn::{self, LSTMState, Linear, Module, RNNConfig, VarStore, LSTM, RNN};
-use tch::{Reduction, Tensor};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use tch::nn::{LSTMState, VarStore, RNN};
+use tch::{Device, Tensor};
+
+pub mod batch;
+pub mod checkpointing;
+pub mod heads;
+pub mod init;
+pub mod layout;
+pub mod loss;
+pub mod regularization;
+pub mod sector;
+pub mod stack;
+use batch::{Batch, BatchBuffer, BatchShape, BatchStats, Batches, TailPolicy, TickSource};
+use heads::{head_columns, head_losses, Head};
+use init::Initialization;
+use layout::Layout;
+use loss::{LossFn, Mse, WeightedMse};
+use regularization::{ActivationRegularization, DropoutMasks};
+use sector::{SectorBlocks, SectorDesc};
+use stack::LSTMStack;
 
 /// The StockLSTM model from https://gitlab.com/tekne/stock-lstm
 #[derive(Debug)]
@@ -18,10 +41,27 @@ pub struct StockLSTM {
     pub date_inputs: usize,
     /// The number of stocks to predict
     pub stocks: usize,
-    /// This model's LSTM layer
-    pub lstm_layer: LSTM,
-    /// This model's linear layer
-    pub linear_layer: Linear,
+    /// This model's recurrent layers
+    pub lstm_layer: LSTMStack,
+    /// Whether the input features are concatenated to the LSTM output before being fed to the heads
+    pub input_skip: bool,
+    /// The sector-level hidden blocks between the LSTM output and the heads, if stocks are grouped by sector
+    pub sectors: Option<SectorBlocks>,
+    /// This model's output heads. Outputs are laid out head by head, with one column per stock in each head.
+    pub heads: Vec<Head>,
+    /// The dropout probability applied to the LSTM output during training, initially the descriptor's
+    pub dropout: f64,
+    /// Whether dropout on the LSTM output uses the same mask at every timestep of a sequence (variational dropout),
+    /// initially the descriptor's
+    pub variational_dropout: bool,
+    /// The dropout probability applied to the hidden state fed back into the LSTMs during training, with the same
+    /// mask at every timestep, initially the descriptor's. If nonzero, training runs the LSTMs one timestep at a time,
+    /// which is much slower.
+    pub recurrent_dropout: f64,
+    /// The penalties on the LSTM output added to the training loss
+    pub activation_regularization: ActivationRegularization,
+    /// The descriptor this model was built from
+    pub desc: StockLSTMDesc,
 }
 
 impl StockLSTM {
@@ -29,28 +69,290 @@ impl StockLSTM {
     pub fn no_inputs(&self) -> usize {
         self.additional_inputs + self.date_inputs + self.stocks * Tick::NN_FIELDS
     }
+    /// Compute the number of outputs of this network
+    pub fn no_outputs(&self) -> usize {
+        self.heads.len() * self.stocks
+    }
+    /// Get the layout of this network's input features, naming each column. See `StockLSTMDesc::input_layout`.
+    pub fn input_layout(&self) -> Layout {
+        self.desc.input_layout()
+    }
+    /// Get the layout of this network's outputs, naming each column. See `StockLSTMDesc::output_layout`.
+    pub fn output_layout(&self) -> Layout {
+        self.desc.output_layout()
+    }
+    /// Get the targets predicted by this network's heads, in output order
+    pub fn targets(&self) -> Vec<Target> {
+        self.heads.iter().map(|head| head.target).collect()
+    }
+    /// Get the specification of the targets predicted by this network, in output order
+    pub fn target_spec(&self) -> TargetSpec {
+        TargetSpec::new(self.targets()).with_kind(self.desc.target_kind)
+    }
+    /// Decode a row of this network's outputs into a prediction for each stock. Returns `None` if the row has the
+    /// wrong width.
+    pub fn predicted_ticks(&self, outputs: &[f32]) -> Option<Vec<PredictedTick>> {
+        if outputs.len() != self.no_outputs() {
+            return None;
+        }
+        self.target_spec().decode(outputs)
+    }
+    /// Get a mutable reference to the head predicting a given target, if any
+    pub fn head_mut(&mut self, target: Target) -> Option<&mut Head> {
+        self.heads.iter_mut().find(|head| head.target == target)
+    }
+    /// Select the columns of a tensor of outputs belonging to the head predicting a given target, if any
+    pub fn head_output(&self, outputs: &Tensor, target: Target) -> Option<Tensor> {
+        let ix = self.heads.iter().position(|head| head.target == target)?;
+        Some(head_columns(outputs, ix, self.stocks))
+    }
+    /// Get the loss weight of each output of this network, as a tensor
+    pub fn loss_weight_tensor(&self) -> Tensor {
+        let mut weights = Vec::with_capacity(self.no_outputs());
+        for head in self.heads.iter() {
+            weights.extend(std::iter::repeat(head.weight).take(self.stocks));
+        }
+        Tensor::of_slice(&weights)
+    }
+    /// Copy this network, whose variables live in a given `VarStore`, to a device, returning the copy together with
+    /// a new `VarStore` on that device holding its variables. Head weights, dropout and regularization are preserved.
+    pub fn to_device(
+        &self,
+        vs: &VarStore,
+        device: Device,
+    ) -> anyhow::Result<(VarStore, StockLSTM)> {
+        let mut new_vs = VarStore::new(device);
+        let mut lstm = self.desc.build(&new_vs);
+        new_vs
+            .copy(vs)
+            .map_err(|err| format_err!("Error copying variables to {:?}: {:?}", device, err))?;
+        lstm.copy_settings_from(self);
+        Ok((new_vs, lstm))
+    }
+    /// Copy the runtime settings of another network, which are not part of its descriptor: its dropout, its
+    /// regularization and the loss weight of each of its heads, matched by position
+    pub fn copy_settings_from(&mut self, other: &StockLSTM) {
+        self.dropout = other.dropout;
+        self.variational_dropout = other.variational_dropout;
+        self.recurrent_dropout = other.recurrent_dropout;
+        self.activation_regularization = other.activation_regularization;
+        for (head, old) in self.heads.iter_mut().zip(other.heads.iter()) {
+            head.weight = old.weight;
+        }
+    }
+    /// Check that the variables in a `VarStore`, e.g. one loaded from a checkpoint, fit this network, returning an
+    /// error enumerating every missing, unexpected or misshapen variable otherwise
+    pub fn validate_against(&self, vs: &VarStore) -> anyhow::Result<()> {
+        self.validate_variables(&vs.variables())
+    }
+    /// Check that a set of named tensors, e.g. those stored in a checkpoint, fit this network's variables, returning an
+    /// error enumerating every missing, unexpected or misshapen tensor otherwise
+    pub fn validate_variables(&self, variables: &HashMap<String, Tensor>) -> anyhow::Result<()> {
+        let expected = VarStore::new(Device::Cpu);
+        self.desc.build(&expected);
+        let mut mismatches = Vec::new();
+        let mut expected: Vec<_> = expected.variables().into_iter().collect();
+        expected.sort_by(|(left, _), (right, _)| left.cmp(right));
+        for (name, var) in expected.iter() {
+            match variables.get(name) {
+                None => mismatches.push(format!("missing variable {}", name)),
+                Some(found) if found.size() != var.size() => mismatches.push(format!(
+                    "variable {} has shape {:?}, expected {:?}",
+                    name,
+                    found.size(),
+                    var.size()
+                )),
+                Some(_) => {}
+            }
+        }
+        let mut unexpected: Vec<_> = variables
+            .keys()
+            .filter(|name| expected.iter().all(|(expected, _)| expected != *name))
+            .collect();
+        unexpected.sort();
+        for name in unexpected {
+            mismatches.push(format!("unexpected variable {}", name))
+        }
+        if mismatches.is_empty() {
+            return Ok(());
+        }
+        Err(format_err!(
+            "Variables do not fit a network with {} inputs ({} additional, {} date and {} stocks of {} fields), {} \
+            hidden units in {} layers and heads {:?}:\n  {}",
+            self.no_inputs(),
+            self.additional_inputs,
+            self.date_inputs,
+            self.stocks,
+            Tick::NN_FIELDS,
+            self.desc.hidden,
+            self.desc.layers,
+            self.targets(),
+            mismatches.join("\n  ")
+        ))
+    }
     /// Compute the loss on a set of inputs and outputs, modifying LSTM state in the process
+    ///
+    /// The loss is the mean squared error, with each head's outputs weighted by the head's weight
     pub fn loss(&self, xs: &Tensor, ys: &Tensor, state: &LSTMState) -> (Tensor, LSTMState) {
-        let (yhat, state) = self.seq_init(xs, state);
-        let loss = yhat.mse_loss(ys, Reduction::Mean);
-        (loss, state)
+        let weights = WeightedMse {
+            weights: self.loss_weight_tensor(),
+        };
+        self.loss_with(&weights, xs, ys, None, state)
+    }
+    /// Compute the loss on a set of inputs and outputs using a given loss function, modifying LSTM state in the process
+    pub fn loss_with<L: LossFn + ?Sized>(
+        &self,
+        loss_fn: &L,
+        xs: &Tensor,
+        ys: &Tensor,
+        mask: Option<&Tensor>,
+        state: &LSTMState,
+    ) -> (Tensor, LSTMState) {
+        let (yhat, penalty, state) = self.forward_regularized(xs, state, true);
+        let loss = loss_fn.loss(&yhat, ys, mask);
+        match penalty {
+            Some(penalty) => (loss + penalty, state),
+            None => (loss, state),
+        }
+    }
+    /// Run this network over a sequence of inputs, enabling dropout if `train` is set
+    pub fn forward_t(&self, input: &Tensor, state: &LSTMState, train: bool) -> (Tensor, LSTMState) {
+        let (output, _penalty, state) = self.forward_regularized(input, state, train);
+        (output, state)
+    }
+    /// Run this network over a sequence of inputs, enabling dropout if `train` is set, and return the activation
+    /// regularization penalty on its hidden states alongside its output if training with regularization enabled
+    pub(crate) fn forward_regularized(
+        &self,
+        input: &Tensor,
+        state: &LSTMState,
+        train: bool,
+    ) -> (Tensor, Option<Tensor>, LSTMState) {
+        let masks = if train {
+            self.dropout_masks(state)
+        } else {
+            DropoutMasks {
+                recurrent: None,
+                variational: None,
+            }
+        };
+        self.forward_masked(input, state, train, &masks)
+    }
+    /// Run this network over a sequence of inputs like `forward_regularized`, with given recurrent and variational
+    /// dropout masks
+    pub(crate) fn forward_masked(
+        &self,
+        input: &Tensor,
+        state: &LSTMState,
+        train: bool,
+        masks: &DropoutMasks,
+    ) -> (Tensor, Option<Tensor>, LSTMState) {
+        debug_assert_eq!(
+            input.size().last().copied(),
+            Some(self.no_inputs() as i64),
+            "Input width does not match the {} additional, {} date and {} stock inputs of the network!",
+            self.additional_inputs,
+            self.date_inputs,
+            self.stocks * Tick::NN_FIELDS
+        );
+        let (hidden, state) = match &masks.recurrent {
+            Some(mask) => self.recurrent_dropout_sequence(input, state, mask),
+            None => self.hidden_sequence(input, state),
+        };
+        let dropped = match &masks.variational {
+            Some(mask) => &hidden * mask,
+            None if !self.variational_dropout && self.dropout > 0.0 => {
+                hidden.dropout(self.dropout, train)
+            }
+            None => hidden.shallow_clone(),
+        };
+        let penalty = if train && self.activation_regularization.is_enabled() {
+            Some(self.activation_regularization.penalty(&hidden, &dropped))
+        } else {
+            None
+        };
+        let output = self.apply_heads(&dropped, input);
+        (output, penalty, state)
+    }
+    /// Run this network's recurrent layers over a sequence of inputs, returning the hidden state at every timestep,
+    /// of shape `[batch, sequence, lstm_outputs]`, before dropout and the output heads are applied
+    pub fn hidden_sequence(&self, xs: &Tensor, state: &LSTMState) -> (Tensor, LSTMState) {
+        self.lstm_layer.seq_init(xs, state)
+    }
+    /// Compute the unweighted mean squared error of each head's predictions against a set of outputs
+    pub fn head_losses(&self, yhat: &Tensor, ys: &Tensor, mask: Option<&Tensor>) -> Vec<Tensor> {
+        head_losses(&self.heads, self.stocks, &Mse, yhat, ys, mask)
+    }
+    /// Apply this network's heads to a hidden state and the corresponding inputs, concatenating their outputs
+    fn apply_heads(&self, hidden: &Tensor, input: &Tensor) -> Tensor {
+        let hidden = if self.input_skip {
+            Tensor::cat(&[hidden, input], -1)
+        } else {
+            hidden.shallow_clone()
+        };
+        let outputs: Vec<Tensor> = match &self.sectors {
+            Some(sectors) => {
+                let features = sectors.stock_features(&hidden);
+                self.heads
+                    .iter()
+                    .map(|head| head.forward_per_stock(&features))
+                    .collect()
+            }
+            None => self
+                .heads
+                .iter()
+                .map(|head| head.forward(&hidden))
+                .collect(),
+        };
+        Tensor::cat(&outputs, -1)
+    }
+    /// Get the layout of this network's batches
+    fn batch_spec(&self) -> BatchSpec {
+        BatchSpec {
+            targets: self.targets(),
+            ..self.desc.batch_spec()
+        }
     }
     /// Package a batch of sequences of ticks and additional data into tensors
-    fn make_batches_impl<'a, A, DF, I, F>(
-        additional_inputs: usize,
-        stocks: usize,
-        date_inputs: usize,
-        mut additional: A,
-        mut time_func: DF,
-        tick_iterators: &mut [Peekable<I>],
+    fn make_batches_impl<'a, A, DF, S, F>(
+        spec: &BatchSpec,
+        additional: A,
+        time_func: DF,
+        tick_iterators: &mut [S],
         batch_size: usize,
         sequence_length: usize,
     ) -> Option<(Tensor, Tensor)>
     where
         A: Iterator<Item = &'a [f32]>,
-        I: Iterator<Item = Tick<F>>,
+        S: TickSource<F>,
         F: Copy + NumCast,
         DF: FnMut(DateTime<Utc>, &mut Vec<f32>),
+    {
+        let rows = batch_size * sequence_length;
+        let dataset =
+            Self::next_batch_dataset(spec.stocks, tick_iterators, rows, spec.target_horizon)?;
+        let window = dataset.window(0, dataset.len().min(rows));
+        Self::window_batch_impl(
+            spec,
+            additional,
+            time_func,
+            window,
+            batch_size,
+            sequence_length,
+        )
+    }
+    /// Take the ticks at the next `rows` distinct times from each iterator, together with the ticks at the
+    /// `lookahead` times after, which are the targets of the last rows, and align them. Returns `None` if the iterators
+    /// are exhausted.
+    fn next_batch_dataset<S, F>(
+        stocks: usize,
+        tick_iterators: &mut [S],
+        rows: usize,
+        lookahead: usize,
+    ) -> Option<Dataset<F>>
+    where
+        S: TickSource<F>,
+        F: Copy,
     {
         // Step 1: verify basic invariants
         assert_eq!(
@@ -58,121 +360,499 @@ impl StockLSTM {
             stocks,
             "Wrong number of input stocks!"
         );
+        assert!(
+            lookahead <= S::LOOKAHEAD,
+            "A target horizon of {} rows needs a tick source which can look further ahead, such as `Lookahead`",
+            lookahead
+        );
 
-        // Step 2: allocate space
-        let rows = batch_size * sequence_length;
-        let input_features =
-            tick_iterators.len() * Tick::NN_FIELDS + additional_inputs + date_inputs;
-        let input_size = rows * input_features;
-        let mut input = Vec::<f32>::with_capacity(input_size);
-        let output_features = tick_iterators.len() * Prediction::NN_FIELDS;
-        let output_size = rows * output_features;
-        let mut output = Vec::<f32>::with_capacity(output_size);
+        // Step 2: take the ticks at the next `rows` distinct times from each iterator, with the raw ticks they were
+        // scaled from
+        let mut ticks: Vec<Vec<Tick<F>>> = vec![Vec::new(); stocks];
+        let mut raw: Vec<Vec<Tick<F>>> = vec![Vec::new(); stocks];
+        for _row in 0..rows {
+            let t = match tick_iterators
+                .iter_mut()
+                .filter_map(|ticks| ticks.peek_nth(0).map(|tick| tick.t))
+                .min()
+            {
+                Some(t) => t,
+                None => break,
+            };
+            for ((iterator, ticks), raw) in tick_iterators
+                .iter_mut()
+                .zip(ticks.iter_mut())
+                .zip(raw.iter_mut())
+            {
+                while let Some(tick) = iterator.peek_nth(0) {
+                    if tick.t != t {
+                        break;
+                    }
+                    ticks.push(*tick);
+                    raw.extend(iterator.peek_raw_nth(0).copied());
+                    iterator.next_tick();
+                }
+            }
+        }
 
-        // Step 3: initialize counters
-        let mut curr_t = tick_iterators
-            .iter_mut()
-            .filter_map(|ticks| ticks.peek().map(|tick| tick.t))
-            .min()?;
+        // Step 3: look ahead at the first ticks at the times after, which are the targets of the last rows, without
+        // consuming them. Later ticks at the same time are skipped on every row but the last, so that a source is
+        // never asked to look further ahead than the horizon.
+        let taken = ticks.iter().map(|ticks| ticks.len()).sum::<usize>();
+        let mut offsets = vec![0; stocks];
+        for row in 0..lookahead {
+            let t = match tick_iterators
+                .iter_mut()
+                .zip(offsets.iter())
+                .filter_map(|(ticks, &offset)| ticks.peek_nth(offset).map(|tick| tick.t))
+                .min()
+            {
+                Some(t) => t,
+                None => break,
+            };
+            for (((iterator, ticks), raw), offset) in tick_iterators
+                .iter_mut()
+                .zip(ticks.iter_mut())
+                .zip(raw.iter_mut())
+                .zip(offsets.iter_mut())
+            {
+                match iterator.peek_nth(*offset) {
+                    Some(tick) if tick.t == t => ticks.push(*tick),
+                    _ => continue,
+                }
+                raw.extend(iterator.peek_raw_nth(*offset).copied());
+                *offset += 1;
+                while row + 1 < lookahead {
+                    match iterator.peek_nth(*offset) {
+                        Some(tick) if tick.t == t => *offset += 1,
+                        _ => break,
+                    }
+                }
+            }
+        }
+        if taken == 0 {
+            return None;
+        }
 
-        // Step 4: fill in rows
-        for _row in 0..rows {
-            // Step 4.a: fill in additional rows, zero filling on missing
+        // Step 4: align the ticks
+        trace!("Taking a batch of {} ticks of {} stocks", taken, stocks);
+        let symbols = (0..stocks).map(|stock| Symbol(stock.to_string())).collect();
+        Some(Dataset::from_ticks(symbols, ticks).with_raw(raw))
+    }
+    /// Package a batch of sequences of rows of a dataset window and additional data into newly allocated tensors
+    fn window_batch_impl<'a, A, DF, F>(
+        spec: &BatchSpec,
+        additional: A,
+        time_func: DF,
+        window: Window<F>,
+        batch_size: usize,
+        sequence_length: usize,
+    ) -> Option<(Tensor, Tensor)>
+    where
+        A: Iterator<Item = &'a [f32]>,
+        F: Copy + NumCast,
+        DF: FnMut(DateTime<Utc>, &mut Vec<f32>),
+    {
+        let stocks = window.dataset.stocks();
+        let mut buffer = BatchBuffer::new(
+            batch_size,
+            sequence_length,
+            stocks * Tick::NN_FIELDS + spec.additional_inputs + spec.date_inputs,
+            stocks * spec.targets.len(),
+        );
+        Self::window_batch_into(spec, additional, time_func, window, &mut buffer)?;
+        Some(buffer.into_tensors())
+    }
+    /// Write a batch of sequences of rows of a dataset window and additional data into a preallocated buffer,
+    /// returning `None` if the window is empty
+    fn window_batch_into<'a, A, DF, F>(
+        spec: &BatchSpec,
+        mut additional: A,
+        mut time_func: DF,
+        window: Window<F>,
+        buffer: &mut BatchBuffer,
+    ) -> Option<()>
+    where
+        A: Iterator<Item = &'a [f32]>,
+        F: Copy + NumCast,
+        DF: FnMut(DateTime<Utc>, &mut Vec<f32>),
+    {
+        // Step 1: check for an empty window
+        let shift = spec.target_horizon;
+        assert!(shift > 0, "Target shift must be positive!");
+        let times = window.times();
+        let last_t = *times.last()?;
+        let stocks = window.dataset.stocks();
+
+        // Step 2: fill in rows, zero filling rows past the end of the window
+        let rows = buffer.batch_size() * buffer.sequence_length();
+        buffer.closes.clear();
+        buffer.next_closes.clear();
+        for row in 0..rows {
+            let in_window = row < times.len();
+            let input = &mut buffer.input_row;
+            // Step 2.a: fill in additional rows, zero filling on missing
             if let Some(additional) = additional.next() {
-                let truncate_additional = additional.len().min(additional_inputs);
+                let truncate_additional = additional.len().min(spec.additional_inputs);
                 input.extend_from_slice(&additional[..truncate_additional]);
-                let additional_fill = additional_inputs - truncate_additional;
+                let additional_fill = spec.additional_inputs - truncate_additional;
                 input.extend(std::iter::repeat(0.0).take(additional_fill));
             } else {
-                input.extend(std::iter::repeat(0.0).take(additional_inputs));
+                input.extend(std::iter::repeat(0.0).take(spec.additional_inputs));
             }
-            // Step 4.b: fill in time data
-            time_func(DateTime::from_utc(curr_t, Utc), &mut input);
-            // Step 4.c: fill in input tick data for the current date, zero filling on missing ticks
-            let mut min_t: Option<NaiveDateTime> = None;
-            for ticks in tick_iterators.iter_mut() {
-                if let Some(tick) = ticks.peek() {
-                    // Check the date
-                    if tick.t == curr_t {
-                        // Write the tick, then
-                        tick.push_tick(&mut input);
-                        // Advance the tick iterator
-                        ticks.next();
-                        // Look at the time of the tick after this tick, and if necessary, update the minimum time
-                        if let Some(tick) = ticks.peek() {
-                            if let Some(t) = min_t {
-                                if tick.t < t {
-                                    min_t = Some(tick.t)
-                                }
-                            } else {
-                                min_t = Some(tick.t)
-                            }
-                        }
-                    } else {
-                        // Mismatched time: zero fill without advancing the iterator
-                        input.extend(std::iter::repeat(0.0).take(Tick::NN_FIELDS));
-                    }
-                } else {
-                    // Empty iterator: zero fill
-                    input.extend(std::iter::repeat(0.0).take(Tick::NN_FIELDS));
+            // Step 2.b: fill in time data, repeating the last time past the end of the window
+            let t = times.get(row).copied().unwrap_or(last_t);
+            time_func(DateTime::from_utc(t, Utc), input);
+            // Step 2.c: fill in input tick data for the current row, zero filling on missing ticks, and record the
+            // raw closes of the row and of the row it targets
+            for stock in 0..stocks {
+                match window.tick(row, stock) {
+                    Some(tick) if in_window => tick.push_tick(input),
+                    _ => input.extend(std::iter::repeat(0.0).take(Tick::NN_FIELDS)),
                 }
+                let close = window
+                    .raw_tick(row, stock)
+                    .filter(|_| in_window)
+                    .and_then(|tick| NumCast::from(tick.c));
+                buffer.closes.push(close.unwrap_or(0.0));
+                let next_close = window
+                    .raw_tick(row + shift, stock)
+                    .filter(|_| in_window)
+                    .and_then(|tick| NumCast::from(tick.c));
+                buffer.next_closes.push(next_close.unwrap_or(0.0));
             }
-            // Step 4.d: if any ticks have been filled in, update the minimum time, moving it forwards
-            if let Some(t) = min_t {
-                curr_t = t;
-            }
-            // Step 4.e: fill in output tick data for the current date, zero filling on missing ticks
-            for ticks in tick_iterators.iter_mut() {
-                if let Some(tick) = ticks.peek() {
-                    // Check the date
-                    if tick.t == curr_t {
-                        // Write the prediction associated with the tick
-                        tick.pred().push_pred(&mut output);
-                    } else {
-                        // Mismatched time: zero fill without advancing the iterator
-                        output.extend(std::iter::repeat(0.0).take(Prediction::NN_FIELDS));
-                    }
-                } else {
-                    // Empty iterator: zero fill
-                    output.extend(std::iter::repeat(0.0).take(Prediction::NN_FIELDS));
-                }
+            // Step 2.d: fill in output tick data for the row `shift` rows ahead target by target, zero
+            // filling on missing ticks, or on missing current ticks or closes for changes, which are computed
+            // from the raw ticks
+            let output = &mut buffer.output_row;
+            for target in spec.targets.iter() {
+                let mut section: Vec<Option<f32>> = (0..stocks)
+                    .map(|stock| {
+                        if in_window {
+                            window.target(row, stock, shift, *target, spec.target_kind)
+                        } else {
+                            None
+                        }
+                    })
+                    .collect();
+                spec.target_kind.cross_section(&mut section);
+                output.extend(section.into_iter().map(|value| value.unwrap_or(0.0)));
             }
+            // Step 2.e: write the row into the buffer's tensors
+            buffer.commit_row(row);
         }
+        Some(())
+    }
+    /// Package a batch of sequences of rows of a dataset window and additional data into tensors. Rows past the end
+    /// of the window are zero filled, so windows of `batch_size * sequence_length` rows are packaged exactly.
+    pub fn make_window_batch<'a, A, DF, F>(
+        &self,
+        additional: A,
+        time_func: DF,
+        window: Window<F>,
+        batch_size: usize,
+        sequence_length: usize,
+    ) -> Option<(Tensor, Tensor)>
+    where
+        A: Iterator<Item = &'a [f32]>,
+        F: Copy + NumCast,
+        DF: FnMut(DateTime<Utc>, &mut Vec<f32>),
+    {
+        assert_eq!(
+            window.dataset.stocks(),
+            self.stocks,
+            "Wrong number of input stocks!"
+        );
+        Self::window_batch_impl(
+            &self.batch_spec(),
+            additional,
+            time_func,
+            window,
+            batch_size,
+            sequence_length,
+        )
+    }
+    /// Package a window of a dataset of many symbols as a batch of per-symbol sequences, for a single stock network
+    /// whose weights are shared across symbols: each sequence of the batch is one symbol's ticks over the window's
+    /// times, so the batch size is the number of symbols and each row has the inputs of a single stock. Additional and
+    /// date inputs are shared by every symbol in a row. Missing ticks and targets are zero filled as in
+    /// `make_window_batch`. Returns `None` if the window is empty, and panics if this network has more than one stock.
+    pub fn make_symbol_batch<'a, A, DF, F>(
+        &self,
+        mut additional: A,
+        mut time_func: DF,
+        window: Window<F>,
+    ) -> Option<(Tensor, Tensor)>
+    where
+        A: Iterator<Item = &'a [f32]>,
+        F: Copy + NumCast,
+        DF: FnMut(DateTime<Utc>, &mut Vec<f32>),
+    {
+        assert_eq!(
+            self.stocks, 1,
+            "Per-symbol batches need a single stock network!"
+        );
+        let times = window.times();
+        if times.is_empty() {
+            return None;
+        }
+        let symbols = window.dataset.stocks();
+        let sequence_length = times.len();
+        let targets = self.targets();
+        let shift = self.desc.target_horizon;
+        assert!(shift > 0, "Target shift must be positive!");
 
-        // Step 5: generate tensors from vectors
-        let input = Tensor::from(&input[..]).view([
-            batch_size as i64,
-            sequence_length as i64,
-            input_features as i64,
-        ]);
-        let output = Tensor::from(&output[..]).view([
-            batch_size as i64,
-            sequence_length as i64,
-            output_features as i64,
-        ]);
+        // Step 1: compute the additional and date inputs of each row, which are shared by every symbol
+        let mut shared: Vec<Vec<f32>> = Vec::with_capacity(sequence_length);
+        for t in times.iter() {
+            let mut row = Vec::with_capacity(self.additional_inputs + self.date_inputs);
+            let given = additional.next().unwrap_or(&[]);
+            let truncate_additional = given.len().min(self.additional_inputs);
+            row.extend_from_slice(&given[..truncate_additional]);
+            row.extend(std::iter::repeat(0.0).take(self.additional_inputs - truncate_additional));
+            time_func(DateTime::from_utc(*t, Utc), &mut row);
+            shared.push(row);
+        }
+
+        // Step 2: compute the targets of each row across symbols, so that cross-sectional targets can be ranked
+        let kind = self.desc.target_kind;
+        let mut values = Vec::with_capacity(sequence_length * targets.len() * symbols);
+        for row in 0..sequence_length {
+            for target in targets.iter() {
+                let start = values.len();
+                values.extend(
+                    (0..symbols).map(|symbol| window.target(row, symbol, shift, *target, kind)),
+                );
+                kind.cross_section(&mut values[start..]);
+            }
+        }
 
-        // Return result!
-        return Some((input, output));
+        // Step 3: fill in each symbol's sequence
+        let mut buffer = BatchBuffer::new(
+            symbols,
+            sequence_length,
+            self.no_inputs(),
+            self.no_outputs(),
+        );
+        for symbol in 0..symbols {
+            for (row, shared) in shared.iter().enumerate() {
+                buffer.input_row.extend_from_slice(shared);
+                match window.tick(row, symbol) {
+                    Some(tick) => tick.push_tick(&mut buffer.input_row),
+                    None => buffer
+                        .input_row
+                        .extend(std::iter::repeat(0.0).take(Tick::NN_FIELDS)),
+                }
+                for target in 0..targets.len() {
+                    let value = values[(row * targets.len() + target) * symbols + symbol];
+                    buffer.output_row.push(value.unwrap_or(0.0));
+                }
+                buffer.commit_row(symbol * sequence_length + row);
+            }
+        }
+        Some(buffer.into_tensors())
     }
-    /// Package a batch of sequences of ticks and additional data into tensors
-    pub fn make_batches<'a, A, DF, I, F>(
+    /// Iterate over consecutive batches of the rows of a dataset, with additional inputs given row by row. The
+    /// iterator knows exactly how many batches it yields. See `Batches`.
+    pub fn batches<'a, F, DF>(
+        &'a self,
+        dataset: &'a Dataset<F>,
+        additional: &'a [Vec<f32>],
+        time_func: DF,
+        batch_size: usize,
+        sequence_length: usize,
+    ) -> Batches<'a, F, DF>
+    where
+        F: Copy + NumCast,
+        DF: FnMut(DateTime<Utc>, &mut Vec<f32>),
+    {
+        Batches::new(
+            self,
+            dataset,
+            additional,
+            time_func,
+            batch_size,
+            sequence_length,
+        )
+    }
+    /// Allocate a buffer for batches of a given shape for this network, optionally in pinned memory, to be reused
+    /// with `make_window_batch_into` or `make_batches_into`
+    pub fn batch_buffer(
+        &self,
+        batch_size: usize,
+        sequence_length: usize,
+        pinned: bool,
+    ) -> BatchBuffer {
+        let alloc = if pinned {
+            BatchBuffer::pinned
+        } else {
+            BatchBuffer::new
+        };
+        alloc(
+            batch_size,
+            sequence_length,
+            self.no_inputs(),
+            self.no_outputs(),
+        )
+    }
+    /// Write a batch of sequences of rows of a dataset window and additional data directly into a preallocated
+    /// buffer, without allocating new tensors. Returns shallow clones of the buffer's tensors, or `None` if the
+    /// window is empty. See `make_window_batch`.
+    pub fn make_window_batch_into<'a, A, DF, F>(
         &self,
         additional: A,
         time_func: DF,
-        tick_iterators: &mut [Peekable<I>],
+        window: Window<F>,
+        buffer: &mut BatchBuffer,
+    ) -> Option<(Tensor, Tensor)>
+    where
+        A: Iterator<Item = &'a [f32]>,
+        F: Copy + NumCast,
+        DF: FnMut(DateTime<Utc>, &mut Vec<f32>),
+    {
+        assert_eq!(
+            window.dataset.stocks(),
+            self.stocks,
+            "Wrong number of input stocks!"
+        );
+        assert_eq!(
+            (buffer.input_features(), buffer.output_features()),
+            (self.no_inputs(), self.no_outputs()),
+            "Batch buffer shape does not match network!"
+        );
+        Self::window_batch_into(&self.batch_spec(), additional, time_func, window, buffer)?;
+        Some(buffer.tensors())
+    }
+    /// Write a batch of sequences of ticks and additional data directly into a preallocated buffer, without allocating
+    /// new tensors. Returns shallow clones of the buffer's tensors, or `None` if the iterators are exhausted. See
+    /// `make_batches`.
+    ///
+    /// Each batch written is recorded in `stats`, if given, so that zero fill and clock skew can be monitored over an
+    /// epoch of training.
+    pub fn make_batches_into<'a, A, DF, S, F>(
+        &self,
+        additional: A,
+        time_func: DF,
+        tick_iterators: &mut [S],
+        buffer: &mut BatchBuffer,
+        stats: Option<&mut BatchStats>,
+    ) -> Option<(Tensor, Tensor)>
+    where
+        A: Iterator<Item = &'a [f32]>,
+        S: TickSource<F>,
+        F: Copy + NumCast,
+        DF: FnMut(DateTime<Utc>, &mut Vec<f32>),
+    {
+        let rows = buffer.batch_size() * buffer.sequence_length();
+        let dataset =
+            Self::next_batch_dataset(self.stocks, tick_iterators, rows, self.desc.target_horizon)?;
+        let window = dataset.window(0, dataset.len().min(rows));
+        let batch = self.make_window_batch_into(additional, time_func, window, buffer)?;
+        if let Some(stats) = stats {
+            stats.observe(window, rows);
+        }
+        Some(batch)
+    }
+    /// Package a batch of sequences of ticks and additional data into tensors of a given shape, together with a mask of
+    /// the rows of real data whose targets are available, handling a final batch too short to fill the shape according
+    /// to its tail policy. Dropped rows are added to `dropped`, so that the rows lost over an epoch can be reported.
+    /// Returns `None` once the iterators are exhausted, including when the final batch is dropped.
+    pub fn make_masked_batches<'a, A, DF, S, F>(
+        &self,
+        additional: A,
+        time_func: DF,
+        tick_iterators: &mut [S],
+        shape: BatchShape,
+        dropped: &mut usize,
+    ) -> Option<Batch>
+    where
+        A: Iterator<Item = &'a [f32]>,
+        S: TickSource<F>,
+        F: Copy + NumCast,
+        DF: FnMut(DateTime<Utc>, &mut Vec<f32>),
+    {
+        let BatchShape {
+            batch_size,
+            sequence_length,
+            tail,
+        } = shape;
+        let rows = batch_size * sequence_length;
+        let spec = self.batch_spec();
+        let shift = spec.target_horizon;
+        let dataset = Self::next_batch_dataset(self.stocks, tick_iterators, rows, shift)?;
+        let available = dataset.len().min(rows);
+        let batch_size = match tail {
+            _ if available == rows => batch_size,
+            TailPolicy::Pad => batch_size,
+            TailPolicy::Drop => {
+                trace!("Dropping a partial batch of {} rows", available);
+                *dropped += available;
+                return None;
+            }
+            TailPolicy::Reshape => {
+                let sequences = available / sequence_length;
+                trace!(
+                    "Reshaping a partial batch of {} rows into {} sequences",
+                    available,
+                    sequences
+                );
+                *dropped += available - sequences * sequence_length;
+                if sequences == 0 {
+                    return None;
+                }
+                sequences
+            }
+        };
+        let window = dataset.window(0, available.min(batch_size * sequence_length));
+        let (input, output) = Self::window_batch_impl(
+            &spec,
+            additional,
+            time_func,
+            window,
+            batch_size,
+            sequence_length,
+        )?;
+        let mask: Vec<f32> = (0..batch_size * sequence_length)
+            .map(|row| {
+                if row < window.len && row + shift < dataset.len() {
+                    1.0
+                } else {
+                    0.0
+                }
+            })
+            .collect();
+        let rows = mask.iter().filter(|&&present| present > 0.0).count();
+        let mask = Tensor::of_slice(&mask).view([batch_size as i64, sequence_length as i64, 1]);
+        Some(Batch {
+            input,
+            output,
+            mask,
+            rows,
+        })
+    }
+    /// Package a batch of sequences of ticks and additional data into tensors, pairing the inputs of each row `t` with
+    /// the targets of row `t + target_horizon`, where rows are the distinct times of the ticks of any stock. A stock
+    /// without a tick in row `t + target_horizon` has zero targets in row `t`, rather than the targets of its next tick,
+    /// so that targets are never taken from a different bar than the horizon implies. The targets of the last rows of the
+    /// batch are looked ahead at and left for the next batch, so horizons longer than one row need a `Lookahead` tick
+    /// source rather than a `Peekable` iterator. Panics if the target horizon is zero or longer than the sources can
+    /// look ahead.
+    pub fn make_batches<'a, A, DF, S, F>(
+        &self,
+        additional: A,
+        time_func: DF,
+        tick_iterators: &mut [S],
         batch_size: usize,
         sequence_length: usize,
     ) -> Option<(Tensor, Tensor)>
     where
         A: Iterator<Item = &'a [f32]>,
-        I: Iterator<Item = Tick<F>>,
+        S: TickSource<F>,
         F: Copy + NumCast,
         DF: FnMut(DateTime<Utc>, &mut Vec<f32>),
     {
         Self::make_batches_impl(
-            self.additional_inputs,
-            self.stocks,
-            self.date_inputs,
+            &self.batch_spec(),
             additional,
             time_func,
             tick_iterators,
@@ -182,6 +862,23 @@ impl StockLSTM {
     }
 }
 
+/// The layout of a network's batches: the widths of its inputs, and the targets of its outputs
+#[derive(Debug, Clone)]
+struct BatchSpec {
+    /// The number of additional inputs
+    additional_inputs: usize,
+    /// The number of date inputs
+    date_inputs: usize,
+    /// The number of stocks
+    stocks: usize,
+    /// The targets predicted for each stock, in output order
+    targets: Vec<Target>,
+    /// How the targets are expressed
+    target_kind: TargetKind,
+    /// How many rows ahead targets are taken from
+    target_horizon: usize,
+}
+
 impl RNN for StockLSTM {
     type State = LSTMState;
     fn zero_state(&self, batch_dim: i64) -> LSTMState {
@@ -191,19 +888,18 @@ impl RNN for StockLSTM {
         self.lstm_layer.step(input, state)
     }
     fn seq_init(&self, input: &Tensor, state: &LSTMState) -> (Tensor, LSTMState) {
-        let (hidden, state) = self.lstm_layer.seq_init(input, state);
-        let output = self.linear_layer.forward(&hidden);
-        (output, state)
-    }
-    fn seq(&self, input: &Tensor) -> (Tensor, LSTMState) {
-        let (hidden, state) = self.lstm_layer.seq(input);
-        let output = self.linear_layer.forward(&hidden);
-        (output, state)
+        self.forward_t(input, state, false)
     }
 }
 
-/// A descriptor for an instance of the StockLSTM model
-#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+/// The default target horizon: predicting the next tick
+fn default_target_horizon() -> usize {
+    1
+}
+
+/// A descriptor for an instance of the StockLSTM model. Descriptors are stored alongside checkpoints, so that a model
+/// can be rebuilt without reconstructing its hyperparameters by hand.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct StockLSTMDesc {
     /// The number of additional input neurons
     pub additional_inputs: usize,
@@ -215,43 +911,213 @@ pub struct StockLSTMDesc {
     pub hidden: usize,
     /// The number of hidden LSTM layers to use
     pub layers: usize,
+    /// The targets to predict, each with its own output head
+    pub heads: Vec<Target>,
+    /// Whether the targets are predicted as levels or as returns
+    #[serde(default)]
+    pub target_kind: TargetKind,
+    /// How many rows ahead targets are taken from, e.g. 5 to predict the tick 5 bars ahead. Must be positive.
+    #[serde(default = "default_target_horizon")]
+    pub target_horizon: usize,
+    /// How to initialize the network's weights
+    #[serde(default)]
+    pub init: Initialization,
+    /// Whether to apply layer normalization to the output of each LSTM layer
+    #[serde(default)]
+    pub layer_norm: bool,
+    /// Whether to add residual connections between stacked LSTM layers
+    #[serde(default)]
+    pub residual: bool,
+    /// Whether to concatenate the input features of each timestep to the LSTM output before the heads, so that the
+    /// heads can directly learn persistence
+    #[serde(default)]
+    pub input_skip: bool,
+    /// Whether to use a bidirectional LSTM. Bidirectional models look ahead in time, and hence are only suitable
+    /// for offline analysis (e.g. smoothing or labeling), not forecasting.
+    #[serde(default)]
+    pub bidirectional: bool,
+    /// The grouping of stocks into sectors, if any. Each sector gets a shared hidden block fed the LSTM output, and
+    /// each stock's outputs are predicted from the LSTM output together with its sector's block.
+    #[serde(default)]
+    pub sectors: Option<SectorDesc>,
+    /// The dropout probability applied to the LSTM output during training, and when estimating uncertainty by Monte
+    /// Carlo dropout
+    #[serde(default)]
+    pub dropout: f64,
+    /// Whether dropout on the LSTM output uses the same mask at every timestep of a sequence (variational dropout)
+    #[serde(default)]
+    pub variational_dropout: bool,
+    /// The dropout probability applied to the hidden state fed back into the LSTMs during training. Must be zero for
+    /// bidirectional networks.
+    #[serde(default)]
+    pub recurrent_dropout: f64,
+}
+
+impl Default for StockLSTMDesc {
+    /// A single-stock, single-layer network with one hidden unit predicting the fields of a `Prediction` as levels of
+    /// the next tick, without date or additional inputs, to be customized with struct update syntax
+    fn default() -> StockLSTMDesc {
+        StockLSTMDesc {
+            additional_inputs: 0,
+            date_inputs: 0,
+            stocks: 1,
+            hidden: 1,
+            layers: 1,
+            heads: Target::PREDICTION.to_vec(),
+            target_kind: TargetKind::default(),
+            target_horizon: default_target_horizon(),
+            init: Initialization::default(),
+            layer_norm: false,
+            residual: false,
+            input_skip: false,
+            bidirectional: false,
+            sectors: None,
+            dropout: 0.0,
+            variational_dropout: false,
+            recurrent_dropout: 0.0,
+        }
+    }
 }
 
 impl StockLSTMDesc {
-    /// Build a `StockLSTM` over a given `VarStore `
-    pub fn build(&self, vs: &VarStore) -> StockLSTM {
-        let inputs = self.additional_inputs + self.date_inputs + self.stocks * Tick::NN_FIELDS;
-        let lstm_layer = nn::lstm(
-            &vs.root(),
-            inputs as i64,
-            self.hidden as i64,
-            RNNConfig {
-                has_biases: true,
-                num_layers: self.layers as i64,
-                dropout: 0.,
-                train: true,
-                bidirectional: false,
-                batch_first: true,
-            },
+    /// Compute the number of inputs of the described network
+    pub fn no_inputs(&self) -> usize {
+        self.additional_inputs + self.date_inputs + self.stocks * Tick::NN_FIELDS
+    }
+    /// Get the number of features output by the LSTM layer at each timestep, which is doubled if bidirectional
+    pub fn lstm_outputs(&self) -> usize {
+        if self.bidirectional {
+            2 * self.hidden
+        } else {
+            self.hidden
+        }
+    }
+    /// Get the number of features shared by every stock's outputs at each timestep, and fed to each sector block
+    pub fn shared_features(&self) -> usize {
+        if self.input_skip {
+            self.lstm_outputs() + self.no_inputs()
+        } else {
+            self.lstm_outputs()
+        }
+    }
+    /// Get the number of features fed to each output head at each timestep, for each stock if grouped by sector
+    pub fn head_inputs(&self) -> usize {
+        match &self.sectors {
+            Some(sectors) => self.shared_features() + sectors.hidden,
+            None => self.shared_features(),
+        }
+    }
+    /// Describe each field in which another descriptor, e.g. one stored in a checkpoint, differs from this one
+    pub fn mismatches(&self, other: &StockLSTMDesc) -> Vec<String> {
+        let mut mismatches = Vec::new();
+        let mut check =
+            |field: &str, expected: &dyn std::fmt::Debug, found: &dyn std::fmt::Debug| {
+                let (expected, found) = (format!("{:?}", expected), format!("{:?}", found));
+                if expected != found {
+                    mismatches.push(format!("{} is {}, expected {}", field, found, expected));
+                }
+            };
+        check(
+            "additional_inputs",
+            &self.additional_inputs,
+            &other.additional_inputs,
         );
-        let linear_layer = nn::linear(
-            &vs.root(),
-            self.hidden as i64,
-            (self.stocks * Prediction::NN_FIELDS) as i64,
-            Default::default(),
+        check("date_inputs", &self.date_inputs, &other.date_inputs);
+        check("stocks", &self.stocks, &other.stocks);
+        check("hidden", &self.hidden, &other.hidden);
+        check("layers", &self.layers, &other.layers);
+        check("heads", &self.heads, &other.heads);
+        check("target_kind", &self.target_kind, &other.target_kind);
+        check(
+            "target_horizon",
+            &self.target_horizon,
+            &other.target_horizon,
         );
-        StockLSTM {
+        check("layer_norm", &self.layer_norm, &other.layer_norm);
+        check("residual", &self.residual, &other.residual);
+        check("input_skip", &self.input_skip, &other.input_skip);
+        check("bidirectional", &self.bidirectional, &other.bidirectional);
+        check("sectors", &self.sectors, &other.sectors);
+        mismatches
+    }
+    /// Package a batch of sequences of rows of a dataset window into tensors for the described network, without
+    /// building it, e.g. to check a data pipeline before allocating a model. See `StockLSTM::make_window_batch`.
+    pub fn make_window_batch<'a, A, DF, F>(
+        &self,
+        additional: A,
+        time_func: DF,
+        window: Window<F>,
+        batch_size: usize,
+        sequence_length: usize,
+    ) -> Option<(Tensor, Tensor)>
+    where
+        A: Iterator<Item = &'a [f32]>,
+        F: Copy + NumCast,
+        DF: FnMut(DateTime<Utc>, &mut Vec<f32>),
+    {
+        assert_eq!(
+            window.dataset.stocks(),
+            self.stocks,
+            "Wrong number of input stocks!"
+        );
+        StockLSTM::window_batch_impl(
+            &self.batch_spec(),
+            additional,
+            time_func,
+            window,
+            batch_size,
+            sequence_length,
+        )
+    }
+    /// Get the layout of the described network's batches
+    fn batch_spec(&self) -> BatchSpec {
+        BatchSpec {
+            additional_inputs: self.additional_inputs,
+            date_inputs: self.date_inputs,
+            stocks: self.stocks,
+            targets: self.heads.clone(),
+            target_kind: self.target_kind,
+            target_horizon: self.target_horizon,
+        }
+    }
+    /// Build a `StockLSTM` over a given `VarStore `
+    ///
+    /// Panics if the network is grouped into sectors which do not fit its stocks.
+    pub fn build(&self, vs: &VarStore) -> StockLSTM {
+        let lstm_layer = LSTMStack::build(&vs.root(), self);
+        let sectors = self.sectors.as_ref().map(|sectors| {
+            if let Err(err) = sectors.validate(self.stocks) {
+                panic!("Invalid sectors: {}", err)
+            }
+            SectorBlocks::new(&(vs.root() / "sectors"), sectors, self.shared_features())
+        });
+        let heads = self
+            .heads
+            .iter()
+            .map(|target| Head::new(&vs.root(), *target, self.head_inputs(), self.stocks))
+            .collect();
+        let lstm = StockLSTM {
             stocks: self.stocks,
             additional_inputs: self.additional_inputs,
             date_inputs: self.date_inputs,
             lstm_layer,
-            linear_layer,
-        }
+            input_skip: self.input_skip,
+            sectors,
+            heads,
+            dropout: self.dropout,
+            variational_dropout: self.variational_dropout,
+            recurrent_dropout: self.recurrent_dropout,
+            activation_regularization: ActivationRegularization::default(),
+            desc: self.clone(),
+        };
+        self.init.apply(vs, &lstm);
+        lstm
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use super::batch::Lookahead;
     use super::*;
     use chrono::{
         naive::{NaiveDate, NaiveDateTime, NaiveTime},
@@ -393,9 +1259,14 @@ mod tests {
         ];
         let time_func = |d: DateTime<Utc>, v: &mut Vec<f32>| v.push(d.minute() as f32);
         let (input_data, output_data) = StockLSTM::make_batches_impl(
-            3,
-            2,
-            1,
+            &BatchSpec {
+                additional_inputs: 3,
+                date_inputs: 1,
+                stocks: 2,
+                targets: Target::PREDICTION.to_vec(),
+                target_kind: TargetKind::Level,
+                target_horizon: 1,
+            },
             additional_data.iter().copied(),
             time_func,
             fake_stocks,
@@ -409,7 +1280,304 @@ mod tests {
         );
         assert_eq!(
             output_data.size3().unwrap(),
-            (4, 2, 2 * Prediction::NN_FIELDS as i64)
+            (4, 2, 2 * Target::PREDICTION.len() as i64)
         );
     }
+
+    /// Test that targets are taken from the given number of rows ahead, across batches
+    #[test]
+    fn horizon_targets() {
+        let t = NaiveDate::from_ymd(2020, 10, 12).and_hms(14, 30, 0);
+        let ticks: Vec<Tick> = (0..7)
+            .map(|ix| Tick {
+                t: t + Duration::minutes(ix),
+                v: 0.0,
+                vw: 0.0,
+                o: 0.0,
+                c: ix as f64,
+                h: 0.0,
+                l: 0.0,
+                n: 0.0,
+            })
+            .collect();
+        let mut iterators = [ticks.iter().copied().peekable()];
+        let mut batch = || {
+            let (_, output) = StockLSTM::make_batches_impl(
+                &BatchSpec {
+                    additional_inputs: 0,
+                    date_inputs: 0,
+                    stocks: 1,
+                    targets: vec![Target::Close],
+                    target_kind: TargetKind::Level,
+                    target_horizon: 2,
+                },
+                std::iter::empty(),
+                |_, _: &mut Vec<f32>| {},
+                &mut iterators,
+                1,
+                3,
+            )?;
+            Some(Vec::<f32>::from(&output.view([-1])))
+        };
+        assert_eq!(batch(), Some(vec![2.0, 3.0, 4.0]));
+        assert_eq!(batch(), Some(vec![5.0, 6.0, 0.0]));
+        assert_eq!(batch(), Some(vec![0.0, 0.0, 0.0]));
+        assert_eq!(batch(), None);
+    }
+
+    /// Test that inputs are paired with targets exactly `shift` rows ahead when bars are missing
+    #[test]
+    fn shifted_targets_with_missing_bars() {
+        let t = NaiveDate::from_ymd(2020, 10, 12).and_hms(14, 30, 0);
+        let ticks = |stock: usize, minutes: &[i64]| -> Vec<Tick> {
+            minutes
+                .iter()
+                .map(|&m| Tick {
+                    t: t + Duration::minutes(m),
+                    v: 0.0,
+                    vw: 0.0,
+                    o: 0.0,
+                    c: (10 * stock) as f64 + m as f64,
+                    h: 0.0,
+                    l: 0.0,
+                    n: 0.0,
+                })
+                .collect()
+        };
+        let a = ticks(0, &[0, 1, 2, 3, 4, 5]);
+        let b = ticks(1, &[0, 2, 3, 5]);
+        let inputs = [
+            vec![0.0, 10.0, 1.0, 0.0, 2.0, 12.0],
+            vec![3.0, 13.0, 4.0, 0.0, 5.0, 15.0],
+        ];
+        let expected = [
+            (
+                1,
+                [
+                    vec![1.0, 0.0, 2.0, 12.0, 3.0, 13.0],
+                    vec![4.0, 0.0, 5.0, 15.0, 0.0, 0.0],
+                ],
+            ),
+            (
+                2,
+                [
+                    vec![2.0, 12.0, 3.0, 13.0, 4.0, 0.0],
+                    vec![5.0, 15.0, 0.0, 0.0, 0.0, 0.0],
+                ],
+            ),
+        ];
+        for (shift, outputs) in expected.iter() {
+            let mut iterators = [
+                Lookahead::new(a.iter().copied()),
+                Lookahead::new(b.iter().copied()),
+            ];
+            let mut batch = || {
+                StockLSTM::make_batches_impl(
+                    &BatchSpec {
+                        additional_inputs: 0,
+                        date_inputs: 0,
+                        stocks: 2,
+                        targets: vec![Target::Close],
+                        target_kind: TargetKind::Level,
+                        target_horizon: *shift,
+                    },
+                    std::iter::empty(),
+                    |_, _: &mut Vec<f32>| {},
+                    &mut iterators,
+                    1,
+                    3,
+                )
+            };
+            for (input, output) in inputs.iter().zip(outputs.iter()) {
+                let (batch_input, batch_output) = batch().unwrap();
+                let closes = batch_input
+                    .view([3, 2, Tick::NN_FIELDS as i64])
+                    .select(2, 3);
+                assert_eq!(Vec::<f32>::from(&closes.view([-1])), *input);
+                assert_eq!(Vec::<f32>::from(&batch_output.view([-1])), *output);
+            }
+            assert!(batch().is_none());
+        }
+    }
+
+    /// Test that final partial batches are padded, dropped or reshaped, with masks and counts of dropped rows
+    #[test]
+    fn partial_batches() {
+        let vs = VarStore::new(Device::Cpu);
+        let lstm = StockLSTMDesc {
+            stocks: 1,
+            hidden: 2,
+            layers: 1,
+            heads: vec![Target::Close],
+            ..Default::default()
+        }
+        .build(&vs);
+        let t = NaiveDate::from_ymd(2020, 10, 12).and_hms(14, 30, 0);
+        let ticks: Vec<Tick> = (0..7)
+            .map(|ix| Tick {
+                t: t + Duration::minutes(ix),
+                v: 0.0,
+                vw: 0.0,
+                o: 0.0,
+                c: ix as f64,
+                h: 0.0,
+                l: 0.0,
+                n: 0.0,
+            })
+            .collect();
+        let batches = |tail| {
+            let mut iterators = [ticks.iter().copied().peekable()];
+            let mut dropped = 0;
+            let mut masks = Vec::new();
+            while let Some(batch) = lstm.make_masked_batches(
+                std::iter::empty(),
+                |_, _: &mut Vec<f32>| {},
+                &mut iterators,
+                BatchShape {
+                    batch_size: 2,
+                    sequence_length: 2,
+                    tail,
+                },
+                &mut dropped,
+            ) {
+                assert_eq!(
+                    batch.rows as f64,
+                    f64::from(batch.mask.sum(tch::Kind::Float))
+                );
+                masks.push(Vec::<f32>::from(&batch.mask.view([-1])));
+            }
+            (masks, dropped)
+        };
+        let full = vec![1.0; 4];
+        assert_eq!(
+            batches(TailPolicy::Pad),
+            (vec![full.clone(), vec![1.0, 1.0, 0.0, 0.0]], 0)
+        );
+        assert_eq!(batches(TailPolicy::Drop), (vec![full.clone()], 3));
+        assert_eq!(
+            batches(TailPolicy::Reshape),
+            (vec![full, vec![1.0, 1.0]], 1)
+        );
+    }
+
+    /// Test that per-symbol batches put each symbol's sequence in its own batch row
+    #[test]
+    fn symbol_batches() {
+        let vs = VarStore::new(Device::Cpu);
+        let lstm = StockLSTMDesc {
+            additional_inputs: 1,
+            stocks: 1,
+            hidden: 2,
+            layers: 1,
+            heads: vec![Target::Close],
+            ..Default::default()
+        }
+        .build(&vs);
+        let t = NaiveDate::from_ymd(2020, 10, 12).and_hms(14, 30, 0);
+        let ticks = |stock: usize, minutes: &[i64]| -> Vec<Tick> {
+            minutes
+                .iter()
+                .map(|&m| Tick {
+                    t: t + Duration::minutes(m),
+                    v: 0.0,
+                    vw: 0.0,
+                    o: 0.0,
+                    c: (10 * stock) as f64 + m as f64,
+                    h: 0.0,
+                    l: 0.0,
+                    n: 0.0,
+                })
+                .collect()
+        };
+        let dataset = Dataset::from_ticks(
+            vec!["A".into(), "B".into()],
+            vec![ticks(0, &[0, 1, 2, 3]), ticks(1, &[0, 2, 3])],
+        );
+        let additional = [[7.0], [8.0], [9.0]];
+        let (input, output) = lstm
+            .make_symbol_batch(
+                additional.iter().map(|row| &row[..]),
+                |_, _: &mut Vec<f32>| {},
+                dataset.window(0, 3),
+            )
+            .unwrap();
+        assert_eq!(input.size3().unwrap(), (2, 3, 1 + Tick::NN_FIELDS as i64));
+        assert_eq!(
+            Vec::<f32>::from(&input.select(2, 0).view([-1])),
+            vec![7.0, 8.0, 9.0, 7.0, 8.0, 9.0]
+        );
+        assert_eq!(
+            Vec::<f32>::from(&input.select(2, 4).view([-1])),
+            vec![0.0, 1.0, 2.0, 10.0, 0.0, 12.0]
+        );
+        assert_eq!(
+            Vec::<f32>::from(&output.view([-1])),
+            vec![1.0, 2.0, 3.0, 0.0, 12.0, 13.0]
+        );
+    }
+
+    /// Test that return targets are computed from the current and next ticks
+    #[test]
+    fn return_targets() {
+        let t = NaiveDate::from_ymd(2020, 10, 12).and_hms(14, 30, 0);
+        let ticks: Vec<_> = [10.0, 11.0, 12.1, 0.0]
+            .iter()
+            .enumerate()
+            .map(|(ix, &c)| Tick {
+                t: t + Duration::minutes(ix as i64),
+                v: 0.0,
+                vw: c,
+                o: c,
+                c,
+                h: c,
+                l: c,
+                n: 0.0,
+            })
+            .collect();
+        // Returns are computed from the raw ticks, not the levels of the scaled ticks fed in
+        let scaled = ticks.iter().map(|tick| Tick { c: 0.0, ..*tick }).collect();
+        let dataset = Dataset::from_ticks(vec!["A".into()], vec![scaled]).with_raw(vec![ticks]);
+        let (_, output) = StockLSTM::window_batch_impl(
+            &BatchSpec {
+                additional_inputs: 0,
+                date_inputs: 0,
+                stocks: 1,
+                targets: vec![Target::Close, Target::Volume],
+                target_kind: TargetKind::Return,
+                target_horizon: 1,
+            },
+            std::iter::empty(),
+            |_, _: &mut Vec<f32>| {},
+            dataset.window(0, 3),
+            1,
+            3,
+        )
+        .unwrap();
+        let output = Vec::<f32>::from(&output.view([-1]));
+        assert!((output[0] - 0.1).abs() < 1e-6 && (output[2] - 0.1).abs() < 1e-6);
+        // The missing close of the last tick is masked, rather than a return of -100%
+        assert_eq!(output[4], 0.0);
+        assert!(output.iter().skip(1).step_by(2).all(|&v| v == 0.0));
+    }
+
+    /// Test that date inputs are part of the network's input width
+    #[test]
+    fn date_inputs_widen_inputs() {
+        let vs = VarStore::new(Device::Cpu);
+        let lstm = StockLSTMDesc {
+            additional_inputs: 1,
+            date_inputs: 4,
+            stocks: 2,
+            hidden: 3,
+            layers: 2,
+            heads: Target::PREDICTION.to_vec(),
+            input_skip: true,
+            ..Default::default()
+        }
+        .build(&vs);
+        assert_eq!(lstm.no_inputs(), 1 + 4 + 2 * Tick::NN_FIELDS);
+        let input = Tensor::zeros(&[2, 5, lstm.no_inputs() as i64], tch::kind::FLOAT_CPU);
+        let (output, _) = lstm.seq_init(&input, &lstm.zero_state(2));
+        assert_eq!(output.size3().unwrap(), (2, 5, lstm.no_outputs() as i64));
+    }
 }
@@ -0,0 +1,169 @@
+/*!
+Regularization of a `StockLSTM`'s recurrent activations: activation regularization (AR), temporal activation
+regularization (TAR) and variational dropout, following Merity et al., "Regularizing and Optimizing LSTM Language
+Models" (2017)
+*/
+use super::StockLSTM;
+use serde::{Deserialize, Serialize};
+use tch::nn::{LSTMState, RNN};
+use tch::{Kind, Tensor};
+
+/// L2 penalties on the hidden states of a network's recurrent layers, added to its training loss. Both are disabled
+/// by default.
+#[derive(Debug, Copy, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct ActivationRegularization {
+    /// The coefficient of the mean squared hidden state after dropout (AR), penalizing large activations
+    #[serde(default)]
+    pub ar: f64,
+    /// The coefficient of the mean squared difference between consecutive hidden states before dropout (TAR),
+    /// penalizing hidden states which change quickly over time
+    #[serde(default)]
+    pub tar: f64,
+}
+
+impl ActivationRegularization {
+    /// Whether either penalty is enabled
+    pub fn is_enabled(&self) -> bool {
+        self.ar != 0.0 || self.tar != 0.0
+    }
+    /// Compute the penalty on a sequence of hidden states of shape `[batch, sequence, features]`, given both before
+    /// and after dropout
+    pub fn penalty(&self, hidden: &Tensor, dropped: &Tensor) -> Tensor {
+        let mut penalty = Tensor::zeros(&[], (Kind::Float, hidden.device()));
+        if self.ar != 0.0 {
+            penalty = penalty + dropped.pow(2.0).mean(Kind::Float) * self.ar;
+        }
+        let sequence_length = hidden.size()[1];
+        if self.tar != 0.0 && sequence_length > 1 {
+            let steps =
+                hidden.narrow(1, 1, sequence_length - 1) - hidden.narrow(1, 0, sequence_length - 1);
+            penalty = penalty + steps.pow(2.0).mean(Kind::Float) * self.tar;
+        }
+        penalty
+    }
+}
+
+/// Sample an inverted dropout mask of a given shape, scaled so that its expected value is one
+fn dropout_mask(shape: &[i64], p: f64, like: &Tensor) -> Tensor {
+    Tensor::ones(shape, (like.kind(), like.device())).dropout(p, true)
+}
+
+/// Apply dropout to a tensor of shape `[batch, sequence, features]` with the same mask at every timestep
+pub fn variational_dropout(xs: &Tensor, p: f64, train: bool) -> Tensor {
+    if !train || p <= 0.0 {
+        return xs.shallow_clone();
+    }
+    let size = xs.size();
+    xs * dropout_mask(&[size[0], 1, size[2]], p, xs)
+}
+
+/// The dropout masks shared by every timestep of a training batch, sampled once per batch so that a batch run in
+/// pieces, e.g. segment by segment when checkpointing, is dropped out exactly as if it were run at once
+#[derive(Debug)]
+pub(crate) struct DropoutMasks {
+    /// The mask of the hidden state fed back into the recurrent layers, if recurrent dropout is enabled
+    pub recurrent: Option<Tensor>,
+    /// The mask of the recurrent layers' output, if variational dropout is enabled
+    pub variational: Option<Tensor>,
+}
+
+impl StockLSTM {
+    /// Sample the dropout masks of a training batch, given its initial state
+    pub(crate) fn dropout_masks(&self, state: &LSTMState) -> DropoutMasks {
+        let h = state.h();
+        let recurrent = if self.recurrent_dropout > 0.0 {
+            Some(dropout_mask(&h.size(), self.recurrent_dropout, &h))
+        } else {
+            None
+        };
+        let variational = if self.variational_dropout && self.dropout > 0.0 {
+            let batch = h.size()[1];
+            let features = self.desc.lstm_outputs() as i64;
+            Some(dropout_mask(&[batch, 1, features], self.dropout, &h))
+        } else {
+            None
+        };
+        DropoutMasks {
+            recurrent,
+            variational,
+        }
+    }
+    /// Run this network's recurrent layers one timestep at a time, applying a recurrent dropout mask, as sampled by
+    /// `dropout_masks`, to the hidden state fed back into them at every timestep. The final state returned is not
+    /// masked.
+    ///
+    /// Panics if the network is bidirectional, since its backward direction cannot be run one timestep at a time.
+    pub(crate) fn recurrent_dropout_sequence(
+        &self,
+        xs: &Tensor,
+        state: &LSTMState,
+        mask: &Tensor,
+    ) -> (Tensor, LSTMState) {
+        assert!(
+            !self.desc.bidirectional,
+            "Recurrent dropout is not supported for bidirectional networks!"
+        );
+        let mut state = LSTMState((state.h(), state.c()));
+        let mut outputs = Vec::with_capacity(xs.size()[1] as usize);
+        for step in 0..xs.size()[1] {
+            let masked = LSTMState((state.h() * mask, state.c()));
+            let (output, next) = self.lstm_layer.seq_init(&xs.narrow(1, step, 1), &masked);
+            outputs.push(output);
+            state = next;
+        }
+        (Tensor::cat(&outputs, 1), state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::Target;
+    use crate::lstm::StockLSTMDesc;
+    use tch::nn::VarStore;
+    use tch::Device;
+
+    #[test]
+    fn penalties_and_masks() {
+        let hidden = Tensor::of_slice(&[1.0f32, 3.0, 0.0, 2.0]).view([1, 4, 1]);
+        let regularization = ActivationRegularization { ar: 2.0, tar: 0.5 };
+        // AR: 2 * (1 + 9 + 0 + 4) / 4 = 7, TAR: 0.5 * (4 + 9 + 4) / 3 = 17 / 6
+        let penalty = regularization.penalty(&hidden, &hidden).double_value(&[]);
+        assert!((penalty - (7.0 + 17.0 / 6.0)).abs() < 1e-5);
+        assert!(!ActivationRegularization::default().is_enabled());
+
+        let ones = Tensor::ones(&[3, 5, 4], tch::kind::FLOAT_CPU);
+        let dropped = variational_dropout(&ones, 0.5, true);
+        let first = dropped.narrow(1, 0, 1);
+        for step in 1..5 {
+            assert!(dropped.narrow(1, step, 1).equal(&first));
+        }
+        assert!(variational_dropout(&ones, 0.5, false).equal(&ones));
+    }
+
+    #[test]
+    fn recurrent_dropout_without_dropout_matches() {
+        tch::manual_seed(3);
+        let vs = VarStore::new(Device::Cpu);
+        let lstm = StockLSTMDesc {
+            additional_inputs: 2,
+            stocks: 1,
+            hidden: 3,
+            layers: 2,
+            heads: vec![Target::Close],
+            layer_norm: true,
+            residual: true,
+            ..Default::default()
+        }
+        .build(&vs);
+        let xs = Tensor::randn(&[2, 6, lstm.no_inputs() as i64], tch::kind::FLOAT_CPU);
+        let state = lstm.zero_state(2);
+        let (expected, expected_state) = lstm.hidden_sequence(&xs, &state);
+        let ones = state.h().ones_like();
+        let (actual, actual_state) = lstm.recurrent_dropout_sequence(&xs, &state, &ones);
+        assert!(expected.allclose(&actual, 1e-5, 1e-6, false));
+        assert!(expected_state
+            .c()
+            .allclose(&actual_state.c(), 1e-5, 1e-6, false));
+    }
+}
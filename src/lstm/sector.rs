@@ -0,0 +1,178 @@
+/*!
+Hierarchical sector structure: shared sector-level hidden blocks between the recurrent layers and the output heads, so
+that related stocks share statistical strength within one network
+*/
+use anyhow::format_err;
+use serde::{Deserialize, Serialize};
+use tch::nn::{self, Linear, Module, Path};
+use tch::Tensor;
+
+/// A grouping of a network's stocks into sectors, each with its own hidden block
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct SectorDesc {
+    /// The name of each sector
+    pub names: Vec<String>,
+    /// The index of each stock's sector, in stock order
+    pub of_stock: Vec<usize>,
+    /// The number of hidden units in each sector's block
+    pub hidden: usize,
+}
+
+impl SectorDesc {
+    /// Group stocks by their sectors, in stock order. Sectors are indexed in order of first appearance, and stocks
+    /// without a known sector are grouped into a sector of their own, named `unknown`.
+    pub fn new(sectors: &[Option<String>], hidden: usize) -> SectorDesc {
+        let mut names: Vec<String> = Vec::new();
+        let of_stock = sectors
+            .iter()
+            .map(|sector| {
+                let name = sector.as_deref().unwrap_or("unknown");
+                match names.iter().position(|existing| existing == name) {
+                    Some(ix) => ix,
+                    None => {
+                        names.push(name.to_owned());
+                        names.len() - 1
+                    }
+                }
+            })
+            .collect();
+        SectorDesc {
+            names,
+            of_stock,
+            hidden,
+        }
+    }
+    /// Get the number of sectors
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+    /// Whether there are no sectors
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+    /// Get the stocks in a given sector
+    pub fn stocks(&self, sector: usize) -> Vec<usize> {
+        (0..self.of_stock.len())
+            .filter(|stock| self.of_stock[*stock] == sector)
+            .collect()
+    }
+    /// Check that this grouping fits a network over a given number of stocks
+    pub fn validate(&self, stocks: usize) -> anyhow::Result<()> {
+        if self.of_stock.len() != stocks {
+            return Err(format_err!(
+                "Sectors are given for {} stocks, but the network has {}",
+                self.of_stock.len(),
+                stocks
+            ));
+        }
+        if let Some(sector) = self.of_stock.iter().find(|sector| **sector >= self.len()) {
+            return Err(format_err!(
+                "Sector {} is out of range for {} sectors",
+                sector,
+                self.len()
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// The sector-level hidden blocks of a `StockLSTM`, mapping the features fed to its heads to per-stock features
+#[derive(Debug)]
+pub struct SectorBlocks {
+    /// Each sector's block, in sector order
+    pub blocks: Vec<Linear>,
+    /// The index of each stock's sector, in stock order
+    of_stock: Vec<i64>,
+}
+
+impl SectorBlocks {
+    /// Build a block with `desc.hidden` units for each described sector, taking `inputs` features
+    pub fn new(path: &Path, desc: &SectorDesc, inputs: usize) -> SectorBlocks {
+        let blocks = (0..desc.len())
+            .map(|ix| {
+                nn::linear(
+                    &(path / format!("sector{}", ix)),
+                    inputs as i64,
+                    desc.hidden as i64,
+                    Default::default(),
+                )
+            })
+            .collect();
+        SectorBlocks {
+            blocks,
+            of_stock: desc.of_stock.iter().map(|sector| *sector as i64).collect(),
+        }
+    }
+    /// Get the features of each stock from the features shared by every stock, of shape `[..., inputs]`: the
+    /// activations of the stock's sector block followed by the shared features, of shape
+    /// `[..., stocks, hidden + inputs]`. The shared features come last so that, as without sectors, each row of head
+    /// weights ends with the columns of the stocks' inputs.
+    pub fn stock_features(&self, features: &Tensor) -> Tensor {
+        let activations: Vec<Tensor> = self
+            .blocks
+            .iter()
+            .map(|block| block.forward(features).tanh())
+            .collect();
+        let activations = Tensor::stack(&activations, -2);
+        let index = Tensor::of_slice(&self.of_stock).to_device(features.device());
+        let by_stock = activations.index_select(activations.dim() as i64 - 2, &index);
+        let mut size = features.size();
+        size.insert(size.len() - 1, self.of_stock.len() as i64);
+        let shared = features.unsqueeze(-2).expand(&size, false);
+        Tensor::cat(&[by_stock, shared], -1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::{Target, TargetKind};
+    use crate::lstm::{init::Initialization, StockLSTMDesc};
+    use tch::nn::{VarStore, RNN};
+    use tch::Device;
+
+    #[test]
+    fn stocks_see_their_sector_block() {
+        let sectors = [
+            Some("Technology".to_string()),
+            Some("Energy".to_string()),
+            None,
+            Some("Technology".to_string()),
+        ];
+        let desc = SectorDesc::new(&sectors, 3);
+        assert_eq!(desc.names, vec!["Technology", "Energy", "unknown"]);
+        assert_eq!(desc.of_stock, vec![0, 1, 2, 0]);
+        assert_eq!(desc.stocks(0), vec![0, 3]);
+        assert!(desc.validate(4).is_ok());
+        assert!(desc.validate(5).is_err());
+
+        let vs = VarStore::new(Device::Cpu);
+        let blocks = SectorBlocks::new(&vs.root(), &desc, 5);
+        let features = Tensor::randn(&[2, 7, 5], tch::kind::FLOAT_CPU);
+        let stock_features = blocks.stock_features(&features);
+        assert_eq!(stock_features.size(), vec![2, 7, 4, 8]);
+        let same_sector = stock_features.select(2, 0) - stock_features.select(2, 3);
+        assert_eq!(f64::from(same_sector.abs().max()), 0.0);
+        let shared = stock_features.select(2, 1).narrow(-1, 3, 5) - &features;
+        assert_eq!(f64::from(shared.abs().max()), 0.0);
+
+        let desc = StockLSTMDesc {
+            date_inputs: 1,
+            stocks: 4,
+            hidden: 6,
+            layers: 1,
+            heads: vec![Target::Close, Target::Volume],
+            target_kind: TargetKind::Return,
+            init: Initialization::recommended(),
+            input_skip: true,
+            sectors: Some(desc),
+            ..Default::default()
+        };
+        let vs = VarStore::new(Device::Cpu);
+        let lstm = desc.build(&vs);
+        lstm.validate_against(&vs).unwrap();
+        let input = Tensor::randn(&[2, 3, desc.no_inputs() as i64], tch::kind::FLOAT_CPU);
+        let (output, _) = lstm.forward_t(&input, &lstm.zero_state(2), false);
+        assert_eq!(output.size(), vec![2, 3, 8]);
+    }
+}
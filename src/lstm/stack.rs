@@ -0,0 +1,133 @@
+/*!
+A stack of LSTMs, optionally with layer normalization and residual connections between layers
+*/
+use super::StockLSTMDesc;
+use tch::nn::{self, LSTMState, LayerNorm, Module, Path, RNNConfig, LSTM, RNN};
+use tch::Tensor;
+
+/// A stack of LSTMs, each fed the output of the previous one.
+///
+/// The states of the LSTMs are concatenated along the first dimension, so that a stack has the same state layout
+/// as a single multi-layer LSTM.
+#[derive(Debug)]
+pub struct LSTMStack {
+    /// The LSTMs in this stack, in order
+    pub lstms: Vec<LSTM>,
+    /// The layer normalizations applied to the output of each LSTM. Empty if layer normalization is disabled.
+    pub norms: Vec<LayerNorm>,
+    /// Whether to add each LSTM's input to its output when their sizes match
+    pub residual: bool,
+    /// The number of state slices (layers times directions) taken up by each LSTM
+    pub state_slices: i64,
+}
+
+impl LSTMStack {
+    /// Build the recurrent stack described by a `StockLSTMDesc`.
+    ///
+    /// Without layer normalization or residual connections, this is a single multi-layer LSTM. Otherwise, it is a
+    /// stack of single-layer LSTMs, since tch does not expose the intermediate outputs of a multi-layer LSTM.
+    pub fn build(path: &Path, desc: &StockLSTMDesc) -> LSTMStack {
+        let directions = if desc.bidirectional { 2 } else { 1 };
+        let config = |num_layers| RNNConfig {
+            has_biases: true,
+            num_layers,
+            dropout: 0.,
+            train: true,
+            bidirectional: desc.bidirectional,
+            batch_first: true,
+        };
+        if !desc.layer_norm && !desc.residual {
+            let lstm = nn::lstm(
+                path,
+                desc.no_inputs() as i64,
+                desc.hidden as i64,
+                config(desc.layers as i64),
+            );
+            return LSTMStack {
+                lstms: vec![lstm],
+                norms: Vec::new(),
+                residual: false,
+                state_slices: desc.layers as i64 * directions,
+            };
+        }
+        let mut lstms = Vec::with_capacity(desc.layers);
+        let mut norms = Vec::new();
+        for layer in 0..desc.layers {
+            let inputs = if layer == 0 {
+                desc.no_inputs()
+            } else {
+                desc.lstm_outputs()
+            };
+            let layer_path = path / format!("layer{}", layer);
+            lstms.push(nn::lstm(
+                &layer_path,
+                inputs as i64,
+                desc.hidden as i64,
+                config(1),
+            ));
+            if desc.layer_norm {
+                norms.push(nn::layer_norm(
+                    &(&layer_path / "norm"),
+                    vec![desc.lstm_outputs() as i64],
+                    Default::default(),
+                ));
+            }
+        }
+        LSTMStack {
+            lstms,
+            norms,
+            residual: desc.residual,
+            state_slices: directions,
+        }
+    }
+    /// Get the slice of a stacked state belonging to the `ix`th LSTM
+    fn layer_state(&self, state: &LSTMState, ix: usize) -> LSTMState {
+        let start = ix as i64 * self.state_slices;
+        LSTMState((
+            state.h().narrow(0, start, self.state_slices),
+            state.c().narrow(0, start, self.state_slices),
+        ))
+    }
+}
+
+/// Concatenate the states of a list of LSTMs into a single stacked state
+fn cat_states(states: &[LSTMState]) -> LSTMState {
+    let hs: Vec<Tensor> = states.iter().map(|state| state.h()).collect();
+    let cs: Vec<Tensor> = states.iter().map(|state| state.c()).collect();
+    LSTMState((Tensor::cat(&hs, 0), Tensor::cat(&cs, 0)))
+}
+
+impl RNN for LSTMStack {
+    type State = LSTMState;
+    fn zero_state(&self, batch_dim: i64) -> LSTMState {
+        let states: Vec<LSTMState> = self
+            .lstms
+            .iter()
+            .map(|lstm| lstm.zero_state(batch_dim))
+            .collect();
+        cat_states(&states)
+    }
+    fn step(&self, input: &Tensor, state: &LSTMState) -> LSTMState {
+        let (_output, state) = self.seq_init(&input.unsqueeze(1), state);
+        state
+    }
+    fn seq_init(&self, input: &Tensor, state: &LSTMState) -> (Tensor, LSTMState) {
+        let mut output = input.shallow_clone();
+        let mut states = Vec::with_capacity(self.lstms.len());
+        for (ix, lstm) in self.lstms.iter().enumerate() {
+            let (hidden, layer_state) = lstm.seq_init(&output, &self.layer_state(state, ix));
+            let hidden = if let Some(norm) = self.norms.get(ix) {
+                norm.forward(&hidden)
+            } else {
+                hidden
+            };
+            output = if self.residual && hidden.size() == output.size() {
+                hidden + &output
+            } else {
+                hidden
+            };
+            states.push(layer_state);
+        }
+        (output, cat_states(&states))
+    }
+}
@@ -0,0 +1,367 @@
+/*!
+Metrics of training and serving, exported in the [Prometheus](https://prometheus.io/) text format, enabled by the
+`metrics` feature
+*/
+use crate::serve::{DriftMonitor, Response, Status};
+use crate::train::{BatchEnd, Callback};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// The upper bounds of the buckets of latency histograms, in seconds
+pub const LATENCY_BUCKETS: [f64; 10] = [
+    0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 1.0,
+];
+
+/// The type of a metric
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum MetricKind {
+    /// A monotonically increasing count
+    Counter,
+    /// A value which may go up and down
+    Gauge,
+    /// A distribution of observations in buckets
+    Histogram,
+}
+
+impl MetricKind {
+    /// Get the Prometheus name of this kind
+    pub fn name(&self) -> &'static str {
+        match self {
+            MetricKind::Counter => "counter",
+            MetricKind::Gauge => "gauge",
+            MetricKind::Histogram => "histogram",
+        }
+    }
+}
+
+/// A histogram of observations, with cumulative bucket counts
+#[derive(Debug, Clone, PartialEq)]
+pub struct Histogram {
+    /// The upper bound of each bucket
+    pub bounds: Vec<f64>,
+    /// The number of observations at most each bound
+    pub counts: Vec<u64>,
+    /// The sum of all observations
+    pub sum: f64,
+    /// The number of observations
+    pub count: u64,
+}
+
+impl Histogram {
+    /// Create an empty histogram with buckets of the given upper bounds, in increasing order
+    pub fn new(bounds: &[f64]) -> Histogram {
+        Histogram {
+            bounds: bounds.to_vec(),
+            counts: vec![0; bounds.len()],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+    /// Record an observation
+    pub fn observe(&mut self, value: f64) {
+        for (bound, count) in self.bounds.iter().zip(self.counts.iter_mut()) {
+            if value <= *bound {
+                *count += 1;
+            }
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+}
+
+/// The values of every metric, keyed by name and rendered labels
+#[derive(Debug, Clone, Default)]
+struct Registry {
+    /// The kind and help text of each metric
+    descriptions: BTreeMap<String, (MetricKind, String)>,
+    /// The value of each counter and gauge
+    values: BTreeMap<(String, String), f64>,
+    /// The value of each histogram
+    histograms: BTreeMap<(String, String), Histogram>,
+}
+
+/// Render labels in the Prometheus format, e.g. `{symbol="AMD"}`, or as an empty string if there are none
+fn render_labels(labels: &[(&str, &str)]) -> String {
+    if labels.is_empty() {
+        return String::new();
+    }
+    let labels: Vec<String> = labels
+        .iter()
+        .map(|(name, value)| {
+            let value = value.replace('\\', "\\\\").replace('"', "\\\"");
+            format!("{}=\"{}\"", name, value)
+        })
+        .collect();
+    format!("{{{}}}", labels.join(","))
+}
+
+/// A shared set of metrics, which can be updated from a training loop or server and rendered for a Prometheus scrape
+/// from another thread
+#[derive(Debug, Clone, Default)]
+pub struct Metrics(Arc<Mutex<Registry>>);
+
+impl Metrics {
+    /// Lock the metrics, recovering them if a user panicked
+    fn lock(&self) -> MutexGuard<Registry> {
+        self.0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+    /// Describe a metric, setting its kind and help text
+    pub fn describe(&self, name: &str, kind: MetricKind, help: &str) {
+        self.lock()
+            .descriptions
+            .insert(name.to_string(), (kind, help.to_string()));
+    }
+    /// Increase a counter by a given amount
+    pub fn inc(&self, name: &str, labels: &[(&str, &str)], by: f64) {
+        *self
+            .lock()
+            .values
+            .entry((name.to_string(), render_labels(labels)))
+            .or_insert(0.0) += by;
+    }
+    /// Set a gauge to a given value
+    pub fn set(&self, name: &str, labels: &[(&str, &str)], value: f64) {
+        self.lock()
+            .values
+            .insert((name.to_string(), render_labels(labels)), value);
+    }
+    /// Record an observation in a histogram, created with the `LATENCY_BUCKETS` if it does not exist yet
+    pub fn observe(&self, name: &str, labels: &[(&str, &str)], value: f64) {
+        self.lock()
+            .histograms
+            .entry((name.to_string(), render_labels(labels)))
+            .or_insert_with(|| Histogram::new(&LATENCY_BUCKETS))
+            .observe(value)
+    }
+    /// Get the value of a counter or gauge, if it has been set
+    pub fn get(&self, name: &str, labels: &[(&str, &str)]) -> Option<f64> {
+        self.lock()
+            .values
+            .get(&(name.to_string(), render_labels(labels)))
+            .copied()
+    }
+    /// Render every metric in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let registry = self.lock();
+        let mut output = String::new();
+        let described = |output: &mut String, name: &str, kind: MetricKind| {
+            let (kind, help) = registry
+                .descriptions
+                .get(name)
+                .map(|(kind, help)| (*kind, help.as_str()))
+                .unwrap_or((kind, ""));
+            if !help.is_empty() {
+                writeln!(output, "# HELP {} {}", name, help).unwrap();
+            }
+            writeln!(output, "# TYPE {} {}", name, kind.name()).unwrap();
+        };
+        let mut last = None;
+        for ((name, labels), value) in registry.values.iter() {
+            if last != Some(name) {
+                described(&mut output, name, MetricKind::Gauge);
+                last = Some(name);
+            }
+            writeln!(output, "{}{} {}", name, labels, value).unwrap();
+        }
+        let mut last = None;
+        for ((name, labels), histogram) in registry.histograms.iter() {
+            if last != Some(name) {
+                described(&mut output, name, MetricKind::Histogram);
+                last = Some(name);
+            }
+            // The `le` label is appended to any existing labels
+            let prefix = labels.trim_end_matches('}');
+            let separator = if prefix.is_empty() { "{" } else { "," };
+            for (bound, count) in histogram.bounds.iter().zip(histogram.counts.iter()) {
+                writeln!(
+                    output,
+                    "{}_bucket{}{}le=\"{}\"}} {}",
+                    name, prefix, separator, bound, count
+                )
+                .unwrap();
+            }
+            writeln!(
+                output,
+                "{}_bucket{}{}le=\"+Inf\"}} {}",
+                name, prefix, separator, histogram.count
+            )
+            .unwrap();
+            writeln!(output, "{}_sum{} {}", name, labels, histogram.sum).unwrap();
+            writeln!(output, "{}_count{} {}", name, labels, histogram.count).unwrap();
+        }
+        output
+    }
+    /// Record the time a training step spent computing, i.e. in the forward and backward passes and optimizer step,
+    /// out of the total time of the step including batching. The fraction of time spent computing is a proxy for
+    /// GPU utilization.
+    pub fn observe_training_step(&self, compute: Duration, total: Duration) {
+        let total = total.as_secs_f64();
+        if total > 0.0 {
+            self.set(
+                "stockburn_train_compute_fraction",
+                &[],
+                compute.as_secs_f64() / total,
+            );
+        }
+    }
+    /// Record the latency of serving a prediction
+    pub fn observe_serving_latency(&self, latency: Duration) {
+        self.observe(
+            "stockburn_serve_latency_seconds",
+            &[],
+            latency.as_secs_f64(),
+        );
+    }
+    /// Record a predictor's diagnostics: its uptime, and each symbol's rolling prediction error and the drift of its
+    /// scaled ticks from the training distribution
+    pub fn record_status(&self, status: &Status) {
+        self.set("stockburn_serve_uptime_seconds", &[], status.uptime_secs);
+        for (symbol, symbol_status) in status.symbols.iter() {
+            let labels = [("symbol", symbol.0.as_str())];
+            if let Some(rmse) = symbol_status.rmse {
+                self.set("stockburn_prediction_rmse", &labels, rmse);
+            }
+            let drift = symbol_status.drift;
+            if let Some(close_drift) = drift.and_then(|drift| drift.close_drift) {
+                self.set("stockburn_scaler_close_drift", &labels, close_drift);
+            }
+            if let Some(volume_drift) = drift.and_then(|drift| drift.volume_drift) {
+                self.set("stockburn_scaler_volume_drift", &labels, volume_drift);
+            }
+        }
+    }
+    /// Record the drift of each symbol's live inputs away from the training distribution: the Kolmogorov-Smirnov
+    /// statistic of each field, and whether any field has drifted
+    pub fn record_drift(&self, monitor: &DriftMonitor) {
+        for (symbol, drift) in monitor.all_drift() {
+            for field in drift {
+                let labels = [
+                    ("symbol", symbol.0.as_str()),
+                    ("field", field.field.as_str()),
+                ];
+                self.set("stockburn_drift_ks_statistic", &labels, field.statistic);
+            }
+            let drifting = monitor.is_drifting(symbol);
+            self.set(
+                "stockburn_drift_detected",
+                &[("symbol", symbol.0.as_str())],
+                if drifting { 1.0 } else { 0.0 },
+            );
+        }
+        let retrain = if monitor.needs_retraining() { 1.0 } else { 0.0 };
+        self.set("stockburn_retraining_needed", &[], retrain);
+    }
+}
+
+/// Route the `/metrics` endpoint to a Prometheus scrape of a set of metrics
+pub fn metrics_route(metrics: &Metrics, path: &str) -> Option<Response> {
+    match path {
+        "/metrics" => Some(Response {
+            content_type: "text/plain; version=0.0.4",
+            body: metrics.render(),
+        }),
+        _ => None,
+    }
+}
+
+/// A callback publishing training throughput, loss and learning rate to a set of metrics
+#[derive(Debug, Clone)]
+pub struct TrainingMetrics {
+    /// The metrics published to
+    pub metrics: Metrics,
+    /// The number of rows in each batch, i.e. the batch size times the sequence length
+    pub rows_per_batch: usize,
+    /// The time of the last batch end, if any
+    last: Option<Instant>,
+}
+
+impl TrainingMetrics {
+    /// Publish training metrics for batches of a given number of rows
+    pub fn new(metrics: Metrics, rows_per_batch: usize) -> TrainingMetrics {
+        metrics.describe(
+            "stockburn_train_batches_total",
+            MetricKind::Counter,
+            "Training batches seen",
+        );
+        metrics.describe(
+            "stockburn_train_rows_total",
+            MetricKind::Counter,
+            "Training rows seen",
+        );
+        metrics.describe(
+            "stockburn_train_compute_fraction",
+            MetricKind::Gauge,
+            "Fraction of training step time spent computing, a proxy for GPU utilization",
+        );
+        TrainingMetrics {
+            metrics,
+            rows_per_batch,
+            last: None,
+        }
+    }
+}
+
+impl Callback for TrainingMetrics {
+    fn on_epoch_start(&mut self, epoch: u64) {
+        self.metrics.set("stockburn_train_epoch", &[], epoch as f64);
+        // Time spent between epochs, e.g. testing, does not count towards throughput
+        self.last = None;
+    }
+    fn on_batch_end(&mut self, batch: &BatchEnd, lr: &mut f64) {
+        let now = Instant::now();
+        let rows = self.rows_per_batch as f64;
+        self.metrics.inc("stockburn_train_batches_total", &[], 1.0);
+        self.metrics.inc("stockburn_train_rows_total", &[], rows);
+        self.metrics.set("stockburn_train_loss", &[], batch.loss);
+        self.metrics.set("stockburn_train_learning_rate", &[], *lr);
+        if let Some(last) = self.last {
+            let elapsed = now.duration_since(last).as_secs_f64();
+            if elapsed > 0.0 {
+                self.metrics
+                    .set("stockburn_train_batches_per_second", &[], 1.0 / elapsed);
+                self.metrics
+                    .set("stockburn_train_rows_per_second", &[], rows / elapsed);
+            }
+        }
+        self.last = Some(now);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn metrics_render_in_prometheus_format() {
+        let metrics = Metrics::default();
+        let mut callback = TrainingMetrics::new(metrics.clone(), 100);
+        let mut lr = 0.01;
+        for batch in 1..=2 {
+            let end = BatchEnd {
+                epoch: 0,
+                batch,
+                loss: 0.5,
+            };
+            callback.on_batch_end(&end, &mut lr);
+        }
+        assert_eq!(metrics.get("stockburn_train_rows_total", &[]), Some(200.0));
+        metrics.set("stockburn_prediction_rmse", &[("symbol", "AMD")], 0.25);
+        metrics.observe_serving_latency(Duration::from_millis(3));
+
+        let rendered = metrics.render();
+        assert!(rendered.contains("# TYPE stockburn_train_batches_total counter\n"));
+        assert!(rendered.contains("stockburn_train_batches_total 2\n"));
+        assert!(rendered.contains("stockburn_prediction_rmse{symbol=\"AMD\"} 0.25\n"));
+        assert!(rendered.contains("stockburn_serve_latency_seconds_bucket{le=\"0.0025\"} 0\n"));
+        assert!(rendered.contains("stockburn_serve_latency_seconds_bucket{le=\"0.005\"} 1\n"));
+        assert!(rendered.contains("stockburn_serve_latency_seconds_count 1\n"));
+        assert_eq!(
+            metrics_route(&metrics, "/metrics").map(|response| response.body),
+            Some(rendered)
+        );
+    }
+}
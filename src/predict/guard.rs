@@ -0,0 +1,313 @@
+/*!
+Sanity checks of live ticks, rejecting or flagging bad prints before they update a predictor's scalers and state
+*/
+use crate::data::calendar::exchange_time;
+use crate::data::Tick;
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Display, Formatter};
+
+/// A violation of a sanity rule by a tick
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Anomaly {
+    /// A price, volume or trade count is not finite, a price is not positive, or a volume or trade count is negative
+    Invalid,
+    /// The tick's timestamp is not after that of the stock's last accepted tick
+    NonMonotonic {
+        /// The time of the last accepted tick
+        previous: NaiveDateTime,
+    },
+    /// The high and low do not contain the open and close
+    InconsistentOhlc,
+    /// The close moved by more than the allowed number of standard deviations of recent log returns
+    Jump {
+        /// The number of standard deviations moved
+        sigmas: f64,
+    },
+}
+
+impl Display for Anomaly {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        match self {
+            Anomaly::Invalid => write!(fmt, "invalid values"),
+            Anomaly::NonMonotonic { previous } => {
+                write!(fmt, "timestamp not after previous tick at {}", previous)
+            }
+            Anomaly::InconsistentOhlc => write!(fmt, "high and low do not contain open and close"),
+            Anomaly::Jump { sigmas } => {
+                write!(fmt, "close jumped {:.1} standard deviations", sigmas)
+            }
+        }
+    }
+}
+
+/// The outcome of checking a tick
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Verdict {
+    /// The tick passed every check
+    Accept,
+    /// The tick violated a rule, but is passed on
+    Flag(Anomaly),
+    /// The tick violated a rule, and should be dropped
+    Reject(Anomaly),
+}
+
+impl Verdict {
+    /// Whether the tick should be passed on to the predictor
+    pub fn accepted(&self) -> bool {
+        !matches!(self, Verdict::Reject(_))
+    }
+    /// The rule the tick violated, if any
+    pub fn anomaly(&self) -> Option<Anomaly> {
+        match self {
+            Verdict::Accept => None,
+            Verdict::Flag(anomaly) | Verdict::Reject(anomaly) => Some(*anomaly),
+        }
+    }
+}
+
+/// The accepted history of a single stock
+#[derive(Debug, Copy, Clone, Default)]
+struct StockState {
+    /// The last accepted tick
+    last: Option<Tick>,
+    /// The moving average of squared close log returns
+    variance: f64,
+    /// The number of returns averaged
+    returns: usize,
+    /// The number of consecutive jumps rejected since the last accepted tick
+    jumps: usize,
+}
+
+/// Checks each stock's live ticks against sanity rules: finite, positive prices, increasing timestamps, consistent
+/// OHLC, and close moves within `max_sigmas` standard deviations of the stock's recent log returns.
+///
+/// Closes are not checked for jumps against a close from an earlier exchange session, since gaps over the open are
+/// genuine. A stock whose close keeps jumping, e.g. after news, is re-anchored at the level it moved to once
+/// `reanchor_after` consecutive jumps were rejected, rather than being rejected for the rest of the run.
+#[derive(Debug, Clone)]
+pub struct TickGuard {
+    /// The largest move of the close allowed, in standard deviations of recent log returns
+    pub max_sigmas: f64,
+    /// The per-tick decay of the moving average of squared log returns
+    pub decay: f64,
+    /// The number of returns to see before checking for jumps
+    pub warmup: usize,
+    /// Whether anomalous ticks are rejected, rather than flagged and passed on
+    pub reject: bool,
+    /// The number of consecutive rejected jumps after which the last of them is taken as the stock's new level
+    pub reanchor_after: usize,
+    /// The number of ticks flagged
+    pub flagged: usize,
+    /// The number of ticks rejected
+    pub rejected: usize,
+    /// The state of each stock
+    stocks: Vec<StockState>,
+}
+
+impl Default for TickGuard {
+    fn default() -> TickGuard {
+        TickGuard::new(8.0, 0.99, 30, true)
+    }
+}
+
+impl TickGuard {
+    /// Create a new guard, checking for jumps of more than `max_sigmas` standard deviations once `warmup` returns have
+    /// been seen, and either rejecting or flagging anomalous ticks. Stocks are re-anchored after 5 consecutive rejected
+    /// jumps.
+    pub fn new(max_sigmas: f64, decay: f64, warmup: usize, reject: bool) -> TickGuard {
+        TickGuard {
+            max_sigmas,
+            decay,
+            warmup,
+            reject,
+            reanchor_after: 5,
+            flagged: 0,
+            rejected: 0,
+            stocks: Vec::new(),
+        }
+    }
+    /// Forget the history of every stock
+    pub fn reset(&mut self) {
+        self.stocks.clear();
+    }
+    /// Find the first rule a stock's tick violates, if any
+    fn anomaly(&self, stock: usize, tick: &Tick) -> Option<Anomaly> {
+        let prices = [tick.o, tick.h, tick.l, tick.c, tick.vw];
+        if prices
+            .iter()
+            .any(|price| !price.is_finite() || *price <= 0.0)
+            || !tick.v.is_finite()
+            || !tick.n.is_finite()
+            || tick.v < 0.0
+            || tick.n < 0.0
+        {
+            return Some(Anomaly::Invalid);
+        }
+        if tick.l > tick.h || tick.h < tick.o.max(tick.c) || tick.l > tick.o.min(tick.c) {
+            return Some(Anomaly::InconsistentOhlc);
+        }
+        let state = self.stocks.get(stock)?;
+        let last = state.last?;
+        if tick.t <= last.t {
+            return Some(Anomaly::NonMonotonic { previous: last.t });
+        }
+        let new_session = exchange_time(tick.t).date() != exchange_time(last.t).date();
+        if !new_session && state.returns >= self.warmup && state.variance > 0.0 {
+            let sigmas = (tick.c / last.c).ln().abs() / state.variance.sqrt();
+            if sigmas > self.max_sigmas {
+                return Some(Anomaly::Jump { sigmas });
+            }
+        }
+        None
+    }
+    /// Check a stock's tick, updating the stock's history unless it is rejected
+    pub fn check(&mut self, stock: usize, tick: &Tick) -> Verdict {
+        let verdict = match self.anomaly(stock, tick) {
+            None => Verdict::Accept,
+            Some(anomaly) if self.reject => Verdict::Reject(anomaly),
+            Some(anomaly) => Verdict::Flag(anomaly),
+        };
+        match verdict {
+            Verdict::Accept => {}
+            Verdict::Flag(_) => self.flagged += 1,
+            Verdict::Reject(anomaly) => {
+                self.rejected += 1;
+                if let Anomaly::Jump { .. } = anomaly {
+                    self.reject_jump(stock, tick);
+                }
+                return verdict;
+            }
+        }
+        if self.stocks.len() <= stock {
+            self.stocks.resize(stock + 1, StockState::default());
+        }
+        let decay = self.decay;
+        let state = &mut self.stocks[stock];
+        state.jumps = 0;
+        match state.last {
+            // Flagged ticks with invalid or out of order values do not contribute to the returns
+            Some(last) if tick.t > last.t && tick.c > 0.0 && tick.c.is_finite() => {
+                let ret = (tick.c / last.c).ln();
+                state.variance = if state.returns == 0 {
+                    ret * ret
+                } else {
+                    decay * state.variance + (1.0 - decay) * ret * ret
+                };
+                state.returns += 1;
+                state.last = Some(*tick);
+            }
+            Some(_) => {}
+            None if tick.c > 0.0 && tick.c.is_finite() => state.last = Some(*tick),
+            None => {}
+        }
+        verdict
+    }
+    /// Count a rejected jump of a stock, taking its tick as the stock's new level once `reanchor_after` consecutive
+    /// jumps were rejected. The jump itself is not averaged into the stock's returns.
+    fn reject_jump(&mut self, stock: usize, tick: &Tick) {
+        let state = &mut self.stocks[stock];
+        state.jumps += 1;
+        if state.jumps >= self.reanchor_after {
+            state.last = Some(*tick);
+            state.jumps = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, NaiveDate};
+
+    #[test]
+    fn guard_rejects_bad_prints() {
+        let start = NaiveDate::from_ymd(2020, 10, 12).and_hms(14, 30, 0);
+        let tick = |minute: i64, c: f64| Tick {
+            t: start + Duration::minutes(minute),
+            o: c,
+            h: c * 1.001,
+            l: c * 0.999,
+            c,
+            v: 100.0,
+            vw: c,
+            n: 10.0,
+        };
+        let mut guard = TickGuard::new(5.0, 0.9, 5, true);
+        for minute in 0..10 {
+            let c = if minute % 2 == 0 { 100.0 } else { 101.0 };
+            assert_eq!(guard.check(0, &tick(minute, c)), Verdict::Accept);
+        }
+        assert_eq!(
+            guard.check(0, &tick(9, 100.0)),
+            Verdict::Reject(Anomaly::NonMonotonic {
+                previous: start + Duration::minutes(9)
+            })
+        );
+        let mut inconsistent = tick(10, 100.0);
+        inconsistent.h = 99.0;
+        assert_eq!(
+            guard.check(0, &inconsistent),
+            Verdict::Reject(Anomaly::InconsistentOhlc)
+        );
+        assert_eq!(
+            guard.check(0, &tick(10, -1.0)),
+            Verdict::Reject(Anomaly::Invalid)
+        );
+        match guard.check(0, &tick(10, 150.0)) {
+            Verdict::Reject(Anomaly::Jump { sigmas }) => assert!(sigmas > 5.0),
+            verdict => panic!("Expected a jump, got {:?}", verdict),
+        }
+        assert_eq!(guard.check(0, &tick(10, 100.5)), Verdict::Accept);
+        assert_eq!(guard.rejected, 4);
+
+        // A new stock has no history to jump from
+        assert_eq!(guard.check(1, &tick(0, 150.0)), Verdict::Accept);
+        guard.reject = false;
+        assert!(guard.check(0, &tick(11, 500.0)).accepted());
+        assert_eq!(guard.flagged, 1);
+    }
+
+    #[test]
+    fn guard_follows_persistent_gaps() {
+        let tick = |t: NaiveDateTime, c: f64| Tick {
+            t,
+            o: c,
+            h: c,
+            l: c,
+            c,
+            v: 100.0,
+            vw: c,
+            n: 10.0,
+        };
+        let mut guard = TickGuard::new(5.0, 0.9, 5, true);
+        guard.reanchor_after = 3;
+        let start = NaiveDate::from_ymd(2020, 10, 12).and_hms(14, 30, 0);
+        let mut minute = 0;
+        let mut next = |c: f64, guard: &mut TickGuard| {
+            minute += 1;
+            guard.check(0, &tick(start + Duration::minutes(minute), c))
+        };
+        for i in 0..10 {
+            let c = if i % 2 == 0 { 100.0 } else { 101.0 };
+            assert_eq!(next(c, &mut guard), Verdict::Accept);
+        }
+
+        // A persistent move on news is rejected a few times, then followed
+        for _ in 0..3 {
+            assert!(!next(130.0, &mut guard).accepted());
+        }
+        assert_eq!(next(130.5, &mut guard), Verdict::Accept);
+        assert_eq!(next(130.0, &mut guard), Verdict::Accept);
+        assert_eq!(guard.rejected, 3);
+
+        // A gap over the open is accepted immediately
+        let open = NaiveDate::from_ymd(2020, 10, 13).and_hms(13, 30, 0);
+        assert_eq!(guard.check(0, &tick(open, 90.0)), Verdict::Accept);
+        assert_eq!(
+            guard.check(0, &tick(open + Duration::minutes(1), 90.5)),
+            Verdict::Accept
+        );
+        assert_eq!(guard.rejected, 3);
+    }
+}
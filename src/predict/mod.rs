@@ -0,0 +1,632 @@
+/*!
+Streaming inference: feed ticks one timestep at a time into a trained `StockLSTM`
+*/
+use crate::data::scale::{
+    QuantileTransformer, TickExpScaler, TickScalerConfig, DEFAULT_AVERAGE_DECAY,
+    DEFAULT_RANGE_DECAY,
+};
+use crate::data::{PredictedTick, Symbol, SymbolRegistry, Tick};
+use crate::lstm::StockLSTM;
+use crate::train::{load_checkpoint, read_preprocessing, read_symbols, Preprocessing};
+use crate::CpuFloat;
+use anyhow::format_err;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::path::Path;
+use tch::nn::{LSTMState, VarStore, RNN};
+use tch::{Device, Tensor};
+
+pub mod guard;
+pub use guard::{Anomaly, TickGuard, Verdict};
+
+/// An estimate of an output, given as a mean and a standard deviation
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct Estimate {
+    /// The mean of the estimate
+    pub mean: f32,
+    /// The standard deviation of the estimate
+    pub std: f32,
+}
+
+/// Make predictions from a stream of ticks, scaling them and keeping track of LSTM state
+#[derive(Debug)]
+pub struct Predictor<DF> {
+    /// The model used for prediction
+    pub lstm: StockLSTM,
+    /// The device the model lives on
+    pub device: Device,
+    /// The time function used to generate date inputs
+    pub time_func: DF,
+    /// The scaler for each stock, created on that stock's first tick
+    pub scalers: Vec<Option<TickExpScaler<CpuFloat>>>,
+    /// The average decay used for new scalers, unless overridden by `scaler_config`
+    pub average_decay: CpuFloat,
+    /// The range decay used for new scalers, unless overridden by `scaler_config`
+    pub range_decay: CpuFloat,
+    /// The parameters of each field of new scalers, overriding the uniform decays, if any
+    pub scaler_config: Option<TickScalerConfig<CpuFloat>>,
+    /// The quantile transform applied to scaled ticks, if any
+    pub quantiles: Option<QuantileTransformer>,
+    /// The symbols of the model's stocks, if known
+    pub symbols: Option<SymbolRegistry>,
+    /// The sanity checks raw ticks must pass before they are scaled and fed in, if any
+    pub guard: Option<TickGuard>,
+    /// The current LSTM state
+    pub state: LSTMState,
+    /// The LSTM state before the last input row was fed in
+    prev_state: LSTMState,
+    /// The last input row fed in, if any
+    last_input: Option<Tensor>,
+    /// The output for the last input row, if any
+    last_output: Option<Vec<f32>>,
+    /// The tick of each stock as fed in with the last input row, if any
+    last_scaled: Vec<Option<Tick>>,
+}
+
+impl<DF> Predictor<DF>
+where
+    DF: FnMut(DateTime<Utc>, &mut Vec<f32>),
+{
+    /// Create a new predictor with a zero initial state, scaling every field of each tick with the same decays
+    pub fn new(
+        lstm: StockLSTM,
+        device: Device,
+        time_func: DF,
+        average_decay: CpuFloat,
+        range_decay: CpuFloat,
+    ) -> Predictor<DF> {
+        let state = lstm.zero_state(1);
+        let prev_state = lstm.zero_state(1);
+        let scalers = vec![None; lstm.stocks];
+        let last_scaled = vec![None; lstm.stocks];
+        Predictor {
+            lstm,
+            device,
+            time_func,
+            scalers,
+            average_decay,
+            range_decay,
+            scaler_config: None,
+            quantiles: None,
+            symbols: None,
+            guard: None,
+            state,
+            prev_state,
+            last_input: None,
+            last_output: None,
+            last_scaled,
+        }
+    }
+    /// Load a checkpoint saved by `train::save_checkpoint` onto a device to predict from, given the path of its
+    /// variables, preprocessing ticks as in training and labelling stocks with the symbols saved alongside it, if any.
+    /// Checkpoints saved without their preprocessing are scaled with the default decays. Returns the `VarStore`
+    /// holding the model's variables together with the predictor.
+    pub fn from_checkpoint<P: AsRef<Path>>(
+        path: P,
+        device: Device,
+        time_func: DF,
+    ) -> anyhow::Result<(VarStore, Predictor<DF>)> {
+        let path = path.as_ref();
+        let (vs, lstm) = load_checkpoint(path, device)?;
+        let preprocessing = read_preprocessing(path)?.unwrap_or_default();
+        let mut predictor = Predictor::new(
+            lstm,
+            device,
+            time_func,
+            DEFAULT_AVERAGE_DECAY,
+            DEFAULT_RANGE_DECAY,
+        )
+        .with_preprocessing(preprocessing);
+        if let Some(symbols) = read_symbols(path)? {
+            predictor = predictor.with_symbols(symbols)?;
+        }
+        Ok((vs, predictor))
+    }
+    /// Reset this predictor's LSTM state and scalers
+    pub fn reset(&mut self) {
+        self.state = self.lstm.zero_state(1);
+        self.prev_state = self.lstm.zero_state(1);
+        for scaler in self.scalers.iter_mut() {
+            *scaler = None;
+        }
+        if let Some(guard) = &mut self.guard {
+            guard.reset();
+        }
+        self.last_input = None;
+        self.last_output = None;
+        for scaled in self.last_scaled.iter_mut() {
+            *scaled = None;
+        }
+    }
+    /// Move this predictor's model, whose variables live in a given `VarStore`, and its state to a device, returning
+    /// a new `VarStore` on that device holding the model's variables
+    pub fn to_device(&mut self, vs: &VarStore, device: Device) -> anyhow::Result<VarStore> {
+        let (new_vs, lstm) = self.lstm.to_device(vs, device)?;
+        let move_state = |state: &LSTMState| {
+            LSTMState((state.h().to_device(device), state.c().to_device(device)))
+        };
+        self.state = move_state(&self.state);
+        self.prev_state = move_state(&self.prev_state);
+        self.last_input = self
+            .last_input
+            .as_ref()
+            .map(|input| input.to_device(device));
+        self.lstm = lstm;
+        self.device = device;
+        Ok(new_vs)
+    }
+    /// Scale a raw tick for a given stock, updating that stock's scaler, and apply the quantile transform, if any.
+    /// Returns `None` while the scaler is warming up.
+    fn scale(&mut self, stock: usize, tick: Tick) -> Option<Tick> {
+        let config = self.new_scaler_config();
+        let scaled = self.scalers[stock]
+            .get_or_insert_with(|| TickExpScaler::with_config(tick, &config))
+            .warm_tick(tick)?;
+        Some(self.transform(scaled))
+    }
+    /// Apply the quantile transform to a scaled tick, if any
+    fn transform(&self, scaled: Tick) -> Tick {
+        match &self.quantiles {
+            Some(quantiles) => quantiles.transform_tick(&scaled),
+            None => scaled,
+        }
+    }
+    /// Get the parameters new scalers are created with
+    fn new_scaler_config(&self) -> TickScalerConfig<CpuFloat> {
+        self.scaler_config
+            .unwrap_or_else(|| TickScalerConfig::uniform(self.average_decay, self.range_decay))
+    }
+    /// Check a raw tick for a given stock against this predictor's guard, if any, returning whether it should be fed
+    /// in. Rejected ticks are treated as missing.
+    fn check(&mut self, stock: usize, tick: &Tick) -> bool {
+        let verdict = match &mut self.guard {
+            Some(guard) => guard.check(stock, tick),
+            None => return true,
+        };
+        match &self.symbols {
+            Some(symbols) => warn_anomaly(&symbols.symbols()[stock], tick, verdict),
+            None => warn_anomaly(&format!("stock {}", stock), tick, verdict),
+        }
+        verdict.accepted()
+    }
+    /// Push a row's additional inputs, truncated or zero filled to the model's number of additional inputs
+    fn push_additional(&self, additional: &[f32], dest: &mut Vec<f32>) {
+        let truncate_additional = additional.len().min(self.lstm.additional_inputs);
+        dest.extend_from_slice(&additional[..truncate_additional]);
+        let additional_fill = self.lstm.additional_inputs - truncate_additional;
+        dest.extend(std::iter::repeat(0.0).take(additional_fill));
+    }
+    /// Build an input row for a timestep, given raw ticks for each stock at that time and additional inputs
+    fn input_row(
+        &mut self,
+        t: NaiveDateTime,
+        ticks: &[Option<Tick>],
+        additional: &[f32],
+    ) -> Vec<f32> {
+        assert_eq!(
+            ticks.len(),
+            self.lstm.stocks,
+            "Wrong number of input stocks!"
+        );
+        let mut input = Vec::with_capacity(self.lstm.no_inputs());
+        self.push_additional(additional, &mut input);
+        (self.time_func)(DateTime::from_utc(t, Utc), &mut input);
+        for (stock, tick) in ticks.iter().enumerate() {
+            let scaled = tick
+                .filter(|tick| self.check(stock, tick))
+                .and_then(|tick| self.scale(stock, tick));
+            if let Some(scaled) = scaled {
+                scaled.push_tick(&mut input);
+            } else {
+                input.extend(std::iter::repeat(0.0).take(Tick::NN_FIELDS));
+            }
+            self.last_scaled[stock] = scaled;
+        }
+        input
+    }
+    /// Feed in the raw ticks for each stock at a given time (`None` for stocks without a tick), returning the
+    /// network's outputs, laid out as in `StockLSTM::targets`
+    pub fn push(&mut self, t: NaiveDateTime, ticks: &[Option<Tick>], additional: &[f32]) -> &[f32] {
+        let row = self.input_row(t, ticks, additional);
+        let input = Tensor::of_slice(&row)
+            .view([1, 1, row.len() as i64])
+            .to_device(self.device);
+        let (output, state) = tch::no_grad(|| self.lstm.forward_t(&input, &self.state, false));
+        self.prev_state = std::mem::replace(&mut self.state, state);
+        self.last_input = Some(input);
+        let output = Vec::<f32>::from(&output.view([-1]));
+        self.last_output = Some(output);
+        self.last_output.as_ref().expect("Just set")
+    }
+    /// Label the stocks of this predictor's model with symbols, e.g. those read alongside its checkpoint by
+    /// `train::read_symbols`, so that ticks and predictions can be keyed by symbol. Returns an error if the registry
+    /// does not have one symbol per stock.
+    pub fn with_symbols(mut self, symbols: SymbolRegistry) -> anyhow::Result<Predictor<DF>> {
+        if symbols.len() != self.lstm.stocks {
+            return Err(format_err!(
+                "Model has {} stocks, but {} symbols were given",
+                self.lstm.stocks,
+                symbols.len()
+            ));
+        }
+        self.symbols = Some(symbols);
+        Ok(self)
+    }
+    /// Scale each field of new stocks' ticks with its own parameters, e.g. to decay volume and trade counts faster
+    /// than prices. Ticks in a scaler's warm-up period are treated as missing. Scalers which already exist are kept
+    /// until the next `reset`.
+    pub fn with_scaler_config(mut self, config: TickScalerConfig<CpuFloat>) -> Predictor<DF> {
+        self.scaler_config = Some(config);
+        self
+    }
+    /// Map each field of scaled ticks through a quantile transform fitted by `QuantileTransformer::fit_ticks` before
+    /// feeding them in. Panics if the transform is not of ticks.
+    pub fn with_quantiles(mut self, quantiles: QuantileTransformer) -> Predictor<DF> {
+        assert_eq!(
+            quantiles.features(),
+            Tick::NN_FIELDS,
+            "Not a transform of ticks"
+        );
+        self.quantiles = Some(quantiles);
+        self
+    }
+    /// Preprocess ticks as a model's inputs were in training, e.g. as read alongside its checkpoint by
+    /// `train::read_preprocessing`. Scalers which already exist are kept until the next `reset`.
+    pub fn with_preprocessing(mut self, preprocessing: Preprocessing) -> Predictor<DF> {
+        self.quantiles = None;
+        if let Some(quantiles) = preprocessing.quantiles {
+            self = self.with_quantiles(quantiles);
+        }
+        self.with_scaler_config(preprocessing.scaler)
+    }
+    /// Check raw ticks against a guard before they update this predictor's scalers and state, dropping rejected ticks
+    /// as if they were missing
+    pub fn with_guard(mut self, guard: TickGuard) -> Predictor<DF> {
+        self.guard = Some(guard);
+        self
+    }
+    /// Feed in the raw ticks of each symbol at a given time, zero filling registered symbols without a tick and
+    /// ignoring unregistered ones, returning the network's outputs as in `push`. Panics if the predictor has no
+    /// symbols.
+    pub fn push_symbols(
+        &mut self,
+        t: NaiveDateTime,
+        ticks: &BTreeMap<Symbol, Tick>,
+        additional: &[f32],
+    ) -> &[f32] {
+        let ticks = self
+            .symbols
+            .as_ref()
+            .expect("Pushing ticks by symbol needs a predictor with symbols!")
+            .arrange(ticks);
+        self.push(t, &ticks, additional)
+    }
+    /// Decode the network's outputs for the last timestep fed in into a prediction for each symbol. Returns `None` if
+    /// no timestep has been fed in yet or the predictor has no symbols.
+    pub fn last_predictions(&self) -> Option<BTreeMap<Symbol, PredictedTick>> {
+        let predicted = self.lstm.predicted_ticks(self.last_output()?)?;
+        Some(self.symbols.as_ref()?.label(predicted))
+    }
+    /// Predict the outputs after the latest tick of each of many symbols at once, given a window of raw ticks for each
+    /// and the additional inputs of every row of the window, e.g. the symbol's embedding and static features as fed by
+    /// `train::SymbolSampler`, returning each symbol's outputs, laid out as in `StockLSTM::targets`. Additional inputs
+    /// are truncated or zero filled as in `push`.
+    ///
+    /// Every window is packed as a sequence of one batch, zero padded at the end, so that a single forward pass from
+    /// a zero state covers every symbol. Each window is checked against a fresh copy of this predictor's guard, if
+    /// any, and scaled from its first accepted tick, independently of this predictor's streaming state, which is left
+    /// untouched apart from the guard's counts. Symbols with empty windows are skipped. Returns an error unless the
+    /// model has a single stock and is unidirectional.
+    pub fn predict_batch(
+        &mut self,
+        windows: &[(Symbol, &[Tick], &[f32])],
+    ) -> anyhow::Result<BTreeMap<Symbol, Vec<f32>>> {
+        if self.lstm.stocks != 1 {
+            return Err(format_err!(
+                "Batch inference packs one symbol per sequence, but the model has {} stocks",
+                self.lstm.stocks
+            ));
+        }
+        if self.lstm.desc.bidirectional {
+            return Err(format_err!(
+                "Batch inference zero pads sequences at the end, but the model is bidirectional"
+            ));
+        }
+        let windows: Vec<_> = windows
+            .iter()
+            .filter(|(_, ticks, _)| !ticks.is_empty())
+            .collect();
+        let batch_size = windows.len();
+        let sequence_length = windows
+            .iter()
+            .map(|(_, ticks, _)| ticks.len())
+            .max()
+            .unwrap_or(0);
+        if batch_size == 0 {
+            return Ok(BTreeMap::new());
+        }
+
+        // Pack each window into its own sequence, leaving padding rows zero
+        let features = self.lstm.no_inputs();
+        let mut input = vec![0.0f32; batch_size * sequence_length * features];
+        let mut row = Vec::with_capacity(features);
+        let config = self.new_scaler_config();
+        for (sequence, (symbol, ticks, additional)) in windows.iter().enumerate() {
+            let mut guard = self.guard.clone().map(|mut guard| {
+                guard.reset();
+                guard
+            });
+            let mut scaler: Option<TickExpScaler<CpuFloat>> = None;
+            for (ix, tick) in ticks.iter().enumerate() {
+                row.clear();
+                self.push_additional(additional, &mut row);
+                (self.time_func)(DateTime::from_utc(tick.t, Utc), &mut row);
+                let accepted = match &mut guard {
+                    Some(guard) => {
+                        let verdict = guard.check(0, tick);
+                        warn_anomaly(symbol, tick, verdict);
+                        verdict.accepted()
+                    }
+                    None => true,
+                };
+                let scaled = if accepted {
+                    scaler
+                        .get_or_insert_with(|| TickExpScaler::with_config(*tick, &config))
+                        .warm_tick(*tick)
+                } else {
+                    None
+                };
+                match scaled {
+                    Some(scaled) => self.transform(scaled).push_tick(&mut row),
+                    None => row.extend(std::iter::repeat(0.0).take(Tick::NN_FIELDS)),
+                }
+                let start = (sequence * sequence_length + ix) * features;
+                input[start..start + features].copy_from_slice(&row);
+            }
+            if let (Some(counts), Some(guard)) = (guard, &mut self.guard) {
+                guard.flagged += counts.flagged;
+                guard.rejected += counts.rejected;
+            }
+        }
+        let input = Tensor::of_slice(&input)
+            .view([batch_size as i64, sequence_length as i64, features as i64])
+            .to_device(self.device);
+
+        // Run a single forward pass, and scatter the output of each window's last row
+        let state = self.lstm.zero_state(batch_size as i64);
+        let (output, _state) = tch::no_grad(|| self.lstm.forward_t(&input, &state, false));
+        let output = Vec::<f32>::from(&output.view([-1]));
+        let no_outputs = self.lstm.no_outputs();
+        Ok(windows
+            .iter()
+            .enumerate()
+            .map(|(sequence, (symbol, ticks, _))| {
+                let start = (sequence * sequence_length + ticks.len() - 1) * no_outputs;
+                (symbol.clone(), output[start..start + no_outputs].to_vec())
+            })
+            .collect())
+    }
+    /// Get the network's outputs for the last timestep fed in, if any
+    pub fn last_output(&self) -> Option<&[f32]> {
+        self.last_output.as_deref()
+    }
+    /// Get the tick of each stock exactly as fed in with the last timestep, i.e. scaled and then quantile transformed,
+    /// with `None` for stocks without a tick, whose tick was rejected by the guard, or whose scaler was warming up
+    pub fn last_scaled(&self) -> &[Option<Tick>] {
+        &self.last_scaled
+    }
+    /// Get the tick of each symbol exactly as fed in with the last timestep, as in `last_scaled`, skipping symbols
+    /// without one. Returns `None` if the predictor has no symbols.
+    pub fn last_scaled_symbols(&self) -> Option<BTreeMap<Symbol, Tick>> {
+        let symbols = self.symbols.as_ref()?;
+        Some(
+            symbols
+                .label(self.last_scaled.iter().copied())
+                .into_iter()
+                .filter_map(|(symbol, scaled)| Some((symbol, scaled?)))
+                .collect(),
+        )
+    }
+    /// Estimate the uncertainty of the outputs for the last timestep fed in using Monte Carlo dropout, by running
+    /// `n_samples` forward passes with dropout enabled.
+    ///
+    /// Returns `None` if no timestep has been fed in yet. Note the model must have a nonzero dropout probability, e.g.
+    /// from its descriptor, for the estimate to be meaningful.
+    pub fn predict_with_uncertainty(&self, n_samples: usize) -> Option<Vec<Estimate>> {
+        let input = self.last_input.as_ref()?;
+        let n = n_samples.max(1) as i64;
+        let inputs = input.repeat(&[n, 1, 1]);
+        let state = LSTMState((
+            self.prev_state.h().repeat(&[1, n, 1]),
+            self.prev_state.c().repeat(&[1, n, 1]),
+        ));
+        let (outputs, _state) = tch::no_grad(|| self.lstm.forward_t(&inputs, &state, true));
+        let outputs = Vec::<f32>::from(&outputs.view([-1]));
+        let no_outputs = self.lstm.no_outputs();
+        let mut estimates = vec![Estimate::default(); no_outputs];
+        for sample in outputs.chunks(no_outputs) {
+            for (estimate, output) in estimates.iter_mut().zip(sample) {
+                estimate.mean += output;
+            }
+        }
+        for estimate in estimates.iter_mut() {
+            estimate.mean /= n as f32;
+        }
+        for sample in outputs.chunks(no_outputs) {
+            for (estimate, output) in estimates.iter_mut().zip(sample) {
+                estimate.std += (output - estimate.mean).powi(2);
+            }
+        }
+        for estimate in estimates.iter_mut() {
+            estimate.std = (estimate.std / n as f32).sqrt();
+        }
+        Some(estimates)
+    }
+}
+
+/// Warn of a tick flagged or rejected by a guard, if it was
+fn warn_anomaly(stock: &dyn Display, tick: &Tick, verdict: Verdict) {
+    if let Some(anomaly) = verdict.anomaly() {
+        let action = if verdict.accepted() {
+            "Flagged"
+        } else {
+            "Rejected"
+        };
+        warn!("{} tick of {} at {}: {}", action, stock, tick.t, anomaly);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lstm::StockLSTMDesc;
+    use crate::train::{save_checkpoint, write_symbols};
+    use chrono::NaiveDate;
+
+    #[test]
+    fn dropout_gives_uncertainty() {
+        tch::manual_seed(1);
+        let vs = VarStore::new(Device::Cpu);
+        let lstm = StockLSTMDesc {
+            additional_inputs: 2,
+            hidden: 16,
+            dropout: 0.5,
+            ..Default::default()
+        }
+        .build(&vs);
+        let mut predictor =
+            Predictor::new(lstm, Device::Cpu, |_, _: &mut Vec<f32>| {}, 0.999, 0.999);
+        assert!(predictor.predict_with_uncertainty(8).is_none());
+        let t = NaiveDate::from_ymd(2020, 10, 12).and_hms(14, 30, 0);
+        let outputs = predictor.push(t, &[None], &[1.0, -1.0]).len();
+        let estimates = predictor.predict_with_uncertainty(64).unwrap();
+        assert_eq!(estimates.len(), outputs);
+        assert!(estimates.iter().all(|estimate| estimate.std > 0.0));
+    }
+
+    #[test]
+    fn checkpoints_are_preprocessed_as_in_training() {
+        let desc = StockLSTMDesc {
+            stocks: 2,
+            hidden: 4,
+            ..Default::default()
+        };
+        let vs = VarStore::new(Device::Cpu);
+        desc.build(&vs);
+        let dir = tempfile::tempdir().unwrap();
+        let preprocessing = Preprocessing {
+            scaler: TickScalerConfig::uniform(0.9, 0.99).with_warmup(2),
+            quantiles: Some(QuantileTransformer {
+                references: vec![vec![-1.0, 0.0, 1.0]; Tick::NN_FIELDS],
+            }),
+        };
+        let path = save_checkpoint(&vs, &desc, &preprocessing, &[], dir.path(), "test").unwrap();
+        let registry =
+            SymbolRegistry::new(vec![Symbol::from("AMD"), Symbol::from("NVDA")]).unwrap();
+        write_symbols(&registry, &path).unwrap();
+        let (_vs, predictor) =
+            Predictor::from_checkpoint(&path, Device::Cpu, |_, _: &mut Vec<f32>| {}).unwrap();
+        assert_eq!(predictor.lstm.desc, desc);
+        assert_eq!(predictor.scaler_config, Some(preprocessing.scaler));
+        assert_eq!(predictor.quantiles, preprocessing.quantiles);
+        assert_eq!(predictor.symbols, Some(registry));
+    }
+
+    #[test]
+    fn batches_match_streaming() {
+        let vs = VarStore::new(Device::Cpu);
+        let lstm = StockLSTMDesc {
+            additional_inputs: 2,
+            hidden: 4,
+            ..Default::default()
+        }
+        .build(&vs);
+        let mut predictor = Predictor::new(lstm, Device::Cpu, |_, _: &mut Vec<f32>| {}, 0.9, 0.9);
+        let t = NaiveDate::from_ymd(2020, 10, 12).and_hms(14, 30, 0);
+        let ticks: Vec<Tick> = (0..5)
+            .map(|m| Tick {
+                t: t + chrono::Duration::minutes(m),
+                o: 10.0 + m as f64,
+                h: 11.0 + m as f64,
+                l: 9.0 + m as f64,
+                c: 10.0 + (m * m) as f64,
+                v: 100.0 * (m + 1) as f64,
+                vw: 10.0 + m as f64,
+                n: 5.0 + m as f64,
+            })
+            .collect();
+        // Each symbol's static features are fed as additional inputs of every row, as by `SymbolSampler`
+        let windows = [
+            (Symbol::from("AMD"), &ticks[..3], &[1.0, 0.0][..]),
+            (Symbol::from("INTC"), &[][..], &[0.0, 1.0][..]),
+            (Symbol::from("NVDA"), &ticks[1..], &[0.0, 1.0][..]),
+        ];
+        let batch = predictor.predict_batch(&windows).unwrap();
+        assert_eq!(batch.len(), 2);
+        for (symbol, window, features) in windows.iter().filter(|(_, window, _)| !window.is_empty())
+        {
+            predictor.reset();
+            for tick in window.iter() {
+                predictor.push(tick.t, &[Some(*tick)], features);
+            }
+            for (batched, streamed) in batch[symbol].iter().zip(predictor.last_output().unwrap()) {
+                assert!((batched - streamed).abs() < 1e-5, "{}", symbol);
+            }
+        }
+    }
+
+    #[test]
+    fn batches_need_single_stock_models() {
+        let vs = VarStore::new(Device::Cpu);
+        let lstm = StockLSTMDesc {
+            stocks: 2,
+            hidden: 4,
+            ..Default::default()
+        }
+        .build(&vs);
+        let mut predictor = Predictor::new(lstm, Device::Cpu, |_, _: &mut Vec<f32>| {}, 0.9, 0.9);
+        assert!(predictor.predict_batch(&[]).is_err());
+    }
+
+    #[test]
+    fn batches_are_guarded_as_streams() {
+        let vs = VarStore::new(Device::Cpu);
+        let lstm = StockLSTMDesc {
+            hidden: 4,
+            ..Default::default()
+        }
+        .build(&vs);
+        let mut predictor = Predictor::new(lstm, Device::Cpu, |_, _: &mut Vec<f32>| {}, 0.9, 0.9)
+            .with_guard(TickGuard::default());
+        let t = NaiveDate::from_ymd(2020, 10, 12).and_hms(14, 30, 0);
+        let mut ticks: Vec<Tick> = (0..5)
+            .map(|m| Tick {
+                t: t + chrono::Duration::minutes(m),
+                o: 10.0 + m as f64,
+                h: 11.0 + m as f64,
+                l: 9.0 + m as f64,
+                c: 10.5 + m as f64,
+                v: 100.0 * (m + 1) as f64,
+                vw: 10.0 + m as f64,
+                n: 5.0 + m as f64,
+            })
+            .collect();
+        ticks[2].c = -1.0;
+        let batch = predictor
+            .predict_batch(&[(Symbol::from("AMD"), &ticks[..], &[][..])])
+            .unwrap();
+        assert_eq!(predictor.guard.as_ref().unwrap().rejected, 1);
+        predictor.reset();
+        for tick in ticks.iter() {
+            predictor.push(tick.t, &[Some(*tick)], &[]);
+        }
+        assert_eq!(predictor.guard.as_ref().unwrap().rejected, 2);
+        for (batched, streamed) in batch[&Symbol::from("AMD")]
+            .iter()
+            .zip(predictor.last_output().unwrap())
+        {
+            assert!((batched - streamed).abs() < 1e-5);
+        }
+    }
+}
@@ -0,0 +1,371 @@
+/*!
+Detecting drift of the live distribution of scaled features away from the distribution a model was trained on, using
+the two-sample Kolmogorov-Smirnov statistic of each field over consecutive, non-overlapping windows of live ticks
+*/
+use crate::data::{Symbol, Tick};
+use crate::predict::Predictor;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Get the two-sample Kolmogorov-Smirnov statistic, i.e. the largest absolute difference between the empirical
+/// distribution functions of two samples. The reference sample must be sorted. Returns zero if either sample is empty,
+/// and ignores values which are not finite.
+pub fn ks_statistic(reference: &[f64], sample: &[f64]) -> f64 {
+    let mut sample: Vec<f64> = sample.iter().copied().filter(|x| x.is_finite()).collect();
+    if reference.is_empty() || sample.is_empty() {
+        return 0.0;
+    }
+    sample.sort_by(|a, b| a.partial_cmp(b).expect("Finite"));
+    let (n, m) = (reference.len() as f64, sample.len() as f64);
+    let (mut i, mut j) = (0, 0);
+    let mut statistic: f64 = 0.0;
+    while i < reference.len() && j < sample.len() {
+        // Step past every copy of the smallest remaining value in both samples before comparing
+        let x = reference[i].min(sample[j]);
+        while i < reference.len() && reference[i] <= x {
+            i += 1;
+        }
+        while j < sample.len() && sample[j] <= x {
+            j += 1;
+        }
+        statistic = statistic.max((i as f64 / n - j as f64 / m).abs());
+    }
+    statistic
+}
+
+/// Get the critical value of the two-sample Kolmogorov-Smirnov statistic at a significance level `alpha`, for samples
+/// of (effective) sizes `n` and `m`, from its asymptotic distribution
+pub fn ks_critical_value(alpha: f64, n: f64, m: f64) -> f64 {
+    (-(alpha / 2.0).ln() / 2.0).sqrt() * ((n + m) / (n * m)).sqrt()
+}
+
+/// Get the effective size of a sample of consecutive, autocorrelated values, `n (1 - rho) / (1 + rho)` for a lag-one
+/// autocorrelation `rho`, which is taken to be at least zero. Values which are not finite are ignored, and the effective
+/// size is at least one for a nonempty sample.
+pub fn effective_sample_size(sample: &[f64]) -> f64 {
+    let sample: Vec<f64> = sample.iter().copied().filter(|x| x.is_finite()).collect();
+    if sample.is_empty() {
+        return 0.0;
+    }
+    let n = sample.len() as f64;
+    let mean = sample.iter().sum::<f64>() / n;
+    let variance: f64 = sample.iter().map(|x| (x - mean).powi(2)).sum();
+    if variance == 0.0 {
+        return n;
+    }
+    let covariance: f64 = sample
+        .windows(2)
+        .map(|pair| (pair[0] - mean) * (pair[1] - mean))
+        .sum();
+    let rho = (covariance / variance).max(0.0);
+    (n * (1.0 - rho) / (1.0 + rho)).max(1.0)
+}
+
+/// A sample of each scaled field of the ticks a model was trained on, against which live ticks are compared
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TrainingDistribution {
+    /// The sorted sample of each field, in the order of `Tick::NN_FIELD_NAMES`
+    pub fields: Vec<Vec<f64>>,
+}
+
+impl TrainingDistribution {
+    /// Sample the distribution of scaled training ticks, keeping at most `max_samples` evenly spaced ticks
+    pub fn new(ticks: &[Tick], max_samples: usize) -> TrainingDistribution {
+        let max_samples = max_samples.max(1);
+        let step = ((ticks.len() + max_samples - 1) / max_samples).max(1);
+        let mut fields = vec![Vec::new(); Tick::NN_FIELDS];
+        for tick in ticks.iter().step_by(step) {
+            for (field, value) in fields.iter_mut().zip(tick.nn_fields().iter()) {
+                if value.is_finite() {
+                    field.push(*value);
+                }
+            }
+        }
+        for field in fields.iter_mut() {
+            field.sort_by(|a, b| a.partial_cmp(b).expect("Finite"));
+        }
+        TrainingDistribution { fields }
+    }
+    /// Sample the distribution of the scaled training ticks of every symbol, pooled, keeping at most `max_samples`
+    /// evenly spaced ticks of each symbol
+    pub fn pooled<'a>(
+        ticks: impl IntoIterator<Item = &'a [Tick]>,
+        max_samples: usize,
+    ) -> TrainingDistribution {
+        let mut pooled = vec![Vec::new(); Tick::NN_FIELDS];
+        for ticks in ticks {
+            let sampled = TrainingDistribution::new(ticks, max_samples);
+            for (field, values) in pooled.iter_mut().zip(sampled.fields) {
+                field.extend(values);
+            }
+        }
+        for field in pooled.iter_mut() {
+            field.sort_by(|a, b| a.partial_cmp(b).expect("Finite"));
+        }
+        TrainingDistribution { fields: pooled }
+    }
+    /// Get the mean and standard deviation of a field, given by its index in `Tick::NN_FIELD_NAMES`, if it has a
+    /// sample with a nonzero standard deviation
+    pub fn moments(&self, field: usize) -> Option<(f64, f64)> {
+        let sample = self.fields.get(field)?;
+        if sample.is_empty() {
+            return None;
+        }
+        let n = sample.len() as f64;
+        let mean = sample.iter().sum::<f64>() / n;
+        let variance = sample.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / n;
+        if variance > 0.0 {
+            Some((mean, variance.sqrt()))
+        } else {
+            None
+        }
+    }
+}
+
+/// The drift of a single field of a symbol's live ticks
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FieldDrift {
+    /// The name of the field, as in `Tick::NN_FIELD_NAMES`
+    pub field: String,
+    /// The Kolmogorov-Smirnov statistic of the live window against the training distribution
+    pub statistic: f64,
+    /// The critical value the statistic is compared against, given the effective size of the live window
+    pub critical: f64,
+    /// Whether the statistic exceeds its critical value
+    pub drifted: bool,
+}
+
+/// Monitors the scaled ticks fed to a live model for drift away from its training distribution, warning when a
+/// symbol starts drifting. Drift of any field suggests the model should be retrained.
+///
+/// Each symbol's ticks are tested in consecutive, non-overlapping windows, so that each tick is only tested once, and
+/// since consecutive ticks are autocorrelated, each window is tested at its effective sample size rather than its
+/// length.
+#[derive(Debug, Clone)]
+pub struct DriftMonitor {
+    /// The distribution of the scaled training ticks
+    pub reference: TrainingDistribution,
+    /// The number of consecutive live ticks of each symbol compared against the training distribution at once
+    pub window: usize,
+    /// The significance level at which a field is flagged as drifted
+    pub alpha: f64,
+    /// The scaled ticks of each symbol's current window
+    windows: BTreeMap<Symbol, Vec<Tick>>,
+    /// The drift of each field of each symbol in its last full window
+    drift: BTreeMap<Symbol, Vec<FieldDrift>>,
+}
+
+impl DriftMonitor {
+    /// Monitor drift away from a training distribution over windows of a given number of ticks, at a significance
+    /// level `alpha`
+    pub fn new(reference: TrainingDistribution, window: usize, alpha: f64) -> DriftMonitor {
+        DriftMonitor {
+            reference,
+            window,
+            alpha,
+            windows: BTreeMap::new(),
+            drift: BTreeMap::new(),
+        }
+    }
+    /// Record a symbol's scaled tick, returning the drift of each of its fields if this fills its window, in which case
+    /// a new window is started
+    pub fn push(&mut self, symbol: &Symbol, scaled: Tick) -> Option<&[FieldDrift]> {
+        let window = self.windows.entry(symbol.clone()).or_default();
+        window.push(scaled);
+        if window.len() < self.window || self.window == 0 {
+            return None;
+        }
+        let window = std::mem::replace(window, Vec::with_capacity(self.window));
+        let was_drifting = self.is_drifting(symbol);
+        let mut live = vec![Vec::with_capacity(window.len()); Tick::NN_FIELDS];
+        for tick in window.iter() {
+            for (field, value) in live.iter_mut().zip(tick.nn_fields().iter()) {
+                field.push(*value);
+            }
+        }
+        let drift: Vec<FieldDrift> = Tick::NN_FIELD_NAMES
+            .iter()
+            .zip(self.reference.fields.iter().zip(live.iter()))
+            .map(|(name, (reference, live))| {
+                let statistic = ks_statistic(reference, live);
+                let critical = ks_critical_value(
+                    self.alpha,
+                    reference.len() as f64,
+                    effective_sample_size(live),
+                );
+                FieldDrift {
+                    field: name.to_string(),
+                    statistic,
+                    critical,
+                    drifted: !reference.is_empty() && statistic > critical,
+                }
+            })
+            .collect();
+        let drifting: Vec<&str> = drift
+            .iter()
+            .filter(|field| field.drifted)
+            .map(|field| field.field.as_str())
+            .collect();
+        if !drifting.is_empty() && !was_drifting {
+            warn!(
+                "Live inputs of {} have drifted from the training distribution in {:?}, consider retraining",
+                symbol, drifting
+            );
+        } else if drifting.is_empty() && was_drifting {
+            debug!("Live inputs of {} are no longer drifting", symbol);
+        }
+        self.drift.insert(symbol.clone(), drift);
+        self.drift.get(symbol).map(|drift| &drift[..])
+    }
+    /// Record the ticks a predictor with symbols was fed with its last timestep, exactly as fed, i.e. scaled and then
+    /// quantile transformed. Symbols whose tick was missing, rejected or warming up are skipped.
+    pub fn observe<DF>(&mut self, predictor: &Predictor<DF>)
+    where
+        DF: FnMut(DateTime<Utc>, &mut Vec<f32>),
+    {
+        for (symbol, scaled) in predictor.last_scaled_symbols().unwrap_or_default() {
+            self.push(&symbol, scaled);
+        }
+    }
+    /// Get the drift of each field of a symbol in its last full window, if any
+    pub fn drift(&self, symbol: &Symbol) -> Option<&[FieldDrift]> {
+        self.drift.get(symbol).map(|drift| &drift[..])
+    }
+    /// Get the drift of each field of every symbol in its last full window
+    pub fn all_drift(&self) -> &BTreeMap<Symbol, Vec<FieldDrift>> {
+        &self.drift
+    }
+    /// Whether any field of a symbol has drifted
+    pub fn is_drifting(&self, symbol: &Symbol) -> bool {
+        self.drift(symbol)
+            .map_or(false, |drift| drift.iter().any(|field| field.drifted))
+    }
+    /// Whether any symbol has drifted, signalling that the model should be retrained
+    pub fn needs_retraining(&self) -> bool {
+        self.drift.keys().any(|symbol| self.is_drifting(symbol))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    #[test]
+    fn autocorrelated_samples_are_smaller() {
+        let shuffled: Vec<f64> = (0..100).map(|i| (i * 37 % 100) as f64).collect();
+        let trending: Vec<f64> = (0..100).map(|i| i as f64).collect();
+        assert!(effective_sample_size(&shuffled) > 50.0);
+        assert!(effective_sample_size(&trending) < 5.0);
+        assert_eq!(effective_sample_size(&[1.0; 10]), 10.0);
+        assert_eq!(effective_sample_size(&[]), 0.0);
+        // Evenly spaced samples are never more than requested
+        let t = NaiveDate::from_ymd(2020, 10, 12).and_hms(14, 30, 0);
+        let ticks = vec![
+            Tick {
+                t,
+                o: 0.0,
+                h: 0.0,
+                l: 0.0,
+                c: 0.0,
+                v: 0.0,
+                vw: 0.0,
+                n: 0.0
+            };
+            1050
+        ];
+        assert_eq!(TrainingDistribution::new(&ticks, 100).fields[3].len(), 96);
+    }
+
+    #[test]
+    fn drift_is_flagged_when_inputs_shift() {
+        assert_eq!(ks_statistic(&[1.0, 2.0, 3.0], &[1.0, 2.0, 3.0]), 0.0);
+        assert_eq!(ks_statistic(&[1.0, 2.0], &[3.0, 4.0]), 1.0);
+        assert!((ks_statistic(&[1.0, 2.0, 3.0, 4.0], &[3.0, 4.0]) - 0.5).abs() < 1e-12);
+
+        let t = NaiveDate::from_ymd(2020, 10, 12).and_hms(14, 30, 0);
+        let tick = |x: f64| Tick {
+            t,
+            o: x,
+            h: x,
+            l: x,
+            c: x,
+            v: 0.5,
+            vw: x,
+            n: 0.5,
+        };
+        let training: Vec<Tick> = (0..1000).map(|i| tick(i as f64 / 1000.0)).collect();
+        let reference = TrainingDistribution::new(&training, 100);
+        assert_eq!(reference.fields[3].len(), 100);
+        let mut monitor = DriftMonitor::new(reference, 50, 0.01);
+
+        let amd = Symbol::from("AMD");
+        for i in 0..49 {
+            assert!(monitor.push(&amd, tick(i as f64 / 50.0)).is_none());
+        }
+        assert!(monitor.push(&amd, tick(0.99)).is_some());
+        assert!(!monitor.needs_retraining());
+        // Windows do not overlap, so the next is only tested once it is full. Its values are shuffled, so that they are
+        // not autocorrelated, and the window is tested at its full size.
+        for i in 0..49 {
+            assert!(monitor
+                .push(&amd, tick(2.0 + (i * 7 % 50) as f64 / 50.0))
+                .is_none());
+        }
+        assert!(monitor.push(&amd, tick(2.0 + 43.0 / 50.0)).is_some());
+        assert!(monitor.is_drifting(&amd));
+        assert!(monitor.needs_retraining());
+        let close = &monitor.drift(&amd).unwrap()[3];
+        assert_eq!(close.field, "c");
+        assert_eq!(close.statistic, 1.0);
+        assert!(!monitor.drift(&amd).unwrap()[4].drifted);
+    }
+
+    #[test]
+    fn observed_ticks_are_those_fed() {
+        use crate::data::scale::TickScalerConfig;
+        use crate::data::SymbolRegistry;
+        use crate::lstm::StockLSTMDesc;
+        use tch::{nn::VarStore, Device};
+
+        let vs = VarStore::new(Device::Cpu);
+        let lstm = StockLSTMDesc {
+            hidden: 4,
+            ..Default::default()
+        }
+        .build(&vs);
+        let amd = Symbol::from("AMD");
+        let mut predictor = Predictor::new(lstm, Device::Cpu, |_, _: &mut Vec<f32>| {}, 0.9, 0.9)
+            .with_scaler_config(TickScalerConfig::uniform(0.9, 0.9).with_warmup(2))
+            .with_symbols(SymbolRegistry::new(vec![amd.clone()]).unwrap())
+            .unwrap();
+        let reference = TrainingDistribution {
+            fields: vec![vec![-1.0, 1.0]; Tick::NN_FIELDS],
+        };
+        let mut monitor = DriftMonitor::new(reference, 10, 0.01);
+        let start = NaiveDate::from_ymd(2020, 10, 12).and_hms(14, 30, 0);
+        let mut fed = Vec::new();
+        for minute in 0..5 {
+            let c = 100.0 + (minute * minute) as f64;
+            let tick = Tick {
+                t: start + chrono::Duration::minutes(minute),
+                o: c,
+                h: c,
+                l: c,
+                c,
+                v: 100.0,
+                vw: c,
+                n: 1.0,
+            };
+            let mut ticks = BTreeMap::new();
+            ticks.insert(amd.clone(), tick);
+            predictor.push_symbols(tick.t, &ticks, &[]);
+            monitor.observe(&predictor);
+            fed.extend(predictor.last_scaled()[0]);
+        }
+        // The ticks of the warm-up period are not fed, so are not recorded either
+        assert_eq!(fed.len(), 3);
+        assert_eq!(monitor.windows[&amd], fed);
+    }
+}
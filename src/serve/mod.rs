@@ -0,0 +1,509 @@
+/*!
+Monitoring a deployed predictor: tracking diagnostics of its predictions as ticks arrive, and serving them over a
+minimal HTTP server with a `/status` endpoint, and a Prometheus `/metrics` endpoint if the `metrics` feature is enabled
+*/
+use crate::data::{PredictedTick, Symbol, Target, TargetKind, Tick};
+#[cfg(feature = "metrics")]
+use crate::metrics::{metrics_route, Metrics};
+use crate::predict::Predictor;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+pub mod drift;
+pub use drift::{DriftMonitor, FieldDrift, TrainingDistribution};
+
+/// How long a connection may take to send its request before it is dropped, so that a stalled client cannot block the
+/// server
+pub const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The state of a symbol's scaler, and how far the scaled ticks it recently produced have drifted from the training
+/// distribution
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScalerDrift {
+    /// The current moving average of the close
+    pub close_average: f64,
+    /// The current range of the close
+    pub close_range: f64,
+    /// The distance of the mean of the recent scaled closes from the mean of the training distribution, in its
+    /// standard deviations, if the training distribution is known
+    pub close_drift: Option<f64>,
+    /// The current moving average of the volume
+    pub volume_average: f64,
+    /// The distance of the mean of the recent scaled volumes from the mean of the training distribution, in its
+    /// standard deviations, if the training distribution is known
+    pub volume_drift: Option<f64>,
+}
+
+/// The diagnostics of a single symbol
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SymbolStatus {
+    /// The time of the symbol's last tick, if any
+    pub t: Option<NaiveDateTime>,
+    /// The last prediction for the symbol, if any
+    pub prediction: Option<PredictedTick>,
+    /// The drift of the symbol's scaler, if it has one
+    pub drift: Option<ScalerDrift>,
+    /// The root mean square error of the symbol's recent close predictions, if any have been realized
+    pub rmse: Option<f64>,
+    /// The number of realized predictions the error is computed over
+    pub errors: usize,
+}
+
+/// The diagnostics of a deployed predictor, as reported by the `/status` endpoint
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Status {
+    /// The version of the model being served, e.g. its checkpoint
+    pub model_version: String,
+    /// The number of seconds since tracking started
+    pub uptime_secs: f64,
+    /// The diagnostics of each symbol
+    pub symbols: BTreeMap<Symbol, SymbolStatus>,
+}
+
+/// The tracked state of a single symbol
+#[derive(Debug, Clone, Default)]
+struct SymbolTracker {
+    /// The time of the last tick
+    t: Option<NaiveDateTime>,
+    /// The last prediction
+    prediction: Option<PredictedTick>,
+    /// The most recent scaled ticks
+    recent: VecDeque<Tick>,
+    /// The current drift of the scaler
+    drift: Option<ScalerDrift>,
+    /// The scaled and raw ticks predicted from, with their predictions, awaiting realization
+    pending: VecDeque<(Tick, Tick, PredictedTick)>,
+    /// The squared errors of the most recent realized predictions
+    errors: VecDeque<f64>,
+}
+
+/// Get the distance of the mean of the finite values of a field of some ticks from a reference mean, in reference
+/// standard deviations, or `None` if there are no finite values
+fn standardized_shift<'a>(
+    ticks: impl Iterator<Item = &'a Tick>,
+    field: fn(&Tick) -> f64,
+    (mean, std): (f64, f64),
+) -> Option<f64> {
+    let (sum, count) = ticks
+        .map(field)
+        .filter(|value| value.is_finite())
+        .fold((0.0, 0), |(sum, count), value| (sum + value, count + 1));
+    if count == 0 {
+        return None;
+    }
+    Some((sum / count as f64 - mean) / std)
+}
+
+/// Tracks the diagnostics of a predictor as ticks are fed to it
+#[derive(Debug, Clone)]
+pub struct StatusTracker {
+    /// The version of the model being served
+    pub model_version: String,
+    /// The number of recent realized predictions the rolling error is computed over, and of recent scaled ticks the
+    /// drift is computed over
+    pub error_window: usize,
+    /// The distribution of the scaled training ticks, which the drift of recent scaled ticks is measured against
+    pub reference: Option<TrainingDistribution>,
+    /// When tracking started
+    started: Instant,
+    /// The state of each symbol
+    symbols: BTreeMap<Symbol, SymbolTracker>,
+}
+
+impl StatusTracker {
+    /// Start tracking a model of a given version, computing rolling errors over a given number of predictions
+    pub fn new(model_version: impl Into<String>, error_window: usize) -> StatusTracker {
+        StatusTracker {
+            model_version: model_version.into(),
+            error_window,
+            reference: None,
+            started: Instant::now(),
+            symbols: BTreeMap::new(),
+        }
+    }
+    /// Measure the drift of recent scaled ticks against the distribution of the scaled training ticks
+    pub fn with_reference(mut self, reference: TrainingDistribution) -> StatusTracker {
+        self.reference = Some(reference);
+        self
+    }
+    /// Record the state of a predictor with symbols after it has been fed the ticks of each symbol at a given time.
+    ///
+    /// Each close prediction is realized `target_horizon` ticks of its symbol later, and compared against the target
+    /// computed as during training: from the ticks as fed to the network for levels, and from the raw ticks for
+    /// returns. Ticks the predictor dropped, e.g. while its scaler was warming up, are not recorded. Ranks and
+    /// z-scores are never realized, so have no rolling error. Does nothing if the predictor has no symbols.
+    pub fn observe<DF>(
+        &mut self,
+        predictor: &Predictor<DF>,
+        t: NaiveDateTime,
+        ticks: &BTreeMap<Symbol, Tick>,
+    ) where
+        DF: FnMut(DateTime<Utc>, &mut Vec<f32>),
+    {
+        let registry = match &predictor.symbols {
+            Some(registry) => registry,
+            None => return,
+        };
+        let horizon = predictor.lstm.desc.target_horizon;
+        let mut predictions = predictor.last_predictions().unwrap_or_default();
+        // The moments of the scaled training closes and volumes, in the order of `Tick::NN_FIELD_NAMES`
+        let reference = self.reference.as_ref();
+        let close_moments = reference.and_then(|reference| reference.moments(3));
+        let volume_moments = reference.and_then(|reference| reference.moments(4));
+        for (stock, symbol) in registry.symbols().iter().enumerate() {
+            let tracker = self.symbols.entry(symbol.clone()).or_default();
+            let prediction = predictions.remove(symbol);
+            let scaler = match &predictor.scalers[stock] {
+                Some(scaler) => scaler,
+                None => continue,
+            };
+            // The tick exactly as fed, scaled and quantile transformed, unless it was dropped
+            let tick = predictor.last_scaled()[stock];
+            if let Some(tick) = tick {
+                tracker.recent.push_back(tick);
+                while tracker.recent.len() > self.error_window {
+                    tracker.recent.pop_front();
+                }
+            }
+            let shift = |field: fn(&Tick) -> f64, moments: Option<(f64, f64)>| {
+                standardized_shift(tracker.recent.iter(), field, moments?)
+            };
+            tracker.drift = Some(ScalerDrift {
+                close_average: scaler.c.average,
+                close_range: scaler.c.range,
+                close_drift: shift(|tick| tick.c, close_moments),
+                volume_average: scaler.v.average,
+                volume_drift: shift(|tick| tick.v, volume_moments),
+            });
+            let (tick, raw) = match (tick, ticks.get(symbol)) {
+                (Some(tick), Some(raw)) => (tick, *raw),
+                _ => continue,
+            };
+            tracker.t = Some(t);
+            if let Some(prediction) = prediction {
+                tracker.pending.push_back((tick, raw, prediction.clone()));
+                tracker.prediction = Some(prediction);
+            }
+            if tracker.pending.len() > horizon {
+                let (previous, previous_raw, predicted) =
+                    tracker.pending.pop_front().expect("Nonempty");
+                // As in training, levels are realized in scaled units, and changes from the raw ticks. Cross-sectional
+                // targets depend on every other symbol's change, so are not realized.
+                let realized = match predicted.kind {
+                    TargetKind::Level => {
+                        tick.target_of_kind(Some(&previous), Target::Close, predicted.kind)
+                    }
+                    TargetKind::Return => {
+                        raw.target_of_kind(Some(&previous_raw), Target::Close, predicted.kind)
+                    }
+                    TargetKind::Rank | TargetKind::ZScore => None,
+                };
+                if let (Some(realized), Some(predicted)) = (realized, predicted.get(Target::Close))
+                {
+                    let error = (predicted - realized) as f64;
+                    tracker.errors.push_back(error * error);
+                    while tracker.errors.len() > self.error_window {
+                        tracker.errors.pop_front();
+                    }
+                }
+            }
+        }
+    }
+    /// Get the current diagnostics
+    pub fn status(&self) -> Status {
+        Status {
+            model_version: self.model_version.clone(),
+            uptime_secs: self.started.elapsed().as_secs_f64(),
+            symbols: self
+                .symbols
+                .iter()
+                .map(|(symbol, tracker)| {
+                    let errors = tracker.errors.len();
+                    let rmse = if errors == 0 {
+                        None
+                    } else {
+                        Some((tracker.errors.iter().sum::<f64>() / errors as f64).sqrt())
+                    };
+                    let status = SymbolStatus {
+                        t: tracker.t,
+                        prediction: tracker.prediction.clone(),
+                        drift: tracker.drift,
+                        rmse,
+                        errors,
+                    };
+                    (symbol.clone(), status)
+                })
+                .collect(),
+        }
+    }
+}
+
+/// A response to an HTTP request
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Response {
+    /// The MIME type of the body
+    pub content_type: &'static str,
+    /// The body
+    pub body: String,
+}
+
+impl Response {
+    /// A JSON response
+    pub fn json(body: String) -> Response {
+        Response {
+            content_type: "application/json",
+            body,
+        }
+    }
+}
+
+/// Answer a single HTTP request on a connection, routing the path of `GET` requests to a handler. Paths the handler
+/// returns `None` for get a 404, and other methods a 405.
+pub fn handle_connection<R>(stream: TcpStream, route: &R) -> io::Result<()>
+where
+    R: Fn(&str) -> Option<Response>,
+{
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // Skip the headers, as no endpoint reads them
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+    let mut parts = request_line.split_whitespace();
+    let (status, response) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some(path)) => match route(path.split('?').next().unwrap_or(path)) {
+            Some(response) => ("200 OK", response),
+            None => ("404 Not Found", Response::json("{}".to_string())),
+        },
+        _ => ("405 Method Not Allowed", Response::json("{}".to_string())),
+    };
+    let mut stream = stream;
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        response.content_type,
+        response.body.len(),
+        response.body
+    )?;
+    stream.flush()
+}
+
+/// Serve HTTP requests on a listener forever, one at a time, routing them as in `handle_connection`. Errors on
+/// individual connections are logged and skipped.
+pub fn serve<R>(listener: TcpListener, route: R)
+where
+    R: Fn(&str) -> Option<Response>,
+{
+    for stream in listener.incoming() {
+        let result = stream.and_then(|stream| handle_connection(stream, &route));
+        if let Err(err) = result {
+            warn!("Error serving request: {}", err);
+        }
+    }
+}
+
+/// Route the `/status` endpoint to a tracker's diagnostics, as JSON
+pub fn status_route(tracker: &Mutex<StatusTracker>, path: &str) -> Option<Response> {
+    match path {
+        "/status" => {
+            let status = tracker
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .status();
+            Some(Response::json(
+                serde_json::to_string(&status).unwrap_or_default(),
+            ))
+        }
+        _ => None,
+    }
+}
+
+/// Serve HTTP requests routed as in `handle_connection` from a background thread, returning the address served on,
+/// e.g. to find the port chosen when binding to port zero
+pub fn spawn_server<A, R>(addr: A, route: R) -> io::Result<(SocketAddr, JoinHandle<()>)>
+where
+    A: ToSocketAddrs,
+    R: Fn(&str) -> Option<Response> + Send + 'static,
+{
+    let listener = TcpListener::bind(addr)?;
+    let addr = listener.local_addr()?;
+    let handle = std::thread::spawn(move || serve(listener, route));
+    Ok((addr, handle))
+}
+
+/// Serve a tracker's diagnostics on a `/status` endpoint from a background thread, as in `spawn_server`
+pub fn spawn_status_server<A: ToSocketAddrs>(
+    addr: A,
+    tracker: Arc<Mutex<StatusTracker>>,
+) -> io::Result<(SocketAddr, JoinHandle<()>)> {
+    spawn_server(addr, move |path| status_route(&tracker, path))
+}
+
+/// Serve a tracker's diagnostics on a `/status` endpoint, and a set of metrics on a `/metrics` endpoint, from a
+/// background thread as in `spawn_server`. The tracker's status is recorded in the metrics on every scrape, so that
+/// only per-prediction metrics, e.g. serving latency and input drift, need to be recorded by the caller.
+#[cfg(feature = "metrics")]
+pub fn spawn_monitoring_server<A: ToSocketAddrs>(
+    addr: A,
+    tracker: Arc<Mutex<StatusTracker>>,
+    metrics: Metrics,
+) -> io::Result<(SocketAddr, JoinHandle<()>)> {
+    spawn_server(addr, move |path| {
+        status_route(&tracker, path).or_else(|| {
+            if path == "/metrics" {
+                let status = tracker
+                    .lock()
+                    .unwrap_or_else(|poisoned| poisoned.into_inner())
+                    .status();
+                metrics.record_status(&status);
+            }
+            metrics_route(&metrics, path)
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::scale::TickScalerConfig;
+    use crate::data::SymbolRegistry;
+    use crate::lstm::StockLSTMDesc;
+    use chrono::{Duration, NaiveDate};
+    use std::io::Read;
+    use tch::nn::VarStore;
+    use tch::Device;
+
+    #[test]
+    fn dropped_ticks_are_not_realized() {
+        let desc = StockLSTMDesc {
+            hidden: 4,
+            heads: vec![Target::Close],
+            ..Default::default()
+        };
+        let vs = VarStore::new(Device::Cpu);
+        let amd = Symbol::from("AMD");
+        let mut predictor = Predictor::new(
+            desc.build(&vs),
+            Device::Cpu,
+            |_, _: &mut Vec<f32>| {},
+            0.5,
+            0.999,
+        )
+        .with_scaler_config(TickScalerConfig::uniform(0.5, 0.999).with_warmup(2))
+        .with_symbols(SymbolRegistry::new(vec![amd.clone()]).unwrap())
+        .unwrap();
+        let mut tracker = StatusTracker::new("v1", 10);
+        let start = NaiveDate::from_ymd(2020, 10, 12).and_hms(14, 30, 0);
+        for minute in 0..4 {
+            let c = 80.0 + minute as f64;
+            let tick = Tick {
+                t: start + Duration::minutes(minute),
+                o: c,
+                h: c,
+                l: c,
+                c,
+                v: 100.0,
+                vw: c,
+                n: 1.0,
+            };
+            let mut ticks = BTreeMap::new();
+            ticks.insert(amd.clone(), tick);
+            predictor.push_symbols(tick.t, &ticks, &[]);
+            tracker.observe(&predictor, tick.t, &ticks);
+        }
+        // Only the two ticks past the warm-up period were fed, and the first of them is realized by the second
+        assert_eq!(tracker.symbols[&amd].recent.len(), 2);
+        assert_eq!(tracker.status().symbols[&amd].errors, 1);
+    }
+
+    #[test]
+    fn status_endpoint_reports_predictions() {
+        let desc = StockLSTMDesc {
+            stocks: 1,
+            hidden: 4,
+            layers: 1,
+            heads: vec![Target::Close],
+            ..Default::default()
+        };
+        let vs = VarStore::new(Device::Cpu);
+        let registry = SymbolRegistry::new(vec![Symbol::from("AMD")]).unwrap();
+        let mut predictor = Predictor::new(
+            desc.build(&vs),
+            Device::Cpu,
+            |_, _: &mut Vec<f32>| {},
+            0.5,
+            0.999,
+        )
+        .with_symbols(registry)
+        .unwrap();
+        // Scaled closes and volumes are centered at -1 in training, with a standard deviation of 1
+        let reference = TrainingDistribution {
+            fields: vec![vec![-2.0, 0.0]; Tick::NN_FIELDS],
+        };
+        let tracker = Arc::new(Mutex::new(
+            StatusTracker::new("v1", 10).with_reference(reference),
+        ));
+
+        let start = NaiveDate::from_ymd(2020, 10, 12).and_hms(14, 30, 0);
+        for minute in 0..3 {
+            let c = 80.0 + minute as f64;
+            let tick = Tick {
+                t: start + Duration::minutes(minute),
+                o: c,
+                h: c,
+                l: c,
+                c,
+                v: 100.0,
+                vw: c,
+                n: 1.0,
+            };
+            let mut ticks = BTreeMap::new();
+            ticks.insert(Symbol::from("AMD"), tick);
+            predictor.push_symbols(tick.t, &ticks, &[]);
+            tracker.lock().unwrap().observe(&predictor, tick.t, &ticks);
+        }
+        let status = tracker.lock().unwrap().status();
+        let amd = &status.symbols[&Symbol::from("AMD")];
+        assert_eq!(amd.t, Some(start + Duration::minutes(2)));
+        assert!(amd.prediction.is_some());
+        assert_eq!(amd.errors, 2);
+        let drift = amd.drift.unwrap();
+        assert!(drift.close_drift.unwrap() > 0.5);
+        assert!(drift.volume_drift.unwrap() > 0.5);
+
+        let (addr, _handle) = spawn_status_server("127.0.0.1:0", tracker).unwrap();
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(stream, "GET /status HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        let body = response.split("\r\n\r\n").nth(1).unwrap();
+        let served: Status = serde_json::from_str(body).unwrap();
+        assert_eq!(served.model_version, "v1");
+        assert_eq!(served.symbols.len(), 1);
+    }
+
+    #[cfg(feature = "metrics")]
+    #[test]
+    fn metrics_endpoint_reports_status() {
+        let tracker = Arc::new(Mutex::new(StatusTracker::new("v1", 10)));
+        let (addr, _handle) =
+            spawn_monitoring_server("127.0.0.1:0", tracker, Metrics::default()).unwrap();
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(stream, "GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("stockburn_serve_uptime_seconds "));
+    }
+}
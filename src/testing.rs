@@ -0,0 +1,136 @@
+/*!
+Property-based testing utilities: `proptest` strategies generating tick sequences with the defects of real data, such as
+gaps, duplicate timestamps, invalid values and unsorted ticks, together with checks of the invariants the data pipeline
+maintains, for reuse in tests of code built on it
+*/
+pub use crate::data::clean::same_tick;
+use crate::data::Tick;
+use chrono::{Duration, NaiveDate, NaiveDateTime};
+use proptest::collection::vec;
+use proptest::prelude::*;
+use proptest::sample::Index;
+
+/// The time of the first tick of generated sequences
+pub fn base_time() -> NaiveDateTime {
+    NaiveDate::from_ymd(2020, 10, 12).and_hms(14, 30, 0)
+}
+
+/// Generate values which are usually valid prices, but occasionally zero, negative, NaN or infinite
+pub fn messy_value() -> impl Strategy<Value = f64> {
+    prop_oneof![
+        8 => 1.0..1000.0f64,
+        1 => Just(f64::NAN),
+        1 => prop_oneof![Just(0.0), Just(-1.0), Just(f64::INFINITY), Just(f64::NEG_INFINITY)],
+    ]
+}
+
+/// Generate valid ticks at a given time, with consistent OHLC
+pub fn tick_at(t: NaiveDateTime) -> impl Strategy<Value = Tick> {
+    (
+        1.0..1000.0f64,
+        -0.05..0.05f64,
+        0.0..0.05f64,
+        0.0..0.05f64,
+        0.0..1e6f64,
+        0.0..1e3f64,
+    )
+        .prop_map(move |(c, open, up, down, v, n)| {
+            let o = c * (1.0 + open);
+            Tick {
+                t,
+                o,
+                h: o.max(c) * (1.0 + up),
+                l: o.min(c) * (1.0 - down),
+                c,
+                v,
+                vw: (o + c) / 2.0,
+                n: n.round(),
+            }
+        })
+}
+
+/// Generate the offsets in minutes of the ticks of a sequence from `base_time`, in increasing order: mostly a minute
+/// apart, but with repeated timestamps and gaps of up to ten hours
+pub fn tick_times(max_len: usize) -> impl Strategy<Value = Vec<i64>> {
+    let steps = prop_oneof![
+        6 => Just(1i64),
+        1 => Just(0i64),
+        1 => 2..600i64,
+    ];
+    vec(steps, 0..max_len).prop_map(|steps| {
+        steps
+            .into_iter()
+            .scan(0, |minute, step| {
+                *minute += step;
+                Some(*minute)
+            })
+            .collect()
+    })
+}
+
+/// Generate sorted sequences of up to `max_len` valid ticks, with gaps and repeated timestamps
+pub fn tick_sequence(max_len: usize) -> impl Strategy<Value = Vec<Tick>> {
+    tick_times(max_len).prop_flat_map(|minutes| {
+        minutes
+            .into_iter()
+            .map(|minute| tick_at(base_time() + Duration::minutes(minute)))
+            .collect::<Vec<_>>()
+    })
+}
+
+/// Generate sequences of up to `max_len` ticks with the defects of real data: gaps, repeated timestamps, exact
+/// duplicates, invalid values and ticks out of order
+pub fn messy_tick_sequence(max_len: usize) -> impl Strategy<Value = Vec<Tick>> {
+    (
+        tick_sequence(max_len),
+        vec(any::<Index>(), 0..3),
+        vec((any::<Index>(), 0..Tick::NN_FIELDS, messy_value()), 0..4),
+        vec((any::<Index>(), any::<Index>()), 0..3),
+    )
+        .prop_map(|(mut ticks, duplicates, corruptions, swaps)| {
+            if ticks.is_empty() {
+                return ticks;
+            }
+            for duplicate in duplicates {
+                let ix = duplicate.index(ticks.len());
+                ticks.insert(ix, ticks[ix]);
+            }
+            for (ix, field, value) in corruptions {
+                let tick = &mut ticks[ix.index(ticks.len())];
+                match field {
+                    0 => tick.o = value,
+                    1 => tick.h = value,
+                    2 => tick.l = value,
+                    3 => tick.c = value,
+                    4 => tick.v = value,
+                    5 => tick.vw = value,
+                    _ => tick.n = value,
+                }
+            }
+            for (left, right) in swaps {
+                let len = ticks.len();
+                ticks.swap(left.index(len), right.index(len));
+            }
+            ticks
+        })
+}
+
+/// Check that ticks are sanitized, i.e. strictly increasing in time, with highs and lows containing their opens and
+/// closes wherever these are not NaN, returning a description of the first violation otherwise
+pub fn check_sanitized(ticks: &[Tick]) -> Result<(), String> {
+    if let Some(w) = ticks.windows(2).find(|w| w[1].t <= w[0].t) {
+        return Err(format!("Tick at {} follows tick at {}", w[1].t, w[0].t));
+    }
+    let violates = |bound: f64, price: f64, high: bool| {
+        !bound.is_nan() && !price.is_nan() && if high { bound < price } else { bound > price }
+    };
+    if let Some(tick) = ticks.iter().find(|tick| {
+        violates(tick.h, tick.o, true)
+            || violates(tick.h, tick.c, true)
+            || violates(tick.l, tick.o, false)
+            || violates(tick.l, tick.c, false)
+    }) {
+        return Err(format!("Tick {:?} has inconsistent OHLC", tick));
+    }
+    Ok(())
+}
@@ -0,0 +1,163 @@
+/*!
+Trading domain types shared by the backtester, the paper trader and broker adapters: the signals strategies emit, the
+orders sent to brokers, and the fills and positions which result
+*/
+use crate::data::{Symbol, SymbolRegistry};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+pub mod sizing;
+pub mod strategy;
+pub use strategy::{Strategy, StrategyContext, ThresholdStrategy, VolatilityScaledStrategy};
+
+/// A desired exposure to a symbol at a given time
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Signal {
+    /// The time of the signal
+    pub t: NaiveDateTime,
+    /// The symbol to trade
+    pub symbol: Symbol,
+    /// The strength of the signal, e.g. a predicted return: positive to go long, negative to go short and zero to be
+    /// flat. Signals are compared by absolute strength when allocating capital.
+    pub strength: f64,
+}
+
+impl Signal {
+    /// Arrange the strengths of a set of signals in stock order, with zero for registered symbols without a signal, as
+    /// taken by `Backtest::step`. Later signals for a symbol override earlier ones, and signals for unregistered
+    /// symbols are ignored.
+    pub fn arrange(signals: &[Signal], symbols: &SymbolRegistry) -> Vec<f64> {
+        let strengths: BTreeMap<Symbol, f64> = signals
+            .iter()
+            .map(|signal| (signal.symbol.clone(), signal.strength))
+            .collect();
+        symbols
+            .arrange(&strengths)
+            .into_iter()
+            .map(|strength| strength.unwrap_or(0.0))
+            .collect()
+    }
+}
+
+/// The side of an order
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Side {
+    /// Buy shares, opening or adding to a long position or covering a short one
+    Buy,
+    /// Sell shares, closing a long position or opening or adding to a short one
+    Sell,
+}
+
+/// The price at which an order may be filled
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum OrderKind {
+    /// Fill at the market price
+    Market,
+    /// Fill at the given price or better
+    Limit(f64),
+}
+
+/// How long an order remains active before being cancelled if not filled
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum TimeInForce {
+    /// Until the end of the trading day
+    Day,
+    /// Until cancelled
+    GoodTillCancelled,
+}
+
+/// An order to trade some shares of a symbol
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Order {
+    /// The symbol to trade
+    pub symbol: Symbol,
+    /// The number of shares to trade, which is always positive
+    pub qty: f64,
+    /// Whether to buy or sell
+    pub side: Side,
+    /// The price at which the order may be filled
+    pub kind: OrderKind,
+    /// How long the order remains active
+    pub time_in_force: TimeInForce,
+}
+
+impl Order {
+    /// A market order for the day to trade a signed number of shares, buying if positive and selling if negative
+    pub fn market(symbol: Symbol, shares: f64) -> Order {
+        Order {
+            symbol,
+            qty: shares.abs(),
+            side: if shares >= 0.0 { Side::Buy } else { Side::Sell },
+            kind: OrderKind::Market,
+            time_in_force: TimeInForce::Day,
+        }
+    }
+    /// Get the signed number of shares traded, positive if buying and negative if selling
+    pub fn shares(&self) -> f64 {
+        match self.side {
+            Side::Buy => self.qty,
+            Side::Sell => -self.qty,
+        }
+    }
+}
+
+/// An executed trade, whether simulated or real
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Fill {
+    /// The time of the fill
+    pub t: NaiveDateTime,
+    /// The symbol traded
+    pub symbol: Symbol,
+    /// The number of shares bought, negative for shares sold
+    pub shares: f64,
+    /// The price of the fill
+    pub price: f64,
+    /// The transaction cost paid
+    pub cost: f64,
+}
+
+/// A position held in a symbol
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Position {
+    /// The symbol held
+    pub symbol: Symbol,
+    /// The number of shares held, negative for short positions
+    pub shares: f64,
+    /// The average price at which the position was entered
+    pub entry_price: f64,
+    /// The current market value of the position, negative for short positions
+    pub market_value: f64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    #[test]
+    fn signals_and_orders() {
+        let t = NaiveDate::from_ymd(2020, 10, 12).and_hms(14, 30, 0);
+        let registry =
+            SymbolRegistry::new(vec![Symbol::from("AMD"), Symbol::from("NVDA")]).unwrap();
+        let signal = |symbol: &str, strength: f64| Signal {
+            t,
+            symbol: Symbol::from(symbol),
+            strength,
+        };
+        let signals = vec![
+            signal("NVDA", 0.5),
+            signal("TSLA", 1.0),
+            signal("NVDA", -0.25),
+        ];
+        assert_eq!(Signal::arrange(&signals, &registry), vec![0.0, -0.25]);
+
+        let order = Order::market(Symbol::from("AMD"), -15.0);
+        assert_eq!(order.side, Side::Sell);
+        assert_eq!(order.qty, 15.0);
+        assert_eq!(order.shares(), -15.0);
+        let json = serde_json::to_string(&order).unwrap();
+        assert_eq!(serde_json::from_str::<Order>(&json).unwrap(), order);
+    }
+}
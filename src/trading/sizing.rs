@@ -0,0 +1,130 @@
+/*!
+Position sizing: turning predicted returns and realized volatility into portfolio weights
+*/
+use crate::predict::Estimate;
+use serde::{Deserialize, Serialize};
+
+/// Get the fraction of equity to allocate to a position under the fractional Kelly criterion, given the mean and
+/// variance of its predicted return. A `fraction` of one gives the full Kelly weight `mean / variance`, which is
+/// clamped to `[-max_leverage, max_leverage]`. Returns zero if the variance is not positive, or if either moment is
+/// not finite.
+pub fn kelly_weight(mean: f64, variance: f64, fraction: f64, max_leverage: f64) -> f64 {
+    if !mean.is_finite() || !variance.is_finite() || variance <= 0.0 {
+        return 0.0;
+    }
+    (fraction * mean / variance)
+        .max(-max_leverage)
+        .min(max_leverage)
+}
+
+/// Get the fractional Kelly weight of a position given an estimate of its return, e.g. from
+/// `Predictor::predict_with_uncertainty`, as in `kelly_weight`
+pub fn kelly_weight_from_estimate(estimate: Estimate, fraction: f64, max_leverage: f64) -> f64 {
+    let std = estimate.std as f64;
+    kelly_weight(estimate.mean as f64, std * std, fraction, max_leverage)
+}
+
+/// Get the scale factor bringing a position with a given realized volatility to a target volatility, capped at
+/// `max_leverage`. Both volatilities must be over the same period. Returns zero if the realized volatility is not
+/// positive or not finite.
+pub fn vol_target_scale(
+    target_volatility: f64,
+    realized_volatility: f64,
+    max_leverage: f64,
+) -> f64 {
+    if !realized_volatility.is_finite() || realized_volatility <= 0.0 {
+        return 0.0;
+    }
+    (target_volatility / realized_volatility).min(max_leverage)
+}
+
+/// Convert a per-period volatility to an annualized volatility, given the number of periods per year
+pub fn annualize_volatility(volatility: f64, periods_per_year: f64) -> f64 {
+    volatility * periods_per_year.sqrt()
+}
+
+/// Convert a portfolio weight into a signed number of shares at a given price, rounded towards zero to whole shares
+/// if `whole` is set. Returns zero if the price is not positive.
+pub fn weight_to_shares(weight: f64, equity: f64, price: f64, whole: bool) -> f64 {
+    if !price.is_finite() || price <= 0.0 {
+        return 0.0;
+    }
+    let shares = weight * equity / price;
+    if whole {
+        shares.trunc()
+    } else {
+        shares
+    }
+}
+
+/// An estimate of a price series' realized volatility, as the root of an exponential moving average of its squared
+/// simple returns
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RealizedVolatility {
+    /// The per-price decay of the moving average
+    pub decay: f64,
+    /// The last price seen
+    last: Option<f64>,
+    /// The moving average of squared returns
+    variance: Option<f64>,
+}
+
+impl RealizedVolatility {
+    /// Create a new estimator with a given decay
+    pub fn new(decay: f64) -> RealizedVolatility {
+        RealizedVolatility {
+            decay,
+            last: None,
+            variance: None,
+        }
+    }
+    /// Record a price, returning the updated volatility estimate, if any. Prices which are not positive and finite
+    /// are ignored.
+    pub fn push(&mut self, price: f64) -> Option<f64> {
+        if !price.is_finite() || price <= 0.0 {
+            return self.volatility();
+        }
+        if let Some(last) = self.last {
+            let ret = price / last - 1.0;
+            let sq = ret * ret;
+            let decay = self.decay;
+            self.variance = Some(
+                self.variance
+                    .map_or(sq, |variance| decay * variance + (1.0 - decay) * sq),
+            );
+        }
+        self.last = Some(price);
+        self.volatility()
+    }
+    /// Get the volatility estimate, which is `None` until two prices have been seen
+    pub fn volatility(&self) -> Option<f64> {
+        self.variance.map(f64::sqrt)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizing_helpers() {
+        assert!((kelly_weight(0.01, 0.04, 0.5, 10.0) - 0.125).abs() < 1e-12);
+        assert_eq!(kelly_weight(0.1, 0.001, 1.0, 2.0), 2.0);
+        assert_eq!(kelly_weight(-0.1, 0.001, 1.0, 2.0), -2.0);
+        assert_eq!(kelly_weight(0.1, 0.0, 1.0, 2.0), 0.0);
+        let estimate = Estimate {
+            mean: 0.01,
+            std: 0.2,
+        };
+        assert!((kelly_weight_from_estimate(estimate, 0.5, 10.0) - 0.125).abs() < 1e-6);
+
+        assert_eq!(vol_target_scale(0.1, 0.2, 2.0), 0.5);
+        assert_eq!(vol_target_scale(0.1, 0.01, 2.0), 2.0);
+        assert_eq!(weight_to_shares(-0.5, 1000.0, 30.0, true), -16.0);
+
+        let mut volatility = RealizedVolatility::new(0.0);
+        assert_eq!(volatility.push(100.0), None);
+        assert!((volatility.push(110.0).unwrap() - 0.1).abs() < 1e-12);
+        assert!((volatility.push(-1.0).unwrap() - 0.1).abs() < 1e-12);
+    }
+}
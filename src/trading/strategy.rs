@@ -0,0 +1,253 @@
+/*!
+Strategies, converting a network's predictions into trading signals, shared by the paper trader and replay harness
+*/
+use super::{sizing::RealizedVolatility, Position, Signal};
+use crate::data::{PredictedTick, Symbol, Target, TargetKind, Tick};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// The market state a strategy decides on
+#[derive(Debug, Copy, Clone)]
+pub struct StrategyContext<'a> {
+    /// The time of the latest ticks
+    pub t: NaiveDateTime,
+    /// The latest raw tick of each symbol which ticked
+    pub ticks: &'a BTreeMap<Symbol, Tick>,
+    /// The latest tick of each symbol which ticked, scaled as fed to the network
+    pub scaled: &'a BTreeMap<Symbol, Tick>,
+    /// The positions currently held
+    pub positions: &'a [Position],
+}
+
+impl StrategyContext<'_> {
+    /// Get the predicted move of a symbol's close: its predicted return, rank or z-score for return and
+    /// cross-sectional models, or the predicted change of its scaled close for level models. Returns `None` if the
+    /// close was not predicted, or if a level was predicted for a symbol which did not tick.
+    pub fn predicted_move(&self, symbol: &Symbol, predicted: &PredictedTick) -> Option<f64> {
+        let c = predicted.get(Target::Close)? as f64;
+        match predicted.kind {
+            TargetKind::Return | TargetKind::Rank | TargetKind::ZScore => Some(c),
+            TargetKind::Level => Some(c - self.scaled.get(symbol)?.c),
+        }
+    }
+}
+
+/// Decision logic converting predictions into signals
+pub trait Strategy {
+    /// Decide on the signals to emit given the latest prediction for each symbol. Symbols without a signal are
+    /// treated as flat.
+    fn on_prediction(
+        &mut self,
+        ctx: &StrategyContext,
+        predictions: &BTreeMap<Symbol, PredictedTick>,
+    ) -> Vec<Signal>;
+}
+
+impl<S: Strategy + ?Sized> Strategy for Box<S> {
+    fn on_prediction(
+        &mut self,
+        ctx: &StrategyContext,
+        predictions: &BTreeMap<Symbol, PredictedTick>,
+    ) -> Vec<Signal> {
+        (**self).on_prediction(ctx, predictions)
+    }
+}
+
+/// Go long symbols predicted to move up by more than a threshold, and short those predicted to move down by more
+/// than it, with signals as strong as the predicted move
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ThresholdStrategy {
+    /// The minimum absolute predicted move to trade on
+    pub threshold: f64,
+    /// Whether long signals are emitted
+    pub long: bool,
+    /// Whether short signals are emitted
+    pub short: bool,
+}
+
+impl Default for ThresholdStrategy {
+    fn default() -> ThresholdStrategy {
+        ThresholdStrategy {
+            threshold: 0.0,
+            long: true,
+            short: true,
+        }
+    }
+}
+
+impl Strategy for ThresholdStrategy {
+    fn on_prediction(
+        &mut self,
+        ctx: &StrategyContext,
+        predictions: &BTreeMap<Symbol, PredictedTick>,
+    ) -> Vec<Signal> {
+        predictions
+            .iter()
+            .filter_map(|(symbol, predicted)| {
+                let predicted = ctx.predicted_move(symbol, predicted)?;
+                let strength = if predicted > self.threshold && self.long
+                    || predicted < -self.threshold && self.short
+                {
+                    predicted
+                } else {
+                    0.0
+                };
+                Some(Signal {
+                    t: ctx.t,
+                    symbol: symbol.clone(),
+                    strength,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Scale each symbol's predicted return by the inverse of its realized volatility, estimated by an exponential moving
+/// average of its squared close returns, so that signals are comparable across symbols. Symbols without a
+/// volatility estimate yet, i.e. before their second tick, are flat.
+///
+/// Only predictions of `TargetKind::Return` are traded, since the moves of other kinds (scaled levels, ranks and
+/// z-scores) are not in units of raw returns; symbols predicted with other kinds are flat.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VolatilityScaledStrategy {
+    /// The per-tick decay of the moving average of squared returns
+    pub decay: f64,
+    /// The minimum absolute scaled signal to trade on
+    pub threshold: f64,
+    /// The realized volatility of each symbol's closes
+    volatilities: BTreeMap<Symbol, RealizedVolatility>,
+}
+
+impl VolatilityScaledStrategy {
+    /// Create a new strategy with a given volatility decay and signal threshold
+    pub fn new(decay: f64, threshold: f64) -> VolatilityScaledStrategy {
+        VolatilityScaledStrategy {
+            decay,
+            threshold,
+            volatilities: BTreeMap::new(),
+        }
+    }
+    /// Get the realized volatility estimate of a symbol, i.e. the root mean square of its close returns, if any
+    pub fn volatility(&self, symbol: &Symbol) -> Option<f64> {
+        self.volatilities.get(symbol)?.volatility()
+    }
+    /// Update the volatility estimates with the latest ticks
+    fn observe(&mut self, ticks: &BTreeMap<Symbol, Tick>) {
+        let decay = self.decay;
+        for (symbol, tick) in ticks {
+            self.volatilities
+                .entry(symbol.clone())
+                .or_insert_with(|| RealizedVolatility::new(decay))
+                .push(tick.c);
+        }
+    }
+}
+
+impl Strategy for VolatilityScaledStrategy {
+    fn on_prediction(
+        &mut self,
+        ctx: &StrategyContext,
+        predictions: &BTreeMap<Symbol, PredictedTick>,
+    ) -> Vec<Signal> {
+        self.observe(ctx.ticks);
+        predictions
+            .iter()
+            .filter_map(|(symbol, predicted)| {
+                if predicted.kind != TargetKind::Return {
+                    return None;
+                }
+                let predicted = ctx.predicted_move(symbol, predicted)?;
+                let strength = match self.volatility(symbol) {
+                    Some(volatility) if volatility > 0.0 => predicted / volatility,
+                    _ => 0.0,
+                };
+                Some(Signal {
+                    t: ctx.t,
+                    symbol: symbol.clone(),
+                    strength: if strength.abs() > self.threshold {
+                        strength
+                    } else {
+                        0.0
+                    },
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    #[test]
+    fn builtin_strategies() {
+        let t = NaiveDate::from_ymd(2020, 10, 12).and_hms(14, 30, 0);
+        let tick = |c: f64| Tick {
+            t,
+            o: c,
+            h: c,
+            l: c,
+            c,
+            v: 100.0,
+            vw: c,
+            n: 1.0,
+        };
+        let predicted = |c: f32| PredictedTick {
+            kind: TargetKind::Return,
+            values: vec![(Target::Close, c)],
+        };
+        let mut predictions = BTreeMap::new();
+        predictions.insert(Symbol::from("AMD"), predicted(0.02));
+        predictions.insert(Symbol::from("NVDA"), predicted(-0.005));
+        let mut ticks = BTreeMap::new();
+        ticks.insert(Symbol::from("AMD"), tick(80.0));
+        ticks.insert(Symbol::from("NVDA"), tick(500.0));
+        let ctx = StrategyContext {
+            t,
+            ticks: &ticks,
+            scaled: &ticks,
+            positions: &[],
+        };
+
+        let mut threshold = ThresholdStrategy {
+            threshold: 0.01,
+            long: true,
+            short: true,
+        };
+        let signals = threshold.on_prediction(&ctx, &predictions);
+        assert_eq!(signals.len(), 2);
+        assert!((signals[0].strength - 0.02).abs() < 1e-6);
+        assert_eq!(signals[1].strength, 0.0);
+
+        let mut scaled = VolatilityScaledStrategy::new(0.0, 0.0);
+        let signals = scaled.on_prediction(&ctx, &predictions);
+        assert!(signals.iter().all(|signal| signal.strength == 0.0));
+        ticks.insert(Symbol::from("AMD"), tick(88.0));
+        ticks.insert(Symbol::from("NVDA"), tick(505.0));
+        let ctx = StrategyContext {
+            t,
+            ticks: &ticks,
+            scaled: &ticks,
+            positions: &[],
+        };
+        let signals = scaled.on_prediction(&ctx, &predictions);
+        assert!((scaled.volatility(&Symbol::from("AMD")).unwrap() - 0.1).abs() < 1e-9);
+        assert!((signals[0].strength - 0.2).abs() < 1e-6);
+        assert!((signals[1].strength + 0.5).abs() < 1e-6);
+
+        // Level predictions are in scaled units, which cannot be compared to raw return volatility
+        let mut levels = predictions.clone();
+        levels.insert(
+            Symbol::from("AMD"),
+            PredictedTick {
+                kind: TargetKind::Level,
+                values: vec![(Target::Close, 90.0)],
+            },
+        );
+        let signals = scaled.on_prediction(&ctx, &levels);
+        assert_eq!(signals.len(), 1);
+        assert_eq!(signals[0].symbol, Symbol::from("NVDA"));
+    }
+}
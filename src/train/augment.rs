@@ -0,0 +1,250 @@
+/*!
+Random augmentation of training batches, to reduce overfitting when training on few symbols
+*/
+use crate::lstm::StockLSTM;
+use rand::{distributions::Distribution, seq::SliceRandom, Rng};
+use rand_distr::Beta;
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+use tch::{Device, Kind, Tensor};
+
+/// Random perturbations applied to the tick features of a batch of inputs of shape `[batch, sequence, features]`.
+/// Additional and date inputs are left untouched, as are targets, except by time warping. Every perturbation is
+/// disabled by default.
+#[derive(Debug, Copy, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct Augmentation {
+    /// The standard deviation of Gaussian noise added independently to every feature
+    #[serde(default)]
+    pub noise_std: f64,
+    /// The maximum relative deviation of a random scale factor, drawn uniformly from `[1 - jitter, 1 + jitter]` for
+    /// each feature of each sequence, by which the feature is multiplied
+    #[serde(default)]
+    pub scale_jitter: f64,
+    /// The maximum relative change in speed when warping sequences in time. Each sequence of inputs and its targets
+    /// are resampled alike, linearly interpolating between rows, at a speed drawn uniformly from `[1 - warp, 1 + warp]`
+    /// and anchored at the last row, so that every row's inputs stay aligned with its targets.
+    #[serde(default)]
+    pub time_warp: f64,
+}
+
+impl Augmentation {
+    /// Whether any perturbation is enabled
+    pub fn is_enabled(&self) -> bool {
+        self.noise_std > 0.0 || self.scale_jitter > 0.0 || self.time_warp > 0.0
+    }
+    /// Augment the tick features of a batch of inputs to a network, warping its targets alike
+    pub fn apply(&self, lstm: &StockLSTM, input: &Tensor, output: &Tensor) -> (Tensor, Tensor) {
+        let start = lstm.additional_inputs + lstm.date_inputs;
+        self.apply_columns(input, output, start..lstm.no_inputs())
+    }
+    /// Augment a range of columns of a batch of inputs, leaving the other columns unchanged, and warp the batch's
+    /// outputs in time alike
+    pub fn apply_columns(
+        &self,
+        input: &Tensor,
+        output: &Tensor,
+        columns: Range<usize>,
+    ) -> (Tensor, Tensor) {
+        if !self.is_enabled() || columns.start >= columns.end {
+            return (input.shallow_clone(), output.shallow_clone());
+        }
+        let (input, output) = if self.time_warp > 0.0 {
+            let positions = warp_positions(&input.size(), self.time_warp, input.device());
+            (resample(input, &positions), resample(output, &positions))
+        } else {
+            (input.shallow_clone(), output.shallow_clone())
+        };
+        let width = input.size()[2];
+        let (start, end) = (columns.start as i64, columns.end as i64);
+        let augmented = self.augment(&input.narrow(2, start, end - start));
+        let input = Tensor::cat(
+            &[
+                input.narrow(2, 0, start),
+                augmented,
+                input.narrow(2, end, width - end),
+            ],
+            2,
+        );
+        (input, output)
+    }
+    /// Perturb every column of a batch of features
+    fn augment(&self, features: &Tensor) -> Tensor {
+        let size = features.size();
+        let options = (features.kind(), features.device());
+        let mut features = features.shallow_clone();
+        if self.scale_jitter > 0.0 {
+            let scale = Tensor::rand(&[size[0], 1, size[2]], options) * (2.0 * self.scale_jitter)
+                + (1.0 - self.scale_jitter);
+            features = features * scale;
+        }
+        if self.noise_std > 0.0 {
+            features = &features + features.randn_like() * self.noise_std;
+        }
+        features
+    }
+}
+
+/// Mixup augmentation: blending each window of a batch, inputs and targets alike, with another window of the same
+/// batch, as `lambda * window + (1 - lambda) * other`, where `lambda` is drawn from a symmetric Beta distribution for
+/// each window. Training on blended windows tends to improve the calibration of a network's predictions.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Mixup {
+    /// The parameter of the Beta distribution blending coefficients are drawn from. Small values keep most windows
+    /// close to one of the pair, while `1.0` blends uniformly. Mixup is disabled if this is not positive.
+    pub alpha: f64,
+}
+
+impl Mixup {
+    /// Blend the windows of a batch of inputs and outputs of shape `[batch, sequence, features]`, pairing each window
+    /// with the window at a random permutation of its index
+    pub fn apply<R: Rng>(&self, input: &Tensor, output: &Tensor, rng: &mut R) -> (Tensor, Tensor) {
+        let mut blended = self.blend(&[input, output], rng).into_iter();
+        let input = blended.next().expect("Blended inputs");
+        let output = blended.next().expect("Blended outputs");
+        (input, output)
+    }
+    /// Blend the windows of a batch like `apply`, together with their sample weights of shape `[batch, 1, 1]`, if any,
+    /// so that each blended window is weighted like the windows it blends rather than the window at its index
+    pub fn apply_weighted<R: Rng>(
+        &self,
+        input: &Tensor,
+        output: &Tensor,
+        weights: Option<&Tensor>,
+        rng: &mut R,
+    ) -> (Tensor, Tensor, Option<Tensor>) {
+        let mut batches = vec![input, output];
+        batches.extend(weights);
+        let mut blended = self.blend(&batches, rng).into_iter();
+        let input = blended.next().expect("Blended inputs");
+        let output = blended.next().expect("Blended outputs");
+        (input, output, blended.next())
+    }
+    /// Blend the windows of several tensors of shape `[batch, sequence, features]` alike
+    fn blend<R: Rng>(&self, batches: &[&Tensor], rng: &mut R) -> Vec<Tensor> {
+        let batch = batches[0].size()[0];
+        if self.alpha <= 0.0 || batch < 2 {
+            return batches.iter().map(|xs| xs.shallow_clone()).collect();
+        }
+        let beta = Beta::new(self.alpha, self.alpha).expect("Alpha is positive");
+        let lambdas: Vec<f32> = (0..batch).map(|_| beta.sample(rng) as f32).collect();
+        let complements: Vec<f32> = lambdas.iter().map(|lambda| 1.0 - lambda).collect();
+        let mut permutation: Vec<i64> = (0..batch).collect();
+        permutation.shuffle(rng);
+
+        let device = batches[0].device();
+        let lambdas = Tensor::of_slice(&lambdas)
+            .view([batch, 1, 1])
+            .to_device(device);
+        let complements = Tensor::of_slice(&complements)
+            .view([batch, 1, 1])
+            .to_device(device);
+        let permutation = Tensor::of_slice(&permutation).to_device(device);
+        let blend = |xs: &Tensor| {
+            xs * lambdas.to_kind(xs.kind())
+                + xs.index_select(0, &permutation) * complements.to_kind(xs.kind())
+        };
+        batches.iter().map(|xs| blend(xs)).collect()
+    }
+}
+
+/// Draw the positions to resample each sequence of a batch of the given size at, at a random speed in
+/// `[1 - warp, 1 + warp]` anchored at its last row, as a tensor of shape `[batch, sequence]`. Rows before the start of
+/// a sequence are clamped to its first row.
+fn warp_positions(size: &[i64], warp: f64, device: Device) -> Tensor {
+    let (batch, sequence_length) = (size[0], size[1]);
+    let last = (sequence_length - 1) as f64;
+    let speeds = Tensor::rand(&[batch, 1], (Kind::Float, device)) * (2.0 * warp) + (1.0 - warp);
+    let lags = Tensor::arange(sequence_length, (Kind::Float, device)).flip(&[0]);
+    (lags.unsqueeze(0) * speeds * -1.0 + last).clamp(0.0, last)
+}
+
+/// Resample each sequence of a batch at fractional row positions, linearly interpolating between rows
+fn resample(features: &Tensor, positions: &Tensor) -> Tensor {
+    let size = features.size();
+    let sequence_length = size[1];
+    let lower = positions.floor();
+    let fraction = (positions - &lower).unsqueeze(2).to_kind(features.kind());
+    let lower = lower.to_kind(Kind::Int64);
+    let upper = (&lower + 1).clamp_max(sequence_length - 1);
+    let gather =
+        |index: &Tensor| features.gather(1, &index.unsqueeze(2).expand(&size, false), false);
+    let lower = gather(&lower);
+    let upper = gather(&upper);
+    &lower + (upper - &lower) * fraction
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    #[test]
+    fn augmentation_only_touches_selected_columns() {
+        tch::manual_seed(7);
+        let input = Tensor::randn(&[4, 10, 6], tch::kind::FLOAT_CPU);
+        let output = Tensor::randn(&[4, 10, 2], tch::kind::FLOAT_CPU);
+        let (unchanged, _) = Augmentation::default().apply_columns(&input, &output, 2..6);
+        assert!(unchanged.equal(&input));
+
+        let augmentation = Augmentation {
+            noise_std: 0.1,
+            scale_jitter: 0.2,
+            time_warp: 0.3,
+        };
+        let (augmented, _) = augmentation.apply_columns(&input, &output, 2..5);
+        assert_eq!(augmented.size(), input.size());
+        assert!(augmented.narrow(2, 0, 2).equal(&input.narrow(2, 0, 2)));
+        assert!(augmented.narrow(2, 5, 1).equal(&input.narrow(2, 5, 1)));
+        assert!(!augmented.narrow(2, 2, 3).equal(&input.narrow(2, 2, 3)));
+
+        // Warping at any speed leaves the last row in place
+        let warped = resample(&input, &warp_positions(&input.size(), 0.5, Device::Cpu));
+        assert!(warped
+            .narrow(1, 9, 1)
+            .allclose(&input.narrow(1, 9, 1), 1e-5, 1e-6, false));
+        let unwarped = resample(&input, &warp_positions(&input.size(), 0.0, Device::Cpu));
+        assert!(unwarped.allclose(&input, 1e-5, 1e-6, false));
+    }
+
+    #[test]
+    fn time_warp_keeps_targets_aligned() {
+        tch::manual_seed(11);
+        // Targets which are a column of the inputs stay that column once both are warped
+        let input = Tensor::randn(&[4, 10, 3], tch::kind::FLOAT_CPU);
+        let output = input.narrow(2, 1, 1) * 2.0;
+        let warp = Augmentation {
+            time_warp: 0.4,
+            ..Default::default()
+        };
+        let (warped_input, warped_output) = warp.apply_columns(&input, &output, 0..3);
+        assert!(!warped_input.allclose(&input, 1e-5, 1e-6, false));
+        assert!(warped_output.allclose(&(warped_input.narrow(2, 1, 1) * 2.0), 1e-5, 1e-6, false));
+    }
+
+    #[test]
+    fn mixup_blends_inputs_and_targets_alike() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let rows = Tensor::of_slice(&[1.0f32, 2.0, 3.0, 4.0, 5.0]).view([5, 1, 1]);
+        let input = rows.expand(&[5, 4, 3], false).copy();
+        let output = rows.expand(&[5, 4, 2], false) * 2.0;
+        let (mixed_input, mixed_output) = Mixup { alpha: 0.4 }.apply(&input, &output, &mut rng);
+        assert_eq!(mixed_input.size(), input.size());
+        assert!(mixed_output.allclose(&(mixed_input.narrow(2, 0, 2) * 2.0), 1e-5, 1e-6, false));
+        assert!(f64::from(mixed_input.min()) >= 1.0 - 1e-6);
+        assert!(f64::from(mixed_input.max()) <= 5.0 + 1e-6);
+        let (unmixed, _) = Mixup { alpha: 0.0 }.apply(&input, &output, &mut rng);
+        assert!(unmixed.equal(&input));
+
+        // Sample weights are blended like the windows they weigh
+        let weights = rows.copy();
+        let (mixed_input, _, mixed_weights) =
+            Mixup { alpha: 0.4 }.apply_weighted(&input, &output, Some(&weights), &mut rng);
+        let mixed_weights = mixed_weights.expect("Blended weights");
+        assert!(mixed_weights.allclose(
+            &mixed_input.narrow(1, 0, 1).narrow(2, 0, 1),
+            1e-5,
+            1e-6,
+            false
+        ));
+    }
+}
@@ -0,0 +1,133 @@
+/*!
+Time and batch budgets bounding training runs, and GPU memory usage reporting
+*/
+use serde::{Deserialize, Serialize};
+use std::process::Command;
+use std::time::{Duration, Instant};
+use tch::Device;
+
+/// Limits on the resources used by a training run
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Default, Serialize, Deserialize)]
+pub struct Budget {
+    /// The maximum wall clock time of the run, if any
+    pub max_wall_clock: Option<Duration>,
+    /// The maximum number of training batches in each epoch, if any
+    pub max_batches_per_epoch: Option<usize>,
+}
+
+impl Budget {
+    /// Start timing a run against this budget
+    pub fn start(self) -> BudgetTimer {
+        BudgetTimer {
+            budget: self,
+            start: Instant::now(),
+        }
+    }
+}
+
+/// A budget, together with the time the run it bounds started
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct BudgetTimer {
+    /// The budget of the run
+    pub budget: Budget,
+    /// The time at which the run started
+    pub start: Instant,
+}
+
+impl BudgetTimer {
+    /// Get the wall clock time elapsed since the run started
+    pub fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+    /// Whether the run has used up its wall clock time
+    pub fn out_of_time(&self) -> bool {
+        self.budget
+            .max_wall_clock
+            .map(|max| self.elapsed() >= max)
+            .unwrap_or(false)
+    }
+    /// Whether an epoch which has seen a given number of training batches should end
+    pub fn epoch_exhausted(&self, batches: usize) -> bool {
+        self.budget
+            .max_batches_per_epoch
+            .map(|max| batches >= max)
+            .unwrap_or(false)
+    }
+}
+
+/// The memory usage of a GPU
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct GpuMemory {
+    /// The memory in use, in MiB
+    pub used_mib: u64,
+    /// The total memory, in MiB
+    pub total_mib: u64,
+}
+
+impl GpuMemory {
+    /// Get the fraction of memory in use
+    pub fn utilization(&self) -> f64 {
+        self.used_mib as f64 / self.total_mib as f64
+    }
+}
+
+/// Parse a line of `nvidia-smi --query-gpu=memory.used,memory.total --format=csv,noheader,nounits` output
+fn parse_memory(line: &str) -> Option<GpuMemory> {
+    let mut fields = line.split(',').map(|field| field.trim().parse::<u64>());
+    let used_mib = fields.next()?.ok()?;
+    let total_mib = fields.next()?.ok()?;
+    Some(GpuMemory {
+        used_mib,
+        total_mib,
+    })
+}
+
+/// Query the memory usage of a CUDA device using `nvidia-smi`, since libtorch does not expose it. Returns `None` for
+/// the CPU, or if `nvidia-smi` is unavailable or fails.
+///
+/// Note this includes memory cached by libtorch's allocator, which is in use as far as other processes are concerned.
+pub fn gpu_memory(device: Device) -> Option<GpuMemory> {
+    let ix = match device {
+        Device::Cuda(ix) => ix,
+        Device::Cpu => return None,
+    };
+    let output = Command::new("nvidia-smi")
+        .arg("--query-gpu=memory.used,memory.total")
+        .arg("--format=csv,noheader,nounits")
+        .arg(format!("--id={}", ix))
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    parse_memory(String::from_utf8_lossy(&output.stdout).lines().next()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn budgets_and_memory() {
+        let unbounded = Budget::default().start();
+        assert!(!unbounded.out_of_time());
+        assert!(!unbounded.epoch_exhausted(usize::MAX));
+        let bounded = Budget {
+            max_wall_clock: Some(Duration::from_secs(0)),
+            max_batches_per_epoch: Some(10),
+        }
+        .start();
+        assert!(bounded.out_of_time());
+        assert!(!bounded.epoch_exhausted(9));
+        assert!(bounded.epoch_exhausted(10));
+        assert_eq!(
+            parse_memory("1234, 16160"),
+            Some(GpuMemory {
+                used_mib: 1234,
+                total_mib: 16160
+            })
+        );
+        assert_eq!(parse_memory("[N/A], 16160"), None);
+        assert_eq!(gpu_memory(Device::Cpu), None);
+    }
+}
@@ -0,0 +1,157 @@
+/*!
+Hooks invoked by a training loop at epoch and batch boundaries, for custom logging, learning rate changes or
+notifications without modifying the loop itself
+*/
+use super::checkpoint::{write_metrics, EpochMetrics};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::PathBuf;
+
+/// The state of training at the end of a batch
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct BatchEnd {
+    /// The epoch, counting from zero
+    pub epoch: u64,
+    /// The number of training batches seen so far this epoch, including this one
+    pub batch: usize,
+    /// The loss of this batch
+    pub loss: f64,
+}
+
+/// A set of hooks invoked by a training loop. Every hook does nothing by default.
+pub trait Callback {
+    /// Called at the start of every epoch
+    fn on_epoch_start(&mut self, _epoch: u64) {}
+    /// Called after every training batch's optimizer step, with the current learning rate, which may be modified to
+    /// change the learning rate of subsequent batches
+    fn on_batch_end(&mut self, _batch: &BatchEnd, _lr: &mut f64) {}
+    /// Called at the end of every epoch, with the metrics of every phase seen so far
+    fn on_epoch_end(&mut self, _epoch: u64, _metrics: &[EpochMetrics]) {}
+    /// Called once training is over, including when stopping early, with the metrics of every phase seen
+    fn on_train_end(&mut self, _metrics: &[EpochMetrics]) {}
+}
+
+/// A list of callbacks, each of which is invoked in order
+#[derive(Default)]
+pub struct Callbacks(pub Vec<Box<dyn Callback>>);
+
+impl Callbacks {
+    /// Add a callback to the end of this list
+    pub fn push<C: Callback + 'static>(&mut self, callback: C) {
+        self.0.push(Box::new(callback))
+    }
+}
+
+impl Callback for Callbacks {
+    fn on_epoch_start(&mut self, epoch: u64) {
+        for callback in self.0.iter_mut() {
+            callback.on_epoch_start(epoch)
+        }
+    }
+    fn on_batch_end(&mut self, batch: &BatchEnd, lr: &mut f64) {
+        for callback in self.0.iter_mut() {
+            callback.on_batch_end(batch, lr)
+        }
+    }
+    fn on_epoch_end(&mut self, epoch: u64, metrics: &[EpochMetrics]) {
+        for callback in self.0.iter_mut() {
+            callback.on_epoch_end(epoch, metrics)
+        }
+    }
+    fn on_train_end(&mut self, metrics: &[EpochMetrics]) {
+        for callback in self.0.iter_mut() {
+            callback.on_train_end(metrics)
+        }
+    }
+}
+
+/// A callback rewriting a CSV file of every phase's metrics at the end of each epoch and of training, so progress can
+/// be followed while training runs. Write errors are logged and otherwise ignored.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct MetricsCsv {
+    /// The path of the CSV file
+    pub path: PathBuf,
+}
+
+impl MetricsCsv {
+    /// Write the metrics seen so far to the CSV file
+    fn write(&self, metrics: &[EpochMetrics]) {
+        let result = File::create(&self.path)
+            .map_err(csv::Error::from)
+            .and_then(|file| write_metrics(BufWriter::new(file), metrics));
+        if let Err(_err) = result {
+            warn!("Error writing metrics to {:?}: {}", self.path, _err);
+        }
+    }
+}
+
+impl Callback for MetricsCsv {
+    fn on_epoch_end(&mut self, _epoch: u64, metrics: &[EpochMetrics]) {
+        self.write(metrics)
+    }
+    fn on_train_end(&mut self, metrics: &[EpochMetrics]) {
+        self.write(metrics)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::train::Phase;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// Records the hooks invoked, and halves the learning rate every other batch
+    struct Recorder(Rc<RefCell<Vec<String>>>);
+
+    impl Callback for Recorder {
+        fn on_epoch_start(&mut self, epoch: u64) {
+            self.0.borrow_mut().push(format!("start {}", epoch))
+        }
+        fn on_batch_end(&mut self, batch: &BatchEnd, lr: &mut f64) {
+            if batch.batch % 2 == 0 {
+                *lr /= 2.0;
+            }
+            self.0.borrow_mut().push(format!("batch {}", batch.batch))
+        }
+        fn on_train_end(&mut self, metrics: &[EpochMetrics]) {
+            self.0.borrow_mut().push(format!("end {}", metrics.len()))
+        }
+    }
+
+    #[test]
+    fn callbacks_are_invoked_in_order() {
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let mut callbacks = Callbacks::default();
+        callbacks.push(Recorder(log.clone()));
+        callbacks.push(Recorder(log.clone()));
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("metrics.csv");
+        callbacks.push(MetricsCsv { path: path.clone() });
+
+        let mut lr = 1.0;
+        let mut metrics = EpochMetrics::new(0, Phase::Training);
+        callbacks.on_epoch_start(0);
+        for batch in 1..=2 {
+            metrics.push(0.5);
+            let end = BatchEnd {
+                epoch: 0,
+                batch,
+                loss: 0.5,
+            };
+            callbacks.on_batch_end(&end, &mut lr);
+        }
+        callbacks.on_epoch_end(0, &[metrics]);
+        callbacks.on_train_end(&[metrics]);
+
+        assert_eq!(lr, 0.25);
+        assert_eq!(
+            *log.borrow(),
+            vec![
+                "start 0", "start 0", "batch 1", "batch 1", "batch 2", "batch 2", "end 1", "end 1"
+            ]
+        );
+        let csv = std::fs::read_to_string(path).unwrap();
+        assert_eq!(csv.lines().count(), 2);
+    }
+}
@@ -0,0 +1,339 @@
+/*!
+Checkpoints of model variables, together with the descriptor of the model and snapshots of training metrics
+*/
+use crate::data::scale::{QuantileTransformer, TickScalerConfig};
+use crate::data::SymbolRegistry;
+use crate::lstm::{StockLSTM, StockLSTMDesc};
+use anyhow::format_err;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use tch::nn::VarStore;
+use tch::{Device, Tensor};
+
+/// A phase of an epoch
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Phase {
+    /// Training on the training set
+    Training,
+    /// Evaluating on the testing set
+    Testing,
+    /// Evaluating on a held-out validation slice, possibly several times per epoch
+    Validation,
+}
+
+/// Summary metrics of the batch losses of a phase of an epoch
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EpochMetrics {
+    /// The epoch, counting from zero
+    pub epoch: u64,
+    /// The phase of the epoch
+    pub phase: Phase,
+    /// The number of batches seen
+    pub batches: usize,
+    /// The sum of the batch losses
+    pub sum_loss: f64,
+    /// The largest batch loss
+    pub max_loss: f64,
+    /// The smallest batch loss
+    pub min_loss: f64,
+}
+
+impl EpochMetrics {
+    /// Start collecting metrics for a phase of an epoch
+    pub fn new(epoch: u64, phase: Phase) -> EpochMetrics {
+        EpochMetrics {
+            epoch,
+            phase,
+            batches: 0,
+            sum_loss: 0.0,
+            max_loss: -f64::INFINITY,
+            min_loss: f64::INFINITY,
+        }
+    }
+    /// Record the loss of a batch
+    pub fn push(&mut self, loss: f64) {
+        self.batches += 1;
+        self.sum_loss += loss;
+        self.max_loss = self.max_loss.max(loss);
+        self.min_loss = self.min_loss.min(loss);
+    }
+    /// Get the average batch loss, which is NaN if no batches have been seen
+    pub fn average_loss(&self) -> f64 {
+        self.sum_loss / self.batches as f64
+    }
+}
+
+/// Write metrics to a CSV file
+pub fn write_metrics<W: Write>(wtr: W, metrics: &[EpochMetrics]) -> Result<(), csv::Error> {
+    let mut wtr = csv::Writer::from_writer(wtr);
+    for metrics in metrics {
+        wtr.serialize(metrics)?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+/// Get the path of the descriptor stored alongside a checkpoint's variables, i.e. `{name}.desc.json` for `{name}.ot`
+pub fn desc_path<P: AsRef<Path>>(path: P) -> PathBuf {
+    path.as_ref().with_extension("desc.json")
+}
+
+/// Write a model descriptor to a JSON file
+pub fn write_desc<P: AsRef<Path>>(desc: &StockLSTMDesc, path: P) -> anyhow::Result<()> {
+    let path = path.as_ref();
+    let mut wtr = BufWriter::new(File::create(path)?);
+    serde_json::to_writer_pretty(&mut wtr, desc)
+        .map_err(|err| format_err!("Error writing descriptor to {:?}: {}", path, err))?;
+    wtr.flush()?;
+    Ok(())
+}
+
+/// Read a model descriptor from a JSON file
+pub fn read_desc<P: AsRef<Path>>(path: P) -> anyhow::Result<StockLSTMDesc> {
+    let path = path.as_ref();
+    let rdr = BufReader::new(File::open(path)?);
+    serde_json::from_reader(rdr)
+        .map_err(|err| format_err!("Error reading descriptor from {:?}: {}", path, err))
+}
+
+/// Get the path of the symbol registry stored alongside a checkpoint's variables, i.e. `{name}.symbols.json` for
+/// `{name}.ot`
+pub fn symbols_path<P: AsRef<Path>>(path: P) -> PathBuf {
+    path.as_ref().with_extension("symbols.json")
+}
+
+/// Write the symbols a model's stocks stand for alongside a checkpoint's variables, given the path of the variables
+pub fn write_symbols<P: AsRef<Path>>(registry: &SymbolRegistry, path: P) -> anyhow::Result<()> {
+    let path = symbols_path(path);
+    let mut wtr = BufWriter::new(File::create(&path)?);
+    serde_json::to_writer_pretty(&mut wtr, registry)
+        .map_err(|err| format_err!("Error writing symbols to {:?}: {}", path, err))?;
+    wtr.flush()?;
+    Ok(())
+}
+
+/// Read the symbols a model's stocks stand for from alongside a checkpoint's variables, given the path of the
+/// variables. Returns `None` if the checkpoint was saved without symbols.
+pub fn read_symbols<P: AsRef<Path>>(path: P) -> anyhow::Result<Option<SymbolRegistry>> {
+    let path = symbols_path(path);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let rdr = BufReader::new(File::open(&path)?);
+    serde_json::from_reader(rdr)
+        .map(Some)
+        .map_err(|err| format_err!("Error reading symbols from {:?}: {}", path, err))
+}
+
+/// How raw ticks were turned into a model's inputs in training, saved alongside a checkpoint so that inference can
+/// preprocess ticks in the same way
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct Preprocessing {
+    /// The parameters each stock's tick scaler was created with
+    pub scaler: TickScalerConfig,
+    /// The quantile transform applied to scaled ticks, if any
+    #[serde(default)]
+    pub quantiles: Option<QuantileTransformer>,
+}
+
+/// Get the path of the preprocessing stored alongside a checkpoint's variables, i.e. `{name}.preprocessing.json` for
+/// `{name}.ot`
+pub fn preprocessing_path<P: AsRef<Path>>(path: P) -> PathBuf {
+    path.as_ref().with_extension("preprocessing.json")
+}
+
+/// Write how a model's inputs were preprocessed alongside a checkpoint's variables, given the path of the variables
+pub fn write_preprocessing<P: AsRef<Path>>(
+    preprocessing: &Preprocessing,
+    path: P,
+) -> anyhow::Result<()> {
+    let path = preprocessing_path(path);
+    let mut wtr = BufWriter::new(File::create(&path)?);
+    serde_json::to_writer_pretty(&mut wtr, preprocessing)
+        .map_err(|err| format_err!("Error writing preprocessing to {:?}: {}", path, err))?;
+    wtr.flush()?;
+    Ok(())
+}
+
+/// Read how a model's inputs were preprocessed from alongside a checkpoint's variables, given the path of the
+/// variables. Returns `None` if the checkpoint was saved without its preprocessing.
+pub fn read_preprocessing<P: AsRef<Path>>(path: P) -> anyhow::Result<Option<Preprocessing>> {
+    let path = preprocessing_path(path);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let rdr = BufReader::new(File::open(&path)?);
+    serde_json::from_reader(rdr)
+        .map(Some)
+        .map_err(|err| format_err!("Error reading preprocessing from {:?}: {}", path, err))
+}
+
+/// Save a checkpoint of a model's variables to `{dir}/{name}.ot`, together with the model's descriptor in
+/// `{dir}/{name}.desc.json`, how its inputs were preprocessed in `{dir}/{name}.preprocessing.json`, and a snapshot of
+/// the metrics so far in `{dir}/{name}.metrics.csv`, creating `dir` if necessary. Returns the path of the variables.
+pub fn save_checkpoint<P: AsRef<Path>>(
+    vs: &VarStore,
+    desc: &StockLSTMDesc,
+    preprocessing: &Preprocessing,
+    metrics: &[EpochMetrics],
+    dir: P,
+    name: &str,
+) -> anyhow::Result<PathBuf> {
+    let dir = dir.as_ref();
+    fs::create_dir_all(dir)?;
+    let path = dir.join(format!("{}.ot", name));
+    vs.save(&path)
+        .map_err(|err| format_err!("Error saving checkpoint to {:?}: {:?}", path, err))?;
+    write_desc(desc, desc_path(&path))?;
+    write_preprocessing(preprocessing, &path)?;
+    write_metrics(
+        File::create(dir.join(format!("{}.metrics.csv", name)))?,
+        metrics,
+    )?;
+    Ok(path)
+}
+
+/// Load a checkpoint's variables into the `VarStore` of an existing model, given the path of the variables.
+///
+/// If a descriptor is stored alongside the variables, it is compared against the model's, and the variables are
+/// checked against the model's shapes before being loaded, so that incompatible checkpoints are reported with a
+/// description of each mismatch rather than failing deep inside libtorch.
+pub fn load_weights<P: AsRef<Path>>(
+    vs: &mut VarStore,
+    lstm: &StockLSTM,
+    path: P,
+) -> anyhow::Result<()> {
+    let path = path.as_ref();
+    let desc = desc_path(path);
+    if desc.exists() {
+        let mismatches = lstm.desc.mismatches(&read_desc(&desc)?);
+        if !mismatches.is_empty() {
+            return Err(format_err!(
+                "Checkpoint {:?} was saved from a different model:\n  {}",
+                path,
+                mismatches.join("\n  ")
+            ));
+        }
+    }
+    let variables = Tensor::load_multi(path)
+        .map_err(|err| format_err!("Error reading checkpoint from {:?}: {:?}", path, err))?;
+    lstm.validate_variables(&variables.into_iter().collect())
+        .map_err(|err| format_err!("Checkpoint {:?} does not fit the model: {}", path, err))?;
+    vs.load(path)
+        .map_err(|err| format_err!("Error loading checkpoint from {:?}: {:?}", path, err))
+}
+
+/// Load a checkpoint saved by `save_checkpoint` onto a device, given the path of its variables, rebuilding the model
+/// from the descriptor stored alongside them
+pub fn load_checkpoint<P: AsRef<Path>>(
+    path: P,
+    device: Device,
+) -> anyhow::Result<(VarStore, StockLSTM)> {
+    let path = path.as_ref();
+    let desc = read_desc(desc_path(path))?;
+    let mut vs = VarStore::new(device);
+    let lstm = desc.build(&vs);
+    load_weights(&mut vs, &lstm, path)?;
+    Ok((vs, lstm))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::{Target, Tick};
+
+    #[test]
+    fn metrics_snapshot() {
+        let mut metrics = EpochMetrics::new(3, Phase::Testing);
+        metrics.push(1.0);
+        metrics.push(3.0);
+        assert_eq!(metrics.average_loss(), 2.0);
+        let mut csv = Vec::new();
+        write_metrics(&mut csv, &[metrics]).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "epoch,phase,batches,sum_loss,max_loss,min_loss\n3,testing,2,4.0,3.0,1.0\n"
+        );
+    }
+
+    #[test]
+    fn checkpoint_roundtrip() {
+        let desc = StockLSTMDesc {
+            additional_inputs: 1,
+            date_inputs: 2,
+            stocks: 2,
+            hidden: 4,
+            layers: 1,
+            heads: vec![Target::Close, Target::Volatility],
+            layer_norm: true,
+            input_skip: true,
+            ..Default::default()
+        };
+        let vs = VarStore::new(Device::Cpu);
+        desc.build(&vs);
+        let dir = tempfile::tempdir().unwrap();
+        let preprocessing = Preprocessing {
+            scaler: TickScalerConfig::uniform(0.9, 0.99).with_warmup(3),
+            quantiles: Some(QuantileTransformer {
+                references: vec![vec![0.0, 1.0]; Tick::NN_FIELDS],
+            }),
+        };
+        let path = save_checkpoint(&vs, &desc, &preprocessing, &[], dir.path(), "test").unwrap();
+        assert_eq!(desc_path(&path), dir.path().join("test.desc.json"));
+        let (loaded_vs, lstm) = load_checkpoint(&path, Device::Cpu).unwrap();
+        assert_eq!(lstm.desc, desc);
+        assert_eq!(read_preprocessing(&path).unwrap(), Some(preprocessing));
+        assert_eq!(read_symbols(&path).unwrap(), None);
+        let registry = SymbolRegistry::new(vec!["AMD".into(), "NVDA".into()]).unwrap();
+        write_symbols(&registry, &path).unwrap();
+        assert_eq!(read_symbols(&path).unwrap(), Some(registry));
+        let loaded = loaded_vs.variables();
+        for (name, var) in vs.variables() {
+            assert!(loaded[&name].equal(&var), "{}", name);
+        }
+    }
+
+    #[test]
+    fn mismatches_are_reported() {
+        let desc = StockLSTMDesc {
+            date_inputs: 2,
+            stocks: 2,
+            hidden: 4,
+            layers: 1,
+            heads: vec![Target::Close],
+            ..Default::default()
+        };
+        let vs = VarStore::new(Device::Cpu);
+        desc.build(&vs);
+        let dir = tempfile::tempdir().unwrap();
+        let path = save_checkpoint(
+            &vs,
+            &desc,
+            &Preprocessing::default(),
+            &[],
+            dir.path(),
+            "test",
+        )
+        .unwrap();
+        let wider = StockLSTMDesc {
+            stocks: 3,
+            ..desc.clone()
+        };
+        assert_eq!(desc.mismatches(&wider), vec!["stocks is 3, expected 2"]);
+        let mut wider_vs = VarStore::new(Device::Cpu);
+        let wider_lstm = wider.build(&wider_vs);
+        assert!(wider_lstm.validate_against(&vs).is_err());
+        let err = load_weights(&mut wider_vs, &wider_lstm, &path).unwrap_err();
+        assert!(err.to_string().contains("stocks is 2, expected 3"));
+        fs::remove_file(desc_path(&path)).unwrap();
+        let err = load_weights(&mut wider_vs, &wider_lstm, &path).unwrap_err();
+        assert!(err.to_string().contains("has shape"));
+        let mut same_vs = VarStore::new(Device::Cpu);
+        let same = desc.build(&same_vs);
+        same.validate_against(&vs).unwrap();
+        load_weights(&mut same_vs, &same, &path).unwrap();
+    }
+}
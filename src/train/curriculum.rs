@@ -0,0 +1,74 @@
+/*!
+Curricula growing the sequence length and batch size of training batches over epochs
+*/
+use serde::{Deserialize, Serialize};
+
+/// A schedule of batch shapes, starting with short sequences in small batches and growing them geometrically over a
+/// number of epochs, which stabilizes early training and produces a useful checkpoint sooner
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct Curriculum {
+    /// The batch size of the first epoch
+    pub start_batch_size: usize,
+    /// The sequence length of the first epoch
+    pub start_sequence_length: usize,
+    /// The batch size once the curriculum is complete
+    pub batch_size: usize,
+    /// The sequence length once the curriculum is complete
+    pub sequence_length: usize,
+    /// The number of epochs taken to reach the final batch shape
+    pub epochs: usize,
+}
+
+impl Curriculum {
+    /// A curriculum which uses the same batch shape every epoch
+    pub fn constant(batch_size: usize, sequence_length: usize) -> Curriculum {
+        Curriculum {
+            start_batch_size: batch_size,
+            start_sequence_length: sequence_length,
+            batch_size,
+            sequence_length,
+            epochs: 0,
+        }
+    }
+    /// Get the batch size and sequence length to use in an epoch
+    pub fn at(&self, epoch: usize) -> (usize, usize) {
+        if epoch >= self.epochs {
+            return (self.batch_size, self.sequence_length);
+        }
+        let progress = epoch as f64 / self.epochs as f64;
+        let grow = |start: usize, end: usize| {
+            let start = start.max(1) as f64;
+            let size = start * (end as f64 / start).powf(progress);
+            (size.round() as usize).max(1)
+        };
+        (
+            grow(self.start_batch_size, self.batch_size),
+            grow(self.start_sequence_length, self.sequence_length),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shapes_grow_to_their_final_size() {
+        let curriculum = Curriculum {
+            start_batch_size: 64,
+            start_sequence_length: 32,
+            batch_size: 256,
+            sequence_length: 180,
+            epochs: 4,
+        };
+        assert_eq!(curriculum.at(0), (64, 32));
+        assert_eq!(curriculum.at(2), (128, 76));
+        assert_eq!(curriculum.at(4), (256, 180));
+        assert_eq!(curriculum.at(10), (256, 180));
+        let shapes: Vec<_> = (0..=4).map(|epoch| curriculum.at(epoch)).collect();
+        assert!(shapes
+            .windows(2)
+            .all(|w| w[0].0 <= w[1].0 && w[0].1 <= w[1].1));
+        assert_eq!(Curriculum::constant(8, 16).at(0), (8, 16));
+    }
+}
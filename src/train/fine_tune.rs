@@ -0,0 +1,153 @@
+/*!
+Fine-tuning a pretrained network with some or all of its recurrent layers frozen, e.g. to cheaply transfer it to a newly
+listed symbol by training only its output heads
+*/
+use super::checkpoint::load_checkpoint;
+use crate::lstm::StockLSTM;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use tch::nn::VarStore;
+use tch::Device;
+
+/// A selection of the recurrent layers of a network
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum LayerSelection {
+    /// No layers
+    None,
+    /// Every recurrent layer, including any layer normalizations
+    All,
+    /// The first this many recurrent layers, counting from the input, including their layer normalizations
+    First(usize),
+}
+
+impl LayerSelection {
+    /// Whether the recurrent layer with a given index, counting from zero at the input, is selected
+    pub fn contains(&self, layer: usize) -> bool {
+        match self {
+            LayerSelection::None => false,
+            LayerSelection::All => true,
+            LayerSelection::First(layers) => layer < *layers,
+        }
+    }
+}
+
+/// Get the index of the recurrent layer a variable of a `StockLSTM` belongs to, given its name, or `None` if it is
+/// not part of a recurrent layer, e.g. a head's weights.
+///
+/// Variables of a stack of LSTMs live under `layer{k}/`, while those of a single multi-layer LSTM are suffixed with
+/// `_l{k}`, optionally followed by `_reverse` for the backward direction.
+pub fn recurrent_layer(name: &str) -> Option<usize> {
+    if name.starts_with("layer") {
+        let rest = &name["layer".len()..];
+        return rest[..rest.find('/')?].parse().ok();
+    }
+    if name.contains('/') {
+        return None;
+    }
+    let suffix = &name[name.rfind("_l")? + 2..];
+    let digits = suffix
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(suffix.len());
+    suffix[..digits].parse().ok()
+}
+
+/// Freeze the selected recurrent layers of a network whose variables live in a `VarStore`, so that optimizers
+/// leave them unchanged. Returns the number of variables frozen.
+pub fn freeze_layers(vs: &VarStore, selection: LayerSelection) -> usize {
+    let mut frozen = 0;
+    for (name, var) in vs.variables() {
+        if recurrent_layer(&name).map_or(false, |layer| selection.contains(layer)) {
+            let _ = var.set_requires_grad(false);
+            frozen += 1;
+        }
+    }
+    frozen
+}
+
+/// Load a checkpoint saved by `save_checkpoint` for fine-tuning, freezing the selected recurrent layers so that
+/// training only updates the remaining layers and the output heads. An optimizer should be built on the returned
+/// `VarStore` as usual; frozen variables never receive gradients, so it leaves them unchanged.
+pub fn fine_tune<P: AsRef<Path>>(
+    checkpoint: P,
+    device: Device,
+    freeze: LayerSelection,
+) -> anyhow::Result<(VarStore, StockLSTM)> {
+    let (vs, lstm) = load_checkpoint(checkpoint, device)?;
+    let frozen = freeze_layers(&vs, freeze);
+    debug!("Froze {} variables for fine-tuning", frozen);
+    Ok((vs, lstm))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::Target;
+    use crate::lstm::StockLSTMDesc;
+    use crate::train::{save_checkpoint, Preprocessing};
+    use tch::{nn, Kind, Tensor};
+
+    #[test]
+    fn layer_names() {
+        assert_eq!(recurrent_layer("weight_ih_l0"), Some(0));
+        assert_eq!(recurrent_layer("bias_hh_l12_reverse"), Some(12));
+        assert_eq!(recurrent_layer("layer3/norm/weight"), Some(3));
+        assert_eq!(recurrent_layer("layer1/weight_hh_l0"), Some(1));
+        assert_eq!(recurrent_layer("close/weight"), None);
+        assert_eq!(recurrent_layer("volatility/bias"), None);
+    }
+
+    #[test]
+    fn fine_tuning_freezes_recurrent_layers() {
+        let desc = StockLSTMDesc {
+            stocks: 1,
+            hidden: 3,
+            layers: 2,
+            heads: vec![Target::Close, Target::Volume],
+            layer_norm: true,
+            ..Default::default()
+        };
+        let vs = VarStore::new(Device::Cpu);
+        desc.build(&vs);
+        assert_eq!(freeze_layers(&vs, LayerSelection::First(1)), 6);
+        let variables = vs.variables();
+        assert!(!variables["layer0/weight_ih_l0"].requires_grad());
+        assert!(!variables["layer0/norm/bias"].requires_grad());
+        assert!(variables["layer1/weight_ih_l0"].requires_grad());
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = save_checkpoint(
+            &vs,
+            &desc,
+            &Preprocessing::default(),
+            &[],
+            dir.path(),
+            "pretrained",
+        )
+        .unwrap();
+        let (vs, lstm) = fine_tune(&path, Device::Cpu, LayerSelection::All).unwrap();
+        for (name, var) in vs.variables() {
+            let head = name.starts_with("close/") || name.starts_with("volume/");
+            assert_eq!(var.requires_grad(), head, "{}", name);
+        }
+
+        // A training step only updates the heads
+        let before: Vec<_> = vs
+            .variables()
+            .into_iter()
+            .map(|(name, var)| (name, var.copy()))
+            .collect();
+        let input = Tensor::ones(&[2, 3, desc.no_inputs() as i64], (Kind::Float, Device::Cpu));
+        let output = Tensor::zeros(
+            &[2, 3, lstm.no_outputs() as i64],
+            (Kind::Float, Device::Cpu),
+        );
+        let (loss, _) = lstm.loss(&input, &output, &lstm.zero_state(2));
+        let mut opt = nn::Adam::default().build(&vs, 0.1).unwrap();
+        opt.backward_step_clip(&loss, 0.5);
+        let variables = vs.variables();
+        for (name, var) in before {
+            let head = name.starts_with("close/") || name.starts_with("volume/");
+            assert_eq!(variables[&name] != var, head, "{}", name);
+        }
+    }
+}
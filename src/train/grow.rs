@@ -0,0 +1,207 @@
+/*!
+Warm-starting newly listed symbols in a trained multi-stock network, by growing its `stocks` and initializing the new
+symbols' input columns and output rows from the existing ones rather than retraining from scratch
+*/
+use super::checkpoint::load_checkpoint;
+use crate::lstm::{StockLSTM, StockLSTMDesc};
+use anyhow::format_err;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use tch::nn::VarStore;
+use tch::{Device, Kind, Tensor};
+
+/// How to initialize the weights of a symbol added to a network
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum NewStock {
+    /// Copy the weights of the existing stock with this index, e.g. a symbol from the same sector
+    Donor(usize),
+    /// Average the weights of every existing stock
+    Average,
+}
+
+/// Grow a variable along a dimension ending in one block of `block` entries per existing stock, appending a block
+/// for each new stock
+fn grow_blocks(
+    var: &Tensor,
+    dim: i64,
+    block: i64,
+    stocks: i64,
+    new_stocks: &[NewStock],
+) -> anyhow::Result<Tensor> {
+    let offset = var.size()[dim as usize] - stocks * block;
+    let mut blocks = vec![var.shallow_clone()];
+    for new_stock in new_stocks {
+        let grown = match *new_stock {
+            NewStock::Donor(donor) if (donor as i64) < stocks => {
+                var.narrow(dim, offset + donor as i64 * block, block)
+            }
+            NewStock::Donor(donor) => {
+                return Err(format_err!(
+                    "Donor stock {} is out of range for a network over {} stocks",
+                    donor,
+                    stocks
+                ))
+            }
+            NewStock::Average if stocks > 0 => {
+                let existing: Vec<Tensor> = (0..stocks)
+                    .map(|stock| var.narrow(dim, offset + stock * block, block))
+                    .collect();
+                Tensor::stack(&existing, 0).mean1(&[0], false, Kind::Float)
+            }
+            NewStock::Average => {
+                return Err(format_err!(
+                    "Cannot average the weights of a network without stocks"
+                ))
+            }
+        };
+        blocks.push(grown);
+    }
+    Ok(Tensor::cat(&blocks, dim))
+}
+
+/// Grow a network, whose variables live in a `VarStore`, to predict additional stocks, returning the grown network
+/// together with a new `VarStore` on the same device holding its variables.
+///
+/// Every variable is copied from the original network, except that the new stocks' columns of the first recurrent
+/// layer's input weights (and of the heads' weights, if the network has an input skip connection) and their rows of
+/// the heads' weights and biases are initialized as described by `new_stocks`, one entry per added stock. The new
+/// stocks come after the existing ones in the grown network's inputs and outputs, so the existing stocks'
+/// predictions are unchanged as long as the new stocks' inputs are zero. Head weights, dropout and regularization are
+/// preserved.
+///
+/// If the network is grouped by sector, each new stock joins its donor's sector, and averaging is not supported.
+pub fn grow_stocks(
+    vs: &VarStore,
+    lstm: &StockLSTM,
+    new_stocks: &[NewStock],
+) -> anyhow::Result<(VarStore, StockLSTM)> {
+    let sectors = match &lstm.desc.sectors {
+        Some(sectors) => {
+            let mut sectors = sectors.clone();
+            for new_stock in new_stocks {
+                let sector = match *new_stock {
+                    NewStock::Donor(donor) => sectors.of_stock.get(donor).copied(),
+                    NewStock::Average => None,
+                };
+                let sector = sector.ok_or_else(|| {
+                    format_err!("New stocks of a network grouped by sector must copy a donor stock")
+                })?;
+                sectors.of_stock.push(sector);
+            }
+            Some(sectors)
+        }
+        None => None,
+    };
+    let desc = StockLSTMDesc {
+        stocks: lstm.desc.stocks + new_stocks.len(),
+        sectors,
+        ..lstm.desc.clone()
+    };
+    let new_vs = VarStore::new(vs.device());
+    let mut grown = desc.build(&new_vs);
+    let stocks = lstm.desc.stocks as i64;
+    let added = new_stocks.len() as i64;
+    let old = vs.variables();
+    tch::no_grad(|| -> anyhow::Result<()> {
+        for (name, mut var) in new_vs.variables() {
+            let mut value = old
+                .get(&name)
+                .ok_or_else(|| format_err!("Variable {} is missing from the network", name))?
+                .shallow_clone();
+            for (dim, size) in var.size().into_iter().enumerate() {
+                let grown_by = size - value.size()[dim];
+                if grown_by == 0 {
+                    continue;
+                }
+                if added == 0 || grown_by % added != 0 {
+                    return Err(format_err!(
+                        "Variable {} grew by {} entries in dimension {} for {} new stocks",
+                        name,
+                        grown_by,
+                        dim,
+                        added
+                    ));
+                }
+                value = grow_blocks(&value, dim as i64, grown_by / added, stocks, new_stocks)?;
+            }
+            var.copy_(&value);
+        }
+        Ok(())
+    })?;
+    grown.copy_settings_from(lstm);
+    debug!(
+        "Grew network from {} to {} stocks",
+        lstm.desc.stocks, desc.stocks
+    );
+    Ok((new_vs, grown))
+}
+
+/// Load a checkpoint saved by `save_checkpoint` onto a device and grow it to predict additional stocks, as described
+/// by `grow_stocks`. The result can be trained further, e.g. with its recurrent layers frozen by `freeze_layers`.
+pub fn grow_checkpoint<P: AsRef<Path>>(
+    checkpoint: P,
+    device: Device,
+    new_stocks: &[NewStock],
+) -> anyhow::Result<(VarStore, StockLSTM)> {
+    let (vs, lstm) = load_checkpoint(checkpoint, device)?;
+    grow_stocks(&vs, &lstm, new_stocks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::{Target, Tick};
+    use tch::nn::RNN;
+
+    #[test]
+    fn growing_preserves_existing_stocks() {
+        let desc = StockLSTMDesc {
+            additional_inputs: 1,
+            date_inputs: 2,
+            stocks: 2,
+            hidden: 4,
+            layers: 2,
+            heads: vec![Target::Close, Target::Volatility],
+            layer_norm: true,
+            input_skip: true,
+            ..Default::default()
+        };
+        let vs = VarStore::new(Device::Cpu);
+        let lstm = desc.build(&vs);
+        let new_stocks = [NewStock::Donor(1), NewStock::Average];
+        let (grown_vs, grown) = grow_stocks(&vs, &lstm, &new_stocks).unwrap();
+        assert_eq!(grown.desc.stocks, 4);
+        grown.validate_against(&grown_vs).unwrap();
+
+        let (old, new) = (vs.variables(), grown_vs.variables());
+        let fields = Tick::NN_FIELDS as i64;
+        let first = (desc.additional_inputs + desc.date_inputs) as i64;
+        let weight_ih = "layer0/weight_ih_l0";
+        assert!(new[weight_ih]
+            .narrow(1, 0, first + 2 * fields)
+            .equal(&old[weight_ih]));
+        assert!(new[weight_ih]
+            .narrow(1, first + 2 * fields, fields)
+            .equal(&old[weight_ih].narrow(1, first + fields, fields)));
+        let bias = &old["close/bias"];
+        let average = (bias.double_value(&[0]) + bias.double_value(&[1])) / 2.0;
+        assert!((new["close/bias"].double_value(&[3]) - average).abs() < 1e-6);
+        assert_eq!(new["close/weight"].size(), vec![4, 4 + 1 + 2 + 4 * fields]);
+        assert!(new["layer1/weight_hh_l0"].equal(&old["layer1/weight_hh_l0"]));
+
+        // With zero inputs for the new stocks, the existing stocks' predictions are unchanged
+        let input = Tensor::randn(&[2, 3, desc.no_inputs() as i64], (Kind::Float, Device::Cpu));
+        let padding = Tensor::zeros(&[2, 3, 2 * fields], (Kind::Float, Device::Cpu));
+        let grown_input = Tensor::cat(&[&input, &padding], 2);
+        let (output, _) = lstm.forward_t(&input, &lstm.zero_state(2), false);
+        let (grown_output, _) = grown.forward_t(&grown_input, &grown.zero_state(2), false);
+        for target in desc.heads.iter() {
+            let old_head = lstm.head_output(&output, *target).unwrap();
+            let new_head = grown.head_output(&grown_output, *target).unwrap();
+            let difference = (new_head.narrow(2, 0, 2) - old_head).abs().max();
+            assert!(f64::from(difference) < 1e-6, "{:?}", target);
+        }
+
+        assert!(grow_stocks(&vs, &lstm, &[NewStock::Donor(2)]).is_err());
+    }
+}
@@ -0,0 +1,175 @@
+/*!
+Interop with PyTorch data pipelines: exposing batches of sequences as `tch` datasets which can be shuffled and
+re-batched with `tch::data::Iter2`, and reading and writing them as tensor files shared with Python preprocessing
+*/
+use crate::lstm::StockLSTM;
+use anyhow::format_err;
+use std::path::Path;
+use tch::data::Iter2;
+use tch::{Device, Tensor};
+
+/// The name of the input tensor in a sequence dataset file
+pub const INPUT_NAME: &str = "input";
+/// The name of the output tensor in a sequence dataset file
+pub const OUTPUT_NAME: &str = "output";
+
+/// A dataset of input and output sequences, of shapes `[sequences, sequence_length, inputs]` and
+/// `[sequences, sequence_length, outputs]`, as packaged by `StockLSTM::batches` or produced by a PyTorch pipeline
+#[derive(Debug)]
+pub struct SequenceDataset {
+    /// The input sequences
+    pub input: Tensor,
+    /// The output sequences
+    pub output: Tensor,
+}
+
+impl SequenceDataset {
+    /// Create a dataset from input and output sequences, returning an error if their shapes do not match
+    pub fn new(input: Tensor, output: Tensor) -> anyhow::Result<SequenceDataset> {
+        let (input_size, output_size) = (input.size(), output.size());
+        if input_size.len() != 3 || output_size.len() != 3 || input_size[..2] != output_size[..2] {
+            return Err(format_err!(
+                "Inputs of shape {:?} and outputs of shape {:?} are not sequences of the same shape",
+                input_size,
+                output_size
+            ));
+        }
+        Ok(SequenceDataset { input, output })
+    }
+    /// Collect batches of sequences, e.g. those yielded by `StockLSTM::batches`, into a single dataset. Returns `None`
+    /// if there are no batches.
+    pub fn from_batches<I>(batches: I) -> Option<SequenceDataset>
+    where
+        I: IntoIterator<Item = (Tensor, Tensor)>,
+    {
+        let (inputs, outputs): (Vec<Tensor>, Vec<Tensor>) = batches.into_iter().unzip();
+        if inputs.is_empty() {
+            return None;
+        }
+        Some(SequenceDataset {
+            input: Tensor::cat(&inputs, 0),
+            output: Tensor::cat(&outputs, 0),
+        })
+    }
+    /// Get the number of sequences in this dataset
+    pub fn len(&self) -> usize {
+        self.input.size()[0] as usize
+    }
+    /// Whether this dataset has no sequences
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// Check that this dataset's sequences fit a network's inputs and outputs
+    pub fn validate(&self, lstm: &StockLSTM) -> anyhow::Result<()> {
+        let (inputs, outputs) = (self.input.size()[2], self.output.size()[2]);
+        if inputs != lstm.no_inputs() as i64 || outputs != lstm.no_outputs() as i64 {
+            return Err(format_err!(
+                "Sequences have {} inputs and {} outputs, but the network has {} and {}",
+                inputs,
+                outputs,
+                lstm.no_inputs(),
+                lstm.no_outputs()
+            ));
+        }
+        Ok(())
+    }
+    /// Move this dataset to a device
+    pub fn to_device(&self, device: Device) -> SequenceDataset {
+        SequenceDataset {
+            input: self.input.to_device(device),
+            output: self.output.to_device(device),
+        }
+    }
+    /// Iterate over this dataset in order in batches of `batch_size` sequences, as a `tch` dataset iterator, which
+    /// can be shuffled with `Iter2::shuffle` and moved to a device with `Iter2::to_device`. The last batch is dropped
+    /// if it is smaller, unless `Iter2::return_smaller_last_batch` is called.
+    pub fn iter(&self, batch_size: usize) -> Iter2 {
+        Iter2::new(&self.input, &self.output, batch_size as i64)
+    }
+    /// Iterate over this dataset in a random order in batches of `batch_size` sequences
+    pub fn shuffled(&self, batch_size: usize) -> Iter2 {
+        let mut iter = self.iter(batch_size);
+        iter.shuffle();
+        iter
+    }
+    /// Save this dataset to a file of named tensors, which can be read by `SequenceDataset::load`
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        Tensor::save_multi(
+            &[(INPUT_NAME, &self.input), (OUTPUT_NAME, &self.output)],
+            path,
+        )
+        .map_err(|err| format_err!("Error saving sequences to {:?}: {:?}", path, err))
+    }
+    /// Load a dataset from a file of named tensors `input` and `output`, as written by `SequenceDataset::save`. Files
+    /// ending in `.npz` are read as NumPy archives, e.g. those written from Python by
+    /// `numpy.savez(path, input=input, output=output)`. Other files, such as `.pt` files, are read as libtorch
+    /// archives, e.g. those written from Python by `torch.jit.save` of a scripted module with `input` and `output`
+    /// buffers.
+    pub fn load<P: AsRef<Path>>(path: P) -> anyhow::Result<SequenceDataset> {
+        let path = path.as_ref();
+        let npz = path
+            .extension()
+            .map_or(false, |extension| extension == "npz");
+        let tensors = if npz {
+            Tensor::read_npz(path)
+        } else {
+            Tensor::load_multi(path)
+        }
+        .map_err(|err| format_err!("Error reading sequences from {:?}: {:?}", path, err))?;
+        let mut input = None;
+        let mut output = None;
+        for (name, tensor) in tensors {
+            // Archives of scripted modules prefix each buffer's name with its path
+            match name.rsplit('.').next().unwrap_or(&name) {
+                INPUT_NAME => input = Some(tensor),
+                OUTPUT_NAME => output = Some(tensor),
+                _ => {}
+            }
+        }
+        match (input, output) {
+            (Some(input), Some(output)) => SequenceDataset::new(input, output),
+            _ => Err(format_err!(
+                "{:?} does not contain both {} and {} tensors",
+                path,
+                INPUT_NAME,
+                OUTPUT_NAME
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tch::Kind;
+
+    #[test]
+    fn sequences_round_trip() {
+        let batches = (0..3).map(|batch| {
+            (
+                Tensor::ones(&[2, 5, 4], (Kind::Float, Device::Cpu)) * batch as f64,
+                Tensor::zeros(&[2, 5, 3], (Kind::Float, Device::Cpu)),
+            )
+        });
+        let dataset = SequenceDataset::from_batches(batches).unwrap();
+        assert_eq!(dataset.len(), 6);
+        assert_eq!(dataset.shuffled(4).count(), 1);
+        let mut iter = dataset.iter(4);
+        iter.return_smaller_last_batch();
+        let sizes: Vec<i64> = iter.map(|(input, _)| input.size()[0]).collect();
+        assert_eq!(sizes, vec![4, 2]);
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sequences.pt");
+        dataset.save(&path).unwrap();
+        let loaded = SequenceDataset::load(&path).unwrap();
+        assert!(loaded.input.equal(&dataset.input));
+        assert!(loaded.output.equal(&dataset.output));
+        assert!(SequenceDataset::new(
+            dataset.input,
+            Tensor::zeros(&[5, 5, 3], (Kind::Float, Device::Cpu))
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,227 @@
+/*!
+Importing the weights of models trained by the original Julia/Knet implementation at
+https://gitlab.com/tekne/stock-lstm, so that they can be evaluated in Rust, e.g. for parity checks
+
+The weights are read from an NPZ archive with one array per parameter, as exported from Julia. For each LSTM layer
+`l`, counting from 1 as in Julia, and each gate `g` in Knet's (and cuDNN's) order `i`, `f`, `c`, `o`, i.e. input,
+forget, new memory and output, the archive contains
+
+- `w{l}_{g}`: the gate's input weights, of shape `[hidden, inputs]`, where `inputs` is the width of the layer's input
+- `r{l}_{g}`: the gate's recurrent weights, of shape `[hidden, hidden]`
+- `bw{l}_{g}` and `br{l}_{g}`: the gate's input and recurrent biases, of shape `[hidden]`
+
+as given by Knet's `rnnparam(rnn, l, id, 1)` and `rnnparam(rnn, l, id, 2)` for gate ids 1 to 4 and 5 to 8. The dense
+output layer is stored as `dense_w`, of shape `[outputs, hidden]`, and `dense_b`, of shape `[outputs]`, with outputs in
+the order of `StockLSTMDesc::output_layout`, i.e. target by target with one output per stock.
+
+Julia arrays are column-major, so archives exported through a row-major tool, e.g. by reading a JLD2 file with `h5py`
+and saving it with `numpy.savez`, see each matrix transposed. Such archives are imported with
+`MatrixOrder::ColumnMajor`.
+*/
+use crate::lstm::StockLSTM;
+use anyhow::format_err;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use tch::nn::VarStore;
+use tch::{Kind, Tensor};
+
+/// The names of the gates of an LSTM layer in Knet's order, which matches the order of the gates' rows in tch's weights
+pub const GATES: [&str; 4] = ["i", "f", "c", "o"];
+
+/// The order in which the matrices of an archive were written
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum MatrixOrder {
+    /// Each matrix has the documented shape
+    RowMajor,
+    /// Each matrix is transposed, as when a column-major Julia array is read by a row-major tool
+    ColumnMajor,
+}
+
+impl Default for MatrixOrder {
+    fn default() -> MatrixOrder {
+        MatrixOrder::RowMajor
+    }
+}
+
+/// Read a Julia model's parameters from an NPZ archive, laid out as described in the module documentation, and map
+/// them to the names and layout of a network's variables. Returns an error if a parameter is missing or has the wrong
+/// shape, or if the network has features the Julia implementation lacks, such as layer normalization.
+pub fn julia_variables<P: AsRef<Path>>(
+    lstm: &StockLSTM,
+    path: P,
+    order: MatrixOrder,
+) -> anyhow::Result<HashMap<String, Tensor>> {
+    let path = path.as_ref();
+    let desc = &lstm.desc;
+    if desc.layer_norm
+        || desc.residual
+        || desc.input_skip
+        || desc.bidirectional
+        || desc.sectors.is_some()
+    {
+        return Err(format_err!(
+            "Julia models are plain unidirectional LSTMs, without layer normalization, residual connections, input \
+            skip connections or sectors"
+        ));
+    }
+    let mut params: HashMap<String, Tensor> = Tensor::read_npz(path)
+        .map_err(|err| format_err!("Error reading Julia weights from {:?}: {:?}", path, err))?
+        .into_iter()
+        .collect();
+    let mut take = |name: &str, shape: &[i64]| -> anyhow::Result<Tensor> {
+        let param = params
+            .remove(name)
+            .ok_or_else(|| format_err!("Parameter {} is missing from {:?}", name, path))?;
+        let param = match order {
+            MatrixOrder::ColumnMajor if shape.len() == 2 => param.tr(),
+            _ => param,
+        };
+        if param.size() != shape {
+            return Err(format_err!(
+                "Parameter {} has shape {:?}, expected {:?}",
+                name,
+                param.size(),
+                shape
+            ));
+        }
+        Ok(param.to_kind(Kind::Float).contiguous())
+    };
+
+    let hidden = desc.hidden as i64;
+    let mut variables = HashMap::new();
+    for layer in 0..desc.layers {
+        let inputs = if layer == 0 {
+            desc.no_inputs() as i64
+        } else {
+            hidden
+        };
+        let l = layer + 1;
+        let mut gates = |prefix: &str, shape: &[i64]| -> anyhow::Result<Tensor> {
+            let gates = GATES
+                .iter()
+                .map(|gate| take(&format!("{}{}_{}", prefix, l, gate), shape))
+                .collect::<anyhow::Result<Vec<Tensor>>>()?;
+            Ok(Tensor::cat(&gates, 0))
+        };
+        let weight_ih = gates("w", &[hidden, inputs])?;
+        let weight_hh = gates("r", &[hidden, hidden])?;
+        let bias_ih = gates("bw", &[hidden])?;
+        let bias_hh = gates("br", &[hidden])?;
+        variables.insert(format!("weight_ih_l{}", layer), weight_ih);
+        variables.insert(format!("weight_hh_l{}", layer), weight_hh);
+        variables.insert(format!("bias_ih_l{}", layer), bias_ih);
+        variables.insert(format!("bias_hh_l{}", layer), bias_hh);
+    }
+
+    let (outputs, stocks) = (lstm.no_outputs() as i64, lstm.stocks as i64);
+    let dense_w = take("dense_w", &[outputs, hidden])?;
+    let dense_b = take("dense_b", &[outputs])?;
+    for (ix, head) in lstm.heads.iter().enumerate() {
+        let rows = ix as i64 * stocks;
+        variables.insert(
+            format!("{}/weight", head.name()),
+            dense_w.narrow(0, rows, stocks),
+        );
+        variables.insert(
+            format!("{}/bias", head.name()),
+            dense_b.narrow(0, rows, stocks),
+        );
+    }
+    if !params.is_empty() {
+        let mut unused: Vec<_> = params.keys().collect();
+        unused.sort();
+        warn!("Ignoring unknown parameters {:?} in {:?}", unused, path);
+    }
+    lstm.validate_variables(&variables)?;
+    Ok(variables)
+}
+
+/// Load a Julia model's weights from an NPZ archive, laid out as described in the module documentation, into the
+/// `VarStore` of a network with the same shape
+pub fn import_julia_weights<P: AsRef<Path>>(
+    vs: &mut VarStore,
+    lstm: &StockLSTM,
+    path: P,
+    order: MatrixOrder,
+) -> anyhow::Result<()> {
+    let variables = julia_variables(lstm, path, order)?;
+    tch::no_grad(|| {
+        for (name, mut var) in vs.variables() {
+            if let Some(value) = variables.get(&name) {
+                var.copy_(&value.to_device(var.device()));
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::Target;
+    use crate::lstm::StockLSTMDesc;
+    use tch::nn::RNN;
+    use tch::Device;
+
+    #[test]
+    fn julia_weights_are_imported() {
+        let desc = StockLSTMDesc {
+            additional_inputs: 1,
+            date_inputs: 2,
+            stocks: 2,
+            hidden: 3,
+            layers: 2,
+            heads: vec![Target::Close, Target::Volume],
+            ..Default::default()
+        };
+        let vs = VarStore::new(Device::Cpu);
+        let lstm = desc.build(&vs);
+
+        // Export the network's weights as Julia would, with matrices transposed as if read from a JLD2 file
+        let variables = vs.variables();
+        let mut params = Vec::new();
+        for layer in 0..desc.layers {
+            for (prefix, name) in [
+                ("w", "weight_ih"),
+                ("r", "weight_hh"),
+                ("bw", "bias_ih"),
+                ("br", "bias_hh"),
+            ]
+            .iter()
+            {
+                let var = &variables[&format!("{}_l{}", name, layer)];
+                for (ix, gate) in GATES.iter().enumerate() {
+                    let param = var.narrow(0, ix as i64 * 3, 3);
+                    let param = if param.dim() == 2 { param.tr() } else { param };
+                    params.push((
+                        format!("{}{}_{}", prefix, layer + 1, gate),
+                        param.contiguous(),
+                    ));
+                }
+            }
+        }
+        let dense_w = Tensor::cat(
+            &[&variables["close/weight"], &variables["volume/weight"]],
+            0,
+        );
+        let dense_b = Tensor::cat(&[&variables["close/bias"], &variables["volume/bias"]], 0);
+        params.push(("dense_w".to_string(), dense_w.tr().contiguous()));
+        params.push(("dense_b".to_string(), dense_b));
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("julia.npz");
+        Tensor::write_npz(&params, &path).unwrap();
+
+        let mut imported_vs = VarStore::new(Device::Cpu);
+        let imported = desc.build(&imported_vs);
+        assert!(
+            import_julia_weights(&mut imported_vs, &imported, &path, MatrixOrder::RowMajor)
+                .is_err()
+        );
+        import_julia_weights(&mut imported_vs, &imported, &path, MatrixOrder::ColumnMajor).unwrap();
+        let input = Tensor::randn(&[2, 4, desc.no_inputs() as i64], tch::kind::FLOAT_CPU);
+        let (expected, _) = lstm.forward_t(&input, &lstm.zero_state(2), false);
+        let (output, _) = imported.forward_t(&input, &imported.zero_state(2), false);
+        assert!(f64::from((output - expected).abs().max()) < 1e-6);
+    }
+}
@@ -0,0 +1,37 @@
+/*!
+Utilities for long-running training jobs
+*/
+pub mod augment;
+pub mod budget;
+pub mod callback;
+pub mod checkpoint;
+pub mod curriculum;
+pub mod fine_tune;
+pub mod grow;
+pub mod interop;
+pub mod julia;
+pub mod schedule;
+pub mod shutdown;
+pub mod symbols;
+pub mod validation;
+pub mod verify;
+pub mod weighting;
+
+pub use augment::{Augmentation, Mixup};
+pub use budget::{gpu_memory, Budget, BudgetTimer};
+pub use callback::{BatchEnd, Callback, Callbacks, MetricsCsv};
+pub use checkpoint::{
+    load_checkpoint, load_weights, read_preprocessing, read_symbols, save_checkpoint,
+    write_preprocessing, write_symbols, EpochMetrics, Phase, Preprocessing,
+};
+pub use curriculum::Curriculum;
+pub use fine_tune::{fine_tune, freeze_layers, LayerSelection};
+pub use grow::{grow_checkpoint, grow_stocks, NewStock};
+pub use interop::SequenceDataset;
+pub use julia::{import_julia_weights, MatrixOrder};
+pub use schedule::{RetrainSchedule, Retrainer, ServingSlot};
+pub use shutdown::Shutdown;
+pub use symbols::SymbolSampler;
+pub use validation::{validate, EarlyStopping, ReduceOnPlateau, ValidationSchedule};
+pub use verify::{verify_data, DataReport, FeatureStats};
+pub use weighting::SampleWeighting;
@@ -0,0 +1,255 @@
+/*!
+Rolling retraining for live deployments: retraining on a trailing window every few trading days, archiving the previous
+checkpoint, and swapping the model serving predictions
+*/
+use super::checkpoint::{desc_path, preprocessing_path, symbols_path};
+use crate::data::{Symbol, Tick};
+use anyhow::format_err;
+use chrono::{NaiveDate, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// How often to retrain, and on how much trailing data
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct RetrainSchedule {
+    /// Retrain every this many trading days
+    pub every_days: usize,
+    /// Retrain on the ticks of this many trailing trading days, including the current one
+    pub window_days: usize,
+}
+
+/// A shared slot holding the model serving predictions, e.g. a `predict::Predictor`, which can be swapped for a
+/// retrained one while other threads use it. A swap waits for the current user to release the slot, so no user ever
+/// sees a partially replaced model.
+#[derive(Debug, Default)]
+pub struct ServingSlot<P>(Arc<Mutex<P>>);
+
+impl<P> Clone for ServingSlot<P> {
+    fn clone(&self) -> Self {
+        ServingSlot(self.0.clone())
+    }
+}
+
+impl<P> ServingSlot<P> {
+    /// Create a slot serving a model
+    pub fn new(serving: P) -> ServingSlot<P> {
+        ServingSlot(Arc::new(Mutex::new(serving)))
+    }
+    /// Lock the slot to use the model it serves. A slot poisoned by a panicking user is recovered.
+    pub fn lock(&self) -> MutexGuard<P> {
+        self.0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+    /// Replace the served model, returning the previous one
+    pub fn swap(&self, serving: P) -> P {
+        std::mem::replace(&mut *self.lock(), serving)
+    }
+}
+
+/// Tracks trading days as ticks arrive, and runs retraining when the schedule says so
+#[derive(Debug, Clone)]
+pub struct Retrainer {
+    /// The retraining schedule
+    pub schedule: RetrainSchedule,
+    /// The checkpoint currently being served, if any
+    pub checkpoint: Option<PathBuf>,
+    /// The directory previous checkpoints are archived to before retraining
+    pub archive_dir: PathBuf,
+    /// The number of trading days started since the last retraining
+    pub days_since_retrain: usize,
+    /// The most recent trading days seen, at most `window_days` of them
+    days: VecDeque<NaiveDate>,
+}
+
+impl Retrainer {
+    /// Create a retrainer for a served checkpoint, if any, archiving previous checkpoints to a directory
+    pub fn new(
+        schedule: RetrainSchedule,
+        checkpoint: Option<PathBuf>,
+        archive_dir: impl Into<PathBuf>,
+    ) -> Retrainer {
+        Retrainer {
+            schedule,
+            checkpoint,
+            archive_dir: archive_dir.into(),
+            days_since_retrain: 0,
+            days: VecDeque::new(),
+        }
+    }
+    /// Record the time of an incoming tick, returning whether it starts a new trading day on which retraining is due.
+    /// Times are expected in nondecreasing order.
+    pub fn observe(&mut self, t: NaiveDateTime) -> bool {
+        let date = t.date();
+        if self.days.back() == Some(&date) {
+            return false;
+        }
+        self.days.push_back(date);
+        while self.days.len() > self.schedule.window_days.max(1) {
+            self.days.pop_front();
+        }
+        self.days_since_retrain += 1;
+        self.due()
+    }
+    /// Whether retraining is due, i.e. whether `every_days` trading days have started since the last retraining
+    pub fn due(&self) -> bool {
+        self.days_since_retrain >= self.schedule.every_days
+    }
+    /// Get the first trading day of the trailing window, if any day has been seen
+    pub fn window_start(&self) -> Option<NaiveDate> {
+        self.days.front().copied()
+    }
+    /// Get the ticks of each symbol within the trailing window, dropping symbols without any
+    pub fn trailing_window(
+        &self,
+        data: &BTreeMap<Symbol, Vec<Tick>>,
+    ) -> BTreeMap<Symbol, Vec<Tick>> {
+        let start = match self.window_start() {
+            Some(start) => start.and_hms(0, 0, 0),
+            None => return BTreeMap::new(),
+        };
+        data.iter()
+            .filter_map(|(symbol, ticks)| {
+                let first = ticks.iter().position(|tick| tick.t >= start)?;
+                Some((symbol.clone(), ticks[first..].to_vec()))
+            })
+            .collect()
+    }
+    /// Copy the files of the served checkpoint to the archive directory, suffixing their names with the current
+    /// trading day. Returns the path of the archived variables, if there was a checkpoint to archive.
+    pub fn archive(&self) -> anyhow::Result<Option<PathBuf>> {
+        let checkpoint = match &self.checkpoint {
+            Some(checkpoint) => checkpoint,
+            None => return Ok(None),
+        };
+        fs::create_dir_all(&self.archive_dir)?;
+        let stem = checkpoint
+            .file_stem()
+            .ok_or_else(|| format_err!("Checkpoint {:?} has no file name", checkpoint))?
+            .to_string_lossy();
+        let day = self
+            .days
+            .back()
+            .map(|day| day.format("%Y-%m-%d").to_string())
+            .unwrap_or_else(|| "initial".to_string());
+        let archived = self.archive_dir.join(format!("{}-{}.ot", stem, day));
+        let companions = [
+            (desc_path(checkpoint), desc_path(&archived)),
+            (symbols_path(checkpoint), symbols_path(&archived)),
+            (
+                preprocessing_path(checkpoint),
+                preprocessing_path(&archived),
+            ),
+            (
+                checkpoint.with_extension("metrics.csv"),
+                archived.with_extension("metrics.csv"),
+            ),
+        ];
+        fs::copy(checkpoint, &archived)
+            .map_err(|err| format_err!("Error archiving checkpoint {:?}: {}", checkpoint, err))?;
+        for (from, to) in companions.iter() {
+            if from.exists() {
+                fs::copy(from, to)?;
+            }
+        }
+        Ok(Some(archived))
+    }
+    /// Retrain on the trailing window of the data: archive the served checkpoint, call `retrain` with the window and
+    /// the served checkpoint (which it may fine-tune), and swap the model it returns into the serving slot. `retrain`
+    /// returns the path of the new checkpoint together with the model to serve, e.g. a `predict::Predictor` warmed up
+    /// on the end of the window.
+    ///
+    /// On error, the previous model keeps serving and retraining stays due.
+    pub fn retrain<P, R>(
+        &mut self,
+        data: &BTreeMap<Symbol, Vec<Tick>>,
+        slot: &ServingSlot<P>,
+        retrain: R,
+    ) -> anyhow::Result<PathBuf>
+    where
+        R: FnOnce(BTreeMap<Symbol, Vec<Tick>>, Option<&Path>) -> anyhow::Result<(PathBuf, P)>,
+    {
+        let window = self.trailing_window(data);
+        if window.is_empty() {
+            return Err(format_err!("No ticks in the trailing window to retrain on"));
+        }
+        if let Some(archived) = self.archive()? {
+            debug!("Archived previous checkpoint to {:?}", archived);
+        }
+        let (checkpoint, serving) = retrain(window, self.checkpoint.as_deref())?;
+        slot.swap(serving);
+        debug!("Now serving retrained checkpoint {:?}", checkpoint);
+        self.checkpoint = Some(checkpoint.clone());
+        self.days_since_retrain = 0;
+        Ok(checkpoint)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    #[test]
+    fn retraining_follows_trading_days() {
+        let dir = tempfile::tempdir().unwrap();
+        let current = dir.path().join("current.ot");
+        fs::write(&current, b"old").unwrap();
+        fs::write(desc_path(&current), b"{}").unwrap();
+        let schedule = RetrainSchedule {
+            every_days: 2,
+            window_days: 2,
+        };
+        let mut retrainer =
+            Retrainer::new(schedule, Some(current.clone()), dir.path().join("archive"));
+
+        let start = NaiveDate::from_ymd(2020, 10, 12).and_hms(14, 30, 0);
+        let ticks: Vec<Tick> = (0..4)
+            .flat_map(|day| (0..2).map(move |minute| (day, minute)))
+            .map(|(day, minute)| Tick {
+                t: start + Duration::days(day) + Duration::minutes(minute),
+                o: 1.0,
+                h: 1.0,
+                l: 1.0,
+                c: 1.0,
+                v: 1.0,
+                vw: 1.0,
+                n: 1.0,
+            })
+            .collect();
+        let mut due = Vec::new();
+        for tick in ticks.iter() {
+            due.push(retrainer.observe(tick.t));
+        }
+        assert_eq!(
+            due,
+            vec![false, false, true, false, true, false, true, false]
+        );
+
+        let mut data = BTreeMap::new();
+        data.insert(Symbol::from("AMD"), ticks);
+        let slot = ServingSlot::new(0usize);
+        let served = slot.clone();
+        let checkpoint = retrainer
+            .retrain(&data, &slot, |window, previous| {
+                assert_eq!(previous, Some(current.as_path()));
+                let rows = window[&Symbol::from("AMD")].len();
+                let path = previous.unwrap().to_path_buf();
+                fs::write(&path, b"new")?;
+                Ok((path, rows))
+            })
+            .unwrap();
+        assert_eq!(checkpoint, current);
+        assert_eq!(*served.lock(), 4);
+        assert!(!retrainer.due());
+        let next = start + Duration::days(4);
+        assert!(!retrainer.observe(next));
+        assert!(retrainer.observe(next + Duration::days(1)));
+        let archived = dir.path().join("archive").join("current-2020-10-15.ot");
+        assert_eq!(fs::read(&archived).unwrap(), b"old");
+        assert!(desc_path(&archived).exists());
+    }
+}
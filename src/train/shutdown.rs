@@ -0,0 +1,38 @@
+/*!
+Graceful shutdown of training jobs on SIGINT or SIGTERM
+*/
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// The exit code used when a second signal arrives before a requested shutdown completes
+pub const FORCED_EXIT_CODE: i32 = 130;
+
+/// A shared flag recording whether shutdown has been requested, which training loops should poll between batches
+#[derive(Debug, Clone, Default)]
+pub struct Shutdown(Arc<AtomicBool>);
+
+impl Shutdown {
+    /// Install a handler for SIGINT and SIGTERM which requests shutdown. A second signal, arriving while the shutdown
+    /// is still in progress, exits the process immediately with `FORCED_EXIT_CODE`.
+    ///
+    /// Only one handler may be installed per process.
+    pub fn install() -> Result<Shutdown, ctrlc::Error> {
+        let shutdown = Shutdown::default();
+        let handle = shutdown.clone();
+        ctrlc::set_handler(move || {
+            if handle.requested() {
+                std::process::exit(FORCED_EXIT_CODE);
+            }
+            handle.request();
+        })?;
+        Ok(shutdown)
+    }
+    /// Request shutdown
+    pub fn request(&self) {
+        self.0.store(true, Ordering::SeqCst)
+    }
+    /// Whether shutdown has been requested
+    pub fn requested(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
@@ -0,0 +1,269 @@
+/*!
+Training a single stock network over many symbols, by sampling windows across them
+*/
+use crate::data::dataset::Dataset;
+use crate::lstm::StockLSTM;
+use anyhow::format_err;
+use chrono::{DateTime, Utc};
+use num::NumCast;
+use rand::Rng;
+use tch::Tensor;
+
+/// Samples batches of windows from many symbols for a network with `stocks = 1`, whose weights are shared across
+/// symbols. This keeps the input width fixed as symbols are added, unlike widening a network's inputs by
+/// `Tick::NN_FIELDS` per symbol.
+///
+/// If `embedding` is set, each row's additional inputs are a one-hot encoding of its symbol, which the network's first
+/// layer maps to a learned per-symbol embedding, so the network must have one additional input per symbol. These are
+/// followed by the symbol's static features, if any, such as those encoded by `StaticEncoding`.
+#[derive(Debug, Clone)]
+pub struct SymbolSampler<F> {
+    /// A single stock dataset for each symbol
+    pub datasets: Vec<Dataset<F>>,
+    /// The length of each sampled sequence
+    pub sequence_length: usize,
+    /// The number of rows after a sequence which must be available, so that its last row has a target
+    pub target_horizon: usize,
+    /// Whether to feed a one-hot encoding of each row's symbol as additional inputs
+    pub embedding: bool,
+    /// The static features of each symbol, fed as additional inputs of every row of the symbol. Empty if there are
+    /// none.
+    pub static_features: Vec<Vec<f32>>,
+}
+
+impl<F: Copy> SymbolSampler<F> {
+    /// Create a sampler over each stock of a dataset, with targets `target_horizon` rows ahead
+    pub fn new(
+        dataset: &Dataset<F>,
+        sequence_length: usize,
+        target_horizon: usize,
+        embedding: bool,
+    ) -> SymbolSampler<F> {
+        let datasets = dataset
+            .symbols
+            .iter()
+            .zip(dataset.ticks.iter())
+            .map(|(symbol, ticks)| Dataset::from_ticks(vec![symbol.clone()], vec![ticks.clone()]))
+            .collect();
+        SymbolSampler {
+            datasets,
+            sequence_length,
+            target_horizon,
+            embedding,
+            static_features: Vec::new(),
+        }
+    }
+    /// Feed the static features of each symbol, in the order of the dataset's symbols, as additional inputs of every
+    /// row of the symbol. Returns an error if there is not one row of features of the same width per symbol.
+    pub fn with_static_features(
+        mut self,
+        static_features: Vec<Vec<f32>>,
+    ) -> anyhow::Result<SymbolSampler<F>> {
+        if static_features.len() != self.symbols() {
+            return Err(format_err!(
+                "Got static features for {} symbols, but sampling from {}",
+                static_features.len(),
+                self.symbols()
+            ));
+        }
+        let width = static_features.first().map_or(0, Vec::len);
+        if static_features
+            .iter()
+            .any(|features| features.len() != width)
+        {
+            return Err(format_err!(
+                "Every symbol must have the same number of static features"
+            ));
+        }
+        self.static_features = static_features;
+        Ok(self)
+    }
+    /// Get the number of symbols sampled from
+    pub fn symbols(&self) -> usize {
+        self.datasets.len()
+    }
+    /// Get the number of additional inputs each row needs, which is one per symbol if embedding symbols
+    pub fn embedding_inputs(&self) -> usize {
+        if self.embedding {
+            self.symbols()
+        } else {
+            0
+        }
+    }
+    /// Get the number of static features of each symbol
+    pub fn static_inputs(&self) -> usize {
+        self.static_features.first().map_or(0, Vec::len)
+    }
+    /// Get the number of additional inputs each row needs: the symbol embedding's followed by the static features
+    pub fn additional_inputs(&self) -> usize {
+        self.embedding_inputs() + self.static_inputs()
+    }
+    /// Get the number of windows of a symbol which can be sampled
+    pub fn windows(&self, symbol: usize) -> usize {
+        let needed = self.sequence_length + self.target_horizon;
+        (self.datasets[symbol].len() + 1).saturating_sub(needed)
+    }
+    /// Get the total number of windows which can be sampled, across every symbol
+    pub fn total_windows(&self) -> usize {
+        (0..self.symbols()).map(|symbol| self.windows(symbol)).sum()
+    }
+    /// Sample the symbol and starting row of a window, with every window across every symbol equally likely. Returns
+    /// `None` if no symbol has enough rows for a window.
+    pub fn sample_window<R: Rng + ?Sized>(&self, rng: &mut R) -> Option<(usize, usize)> {
+        let total = self.total_windows();
+        if total == 0 {
+            return None;
+        }
+        let mut ix = rng.gen_range(0, total);
+        for symbol in 0..self.symbols() {
+            let windows = self.windows(symbol);
+            if ix < windows {
+                return Some((symbol, ix));
+            }
+            ix -= windows;
+        }
+        unreachable!("Window index is less than the total number of windows")
+    }
+    /// Sample a batch of `batch_size` windows, returning tensors of inputs and outputs for a single stock network.
+    /// Returns `None` if no symbol has enough rows for a window. Panics if the network has more than one stock, or
+    /// a number of additional inputs other than `additional_inputs`.
+    pub fn sample<R, DF>(
+        &self,
+        lstm: &StockLSTM,
+        mut time_func: DF,
+        batch_size: usize,
+        rng: &mut R,
+    ) -> Option<(Tensor, Tensor)>
+    where
+        F: NumCast,
+        R: Rng + ?Sized,
+        DF: FnMut(DateTime<Utc>, &mut Vec<f32>),
+    {
+        assert_eq!(
+            lstm.stocks, 1,
+            "Sampled symbols need a single stock network!"
+        );
+        assert_eq!(
+            lstm.additional_inputs,
+            self.additional_inputs(),
+            "Network needs one additional input per embedded symbol and static feature!"
+        );
+        let mut inputs = Vec::with_capacity(batch_size);
+        let mut outputs = Vec::with_capacity(batch_size);
+        let mut additional = Vec::with_capacity(self.additional_inputs());
+        for _ in 0..batch_size {
+            let (symbol, start) = self.sample_window(rng)?;
+            additional.clear();
+            if self.embedding {
+                additional.resize(self.embedding_inputs(), 0.0);
+                additional[symbol] = 1.0;
+            }
+            if let Some(features) = self.static_features.get(symbol) {
+                additional.extend_from_slice(features);
+            }
+            let window = self.datasets[symbol].window(start, self.sequence_length);
+            let (input, output) = lstm.make_window_batch(
+                std::iter::repeat(&additional[..]),
+                &mut time_func,
+                window,
+                1,
+                self.sequence_length,
+            )?;
+            inputs.push(input);
+            outputs.push(output);
+        }
+        Some((Tensor::cat(&inputs, 0), Tensor::cat(&outputs, 0)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::{Target, Tick};
+    use crate::lstm::StockLSTMDesc;
+    use chrono::{Duration, NaiveDate};
+    use rand::{rngs::StdRng, SeedableRng};
+    use tch::{nn::VarStore, Device};
+
+    #[test]
+    fn windows_are_sampled_across_symbols() {
+        let t = NaiveDate::from_ymd(2020, 10, 12).and_hms(14, 30, 0);
+        let ticks = |stock: usize, len: i64| -> Vec<Tick> {
+            (0..len)
+                .map(|m| Tick {
+                    t: t + Duration::minutes(m),
+                    v: 0.0,
+                    vw: 0.0,
+                    o: 0.0,
+                    c: (100 * stock) as f64 + m as f64,
+                    h: 0.0,
+                    l: 0.0,
+                    n: 0.0,
+                })
+                .collect()
+        };
+        let dataset = Dataset::from_ticks(
+            vec!["A".into(), "B".into(), "C".into()],
+            vec![ticks(0, 5), ticks(1, 3), ticks(2, 2)],
+        );
+        let sampler = SymbolSampler::new(&dataset, 2, 1, true);
+        assert_eq!(
+            (0..3)
+                .map(|symbol| sampler.windows(symbol))
+                .collect::<Vec<_>>(),
+            vec![3, 1, 0]
+        );
+        let vs = VarStore::new(Device::Cpu);
+        let lstm = StockLSTMDesc {
+            additional_inputs: sampler.embedding_inputs(),
+            stocks: 1,
+            hidden: 2,
+            layers: 1,
+            heads: vec![Target::Close],
+            ..Default::default()
+        }
+        .build(&vs);
+        let mut rng = StdRng::seed_from_u64(3);
+        let (input, output) = sampler
+            .sample(&lstm, |_, _: &mut Vec<f32>| {}, 16, &mut rng)
+            .unwrap();
+        assert_eq!(input.size3().unwrap(), (16, 2, 3 + Tick::NN_FIELDS as i64));
+        let input = input.view([-1, 3 + Tick::NN_FIELDS as i64]);
+        let one_hot = Vec::<f32>::from(&input.narrow(1, 0, 3).sum1(&[1], false, tch::Kind::Float));
+        assert_eq!(one_hot, vec![1.0; 32]);
+        assert_eq!(f64::from(input.select(1, 2).sum(tch::Kind::Float)), 0.0);
+        let closes = input.select(1, 3 + 3);
+        let targets = output.view([-1]);
+        assert_eq!(Vec::<f32>::from(&(targets - closes)), vec![1.0; 32]);
+
+        // Each row's static features follow its symbol's one-hot encoding
+        let sampler = sampler
+            .with_static_features(vec![vec![1.0, 2.0], vec![3.0, 4.0], vec![5.0, 6.0]])
+            .unwrap();
+        assert_eq!(sampler.additional_inputs(), 5);
+        let vs = VarStore::new(Device::Cpu);
+        let lstm = StockLSTMDesc {
+            additional_inputs: sampler.additional_inputs(),
+            stocks: 1,
+            hidden: 2,
+            layers: 1,
+            heads: vec![Target::Close],
+            ..Default::default()
+        }
+        .build(&vs);
+        let (input, _) = sampler
+            .sample(&lstm, |_, _: &mut Vec<f32>| {}, 16, &mut rng)
+            .unwrap();
+        let input = input.view([-1, 5 + Tick::NN_FIELDS as i64]);
+        let symbol = input
+            .narrow(1, 0, 3)
+            .matmul(&Tensor::of_slice(&[0.0f32, 1.0, 2.0]));
+        let first = Vec::<f32>::from(&(input.select(1, 3) - &symbol * 2.0 - 1.0));
+        let second = Vec::<f32>::from(&(input.select(1, 4) - &symbol * 2.0 - 2.0));
+        assert_eq!(first, vec![0.0; 32]);
+        assert_eq!(second, vec![0.0; 32]);
+        assert!(SymbolSampler::new(&dataset, 2, 1, false)
+            .with_static_features(vec![vec![1.0]])
+            .is_err());
+    }
+}
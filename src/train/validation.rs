@@ -0,0 +1,150 @@
+/*!
+Periodic validation during training, and the early stopping and learning rate schedules driven by it
+*/
+use super::checkpoint::{EpochMetrics, Phase};
+use crate::lstm::loss::{LossFn, WeightedMse};
+use crate::lstm::StockLSTM;
+use serde::{Deserialize, Serialize};
+use tch::nn::RNN;
+use tch::{Device, Tensor};
+
+/// How often to run validation passes during training
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct ValidationSchedule {
+    /// Run a validation pass every this many training batches, as well as at the end of every epoch
+    pub every_batches: usize,
+}
+
+impl ValidationSchedule {
+    /// Whether a validation pass is due after a given number of training batches of an epoch
+    pub fn due(&self, batches: usize) -> bool {
+        self.every_batches > 0 && batches > 0 && batches % self.every_batches == 0
+    }
+}
+
+/// Run a validation pass of a network over batches of inputs and outputs, without dropout or gradients, returning
+/// the metrics of the weighted loss of each batch. Each batch is run from a zero state.
+pub fn validate<I>(lstm: &StockLSTM, batches: I, device: Device, epoch: u64) -> EpochMetrics
+where
+    I: IntoIterator<Item = (Tensor, Tensor)>,
+{
+    let loss_fn = WeightedMse {
+        weights: lstm.loss_weight_tensor().to_device(device),
+    };
+    let mut metrics = EpochMetrics::new(epoch, Phase::Validation);
+    for (input, output) in batches {
+        let (input, output) = (input.to_device(device), output.to_device(device));
+        let batch_size = input.size()[0];
+        let loss = tch::no_grad(|| {
+            let (yhat, _) = lstm.forward_t(&input, &lstm.zero_state(batch_size), false);
+            loss_fn.loss(&yhat, &output, None)
+        });
+        metrics.push(f64::from(loss));
+    }
+    metrics
+}
+
+/// Tracks the best validation loss seen, and signals when it has not improved for too long
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EarlyStopping {
+    /// The number of validation passes without improvement after which to stop
+    pub patience: usize,
+    /// The amount by which the loss must decrease to count as an improvement
+    pub min_delta: f64,
+    /// The best loss seen so far
+    pub best: f64,
+    /// The number of validation passes since the best loss was seen
+    pub since_best: usize,
+}
+
+impl EarlyStopping {
+    /// Create a tracker stopping after `patience` validation passes without improvement
+    pub fn new(patience: usize, min_delta: f64) -> EarlyStopping {
+        EarlyStopping {
+            patience,
+            min_delta,
+            best: f64::INFINITY,
+            since_best: 0,
+        }
+    }
+    /// Record a validation loss, returning whether it is the best seen so far. NaN losses never improve.
+    pub fn observe(&mut self, loss: f64) -> bool {
+        if loss < self.best - self.min_delta {
+            self.best = loss;
+            self.since_best = 0;
+            true
+        } else {
+            self.since_best += 1;
+            false
+        }
+    }
+    /// Whether training should stop
+    pub fn should_stop(&self) -> bool {
+        self.since_best >= self.patience
+    }
+}
+
+/// Reduces the learning rate by a factor when the validation loss plateaus
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReduceOnPlateau {
+    /// The current learning rate
+    pub lr: f64,
+    /// The factor by which to multiply the learning rate on a plateau
+    pub factor: f64,
+    /// The smallest learning rate to reduce to
+    pub min_lr: f64,
+    /// Tracks improvement of the validation loss, with the number of validation passes without improvement after
+    /// which to reduce the learning rate as its patience
+    pub plateau: EarlyStopping,
+}
+
+impl ReduceOnPlateau {
+    /// Create a schedule starting at a learning rate, which is multiplied by `factor` after `patience` validation
+    /// passes without improvement
+    pub fn new(lr: f64, factor: f64, patience: usize, min_lr: f64) -> ReduceOnPlateau {
+        ReduceOnPlateau {
+            lr,
+            factor,
+            min_lr,
+            plateau: EarlyStopping::new(patience, 0.0),
+        }
+    }
+    /// Record a validation loss, returning the new learning rate if it should be reduced
+    pub fn observe(&mut self, loss: f64) -> Option<f64> {
+        self.plateau.observe(loss);
+        if !self.plateau.should_stop() || self.lr <= self.min_lr {
+            return None;
+        }
+        self.plateau.since_best = 0;
+        self.lr = (self.lr * self.factor).max(self.min_lr);
+        Some(self.lr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn schedules_follow_validation_losses() {
+        let schedule = ValidationSchedule { every_batches: 3 };
+        let due: Vec<_> = (0..7).filter(|&batches| schedule.due(batches)).collect();
+        assert_eq!(due, vec![3, 6]);
+
+        let mut stopping = EarlyStopping::new(3, 0.0);
+        let mut plateau = ReduceOnPlateau::new(0.1, 0.5, 2, 0.03);
+        let mut reduced = Vec::new();
+        for &loss in &[3.0, 2.0, 2.5, 2.0, 1.0, 1.5, 1.5, 1.5, 1.5] {
+            stopping.observe(loss);
+            reduced.push(plateau.observe(loss));
+            if stopping.should_stop() {
+                break;
+            }
+        }
+        assert_eq!(stopping.best, 1.0);
+        assert_eq!(
+            reduced,
+            vec![None, None, None, Some(0.05), None, None, Some(0.03), None]
+        );
+    }
+}
@@ -0,0 +1,278 @@
+/*!
+Dry runs of the data pipeline, batching a dataset for one epoch without building a model, to catch misconfigured data
+before allocating a GPU
+*/
+use crate::data::{dataset::Dataset, Symbol, Tick};
+use crate::lstm::{batch::BatchStats, StockLSTMDesc};
+use chrono::{DateTime, Utc};
+use num::NumCast;
+use std::fmt::{self, Display, Formatter};
+
+/// Summary statistics of the values of a single feature
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct FeatureStats {
+    /// The number of finite values seen
+    pub count: u64,
+    /// The number of non-finite values seen, e.g. NaNs produced by a misconfigured scaler
+    pub non_finite: u64,
+    /// The sum of the finite values
+    pub sum: f64,
+    /// The sum of the squares of the finite values
+    pub sum_squares: f64,
+    /// The smallest finite value
+    pub min: f64,
+    /// The largest finite value
+    pub max: f64,
+}
+
+impl Default for FeatureStats {
+    fn default() -> FeatureStats {
+        FeatureStats {
+            count: 0,
+            non_finite: 0,
+            sum: 0.0,
+            sum_squares: 0.0,
+            min: f64::INFINITY,
+            max: -f64::INFINITY,
+        }
+    }
+}
+
+impl FeatureStats {
+    /// Record a value
+    pub fn push(&mut self, value: f64) {
+        if !value.is_finite() {
+            self.non_finite += 1;
+            return;
+        }
+        self.count += 1;
+        self.sum += value;
+        self.sum_squares += value * value;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+    }
+    /// Get the mean of the finite values, which is NaN if there are none
+    pub fn mean(&self) -> f64 {
+        self.sum / self.count as f64
+    }
+    /// Get the standard deviation of the finite values, which is NaN if there are none
+    pub fn std(&self) -> f64 {
+        let mean = self.mean();
+        (self.sum_squares / self.count as f64 - mean * mean)
+            .max(0.0)
+            .sqrt()
+    }
+}
+
+/// The result of a dry run of the data pipeline
+#[derive(Debug, Clone, PartialEq)]
+pub struct DataReport {
+    /// The symbols of the dataset, in stock order
+    pub symbols: Vec<Symbol>,
+    /// The zero fill and alignment statistics of an epoch's batches, which count its batches and rows
+    pub stats: BatchStats,
+    /// The name and statistics of each input feature. Zero filled ticks are not counted.
+    pub inputs: Vec<(String, FeatureStats)>,
+    /// The name and statistics of each output. Rows without a target tick are not counted.
+    pub outputs: Vec<(String, FeatureStats)>,
+}
+
+impl DataReport {
+    /// Get the fraction of rows in which each stock is zero filled
+    pub fn zero_fill_rates(&self) -> Vec<f64> {
+        self.stats.zero_fill_rates()
+    }
+    /// Describe every likely misconfiguration found: stocks zero filled in more than a given fraction of rows, and
+    /// features which are non-finite, constant or never seen
+    pub fn problems(&self, max_zero_fill: f64) -> Vec<String> {
+        let mut problems = Vec::new();
+        if self.stats.batches == 0 {
+            problems.push("the dataset yields no batches".to_string());
+        }
+        for (symbol, rate) in self.symbols.iter().zip(self.zero_fill_rates()) {
+            if rate > max_zero_fill {
+                problems.push(format!(
+                    "{} is zero filled in {:.1}% of rows",
+                    symbol,
+                    100.0 * rate
+                ));
+            }
+        }
+        for (name, stats) in self.inputs.iter().chain(self.outputs.iter()) {
+            if stats.non_finite > 0 {
+                problems.push(format!(
+                    "{} has {} non-finite values",
+                    name, stats.non_finite
+                ));
+            } else if stats.count == 0 {
+                problems.push(format!("{} has no values", name));
+            } else if stats.min == stats.max {
+                problems.push(format!("{} is constant at {}", name, stats.min));
+            }
+        }
+        problems
+    }
+}
+
+impl Display for DataReport {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        writeln!(
+            fmt,
+            "{} stocks, {} rows, {} batches, mean skew {:.1}s, max skew {}s",
+            self.symbols.len(),
+            self.stats.rows,
+            self.stats.batches,
+            self.stats.mean_skew(),
+            self.stats.max_skew
+        )?;
+        writeln!(fmt, "zero fill rates:")?;
+        for (symbol, rate) in self.symbols.iter().zip(self.zero_fill_rates()) {
+            writeln!(fmt, "  {}: {:.4}", symbol, rate)?;
+        }
+        write!(fmt, "features (mean, std, min, max, non-finite):")?;
+        for (name, stats) in self.inputs.iter().chain(self.outputs.iter()) {
+            write!(
+                fmt,
+                "\n  {}: {:.6} {:.6} {:.6} {:.6} {}",
+                name,
+                stats.mean(),
+                stats.std(),
+                stats.min,
+                stats.max,
+                stats.non_finite
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Run the batching of a dataset for the described network over one epoch, without building the network, collecting
+/// the statistics of its batches, including the zero fill rate of each stock, and statistics of every input feature and
+/// output.
+///
+/// Batches are consecutive windows of `batch_size * sequence_length` rows, packaged exactly as for training without
+/// additional inputs. Panics if the dataset does not have the described number of stocks.
+pub fn verify_data<F, DF>(
+    desc: &StockLSTMDesc,
+    dataset: &Dataset<F>,
+    mut time_func: DF,
+    batch_size: usize,
+    sequence_length: usize,
+) -> DataReport
+where
+    F: Copy + NumCast,
+    DF: FnMut(DateTime<Utc>, &mut Vec<f32>),
+{
+    let stocks = desc.stocks;
+    let (no_inputs, no_outputs) = (desc.no_inputs(), stocks * desc.heads.len());
+    let first_tick = desc.additional_inputs + desc.date_inputs;
+    let mut report = DataReport {
+        symbols: dataset.symbols.clone(),
+        stats: BatchStats::new(stocks),
+        inputs: desc
+            .input_layout()
+            .column_names()
+            .map(|name| (name.to_string(), FeatureStats::default()))
+            .collect(),
+        outputs: desc
+            .output_layout()
+            .column_names()
+            .map(|name| (name.to_string(), FeatureStats::default()))
+            .collect(),
+    };
+    let rows = (batch_size * sequence_length).max(1);
+    for window in dataset.windows(rows, rows) {
+        let (input, output) = match desc.make_window_batch(
+            std::iter::empty(),
+            &mut time_func,
+            window,
+            batch_size,
+            sequence_length,
+        ) {
+            Some(batch) => batch,
+            None => continue,
+        };
+        report.stats.observe(window, rows);
+        let input = Vec::<f32>::from(&input.view([-1]));
+        let output = Vec::<f32>::from(&output.view([-1]));
+        for row in 0..window.len {
+            let input = &input[row * no_inputs..(row + 1) * no_inputs];
+            for (column, value) in input[..first_tick].iter().enumerate() {
+                report.inputs[column].1.push(*value as f64);
+            }
+            for stock in 0..stocks {
+                if window.tick(row, stock).is_none() {
+                    continue;
+                }
+                let start = first_tick + stock * Tick::NN_FIELDS;
+                for column in start..start + Tick::NN_FIELDS {
+                    report.inputs[column].1.push(input[column] as f64);
+                }
+            }
+            let output = &output[row * no_outputs..(row + 1) * no_outputs];
+            for (column, value) in output.iter().enumerate() {
+                if window
+                    .tick(row + desc.target_horizon, column % stocks)
+                    .is_some()
+                {
+                    report.outputs[column].1.push(*value as f64);
+                }
+            }
+        }
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::Target;
+    use chrono::{Duration, NaiveDate};
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn dry_run_reports_zero_fill() {
+        let start = NaiveDate::from_ymd(2020, 10, 12).and_hms(14, 30, 0);
+        let tick = |minute: i64, c: f32| Tick {
+            t: start + Duration::minutes(minute),
+            o: c,
+            h: c,
+            l: c,
+            c,
+            v: 1.0,
+            vw: c,
+            n: 1.0,
+        };
+        let mut data = BTreeMap::new();
+        data.insert(
+            Symbol::from("AMD"),
+            (0..8).map(|minute| tick(minute, minute as f32)).collect(),
+        );
+        data.insert(
+            Symbol::from("NVDA"),
+            (0..8).step_by(2).map(|minute| tick(minute, 1.0)).collect(),
+        );
+        let dataset = Dataset::new(data);
+        let desc = StockLSTMDesc {
+            stocks: 2,
+            hidden: 4,
+            layers: 1,
+            heads: vec![Target::Close],
+            ..Default::default()
+        };
+        let report = verify_data(&desc, &dataset, |_, _| {}, 2, 2);
+        assert_eq!(report.stats.batches, 2);
+        assert_eq!(report.stats.rows, 8);
+        assert_eq!(report.zero_fill_rates(), vec![0.0, 0.5]);
+        let (name, close) = &report.inputs[3];
+        assert_eq!(name, "stock0.c");
+        assert_eq!(close.count, 8);
+        assert!((close.mean() - 3.5).abs() < 1e-12);
+        assert_eq!(report.outputs[0].1.count, 7);
+        let problems = report.problems(0.25);
+        assert!(problems.contains(&"NVDA is zero filled in 50.0% of rows".to_string()));
+        assert!(problems
+            .iter()
+            .any(|problem| problem.starts_with("stock1.c is constant")));
+    }
+}
@@ -0,0 +1,99 @@
+/*!
+Per-window sample weights, so training doesn't spend all its capacity on the mass of quiet bars
+*/
+use serde::{Deserialize, Serialize};
+use tch::{Kind, Tensor};
+
+/// Offset keeping volatility weights finite for windows without any price movement
+const VOLATILITY_EPSILON: f64 = 1e-8;
+
+/// Weights for the windows of a batch, to be used in the loss via a `lstm::loss::SampleWeighted`. Every weighting is
+/// disabled by default, giving every window a weight of one.
+#[derive(Debug, Copy, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct SampleWeighting {
+    /// The exponent of each window's realized volatility, relative to the mean over its batch, in its weight, so
+    /// that `1.0` weights windows proportionally to their realized volatility
+    #[serde(default)]
+    pub volatility: f64,
+    /// The half-life of recency weighting, in rows: a window's weight is halved for every this many rows between its
+    /// last row and the end of the data. Disabled if not positive.
+    #[serde(default)]
+    pub recency_half_life: f64,
+}
+
+impl SampleWeighting {
+    /// Whether any weighting is enabled
+    pub fn is_enabled(&self) -> bool {
+        self.volatility != 0.0 || self.recency_half_life > 0.0
+    }
+    /// Compute the weights of the windows of a batch from their stocks' raw closes, of shape `[batch, sequence, stocks]`
+    /// as returned by `BatchBuffer::closes`, where each window follows the previous one in time and `rows_after` rows
+    /// of data follow the last window. Returns a tensor of shape `[batch, 1, 1]`, to be computed before any windows
+    /// are permuted, e.g. by `Mixup::apply_weighted`, which blends the weights along with the windows.
+    ///
+    /// The realized volatility of a window is the root mean square log return of its stocks' closes between
+    /// consecutive rows, ignoring returns from or to missing ticks.
+    pub fn weights(&self, closes: &Tensor, rows_after: usize) -> Tensor {
+        let size = closes.size();
+        let (batch, sequence_length) = (size[0], size[1]);
+        let device = closes.device();
+        let mut weights = Tensor::ones(&[batch], (Kind::Float, device));
+        if self.volatility != 0.0 && sequence_length > 1 {
+            let realized = realized_volatility(closes);
+            let relative = (&realized + VOLATILITY_EPSILON)
+                / (realized.mean(Kind::Float) + VOLATILITY_EPSILON);
+            weights = weights * relative.pow(self.volatility);
+        }
+        if self.recency_half_life > 0.0 {
+            let recency: Vec<f32> = (0..batch)
+                .map(|window| {
+                    let age = rows_after as f64 + ((batch - 1 - window) * sequence_length) as f64;
+                    0.5f64.powf(age / self.recency_half_life) as f32
+                })
+                .collect();
+            weights = weights * Tensor::of_slice(&recency).to_device(device);
+        }
+        weights.view([batch, 1, 1])
+    }
+}
+
+/// Compute the realized volatility of the raw closes of each window of a batch, of shape `[batch]`
+fn realized_volatility(closes: &Tensor) -> Tensor {
+    let closes = closes.to_kind(Kind::Float);
+    let steps = closes.size()[1] - 1;
+    let (before, after) = (closes.narrow(1, 0, steps), closes.narrow(1, 1, steps));
+    let present = before.gt(0.0).to_kind(Kind::Float) * after.gt(0.0).to_kind(Kind::Float);
+    // Missing closes are replaced by ones, so that their log returns are zero rather than infinite
+    let absent = 1.0 - &present;
+    let changes = ((after * &present + &absent) / (before * &present + &absent)).log();
+    let squares = (&changes * &changes).sum1(&[1, 2], false, Kind::Float);
+    let counts = present.sum1(&[1, 2], false, Kind::Float).clamp_min(1.0);
+    (squares / counts).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn volatile_and_recent_windows_weigh_more() {
+        // The first window's close doubles every row, the second's grows eightfold, ignoring the missing tick
+        let closes = [1.0f32, 2.0, 4.0, 8.0, 1.0, 8.0, 0.0, 5.0];
+        let closes = Tensor::of_slice(&closes).view([2, 4, 1]);
+
+        let volatility = SampleWeighting {
+            volatility: 1.0,
+            recency_half_life: 0.0,
+        };
+        let weights = Vec::<f32>::from(&volatility.weights(&closes, 0).view([2]));
+        assert!((weights[0] - 0.5).abs() < 1e-5);
+        assert!((weights[1] - 1.5).abs() < 1e-5);
+
+        let recency = SampleWeighting {
+            volatility: 0.0,
+            recency_half_life: 4.0,
+        };
+        let weights = Vec::<f32>::from(&recency.weights(&closes, 4).view([2]));
+        assert_eq!(weights, vec![0.25, 0.5]);
+    }
+}
@@ -2,8 +2,10 @@
 Miscellaneous utilities for `stockburn`
 */
 
+use anyhow::format_err;
 use chrono::Duration;
 use num::{Float, NumCast};
+use tch::{Cuda, Device};
 
 /// Convert a `chrono::Duration` to a floating point containing the number of nanoseconds
 pub fn to_ns<F: Float>(dur: Duration) -> F {
@@ -17,6 +19,37 @@ pub fn to_s<F: Float>(dur: Duration) -> F {
     dur_ns / ns_in_sec
 }
 
+/// Get every device available to this process: the CPU, followed by each CUDA device in order of index
+pub fn available_devices() -> Vec<Device> {
+    let cuda = (0..Cuda::device_count().max(0) as usize).map(Device::Cuda);
+    std::iter::once(Device::Cpu).chain(cuda).collect()
+}
+
+/// Parse a device specification: `cpu`, `cuda` for the first CUDA device if available and the CPU otherwise, or
+/// `cuda:N` for the CUDA device with index `N`, which must be available
+pub fn parse_device(spec: &str) -> anyhow::Result<Device> {
+    match spec.trim() {
+        "cpu" => Ok(Device::Cpu),
+        "cuda" => Ok(Device::cuda_if_available()),
+        spec if spec.starts_with("cuda:") => {
+            let ix: usize = spec["cuda:".len()..]
+                .parse()
+                .map_err(|_| format_err!("Invalid CUDA device index in {:?}", spec))?;
+            let count = Cuda::device_count().max(0) as usize;
+            if ix < count {
+                Ok(Device::Cuda(ix))
+            } else {
+                Err(format_err!(
+                    "CUDA device {} requested, but only {} CUDA devices are available",
+                    ix,
+                    count
+                ))
+            }
+        }
+        spec => Err(format_err!("Invalid device: {:?}", spec)),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -26,4 +59,14 @@ mod tests {
         assert_eq!(to_s::<f64>(Duration::minutes(1)), 60.0);
         assert_eq!(to_s::<f32>(Duration::days(1)), 60.0 * 60.0 * 24.0);
     }
+
+    #[test]
+    fn device_parsing() {
+        assert_eq!(parse_device("cpu").unwrap(), Device::Cpu);
+        assert_eq!(parse_device("cuda").unwrap(), Device::cuda_if_available());
+        assert!(parse_device("cuda:x").is_err());
+        assert!(parse_device("cuda:1000").is_err());
+        assert!(parse_device("tpu").is_err());
+        assert_eq!(available_devices()[0], Device::Cpu);
+    }
 }
\ No newline at end of file
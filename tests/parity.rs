@@ -0,0 +1,112 @@
+/*!
+Numerical parity of the forward pass and one optimizer step against the PyTorch reference implementation in
+`tests/parity/reference.py`, on a fixed seed and a fixed batch, guarding against silent behavioral drift when tch or the
+model code changes.
+
+These tests need Python 3 with `numpy` and `torch`, and are hence ignored by default. Run them with
+`cargo test --test parity -- --ignored`, setting `STOCKBURN_PYTHON` to use an interpreter other than `python3`.
+*/
+use std::collections::HashMap;
+use std::path::Path;
+use std::process::Command;
+use stockburn::data::Target;
+use stockburn::lstm::StockLSTMDesc;
+use tch::nn::{self, OptimizerConfig, VarStore, RNN};
+use tch::{Device, Tensor};
+
+const SEED: i64 = 1234;
+const LEARNING_RATE: f64 = 0.01;
+const TOLERANCE: f64 = 1e-5;
+
+/// Get a deterministic tensor of a given shape, varying smoothly along its elements
+fn fixed_tensor(shape: &[i64], frequency: f64) -> Tensor {
+    let len = shape.iter().product::<i64>();
+    (Tensor::arange(len, tch::kind::FLOAT_CPU) * frequency)
+        .sin()
+        .view(shape)
+}
+
+/// Assert that a tensor matches its reference up to `TOLERANCE`
+fn assert_close(name: &str, found: &Tensor, expected: &Tensor) {
+    assert_eq!(found.size(), expected.size(), "Shape of {}", name);
+    let difference = f64::from((found - expected).abs().max());
+    assert!(
+        difference < TOLERANCE,
+        "{} differs from the reference by {}",
+        name,
+        difference
+    );
+}
+
+/// Run the reference implementation on a network's variables and a batch, returning its results by name
+fn reference(
+    vs: &VarStore,
+    heads: &[Target],
+    input: &Tensor,
+    output: &Tensor,
+) -> HashMap<String, Tensor> {
+    let dir = tempfile::tempdir().unwrap();
+    let (model, results) = (
+        dir.path().join("model.npz"),
+        dir.path().join("reference.npz"),
+    );
+    let mut tensors: Vec<(String, Tensor)> = vs.variables().into_iter().collect();
+    tensors.push(("input".to_string(), input.shallow_clone()));
+    tensors.push(("output".to_string(), output.shallow_clone()));
+    Tensor::write_npz(&tensors, &model).unwrap();
+
+    let python = std::env::var("STOCKBURN_PYTHON").unwrap_or_else(|_| "python3".to_string());
+    let script = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/parity/reference.py");
+    let heads: Vec<&str> = heads.iter().map(|head| head.name()).collect();
+    let status = Command::new(&python)
+        .arg(&script)
+        .arg(&model)
+        .arg(&results)
+        .arg("--heads")
+        .arg(heads.join(","))
+        .arg("--lr")
+        .arg(LEARNING_RATE.to_string())
+        .status()
+        .unwrap_or_else(|err| panic!("Error running {} {:?}: {}", python, script, err));
+    assert!(
+        status.success(),
+        "Reference implementation failed: {}",
+        status
+    );
+    Tensor::read_npz(&results).unwrap().into_iter().collect()
+}
+
+#[test]
+#[ignore]
+fn forward_and_step_match_reference() {
+    tch::manual_seed(SEED);
+    let desc = StockLSTMDesc {
+        additional_inputs: 1,
+        date_inputs: 2,
+        stocks: 2,
+        hidden: 8,
+        layers: 2,
+        heads: vec![Target::Close, Target::Volume],
+        ..Default::default()
+    };
+    let vs = VarStore::new(Device::Cpu);
+    let lstm = desc.build(&vs);
+    let input = fixed_tensor(&[3, 5, desc.no_inputs() as i64], 0.37);
+    let output = fixed_tensor(&[3, 5, lstm.no_outputs() as i64], 0.11);
+    let results = reference(&vs, &desc.heads, &input, &output);
+
+    let state = lstm.zero_state(3);
+    let (yhat, _) = lstm.forward_t(&input, &state, false);
+    assert_close("output", &yhat, &results["output"]);
+    let (loss, _) = lstm.loss(&input, &output, &state);
+    assert_close("loss", &loss.view([1]), &results["loss"]);
+
+    let mut opt = nn::Adam::default().build(&vs, LEARNING_RATE).unwrap();
+    opt.backward_step(&loss);
+    for (name, var) in vs.variables() {
+        let expected = results
+            .get(&name)
+            .unwrap_or_else(|| panic!("Reference is missing variable {}", name));
+        assert_close(&name, &var, expected);
+    }
+}
@@ -0,0 +1,63 @@
+#!/usr/bin/env python3
+"""
+Reference implementation of a plain StockLSTM in PyTorch, for the numerical parity tests in tests/parity.rs
+
+Reads a network's variables, named as in its tch VarStore, together with a fixed `input` and `output` batch from an NPZ
+archive. Writes the network's outputs on the batch, its mean squared error, and every variable after one Adam step on
+that loss to another NPZ archive.
+"""
+import argparse
+
+import numpy as np
+import torch
+
+
+def main():
+    parser = argparse.ArgumentParser(description=__doc__)
+    parser.add_argument("model", help="NPZ archive of the network's variables and the batch")
+    parser.add_argument("reference", help="NPZ archive to write the reference results to")
+    parser.add_argument("--heads", required=True, help="Comma separated names of the heads, in output order")
+    parser.add_argument("--lr", type=float, required=True, help="The learning rate of the Adam step")
+    args = parser.parse_args()
+
+    data = np.load(args.model)
+    heads = args.heads.split(",")
+    layers = sum(1 for name in data.files if name.startswith("weight_ih_l"))
+    inputs = data["weight_ih_l0"].shape[1]
+    hidden = data["weight_hh_l0"].shape[1]
+
+    lstm = torch.nn.LSTM(inputs, hidden, layers, batch_first=True)
+    linears = [torch.nn.Linear(hidden, data[head + "/weight"].shape[0]) for head in heads]
+    with torch.no_grad():
+        for name, param in lstm.named_parameters():
+            param.copy_(torch.from_numpy(data[name]))
+        for head, linear in zip(heads, linears):
+            linear.weight.copy_(torch.from_numpy(data[head + "/weight"]))
+            linear.bias.copy_(torch.from_numpy(data[head + "/bias"]))
+
+    xs = torch.from_numpy(data["input"])
+    ys = torch.from_numpy(data["output"])
+    hidden_states, _ = lstm(xs)
+    yhat = torch.cat([linear(hidden_states) for linear in linears], -1)
+    loss = ((yhat - ys) ** 2).mean()
+
+    params = list(lstm.parameters()) + [param for linear in linears for param in linear.parameters()]
+    optimizer = torch.optim.Adam(params, lr=args.lr)
+    optimizer.zero_grad()
+    loss.backward()
+    optimizer.step()
+
+    results = {
+        "output": yhat.detach().numpy(),
+        "loss": np.array([loss.item()], dtype=np.float32),
+    }
+    for name, param in lstm.named_parameters():
+        results[name] = param.detach().numpy()
+    for head, linear in zip(heads, linears):
+        results[head + "/weight"] = linear.weight.detach().numpy()
+        results[head + "/bias"] = linear.bias.detach().numpy()
+    np.savez(args.reference, **results)
+
+
+if __name__ == "__main__":
+    main()
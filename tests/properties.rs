@@ -0,0 +1,113 @@
+/*!
+Property-based tests of tick parsing, sanitization, scaling and batching on arbitrary tick sequences, generated by the
+strategies of `stockburn::testing`. Run with `cargo test --features testing --test properties`.
+*/
+use proptest::collection::vec;
+use proptest::prelude::*;
+use std::collections::BTreeSet;
+use stockburn::data::{clean::sanitize, polygon::*, scale::ExpScaler, Target, Tick};
+use stockburn::lstm::{
+    batch::{BatchShape, Lookahead, TailPolicy},
+    StockLSTMDesc,
+};
+use stockburn::testing::*;
+use tch::nn::VarStore;
+use tch::Device;
+
+proptest! {
+    #[test]
+    fn read_ticks_inverts_write_ticks(ticks in messy_tick_sequence(64)) {
+        let mut buffer = Vec::new();
+        let written = write_ticks(&mut buffer, ticks.iter().copied()).unwrap();
+        prop_assert_eq!(written, ticks.len());
+        let read = read_ticks(&buffer[..], None);
+        prop_assert_eq!(read.len(), ticks.len());
+        for (read, tick) in read.iter().zip(ticks.iter()) {
+            prop_assert!(same_tick(read, tick), "Read {:?}, wrote {:?}", read, tick);
+        }
+    }
+
+    #[test]
+    fn sanitized_ticks_are_clean(ticks in messy_tick_sequence(64)) {
+        let mut sanitized = ticks.clone();
+        let report = sanitize(&mut sanitized);
+        prop_assert!(sanitized.len() <= ticks.len());
+        prop_assert_eq!(check_sanitized(&sanitized), Ok(()));
+        let times: BTreeSet<_> = ticks.iter().map(|tick| tick.t).collect();
+        prop_assert_eq!(sanitized.len(), times.len(), "{:?}", report);
+        let mut again = sanitized.clone();
+        prop_assert!(sanitize(&mut again).is_clean());
+    }
+
+    #[test]
+    fn scaled_values_are_bounded(
+        start in 1.0..1000.0f64,
+        values in vec(messy_value(), 0..64),
+        steps in vec(0..600i64, 0..64),
+    ) {
+        let mut scaler = ExpScaler::start(start, 0.99, 0.999);
+        for (value, step) in values.into_iter().zip(steps.into_iter().cycle()) {
+            let scaled = scaler.scale(value);
+            prop_assert!(
+                scaled.is_finite() && scaled.abs() <= 3.0 + 1e-9,
+                "Scaled {} to {}",
+                value,
+                scaled
+            );
+            scaler.update(value, chrono::Duration::seconds(step));
+        }
+    }
+
+    #[test]
+    fn batches_mask_every_row_with_a_target(
+        stocks in vec(tick_sequence(48), 1..4),
+        batch_size in 1..4usize,
+        sequence_length in 1..8usize,
+        target_horizon in 1..3usize,
+    ) {
+        let stocks: Vec<Vec<Tick>> = stocks
+            .into_iter()
+            .map(|mut ticks| {
+                sanitize(&mut ticks);
+                ticks
+            })
+            .collect();
+        let times: BTreeSet<_> = stocks.iter().flatten().map(|tick| tick.t).collect();
+        let vs = VarStore::new(Device::Cpu);
+        let lstm = StockLSTMDesc {
+            stocks: stocks.len(),
+            hidden: 2,
+            layers: 1,
+            heads: vec![Target::Close],
+            target_horizon,
+            ..Default::default()
+        }
+        .build(&vs);
+        let mut iterators: Vec<_> = stocks
+            .iter()
+            .map(|ticks| Lookahead::new(ticks.iter().copied()))
+            .collect();
+        let (mut rows, mut dropped) = (0, 0);
+        while let Some(batch) = lstm.make_masked_batches(
+            std::iter::empty(),
+            |_, _: &mut Vec<f32>| {},
+            &mut iterators,
+            BatchShape {
+                batch_size,
+                sequence_length,
+                tail: TailPolicy::Pad,
+            },
+            &mut dropped,
+        ) {
+            prop_assert_eq!(
+                batch.mask.size(),
+                vec![batch_size as i64, sequence_length as i64, 1]
+            );
+            prop_assert_eq!(batch.rows as f64, f64::from(batch.mask.sum(tch::Kind::Float)));
+            prop_assert_eq!(batch.input.isfinite().all().int64_value(&[]), 1);
+            rows += batch.rows;
+        }
+        prop_assert_eq!(dropped, 0);
+        prop_assert_eq!(rows, times.len().saturating_sub(target_horizon));
+    }
+}
@@ -0,0 +1,57 @@
+/*!
+Test data processing on canonical fake scenarios
+*/
+use stockburn::data::{clean::sanitize, fake::scenarios::*, scale::TickExpScaler};
+
+#[test]
+fn scenarios_are_clean_and_deterministic() {
+    for scenario in all() {
+        let data = scenario.build();
+        assert_eq!(data.len(), SCENARIO_STOCKS, "{}", scenario.name);
+        assert_eq!(data, scenario.build(), "{}", scenario.name);
+        for ticks in data.values() {
+            assert_eq!(ticks.len(), SCENARIO_SESSIONS * 391 - 1);
+            let mut sanitized = ticks.clone();
+            assert!(sanitize(&mut sanitized).is_clean(), "{}", scenario.name);
+        }
+        let dataset = scenario.dataset();
+        assert_eq!(dataset.len(), SCENARIO_SESSIONS * 391 - 1);
+        assert!((0..dataset.len()).all(|row| dataset.present(row).all(|present| present)));
+    }
+}
+
+#[test]
+fn scaled_scenarios_are_bounded() {
+    for scenario in all() {
+        for ticks in scenario.build().values() {
+            let mut scaler = TickExpScaler::with_start(ticks[0], 0.99, 0.999);
+            for tick in ticks.iter().skip(1) {
+                let scaled = scaler.tick(*tick);
+                for value in &[scaled.o, scaled.h, scaled.l, scaled.c, scaled.v, scaled.vw] {
+                    assert!(value.is_finite() && value.abs() <= 3.0 + 1e-9);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn scenario_dynamics() {
+    for ticks in trend().build().values() {
+        assert!(ticks.last().unwrap().c > 1.05 * SCENARIO_PRICE);
+    }
+    for tick in mean_revert().build().values().flatten() {
+        assert!((tick.c / SCENARIO_PRICE).ln().abs() < 0.05);
+    }
+    for ticks in crash().build().values() {
+        let worst = ticks
+            .windows(2)
+            .map(|w| (w[1].c / w[0].c).ln())
+            .fold(0.0, f64::min);
+        assert!(worst < 0.8 * CRASH_LOG_RETURN);
+    }
+    for tick in flat().build().values().flatten() {
+        assert_eq!(tick.c, SCENARIO_PRICE);
+        assert_eq!(tick.h, tick.l);
+    }
+}